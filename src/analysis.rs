@@ -0,0 +1,291 @@
+use detached_str::{Str, StrSlice};
+
+use core::fmt;
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{parse_script, tokenize, Expression, TokenKind};
+
+/// A problem in a script that doesn't stop it from running, but probably isn't what
+/// its author meant.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    source: Str,
+    at: Option<StrSlice>,
+    pub message: String,
+    pub label: Option<String>,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        crate::error::fmt_warning(
+            &self.source,
+            self.at,
+            &self.message,
+            self.label.as_deref(),
+            f,
+        )
+    }
+}
+
+/// Names the shell itself looks up, which a script may define without using.
+const HOOKS: &[&str] = &[
+    "prompt",
+    "incomplete_prompt",
+    "continuation_prompt",
+    "report",
+];
+
+/// Where a warning points, before it's matched up with the tokens of the script.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Site {
+    /// The nth `let` of a name.
+    Let(usize),
+    /// The nth function parameter with a name.
+    Param(usize),
+    /// The nth use of a name.
+    Use(usize),
+}
+
+/// Look for bindings that are never used, function parameters and variables
+/// that shadow an outer variable, constants that are bound again, and code
+/// after a call to `exit`.
+///
+/// Scripts with syntax errors have no warnings; running them reports the error instead.
+pub fn analyze(script: &str) -> Vec<Warning> {
+    let expr = match parse_script(script) {
+        Ok(expr) => expr,
+        Err(_) => return vec![],
+    };
+
+    let mut analysis = Analysis::default();
+    analysis.walk(&expr, &mut vec![BTreeSet::new()], false);
+
+    for (name, count) in analysis.lets.clone() {
+        if name.starts_with('_')
+            || name.chars().all(|c| !c.is_lowercase())
+            || HOOKS.contains(&name.as_str())
+            || analysis.used.contains(&name)
+        {
+            continue;
+        }
+        for n in 0..count {
+            analysis.found.push((
+                name.clone(),
+                Site::Let(n),
+                format!("unused variable `{}`", name),
+                Some(format!("`{}` is never used", name)),
+            ));
+        }
+    }
+
+    let source = Str::from(script);
+    let sites = sites(script);
+    let mut warnings = analysis
+        .found
+        .into_iter()
+        .map(|(name, site, message, label)| Warning {
+            at: sites.get(&(name, site)).copied(),
+            source: source.clone(),
+            message,
+            label,
+        })
+        .collect::<Vec<_>>();
+    warnings.sort_by_key(|warning| warning.at.map(|at| at.start()));
+    warnings
+}
+
+#[derive(Default)]
+struct Analysis {
+    /// How many times each name is bound with `let`, `local` or `const`.
+    lets: BTreeMap<String, usize>,
+    /// The `let` sites of the names bound with `const`.
+    constants: BTreeMap<String, Site>,
+    /// How many times each name is a function parameter.
+    params: BTreeMap<String, usize>,
+    /// How many times each name is used.
+    uses: BTreeMap<String, usize>,
+    used: BTreeSet<String>,
+    found: Vec<(String, Site, String, Option<String>)>,
+}
+
+impl Analysis {
+    /// Walk the expression in the order its tokens appear in the script, keeping
+    /// track of the names bound in each function around it.
+    fn walk(&mut self, expr: &Expression, scopes: &mut Vec<BTreeSet<String>>, in_function: bool) {
+        match expr {
+            Expression::Symbol(name) => {
+                let n = *self.uses.get(name).unwrap_or(&0);
+                self.uses.insert(name.clone(), n + 1);
+                self.used.insert(name.clone());
+                if let Some((object, _)) = name.split_once('.') {
+                    self.used.insert(object.to_string());
+                }
+            }
+            Expression::Assign(name, value)
+            | Expression::Local(name, value)
+            | Expression::Const(name, value) => {
+                let n = *self.lets.get(name).unwrap_or(&0);
+                self.lets.insert(name.clone(), n + 1);
+
+                // Functions get their own copies of variables, so only the bindings
+                // outside of them can't be bound again.
+                if let (Some(&site), false) = (self.constants.get(name), in_function) {
+                    let message = format!("constant `{}` is bound again", name);
+                    self.found.push((
+                        name.clone(),
+                        site,
+                        message.clone(),
+                        Some(String::from("it's defined as a constant here")),
+                    ));
+                    self.found.push((
+                        name.clone(),
+                        Site::Let(n),
+                        message,
+                        Some(String::from("so binding it here is an error")),
+                    ));
+                } else if matches!(expr, Expression::Const(..)) && !in_function {
+                    self.constants.insert(name.clone(), Site::Let(n));
+                }
+
+                let outer = scopes[..scopes.len() - 1]
+                    .iter()
+                    .any(|scope| scope.contains(name));
+                let local = scopes.last().unwrap().contains(name);
+                let is_let = matches!(expr, Expression::Assign(..));
+                if is_let && in_function && outer && !local && !name.starts_with('_') {
+                    self.found.push((
+                        name.clone(),
+                        Site::Let(n),
+                        format!(
+                            "`{}` shadows a variable defined outside this function",
+                            name
+                        ),
+                        Some(String::from(
+                            "this only changes the function's own copy of the variable; use `local` if that's what you want",
+                        )),
+                    ));
+                }
+                scopes.last_mut().unwrap().insert(name.clone());
+                self.walk(value, scopes, in_function);
+            }
+            Expression::Lambda(param, body, _) => {
+                let n = *self.params.get(param).unwrap_or(&0);
+                self.params.insert(param.clone(), n + 1);
+
+                if !param.starts_with('_') && scopes.iter().any(|scope| scope.contains(param)) {
+                    self.found.push((
+                        param.clone(),
+                        Site::Param(n),
+                        format!("parameter `{}` shadows a variable of the same name", param),
+                        None,
+                    ));
+                }
+                scopes.push(BTreeSet::from([param.clone()]));
+                self.walk(body, scopes, true);
+                scopes.pop();
+            }
+            // Macros run in the environment they're called from, so they can't shadow anything.
+            Expression::Macro(param, body) => {
+                let n = *self.params.get(param).unwrap_or(&0);
+                self.params.insert(param.clone(), n + 1);
+                scopes.last_mut().unwrap().insert(param.clone());
+                self.walk(body, scopes, in_function);
+            }
+            Expression::For(names, list, body) => {
+                scopes.last_mut().unwrap().extend(names.iter().cloned());
+                self.walk(list, scopes, in_function);
+                self.walk(body, scopes, in_function);
+            }
+            Expression::Do(exprs) => self.walk_block(exprs, scopes, in_function),
+            Expression::List(exprs) => {
+                for expr in exprs {
+                    self.walk(expr, scopes, in_function);
+                }
+            }
+            Expression::Map(exprs) => {
+                for expr in exprs.values() {
+                    self.walk(expr, scopes, in_function);
+                }
+            }
+            Expression::If(cond, then, otherwise) => {
+                self.walk(cond, scopes, in_function);
+                self.walk(then, scopes, in_function);
+                self.walk(otherwise, scopes, in_function);
+            }
+            Expression::Apply(f, args) => {
+                self.walk(f, scopes, in_function);
+                for arg in args {
+                    self.walk(arg, scopes, in_function);
+                }
+            }
+            Expression::Group(inner) | Expression::Quote(inner) | Expression::Yield(inner) => {
+                self.walk(inner, scopes, in_function)
+            }
+            _ => {}
+        }
+    }
+
+    fn walk_block(
+        &mut self,
+        exprs: &[Expression],
+        scopes: &mut Vec<BTreeSet<String>>,
+        in_function: bool,
+    ) {
+        for (i, expr) in exprs.iter().enumerate() {
+            let exit = match expr {
+                Expression::Apply(f, _) => match f.as_ref() {
+                    Expression::Symbol(name) => Some(name),
+                    _ => None,
+                },
+                Expression::Symbol(name) => Some(name),
+                _ => None,
+            }
+            .filter(|name| *name == "exit" || *name == "quit")
+            .map(|name| (name.clone(), *self.uses.get(name).unwrap_or(&0)));
+            self.walk(expr, scopes, in_function);
+
+            if let Some((name, n)) = exit {
+                if i + 1 < exprs.len() {
+                    self.found.push((
+                        name.clone(),
+                        Site::Use(n),
+                        String::from("unreachable code"),
+                        Some(format!("nothing after this `{}` will run", name)),
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// Find where each `let`, `local` or `const`, function parameter, and use of a name is in the script.
+fn sites(script: &str) -> BTreeMap<(String, Site), StrSlice> {
+    let (tokens, _) = tokenize(script);
+    let tokens = tokens
+        .into_iter()
+        .filter(|t| !matches!(t.kind, TokenKind::Whitespace | TokenKind::Comment))
+        .collect::<Vec<_>>();
+
+    let mut counts = BTreeMap::new();
+    let mut sites = BTreeMap::new();
+    let mut add = |name: &str, site: fn(usize) -> Site, at: StrSlice| {
+        let n = counts.entry((name.to_string(), site(0))).or_insert(0);
+        sites.insert((name.to_string(), site(*n)), at);
+        *n += 1;
+    };
+
+    for (i, token) in tokens.iter().enumerate() {
+        let text = token.range.to_str(script);
+        let previous = i.checked_sub(1).map(|i| tokens[i].range.to_str(script));
+        let next = tokens.get(i + 1).map(|t| t.range.to_str(script));
+
+        if matches!(previous, Some("let") | Some("local") | Some("const")) {
+            add(text, Site::Let, token.range);
+        } else if token.kind == TokenKind::Symbol && matches!(next, Some("->") | Some("~>")) {
+            add(text, Site::Param, token.range);
+        } else if token.kind == TokenKind::Symbol && previous != Some("for") {
+            add(text, Site::Use, token.range);
+        }
+    }
+    sites
+}
@@ -2,28 +2,43 @@
 
 mod binary;
 
-use dune::{parse_script, Diagnostic, Environment, Error, Expression, SyntaxError, TokenKind};
+use dune::{
+    parse_script, Diagnostic, Environment, Error, Expression, Int, LastCommand, Style, SyntaxError,
+    Token, TokenKind,
+};
+
+use binary::{
+    abbreviations::{self, ExpandAbbreviation},
+    completion, daemon,
+    edit::PendingEdit,
+    fuzzy::{PickDirectory, PickFile},
+    history::{expand_last_argument, LastArgument},
+    keybindings::{self, BoundKey},
+};
 
-use clap::{arg, crate_authors, crate_description, App};
+use clap::{arg, crate_authors, crate_description, App, AppSettings};
 
 use rustyline::completion::{Completer, FilenameCompleter, Pair as PairComplete};
 use rustyline::config::OutputStreamType;
 use rustyline::highlight::Highlighter;
 use rustyline::hint::{Hinter, HistoryHinter};
+use rustyline::line_buffer::LineBuffer;
 use rustyline::validate::{
     MatchingBracketValidator, ValidationContext, ValidationResult, Validator,
 };
 use rustyline::{error::ReadlineError, Editor};
-use rustyline::{CompletionType, Config, Context, EditMode};
+use rustyline::{CompletionType, Config, Context, EditMode, EventHandler, KeyEvent, Modifiers};
 use rustyline_derive::Helper;
 
 use os_info::Type;
 
 use std::{
     borrow::Cow::{self, Borrowed, Owned},
+    collections::{BTreeMap, HashMap},
     path::PathBuf,
     process::exit,
     sync::{Arc, Mutex},
+    time::Instant,
 };
 
 #[rustfmt::skip]
@@ -49,17 +64,86 @@ fn new_editor(env: &Environment) -> Editor<DuneHelper> {
         .build();
 
     let mut rl = Editor::with_config(config);
+    let history = Arc::new(Mutex::new(vec![]));
+    let edit = Arc::new(PendingEdit::default());
+    rl.bind_sequence(
+        KeyEvent::alt('.'),
+        EventHandler::Conditional(Box::new(LastArgument::new(history.clone(), edit.clone()))),
+    );
+    let abbreviations = Arc::new(Mutex::new(abbreviations::get(env)));
+    rl.bind_sequence(
+        KeyEvent::new(' ', Modifiers::NONE),
+        EventHandler::Conditional(Box::new(ExpandAbbreviation::new(
+            abbreviations.clone(),
+            edit.clone(),
+        ))),
+    );
+    rl.bind_sequence(
+        KeyEvent::ctrl('T'),
+        EventHandler::Conditional(Box::new(PickFile)),
+    );
+    let directory = Arc::new(Mutex::new(None));
+    let keybindings = Arc::new(Mutex::new(HashMap::new()));
+    rl.bind_sequence(
+        KeyEvent::alt('c'),
+        EventHandler::Conditional(Box::new(PickDirectory::new(directory.clone()))),
+    );
+    let bindings = keybindings::get(env);
+    for key in bindings.keys() {
+        rl.bind_sequence(
+            *key,
+            EventHandler::Conditional(Box::new(BoundKey::new(
+                *key,
+                keybindings.clone(),
+                edit.clone(),
+            ))),
+        );
+    }
+    *keybindings.lock().unwrap() = bindings;
     let h = DuneHelper {
         completer: FilenameCompleter::new(),
         hinter: HistoryHinter {},
         validator: MatchingBracketValidator::new(),
         colored_prompt: "".to_string(),
         env: env.clone(),
+        history,
+        abbreviations,
+        edit,
+        directory,
+        keybindings,
+        flags: completion::FlagCache::default(),
+        highlights: Mutex::default(),
     };
     rl.set_helper(Some(h));
     rl
 }
 
+/// Add a line to the history of the editor, with its secrets masked.
+fn add_history_entry(rl: &mut Editor<DuneHelper>, line: &str) {
+    let line = dune::redact(line);
+    if rl.add_history_entry(&line) {
+        if let Some(helper) = rl.helper() {
+            helper.history.lock().unwrap().push(line);
+        }
+    }
+}
+
+/// Mask the secrets in every line of the history.
+fn redact_history(rl: &mut Editor<DuneHelper>) {
+    let lines = rl
+        .history()
+        .iter()
+        .map(|line| dune::redact(line))
+        .collect::<Vec<_>>();
+    rl.history_mut().clear();
+    for line in &lines {
+        rl.add_history_entry(line);
+    }
+    if let Some(helper) = rl.helper() {
+        *helper.history.lock().unwrap() = lines;
+    }
+}
+
 fn strip_ansi_escapes(text: impl ToString) -> String {
     let text = text.to_string();
 
@@ -109,6 +193,19 @@ struct DuneHelper {
     colored_prompt: String,
     validator: MatchingBracketValidator,
     env: Environment,
+    /// The lines in the history, for keybindings that need them.
+    history: Arc<Mutex<Vec<String>>>,
+    /// The abbreviations to expand as they're typed.
+    abbreviations: Arc<Mutex<BTreeMap<String, String>>>,
+    /// The edit a keybinding is waiting for the completer to make.
+    edit: Arc<PendingEdit>,
+    /// The `cd` to a directory picked with Alt-C, which is run instead of the line.
+    directory: Arc<Mutex<Option<String>>>,
+    /// The editing commands bound to keys in `KEYBINDINGS`.
+    keybindings: Arc<Mutex<HashMap<KeyEvent, keybindings::Action>>>,
+    /// The flags of the programs that have been completed so far.
+    flags: completion::FlagCache,
+    highlights: Mutex<HighlightCache>,
 }
 
 impl DuneHelper {
@@ -121,6 +218,10 @@ impl DuneHelper {
 
     fn update_env(&mut self, env: &Environment) {
         self.env = env.clone();
+        *self.abbreviations.lock().unwrap() = abbreviations::get(env);
+        *self.keybindings.lock().unwrap() = keybindings::get(env);
+        // Commands may have been defined, or the directory changed.
+        self.highlights.get_mut().unwrap().commands.clear();
     }
 }
 
@@ -132,6 +233,108 @@ impl Completer for DuneHelper {
         line: &str,
         pos: usize,
         ctx: &Context<'_>,
+    ) -> Result<(usize, Vec<PairComplete>), ReadlineError> {
+        if let Some(text) = self.edit.text() {
+            // The edit is made in `update`, which knows where it starts.
+            return Ok((
+                pos,
+                vec![PairComplete {
+                    display: text.clone(),
+                    replacement: text,
+                }],
+            ));
+        }
+
+        let (mut start, pairs) = self.complete_path(line, pos, ctx)?;
+        let cwd = self.env.get_cwd();
+        let mut candidates = pairs
+            .into_iter()
+            .map(|pair| completion::Candidate {
+                description: completion::describe_file(&cwd, &pair.replacement),
+                pair,
+            })
+            .collect::<Vec<_>>();
+
+        if let Some((word_start, word)) = completion::command_word(line, pos) {
+            if !word.contains('/') && (start == word_start || candidates.is_empty()) {
+                start = word_start;
+                let abbreviations = self.abbreviations.lock().unwrap();
+                let mut commands = completion::commands(word, &self.env, &abbreviations);
+                commands.retain(|c| {
+                    !candidates
+                        .iter()
+                        .any(|f| f.pair.replacement == c.pair.replacement)
+                });
+                commands.append(&mut candidates);
+                candidates = commands;
+            }
+        }
+
+        // Complete the flags of external programs from their help.
+        let word_start = line[..pos]
+            .rfind(char::is_whitespace)
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        if line[word_start..pos].starts_with('-') {
+            if let Some(program) = completion::command_name(line, pos) {
+                if !self.env.is_defined(program) {
+                    start = word_start;
+                    candidates = self
+                        .flags
+                        .complete(program, &line[word_start..pos], &self.env);
+                }
+            }
+        }
+
+        if candidates.len() > 1 {
+            // Complete as much as all of the candidates have in common first,
+            // and only show the menu when that doesn't get any further.
+            let prefix = completion::common_prefix(&candidates);
+            let typed = &line[start..pos];
+            if prefix.len() > typed.len() {
+                return Ok((
+                    start,
+                    vec![PairComplete {
+                        display: prefix.clone(),
+                        replacement: prefix,
+                    }],
+                ));
+            }
+
+            match completion::menu(&candidates) {
+                Ok(Some(picked)) => candidates = vec![candidates.swap_remove(picked)],
+                // Leave the line the way it was.
+                Ok(None) => {
+                    return Ok((
+                        start,
+                        vec![PairComplete {
+                            display: typed.to_string(),
+                            replacement: typed.to_string(),
+                        }],
+                    ))
+                }
+                // The menu couldn't be drawn, so fall back on listing the candidates.
+                Err(_) => {}
+            }
+        }
+        Ok((start, candidates.into_iter().map(|c| c.pair).collect()))
+    }
+
+    fn update(&self, line: &mut LineBuffer, start: usize, elected: &str) {
+        if !self.edit.apply(line) {
+            let end = line.pos();
+            line.replace(start..end, elected)
+        }
+    }
+}
+
+impl DuneHelper {
+    /// Complete the path before the cursor.
+    fn complete_path(
+        &self,
+        line: &str,
+        pos: usize,
+        ctx: &Context<'_>,
     ) -> Result<(usize, Vec<PairComplete>), ReadlineError> {
         let mut path = PathBuf::from(self.env.get_cwd());
         if std::env::set_current_dir(&path).is_ok() {
@@ -180,56 +383,147 @@ impl Completer for DuneHelper {
     }
 }
 
-fn syntax_highlight(line: &str) -> String {
-    let (tokens, diagnostics) = dune::tokenize(line);
+/// Is this the name of something that can be run, like a function, builtin, or program?
+fn is_command(name: &str, env: &Environment, abbreviations: &BTreeMap<String, String>) -> bool {
+    if env.is_defined(name) || abbreviations.contains_key(name) {
+        return true;
+    }
+    dune::find_program(name, env).is_some()
+}
 
+/// Find the bracket under the cursor, or right before it, and the bracket that matches it.
+fn matching_brackets(tokens: &[Token], line: &str, pos: usize) -> Option<(usize, usize)> {
+    let is_bracket = |i: usize| {
+        tokens[i].kind == TokenKind::Punctuation
+            && matches!(
+                tokens[i].range.to_str(line),
+                "(" | ")" | "[" | "]" | "{" | "}"
+            )
+    };
+    let at = (0..tokens.len())
+        .find(|&i| tokens[i].range.start() == pos && is_bracket(i))
+        .or_else(|| (0..tokens.len()).find(|&i| tokens[i].range.end() == pos && is_bracket(i)))?;
+
+    let (open, close) = match tokens[at].range.to_str(line) {
+        "(" | ")" => ("(", ")"),
+        "[" | "]" => ("[", "]"),
+        _ => ("{", "}"),
+    };
+    let mut depth = 0;
+    let mut step = |i: usize| {
+        match tokens[i].range.to_str(line) {
+            b if b == open && is_bracket(i) => depth += 1,
+            b if b == close && is_bracket(i) => depth -= 1,
+            _ => {}
+        }
+        depth == 0
+    };
+    let other = if tokens[at].range.to_str(line) == open {
+        (at..tokens.len()).find(|&i| step(i))
+    } else {
+        (0..=at).rev().find(|&i| step(i))
+    }?;
+    Some((at, other))
+}
+
+/// The tokens of the last line that was highlighted, and which names were
+/// found to be commands, so highlighting after each keystroke only tokenizes
+/// the line from where it changed, and doesn't search the PATH again.
+#[derive(Default)]
+struct HighlightCache {
+    line: String,
+    tokens: Vec<Token>,
+    diagnostics: Vec<Diagnostic>,
+    commands: BTreeMap<String, bool>,
+}
+
+impl HighlightCache {
+    fn update(&mut self, line: &str) {
+        if line != self.line {
+            (self.tokens, self.diagnostics) =
+                dune::retokenize(line, &self.line, &self.tokens, &self.diagnostics);
+            self.line = line.to_string();
+        }
+    }
+}
+
+fn syntax_highlight(
+    line: &str,
+    tokens: &[Token],
+    diagnostics: &[Diagnostic],
+    pos: usize,
+    mut is_command: impl FnMut(&str) -> bool,
+) -> String {
+    let brackets = matching_brackets(tokens, line, pos);
+
+    let (theme, plain) = (dune::theme(), Style::default());
     let mut result = String::new();
     let mut is_colored = false;
+    // Switch to another style, ending the last one so its attributes don't carry over.
+    let restyle = |result: &mut String, is_colored: &mut bool, style: &Style| {
+        if *is_colored {
+            result.push_str("\x1b[m\x1b[0m");
+        }
+        result.push_str(&style.start());
+        *is_colored = !style.is_empty();
+    };
+    // Is the next word the name of a command?
+    let mut at_command = true;
+
+    for (i, (token, diagnostic)) in tokens.iter().zip(diagnostics).enumerate() {
+        let was_at_command = at_command;
+        match (token.kind, token.range.to_str(line)) {
+            (TokenKind::Whitespace | TokenKind::Comment, _) => {}
+            (TokenKind::Punctuation, ";" | "(" | "{")
+            | (TokenKind::Operator, "|" | "&&" | "||")
+            | (TokenKind::Keyword, "then" | "else") => at_command = true,
+            _ => at_command = false,
+        }
+
+        if let Some((a, b)) = brackets {
+            if i == a || i == b {
+                restyle(&mut result, &mut is_colored, &theme.bracket);
+                result.push_str(token.range.to_str(line));
+                restyle(&mut result, &mut is_colored, &plain);
+                continue;
+            }
+        }
 
-    for (token, diagnostic) in tokens.iter().zip(&diagnostics) {
         match (token.kind, token.range.to_str(line)) {
             (TokenKind::BooleanLiteral, b) => {
-                result.push_str("\x1b[95m");
-                is_colored = true;
+                restyle(&mut result, &mut is_colored, &theme.boolean);
                 result.push_str(b);
             }
             (
                 TokenKind::Punctuation,
                 o @ ("@" | "\'" | "=" | "|" | ">>" | "<<" | ">>>" | "->" | "~>"),
             ) => {
-                result.push_str("\x1b[96m");
-                is_colored = true;
+                restyle(&mut result, &mut is_colored, &theme.punctuation);
                 result.push_str(o);
             }
             (TokenKind::Punctuation, o) => {
-                if is_colored {
-                    result.push_str("\x1b[m\x1b[0m");
-                    is_colored = false;
-                }
+                restyle(&mut result, &mut is_colored, &plain);
                 result.push_str(o);
             }
             (TokenKind::Keyword, k) => {
-                result.push_str("\x1b[95m");
-                is_colored = true;
+                restyle(&mut result, &mut is_colored, &theme.keyword);
                 result.push_str(k);
             }
             (TokenKind::Operator, k) => {
-                result.push_str("\x1b[38;5;220m");
-                is_colored = true;
+                restyle(&mut result, &mut is_colored, &theme.operator);
                 result.push_str(k);
             }
             (TokenKind::StringLiteral, s) => {
-                result.push_str("\x1b[38;5;208m");
-                is_colored = true;
+                restyle(&mut result, &mut is_colored, &theme.string);
 
                 if let Diagnostic::InvalidStringEscapes(ranges) = diagnostic {
                     let mut last_end = token.range.start();
 
                     for &range in ranges.iter() {
                         result.push_str(&line[last_end..range.start()]);
-                        result.push_str("\x1b[38;5;9m");
+                        restyle(&mut result, &mut is_colored, &theme.invalid);
                         result.push_str(range.to_str(line));
-                        result.push_str("\x1b[38;5;208m");
+                        restyle(&mut result, &mut is_colored, &theme.string);
                         last_end = range.end();
                     }
 
@@ -240,33 +534,41 @@ fn syntax_highlight(line: &str) -> String {
             }
             (TokenKind::IntegerLiteral | TokenKind::FloatLiteral, l) => {
                 if let Diagnostic::InvalidNumber(e) = diagnostic {
-                    result.push_str("\x1b[38;5;9m");
+                    restyle(&mut result, &mut is_colored, &theme.invalid);
                     result.push_str(e.to_str(line));
-                    is_colored = true;
                 } else {
-                    if is_colored {
-                        result.push_str("\x1b[m\x1b[0m");
-                        is_colored = false;
-                    }
+                    restyle(&mut result, &mut is_colored, &theme.number);
                     result.push_str(l);
                 }
             }
             (TokenKind::Symbol, l) => {
+                // Parameters and map keys aren't commands, even at the start of a line.
+                let next = tokens[i + 1..]
+                    .iter()
+                    .find(|t| t.kind != TokenKind::Whitespace)
+                    .map(|t| t.range.to_str(line));
+                let names_command =
+                    was_at_command && !matches!(next, Some("->" | "~>" | ":" | "="));
+
                 if let Diagnostic::IllegalChar(e) = diagnostic {
-                    result.push_str("\x1b[38;5;9m");
+                    restyle(&mut result, &mut is_colored, &theme.invalid);
                     result.push_str(e.to_str(line));
-                    is_colored = true;
                 } else {
-                    if l == "None" {
-                        result.push_str("\x1b[91m");
-                        is_colored = true;
+                    let style = if names_command && l != "None" {
+                        // Commands that exist, and typos.
+                        if is_command(l) {
+                            &theme.command
+                        } else {
+                            &theme.unknown_command
+                        }
+                    } else if l == "None" {
+                        &theme.none
                     } else if matches!(l, "echo" | "exit" | "clear" | "cd" | "rm") {
-                        result.push_str("\x1b[94m");
-                        is_colored = true;
-                    } else if is_colored {
-                        result.push_str("\x1b[m\x1b[0m");
-                        is_colored = false;
-                    }
+                        &theme.builtin
+                    } else {
+                        &plain
+                    };
+                    restyle(&mut result, &mut is_colored, style);
                     result.push_str(l);
                 }
             }
@@ -274,8 +576,7 @@ fn syntax_highlight(line: &str) -> String {
                 result.push_str(w);
             }
             (TokenKind::Comment, w) => {
-                result.push_str("\x1b[38;5;247m");
-                is_colored = true;
+                restyle(&mut result, &mut is_colored, &theme.comment);
                 result.push_str(w);
             }
         }
@@ -283,9 +584,8 @@ fn syntax_highlight(line: &str) -> String {
     if diagnostics.len() > tokens.len() {
         for diagnostic in &diagnostics[tokens.len()..] {
             if let Diagnostic::NotTokenized(e) = diagnostic {
-                result.push_str("\x1b[38;5;9m");
+                restyle(&mut result, &mut is_colored, &theme.invalid);
                 result.push_str(e.to_str(line));
-                is_colored = true;
             }
         }
     }
@@ -350,15 +650,30 @@ impl Highlighter for DuneHelper {
     }
 
     fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
-        Owned("\x1b[1m".to_owned() + hint + "\x1b[m")
+        Owned(dune::theme().hint.paint(hint))
     }
 
-    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
-        Owned(syntax_highlight(line))
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        let abbreviations = self.abbreviations.lock().unwrap();
+        let mut cache = self.highlights.lock().unwrap();
+        cache.update(line);
+        let HighlightCache {
+            tokens,
+            diagnostics,
+            commands,
+            ..
+        } = &mut *cache;
+        Owned(syntax_highlight(line, tokens, diagnostics, pos, |name| {
+            *commands
+                .entry(name.to_string())
+                .or_insert_with(|| is_command(name, &self.env, &abbreviations))
+        }))
     }
 
-    fn highlight_char(&self, line: &str, _pos: usize) -> bool {
-        syntax_highlight(line) != line
+    fn highlight_char(&self, line: &str, pos: usize) -> bool {
+        let mut cache = self.highlights.lock().unwrap();
+        cache.update(line);
+        syntax_highlight(line, &cache.tokens, &cache.diagnostics, pos, |_| true) != line
     }
 }
 
@@ -449,72 +764,160 @@ fn repl(
     let mut lines = vec![];
 
     let history_path = get_history_path();
+    binary::report_cwd(&atomic_env.lock().unwrap().get_cwd());
     loop {
         let mut env = atomic_env.lock().unwrap();
         let mut rl = atomic_rl.lock().unwrap();
         let cwd = env.get_cwd();
         // let prompt = format!("{}", Expression::Apply(Box::new(env.get("prompt").unwrap()), vec![env.get_cwd().into()]).eval(&mut env)?);
 
-        let prompt = Expression::Apply(
-            Box::new(Expression::Symbol(
-                if lines.is_empty() {
-                    "prompt"
-                } else {
-                    "incomplete_prompt"
-                }
-                .to_string(),
-            )),
-            vec![cwd.clone().into()],
-        )
-        .eval(&mut env)
-        .unwrap_or_else(|_| format!("{}$ ", cwd).into())
-        .to_string();
+        let prompt = if lines.is_empty() {
+            Expression::Apply(
+                Box::new(Expression::Symbol("prompt".to_string())),
+                vec![cwd.clone().into()],
+            )
+            .eval(&mut env)
+            .ok()
+            .map(|prompt| prompt.to_string())
+        } else {
+            continuation_prompt(&lines.join("\n"), &cwd, &mut env)
+        }
+        .unwrap_or_else(|| format!("{}$ ", cwd));
         rl.helper_mut()
             .expect("No helper")
             .set_prompt(prompt.clone());
         rl.helper_mut().expect("No helper").update_env(&env);
+        if lines.is_empty() {
+            binary::set_title(&cwd);
+        }
         let line = readline(prompt, &mut rl);
+        let picked = rl
+            .helper()
+            .and_then(|helper| helper.directory.lock().unwrap().take());
+        let line = match picked {
+            Some(command) => {
+                println!("{}", command);
+                command
+            }
+            None => line,
+        };
+        let expanded = expand_last_argument(&line, rl.history().last().map(String::as_str));
+        if expanded != line {
+            println!("{}", expanded);
+        }
+        let line = expanded;
         lines.push(line.clone());
         let text = lines.join("\n");
 
         match parse(&text) {
             Ok(expr) => {
-                rl.add_history_entry(text.as_str());
+                add_history_entry(&mut rl, text.as_str());
                 if let Some(path) = &history_path {
                     if rl.save_history(path).is_err() {
                         eprintln!("Failed to save history");
                     }
                 }
-                let val = expr.eval(&mut env);
+                // A lone name at the end of the line is a directory to change to
+                // with `autocd`. Otherwise it's the name of a program to run, which
+                // is run below once it evaluates to itself, except in strict mode,
+                // where it would be an error first, so it's run here instead.
+                let expr = match expr {
+                    Expression::Do(mut exprs) => {
+                        if let Some(last) = exprs.last_mut() {
+                            if let Expression::Symbol(name) = last {
+                                let dir = PathBuf::from(env.get_cwd()).join(&*name);
+                                if env.options().autocd && !env.is_defined(name) && dir.is_dir() {
+                                    *last = Expression::Apply(
+                                        Box::new(Expression::Symbol("cd".to_string())),
+                                        vec![Expression::String(name.clone())],
+                                    );
+                                } else if env.options().strict && !env.is_defined(name) {
+                                    *last = Expression::Apply(Box::new(last.clone()), vec![]);
+                                }
+                            }
+                        }
+                        Expression::Do(exprs)
+                    }
+                    expr => expr,
+                };
+                // Show what's running in the title of the console while it runs.
+                binary::set_title(&text.split_whitespace().collect::<Vec<_>>().join(" "));
+                // Only the programs this line runs should be notified about.
+                dune::take_runs();
+                let started = Instant::now();
+                let val = dune::eval_cancelable(&expr, &mut env);
+                // What the line evaluated to and how long it took, for `shopt annotate`.
+                let mut annotated = None;
+                let mut elapsed = started.elapsed();
+                // The exit code of the error the line failed with, if it did.
+                let mut failed = None;
                 match val.clone() {
                     Ok(Expression::Symbol(name)) => {
-                        if let Err(e) =
-                            Expression::Apply(Box::new(Expression::Symbol(name)), vec![])
-                                .eval(&mut env)
-                        {
-                            eprintln!("{}", e)
+                        let ran = dune::eval_cancelable(
+                            &Expression::Apply(Box::new(Expression::Symbol(name)), vec![]),
+                            &mut env,
+                        );
+                        elapsed = started.elapsed();
+                        match ran {
+                            Ok(value) => annotated = Some(value),
+                            Err(e) => {
+                                failed = Some(exit_code(&e));
+                                eprintln!("{}", dune::redact(&e.in_source(&text).to_string()))
+                            }
                         }
                     }
-                    Ok(Expression::None) => {}
+                    Ok(Expression::None) => annotated = Some(Expression::None),
+                    // Results too tall for the console are paged.
                     Ok(Expression::Macro(_, _)) => {
-                        let _ = Expression::Apply(
-                            Box::new(Expression::Symbol("report".to_string())),
-                            vec![Expression::Apply(
-                                Box::new(val.unwrap().clone()),
-                                vec![env.get_cwd().into()],
-                            )],
-                        )
-                        .eval(&mut env);
+                        let _ = binary::pager::paging(|| {
+                            Expression::Apply(
+                                Box::new(Expression::Symbol("report".to_string())),
+                                vec![Expression::Apply(
+                                    Box::new(val.unwrap().clone()),
+                                    vec![env.get_cwd().into()],
+                                )],
+                            )
+                            .eval(&mut env)
+                        });
                     }
                     Ok(val) => {
-                        let _ = Expression::Apply(
-                            Box::new(Expression::Symbol("report".to_string())),
-                            vec![Expression::Quote(Box::new(val))],
-                        )
-                        .eval(&mut env);
+                        annotated = Some(val.clone());
+                        // Keep the result around, so the next line can use it.
+                        env.define("it", val.clone());
+                        let _ = binary::pager::paging(|| {
+                            Expression::Apply(
+                                Box::new(Expression::Symbol("report".to_string())),
+                                vec![Expression::Quote(Box::new(val))],
+                            )
+                            .eval(&mut env)
+                        });
                     }
                     Err(e) => {
-                        eprintln!("{}", e)
+                        failed = Some(exit_code(&e));
+                        eprintln!("{}", dune::redact(&e.in_source(&text).to_string()))
+                    }
+                }
+                let runs = dune::take_runs();
+                // Without an error, the line went as well as the last program it ran.
+                let code = failed
+                    .unwrap_or_else(|| runs.last.map_or(0, |run| run.code.unwrap_or(1) as Int));
+                env.set_last_command(Some(LastCommand {
+                    code,
+                    seconds: elapsed.as_secs_f64(),
+                }));
+                if let Some(value) = annotated.filter(|_| env.options().annotate) {
+                    println!("{}", binary::annotation(&value, elapsed));
+                }
+                if let Some(run) = runs.longest {
+                    binary::notify::finished(&run);
+                }
+                // The line may have marked a secret that's in the history, like `sys@redact "..."`.
+                if dune::redact(&text) != text {
+                    redact_history(&mut rl);
+                    if let Some(path) = &history_path {
+                        if rl.save_history(path).is_err() {
+                            eprintln!("Failed to save history");
+                        }
                     }
                 }
                 lines = vec![];
@@ -522,28 +925,103 @@ fn repl(
 
             Err(e) => {
                 if line.is_empty() {
-                    eprintln!("{}", e);
+                    eprintln!("{}", dune::redact(&e.to_string()));
                     lines = vec![];
                 } else {
-                    rl.add_history_entry(text.as_str());
+                    add_history_entry(&mut rl, text.as_str());
                 }
             }
         }
     }
 }
 
+/// The prompt for the next line of incomplete input. `continuation_prompt`
+/// can be a string, or a function given the directory, the brackets left open
+/// and how deep they are, the number of the line, and the input so far.
+/// Without it, `incomplete_prompt` is given the directory.
+fn continuation_prompt(text: &str, cwd: &str, env: &mut Environment) -> Option<String> {
+    let prompt = match env.get("continuation_prompt") {
+        Some(Expression::String(prompt)) => return Some(prompt),
+        Some(prompt) => {
+            let brackets = open_brackets(text);
+            let mut context = BTreeMap::new();
+            context.insert("cwd".to_string(), Expression::from(cwd));
+            context.insert(
+                "depth".to_string(),
+                Expression::Integer(brackets.chars().count() as Int),
+            );
+            context.insert("brackets".to_string(), Expression::String(brackets));
+            context.insert(
+                "line".to_string(),
+                Expression::Integer(text.lines().count() as Int + 1),
+            );
+            context.insert("input".to_string(), Expression::from(text));
+            Expression::Apply(
+                Box::new(prompt),
+                vec![Expression::Quote(Box::new(Expression::from(context)))],
+            )
+        }
+        None => Expression::Apply(
+            Box::new(Expression::Symbol("incomplete_prompt".to_string())),
+            vec![cwd.into()],
+        ),
+    };
+    prompt.eval(env).ok().map(|prompt| prompt.to_string())
+}
+
+/// The brackets left open in incomplete input, from the outermost to the
+/// innermost, like `{(`.
+fn open_brackets(text: &str) -> String {
+    let (tokens, _) = dune::tokenize(text);
+    let mut open = String::new();
+    for token in tokens.iter().filter(|t| t.kind == TokenKind::Punctuation) {
+        match token.range.to_str(text) {
+            bracket @ ("(" | "[" | "{") => open.push_str(bracket),
+            ")" | "]" | "}" => {
+                open.pop();
+            }
+            _ => {}
+        }
+    }
+    open
+}
+
+/// The exit code a shell would give for an error, like 130 for Ctrl-C.
+fn exit_code(error: &Error) -> Int {
+    match error {
+        Error::CommandExited(_, _, code) => *code,
+        Error::Interrupted => 130,
+        _ => 1,
+    }
+}
+
 fn run_text(text: &str, env: &mut Environment) -> Result<Expression, Error> {
     parse(text)?.eval(env)
 }
 
+fn report(val: Expression, env: &mut Environment) -> Result<(), Error> {
+    Expression::Apply(
+        Box::new(Expression::Symbol("report".to_string())),
+        vec![val],
+    )
+    .eval(env)?;
+    Ok(())
+}
+
 fn run_file(path: PathBuf, env: &mut Environment) -> Result<Expression, Error> {
-    match std::fs::read_to_string(path) {
-        Ok(prelude) => run_text(&prelude, env),
+    match std::fs::read_to_string(&path) {
+        Ok(prelude) => binary::scripts::running(&path, || run_text(&prelude, env)),
         Err(e) => Err(Error::CustomError(format!("Failed to read file: {}", e))),
     }
 }
 
 fn main() -> Result<(), Error> {
+    // Windows consoles only understand the escape codes used for colors once
+    // they're asked to.
+    #[cfg(windows)]
+    crossterm::ansi_support::supports_ansi();
+    dune::forward_exit_signals();
+
     let matches = App::new(
         r#"
         888                            
@@ -558,14 +1036,136 @@ fn main() -> Result<(), Error> {
     )
     .author(crate_authors!())
     .about(crate_description!())
+    // Everything after the script is the script's, even flags.
+    .setting(AppSettings::TrailingVarArg)
     .args(&[
-        arg!([FILE] "Execute a given input file"),
+        arg!([FILE] ... "Execute a given input file, with the arguments after it in `sys@argv`")
+            .multiple_values(true),
         arg!(-i --interactive "Start an interactive REPL"),
         arg!(-x --exec <INPUT> ... "Execute a given input string")
             .multiple_values(true)
             .required(false),
+        arg!(--strict "Make using an undefined variable an error"),
+        arg!(-e --errexit "Stop a script when a program it runs fails"),
+        arg!(--warnings "Warn about unused, shadowed, and unreachable code in the script before running it"),
+        arg!(--daemon "Evaluate scripts sent by `dunesh eval --remote` in one long-lived environment"),
     ])
+    .subcommand(
+        App::new("eval")
+            .about("Evaluate a given input string")
+            .args(&[
+                arg!(-r --remote "Send the input to a running `dunesh --daemon`"),
+                arg!(<INPUT> ... "The input to evaluate").multiple_values(true),
+            ]),
+    )
+    .subcommand(
+        App::new("doctor")
+            .about("Check your setup for problems, like an unwritable history or a prelude that doesn't parse"),
+    )
+    .subcommand(
+        App::new("pkg")
+            .about("Install Dune modules from git repositories, to load with `use <name>`")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                App::new("install")
+                    .about("Install a package, or every package in the lockfile at the versions recorded there")
+                    .args(&[
+                        arg!([SOURCE] "A git URL, a path to a git repository, or a GitHub repository like `owner/name`"),
+                        arg!(-v --version <VERSION> "The tag, branch, or commit to install")
+                            .required(false),
+                    ]),
+            )
+            .subcommand(
+                App::new("remove")
+                    .about("Uninstall a package")
+                    .arg(arg!(<NAME> "The package to uninstall")),
+            )
+            .subcommand(App::new("list").about("List the installed packages and their versions")),
+    )
+    .subcommand(
+        App::new("export-sh")
+            .about("Translate a script that only runs programs, pipes, variables, and conditionals into POSIX sh")
+            .arg(arg!(<FILE> "The script to translate")),
+    )
+    .subcommand(
+        App::new("self-update")
+            .about("Update dunesh to the latest release, after checking the download against its published checksum")
+            .arg(arg!(--check "Only check whether there's a newer release, without installing it")),
+    )
     .get_matches();
+
+    if matches.subcommand_matches("doctor").is_some() {
+        exit(if binary::doctor::run(get_history_path()) {
+            0
+        } else {
+            1
+        });
+    }
+
+    if let Some(export) = matches.subcommand_matches("export-sh") {
+        let path = export.value_of("FILE").unwrap();
+        let result = std::fs::read_to_string(path)
+            .map_err(|e| Error::CustomError(format!("could not read {}: {}", path, e)))
+            .and_then(|script| dune::export_sh(&parse(&script)?));
+        match result {
+            Ok(sh) => print!("{}", sh),
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(1);
+            }
+        }
+        return Ok(());
+    }
+
+    if let Some(update) = matches.subcommand_matches("self-update") {
+        if let Err(e) = binary::update::run(update.is_present("check")) {
+            eprintln!("{}", e);
+            exit(1);
+        }
+        return Ok(());
+    }
+
+    if let Some(pkg) = matches.subcommand_matches("pkg") {
+        let result = match pkg.subcommand() {
+            Some(("install", install)) => match install.value_of("SOURCE") {
+                Some(source) => binary::packages::install(source, install.value_of("version")),
+                None => binary::packages::install_locked(),
+            },
+            Some(("remove", remove)) => binary::packages::remove(remove.value_of("NAME").unwrap()),
+            _ => binary::packages::list(),
+        };
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            exit(1);
+        }
+        return Ok(());
+    }
+
+    let eval_input = matches.subcommand_matches("eval").map(|eval| {
+        (
+            eval.values_of("INPUT")
+                .unwrap()
+                .map(String::from)
+                .collect::<Vec<_>>()
+                .join(" "),
+            eval.is_present("remote"),
+        )
+    });
+    if let Some((text, true)) = &eval_input {
+        if let Err(e) = daemon::eval_remote(text) {
+            eprintln!("{}", e);
+            exit(1);
+        }
+        return Ok(());
+    }
+
+    binary::scripts::set_args(
+        matches
+            .values_of("FILE")
+            .map(|args| args.skip(1).map(String::from).collect())
+            .unwrap_or_default(),
+    );
+
     let mut env = Environment::new();
 
     binary::init(&mut env);
@@ -580,49 +1180,71 @@ fn main() -> Result<(), Error> {
     )?
     .eval(&mut env)?;
 
+    parse(
+        r#"let prompt-status = _ -> {
+            let last = shell@last-command ();
+            if last == None { "" } else {
+                let took = if last@seconds >= 2.0 { console@paint "prompt-duration" (" " + (fmt@duration last@seconds)) } else { "" };
+                let failed = if last@success { "" } else { console@paint "prompt-status" (" [" + (str last@code) + "]") };
+                took + failed
+            }
+        }"#,
+    )?
+    .eval(&mut env)?;
     parse(
         "let prompt = cwd -> \
-            fmt@bold ((fmt@dark@blue \"(dune) \") + \
-            (fmt@bold (fmt@dark@green cwd)) + \
-            (fmt@bold (fmt@dark@blue \"$ \")))",
+            (console@paint \"prompt\" \"(dune) \") + \
+            (console@paint \"prompt-path\" cwd) + \
+            (prompt-status ()) + \
+            (console@paint \"prompt\" \"$ \")",
     )?
     .eval(&mut env)?;
     parse(
         r#"let incomplete_prompt = cwd ->
-            ((len cwd) + (len "(dune) ")) * " " + (fmt@bold (fmt@dark@yellow "> "));"#,
+            ((len cwd) + (len "(dune) ")) * " " + (console@paint "prompt-incomplete" "> ");"#,
     )?
     .eval(&mut env)?;
 
+    if matches.is_present("strict") {
+        env.options_mut().strict = true;
+    }
+    if matches.is_present("errexit") {
+        env.options_mut().errexit = true;
+    }
+
     if matches.is_present("FILE") {
         let path = PathBuf::from(matches.value_of("FILE").unwrap());
 
-        if let Err(e) = run_file(path, &mut env) {
-            eprintln!("{}", e)
+        let script = std::fs::read_to_string(&path).unwrap_or_default();
+        if matches.is_present("warnings") {
+            for warning in dune::analyze(&script) {
+                eprintln!("{}", warning);
+            }
+        }
+
+        let result = run_file(path, &mut env);
+        if let Err(e) = &result {
+            eprintln!("{}", dune::redact(&e.in_source(&script).to_string()))
         }
 
         if !matches.is_present("interactive") && !matches.is_present("exec") {
+            if let Err(Error::CommandExited(_, _, status)) = result {
+                exit(status as i32);
+            }
             return Ok(());
         }
     }
 
     if matches.is_present("exec") {
-        match run_text(
-            &matches
-                .values_of("exec")
-                .unwrap()
-                .map(String::from)
-                .collect::<Vec<_>>()
-                .join(" "),
-            &mut env,
-        ) {
-            Ok(result) => {
-                Expression::Apply(
-                    Box::new(Expression::Symbol("report".to_string())),
-                    vec![result],
-                )
-                .eval(&mut env)?;
-            }
-            Err(e) => eprintln!("{}", e),
+        let text = matches
+            .values_of("exec")
+            .unwrap()
+            .map(String::from)
+            .collect::<Vec<_>>()
+            .join(" ");
+        match run_text(&text, &mut env) {
+            Ok(result) => report(result, &mut env)?,
+            Err(e) => eprintln!("{}", dune::redact(&e.in_source(&text).to_string())),
         }
 
         if !matches.is_present("interactive") {
@@ -630,6 +1252,30 @@ fn main() -> Result<(), Error> {
         }
     }
 
+    if let Some((text, _)) = eval_input {
+        match run_text(&text, &mut env) {
+            Ok(result) => report(result, &mut env)?,
+            Err(e) => eprintln!("{}", dune::redact(&e.in_source(&text).to_string())),
+        }
+        return Ok(());
+    }
+
+    if matches.is_present("daemon") {
+        if let Some(home_dir) = dirs::home_dir() {
+            let prelude_path = home_dir.join(".dune-prelude");
+            if prelude_path.exists() {
+                if let Err(e) = run_file(prelude_path, &mut env) {
+                    eprintln!("Error while running custom prelude: {}", e);
+                }
+            }
+        }
+        return daemon::serve(&mut env);
+    }
+
+    // Consoles the REPL runs in can usually open links, which the prelude can
+    // turn off with `shopt "hyperlinks" False`.
+    env.options_mut().hyperlinks = true;
+
     if let Some(home_dir) = dirs::home_dir() {
         let prelude_path = home_dir.join(".dune-prelude");
         // If file doesn't exist
@@ -660,20 +1306,29 @@ fn main() -> Result<(), Error> {
         }
     }
 
+    for problem in keybindings::problems(&env) {
+        eprintln!("{}", problem);
+    }
     let mut rl = new_editor(&env);
     let history_path = get_history_path();
     if let Some(path) = history_path {
-        if rl.load_history(&path).is_err() {}
+        if rl.load_history(&path).is_ok() {
+            if let Some(helper) = rl.helper() {
+                *helper.history.lock().unwrap() = rl.history().iter().cloned().collect();
+            }
+        }
     }
 
     let editor_ref = Arc::new(Mutex::new(rl));
-    let editor_ref_copy = editor_ref.clone();
-
     let env_ref = Arc::new(Mutex::new(env));
-    let env_ref_copy = env_ref.clone();
 
-    ctrlc::set_handler(move || {
-        repl(editor_ref_copy.clone(), env_ref_copy.clone()).expect("Error in REPL");
+    ctrlc::set_handler(|| {
+        // Ctrl-C is meant for the program running in the foreground, which
+        // gets the signal itself, and then control returns to the prompt.
+        // Otherwise, it stops the code being evaluated.
+        if !dune::is_foreground_running() {
+            dune::cancel();
+        }
     })
     .expect("Error setting Ctrl-C handler");
 
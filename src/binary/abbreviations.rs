@@ -0,0 +1,48 @@
+use super::{completion::command_word, edit::PendingEdit};
+use dune::{Environment, Expression};
+use rustyline::{Cmd, ConditionalEventHandler, Event, EventContext, RepeatCount};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+/// The variable that `abbr` stores the abbreviations in.
+pub const ABBREVIATIONS: &str = "ABBREVIATIONS";
+
+/// Get the abbreviations defined in an environment.
+pub fn get(env: &Environment) -> BTreeMap<String, String> {
+    match env.get(ABBREVIATIONS) {
+        Some(Expression::Map(abbreviations)) => abbreviations
+            .into_iter()
+            .map(|(name, expansion)| (name, expansion.to_string()))
+            .collect(),
+        _ => BTreeMap::new(),
+    }
+}
+
+/// Expands an abbreviation in place when a space is typed after it, as long as
+/// it's the name of the command and not one of its arguments.
+pub struct ExpandAbbreviation {
+    abbreviations: Arc<Mutex<BTreeMap<String, String>>>,
+    edit: Arc<PendingEdit>,
+}
+
+impl ExpandAbbreviation {
+    pub fn new(
+        abbreviations: Arc<Mutex<BTreeMap<String, String>>>,
+        edit: Arc<PendingEdit>,
+    ) -> Self {
+        Self {
+            abbreviations,
+            edit,
+        }
+    }
+}
+
+impl ConditionalEventHandler for ExpandAbbreviation {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, ctx: &EventContext) -> Option<Cmd> {
+        let (start, word) = command_word(ctx.line(), ctx.pos())?;
+        let expansion = self.abbreviations.lock().unwrap().get(word)?.clone();
+        Some(self.edit.replace(start, expansion + " "))
+    }
+}
@@ -0,0 +1,440 @@
+use super::init::format_size;
+use crossterm::{
+    cursor::{self, MoveToColumn, MoveUp},
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    queue,
+    style::{Attribute, Print, SetAttribute},
+    terminal::{self, Clear, ClearType},
+};
+use dune::{Environment, Expression};
+use rustyline::completion::Pair;
+use std::{
+    collections::BTreeMap,
+    io::{stdout, Read, Write},
+    path::Path,
+    process::{Command, Stdio},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// The most completions the menu shows at once.
+const MENU_HEIGHT: usize = 10;
+
+/// How long a program gets to print its help before we give up on it.
+const HELP_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long completing waits for a program's flags before leaving them to the
+/// next Tab, so a slow program doesn't hold up typing.
+const FLAGS_WAIT: Duration = Duration::from_millis(200);
+
+/// A completion, along with a short description of what it is.
+pub struct Candidate {
+    pub pair: Pair,
+    pub description: String,
+}
+
+/// Find the word before the cursor, if it's the name of a command and not one
+/// of its arguments. Returns where the word starts, and the word itself.
+pub fn command_word(line: &str, pos: usize) -> Option<(usize, &str)> {
+    let before = &line[..pos];
+    let start = before
+        .char_indices()
+        .rev()
+        .find(|(_, c)| c.is_whitespace() || "|;({".contains(*c))
+        .map(|(i, c)| i + c.len_utf8())
+        .unwrap_or(0);
+    let (rest, word) = before.split_at(start);
+
+    let rest = rest.trim_end();
+    if rest.is_empty() || rest.ends_with(|c: char| "|;({".contains(c)) || rest.ends_with("&&") {
+        Some((start, word))
+    } else {
+        None
+    }
+}
+
+/// Find the name of the command the cursor is in the arguments of.
+pub fn command_name(line: &str, pos: usize) -> Option<&str> {
+    let before = &line[..pos];
+    let start = ["|", ";", "(", ")", "{", "}", "&&"]
+        .iter()
+        .filter_map(|sep| before.rfind(sep).map(|i| i + sep.len()))
+        .max()
+        .unwrap_or(0);
+    before[start..].split_whitespace().next()
+}
+
+/// The flags of external programs, found by parsing their man page (or their
+/// `--help` output), so each program only has to be asked once. A program
+/// that's still being asked has no flags yet.
+#[derive(Default)]
+pub struct FlagCache(Arc<Mutex<BTreeMap<String, Option<Flags>>>>);
+
+/// The flags of a program, and what each of them does.
+type Flags = Vec<(String, String)>;
+
+impl FlagCache {
+    /// Get the flags of a program that start with `prefix`.
+    ///
+    /// Asking a script for its help may just run it, so only programs found in
+    /// `PATH` are asked, never one named by its path like `./deploy.sh`. They're
+    /// asked on another thread, and if that takes a while, the flags are ready
+    /// for the next Tab instead.
+    pub fn complete(&self, program: &str, prefix: &str, env: &Environment) -> Vec<Candidate> {
+        if program.contains('/') || (cfg!(windows) && program.contains('\\')) {
+            return vec![];
+        }
+        let path = match dune::find_program(program, env) {
+            // Windows finds programs in the working directory too, which aren't asked either.
+            Some(path) if path.parent() != Some(Path::new(&env.get_cwd())) => path,
+            _ => return vec![],
+        };
+
+        let (done, asked) = mpsc::channel();
+        {
+            let mut cache = self.0.lock().unwrap();
+            if !cache.contains_key(program) {
+                cache.insert(program.to_string(), None);
+                let (cache, program) = (self.0.clone(), program.to_string());
+                thread::spawn(move || {
+                    let help = run_for_help(Command::new("man").arg(&program))
+                        .filter(|help| !parse_flags(help).is_empty())
+                        .or_else(|| run_for_help(Command::new(path).arg("--help")))
+                        .unwrap_or_default();
+                    cache
+                        .lock()
+                        .unwrap()
+                        .insert(program, Some(parse_flags(&help)));
+                    let _ = done.send(());
+                });
+            }
+        }
+        // Without a thread asking, this returns right away.
+        let _ = asked.recv_timeout(FLAGS_WAIT);
+
+        let cache = self.0.lock().unwrap();
+        let Some(Some(flags)) = cache.get(program) else {
+            return vec![];
+        };
+        flags
+            .iter()
+            .filter(|(flag, _)| flag.starts_with(prefix))
+            .map(|(flag, description)| Candidate {
+                pair: Pair {
+                    display: flag.clone(),
+                    replacement: flag.clone(),
+                },
+                description: description.clone(),
+            })
+            .collect()
+    }
+}
+
+/// Run a program and get everything it prints, killing it if it takes too long.
+fn run_for_help(command: &mut Command) -> Option<String> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .env("MANPAGER", "cat")
+        .spawn()
+        .ok()?;
+
+    // Read while the program runs, so it can't block on a full pipe.
+    let read = |pipe: Option<Box<dyn Read + Send>>| {
+        thread::spawn(move || {
+            let mut text = String::new();
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_string(&mut text);
+            }
+            text
+        })
+    };
+    let stdout = read(
+        child
+            .stdout
+            .take()
+            .map(|p| Box::new(p) as Box<dyn Read + Send>),
+    );
+    let stderr = read(
+        child
+            .stderr
+            .take()
+            .map(|p| Box::new(p) as Box<dyn Read + Send>),
+    );
+
+    let started = Instant::now();
+    while child.try_wait().ok()?.is_none() {
+        if started.elapsed() > HELP_TIMEOUT {
+            let _ = child.kill();
+            let _ = child.wait();
+            return None;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    Some(stdout.join().ok()? + &stderr.join().ok()?)
+}
+
+/// Find the flags in a program's help, like `-a, --all  do not ignore entries starting with .`.
+pub fn parse_flags(help: &str) -> Vec<(String, String)> {
+    // Man pages make text bold by typing over it with backspaces.
+    let mut text = String::new();
+    for c in help.chars() {
+        if c == '\u{8}' {
+            text.pop();
+        } else {
+            text.push(c);
+        }
+    }
+
+    let mut flags = BTreeMap::new();
+    let mut lines = text.lines().map(str::trim).peekable();
+    while let Some(line) = lines.next() {
+        if !line.starts_with('-') {
+            continue;
+        }
+        // The flags are separated from their description by at least two spaces, or a tab.
+        let (names, mut description) =
+            match line.find("  ").into_iter().chain(line.find('\t')).min() {
+                Some(i) => (&line[..i], line[i..].trim()),
+                None => (line, ""),
+            };
+        // Long flags often have their description on the next line.
+        if description.is_empty() {
+            if let Some(next) = lines.peek().filter(|next| !next.starts_with('-')) {
+                description = next;
+            }
+        }
+        for name in names.split(|c: char| c == ',' || c.is_whitespace()) {
+            let name = name.split(['=', '[', '<']).next().unwrap_or_default();
+            if name.starts_with('-') && name.len() > 1 && !name.ends_with('-') {
+                flags
+                    .entry(name.to_string())
+                    .or_insert_with(|| description.to_string());
+            }
+        }
+    }
+    flags.into_iter().collect()
+}
+
+/// Get every function, builtin, alias, abbreviation, and program whose name starts with `prefix`,
+/// or the members of a module for a prefix like `fs@re`.
+pub fn commands(
+    prefix: &str,
+    env: &Environment,
+    abbreviations: &BTreeMap<String, String>,
+) -> Vec<Candidate> {
+    if let Some((module, member)) = prefix.rsplit_once('@') {
+        return members(module, member, env);
+    }
+
+    let mut found = BTreeMap::new();
+    for dir in dune::program_dirs(env) {
+        for entry in std::fs::read_dir(dir).into_iter().flatten().flatten() {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            match dune::program_name(&file_name) {
+                Some(name) if name.starts_with(prefix) => {
+                    found.insert(name.to_string(), String::from("program"));
+                }
+                _ => {}
+            }
+        }
+    }
+    for (name, value) in env.bindings() {
+        if name.starts_with(prefix) {
+            found.insert(name.clone(), describe_value(value));
+        }
+    }
+    for (name, expansion) in abbreviations {
+        if name.starts_with(prefix) {
+            found.insert(name.clone(), format!("abbreviation for {}", expansion));
+        }
+    }
+
+    found
+        .into_iter()
+        .map(|(name, description)| Candidate {
+            pair: Pair {
+                display: name.clone(),
+                replacement: name,
+            },
+            description,
+        })
+        .collect()
+}
+
+/// Get the members of a module like `fs` or `std@fs` whose names start with
+/// `prefix`. Private members are only completed once a `_` is typed.
+fn members(module: &str, prefix: &str, env: &Environment) -> Vec<Candidate> {
+    let mut path = module.split('@');
+    let mut value = path.next().and_then(|name| env.get(name));
+    for name in path {
+        value = match value {
+            Some(Expression::Map(map)) => map.get(name).cloned(),
+            _ => None,
+        };
+    }
+    let members = match value {
+        Some(Expression::Map(map)) => map,
+        _ => return vec![],
+    };
+
+    members
+        .iter()
+        .filter(|(name, _)| {
+            name.starts_with(prefix) && (prefix.starts_with('_') || !dune::is_private(name))
+        })
+        .map(|(name, value)| {
+            let replacement = format!("{}@{}", module, name);
+            Candidate {
+                pair: Pair {
+                    display: replacement.clone(),
+                    replacement,
+                },
+                description: describe_value(value),
+            }
+        })
+        .collect()
+}
+
+/// Describe a value bound to a name, like the first line of a builtin's help.
+pub fn describe_value(value: &Expression) -> String {
+    match value {
+        Expression::Builtin(builtin) => builtin
+            .help
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .unwrap_or("builtin")
+            .to_string(),
+        Expression::Lambda(..) => String::from("function"),
+        Expression::Macro(..) => String::from("macro"),
+        Expression::Symbol(name) => format!("alias for {}", name),
+        Expression::Map(_) => String::from("module"),
+        _ => String::from("variable"),
+    }
+}
+
+/// Describe a file a completion names, like `dir` or `file, 1.2KiB`.
+pub fn describe_file(cwd: &str, replacement: &str) -> String {
+    let mut path = String::new();
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        // Completions escape spaces and other special characters with backslashes.
+        path.push(if c == '\\' {
+            chars.next().unwrap_or(c)
+        } else {
+            c
+        });
+    }
+    let path = match path.strip_prefix("~/") {
+        Some(rest) => dirs::home_dir().unwrap_or_default().join(rest),
+        None => Path::new(cwd).join(path),
+    };
+
+    match std::fs::symlink_metadata(&path) {
+        Ok(meta) if meta.file_type().is_symlink() => match std::fs::read_link(&path) {
+            Ok(target) => format!("link to {}", target.display()),
+            Err(_) => String::from("link"),
+        },
+        Ok(meta) if meta.is_dir() => String::from("dir"),
+        Ok(meta) => format!("file, {}", format_size(meta.len() as f64)),
+        Err(_) => String::new(),
+    }
+}
+
+/// Let the user pick one of the candidates from a menu drawn under the line
+/// they're editing. Returns `Ok(None)` if they didn't pick any of them.
+pub fn menu(candidates: &[Candidate]) -> std::io::Result<Option<usize>> {
+    let (column, _) = cursor::position()?;
+    let width = match terminal::size() {
+        Ok((width, _)) if width > 0 => width as usize,
+        _ => 80,
+    };
+    let name_width = candidates
+        .iter()
+        .map(|c| c.pair.display.chars().count())
+        .max()
+        .unwrap_or(0);
+
+    let mut out = stdout();
+    let mut selected: usize = 0;
+    let picked = loop {
+        // Scroll the menu so the selected candidate is always visible.
+        let top = selected.saturating_sub(MENU_HEIGHT - 1);
+        let shown = &candidates[top..candidates.len().min(top + MENU_HEIGHT)];
+
+        queue!(out, Print("\r\n"), Clear(ClearType::FromCursorDown))?;
+        for (i, candidate) in shown.iter().enumerate() {
+            let row = format!(
+                "{:width$}  {}",
+                candidate.pair.display,
+                candidate.description,
+                width = name_width
+            );
+            let row = row
+                .chars()
+                .take(width.saturating_sub(1))
+                .collect::<String>();
+            if top + i == selected {
+                queue!(out, SetAttribute(Attribute::Reverse))?;
+            }
+            queue!(out, Print(row), SetAttribute(Attribute::Reset))?;
+            if i + 1 < shown.len() {
+                queue!(out, Print("\r\n"))?;
+            }
+        }
+        queue!(out, MoveUp(shown.len() as u16), MoveToColumn(column))?;
+        out.flush()?;
+
+        if let Event::Key(KeyEvent {
+            code,
+            modifiers,
+            kind: KeyEventKind::Press,
+            ..
+        }) = event::read()?
+        {
+            match (code, modifiers) {
+                (KeyCode::Down | KeyCode::Tab, _) | (KeyCode::Char('n'), KeyModifiers::CONTROL) => {
+                    selected = (selected + 1) % candidates.len()
+                }
+                (KeyCode::Up | KeyCode::BackTab, _)
+                | (KeyCode::Char('p'), KeyModifiers::CONTROL) => {
+                    selected = (selected + candidates.len() - 1) % candidates.len()
+                }
+                (KeyCode::Enter, _) => break Some(selected),
+                _ => break None,
+            }
+        }
+    };
+
+    queue!(
+        out,
+        Print("\r\n"),
+        Clear(ClearType::FromCursorDown),
+        MoveUp(1),
+        MoveToColumn(column)
+    )?;
+    out.flush()?;
+    Ok(picked)
+}
+
+/// The longest prefix all of the candidates' replacements share.
+pub fn common_prefix(candidates: &[Candidate]) -> String {
+    let mut prefix = match candidates.first() {
+        Some(first) => first.pair.replacement.clone(),
+        None => return String::new(),
+    };
+    for candidate in &candidates[1..] {
+        let len = prefix
+            .char_indices()
+            .zip(candidate.pair.replacement.chars())
+            .take_while(|((_, a), b)| a == b)
+            .last()
+            .map(|((i, a), _)| i + a.len_utf8())
+            .unwrap_or(0);
+        prefix.truncate(len);
+    }
+    prefix
+}
@@ -0,0 +1,302 @@
+use dune::{Environment, Error};
+#[cfg(unix)]
+use std::path::PathBuf;
+
+/// Get the path of the socket the daemon listens on: in the home directory, or
+/// without one, in a directory in the shared temp dir that only this user can use.
+#[cfg(unix)]
+pub fn socket_path() -> Result<PathBuf, Error> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+
+    if let Some(home) = dirs::home_dir() {
+        return Ok(home.join(".dune-daemon.sock"));
+    }
+    let uid = unsafe { libc::getuid() };
+    let dir = std::env::temp_dir().join(format!("dune-{}", uid));
+    let _ = std::fs::DirBuilder::new().mode(0o700).create(&dir);
+    // Anyone can make a directory in the temp dir, so make sure this one is
+    // ours, and that no one else can get into it.
+    match std::fs::symlink_metadata(&dir) {
+        Ok(meta)
+            if meta.is_dir() && meta.uid() == uid && meta.permissions().mode() & 0o077 == 0 =>
+        {
+            Ok(dir.join("daemon.sock"))
+        }
+        _ => Err(Error::CustomError(format!(
+            "{} isn't a directory only you can use, so the daemon can't keep its socket there",
+            dir.display()
+        ))),
+    }
+}
+
+/// Get the name of the pipe the daemon listens on, which is different for
+/// every user.
+#[cfg(windows)]
+pub fn pipe_name() -> String {
+    format!(
+        r"\\.\pipe\dune-daemon-{}",
+        std::env::var("USERNAME").unwrap_or_default()
+    )
+}
+
+/// Evaluate every script sent to the daemon's socket in the same environment,
+/// sending anything the script prints back to the client that sent it.
+#[cfg(unix)]
+pub fn serve(env: &mut Environment) -> Result<(), Error> {
+    use std::{
+        io::{Read, Write},
+        os::unix::{
+            fs::{FileTypeExt, PermissionsExt},
+            io::AsRawFd,
+            net::{UnixListener, UnixStream},
+        },
+    };
+
+    let path = socket_path()?;
+    if let Ok(meta) = std::fs::symlink_metadata(&path) {
+        if UnixStream::connect(&path).is_ok() {
+            return Err(Error::CustomError(format!(
+                "a daemon is already listening on {}",
+                path.display()
+            )));
+        }
+        if !meta.file_type().is_socket() {
+            return Err(Error::CustomError(format!(
+                "{} is in the way of the daemon's socket, and isn't a socket",
+                path.display()
+            )));
+        }
+        // A socket left behind by a daemon that didn't shut down cleanly
+        // would stop us from binding to the path again.
+        let _ = std::fs::remove_file(&path);
+    }
+    let error = |e: std::io::Error| {
+        Error::CustomError(format!("could not listen on {}: {}", path.display(), e))
+    };
+    let listener = UnixListener::bind(&path).map_err(error)?;
+    // Only this user can send the daemon scripts to run.
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).map_err(error)?;
+    eprintln!("dune daemon listening on {}", path.display());
+
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("could not accept connection: {}", e);
+                continue;
+            }
+        };
+
+        let mut script = String::new();
+        if let Err(e) = stream.read_to_string(&mut script) {
+            eprintln!("could not read script: {}", e);
+            continue;
+        }
+
+        // Point stdout and stderr at the client while the script runs, so
+        // builtins and the programs the script runs write to the client too.
+        let _ = std::io::stdout().flush();
+        let _ = std::io::stderr().flush();
+        let (stdout, stderr) = unsafe { (libc::dup(1), libc::dup(2)) };
+        unsafe {
+            libc::dup2(stream.as_raw_fd(), 1);
+            libc::dup2(stream.as_raw_fd(), 2);
+        }
+
+        if let Err(e) = crate::run_text(&script, env).and_then(|result| crate::report(result, env))
+        {
+            eprintln!("{}", dune::redact(&e.in_source(&script).to_string()));
+        }
+
+        let _ = std::io::stdout().flush();
+        let _ = std::io::stderr().flush();
+        unsafe {
+            libc::dup2(stdout, 1);
+            libc::dup2(stderr, 2);
+            libc::close(stdout);
+            libc::close(stderr);
+        }
+    }
+
+    Ok(())
+}
+
+/// Evaluate every script sent to the daemon's named pipe in the same environment,
+/// sending anything the script prints back to the client that sent it. A pipe
+/// can't be half closed like a socket, so clients end their script with a NUL.
+#[cfg(windows)]
+pub fn serve(env: &mut Environment) -> Result<(), Error> {
+    use std::{
+        fs::File,
+        io::{BufRead, BufReader, Write},
+        os::windows::io::{AsRawHandle, FromRawHandle},
+    };
+    use windows_sys::Win32::{
+        Foundation::{ERROR_PIPE_CONNECTED, HANDLE, INVALID_HANDLE_VALUE},
+        Storage::FileSystem::{
+            FlushFileBuffers, FILE_FLAG_FIRST_PIPE_INSTANCE, PIPE_ACCESS_DUPLEX,
+        },
+        System::{
+            Console::{GetStdHandle, SetStdHandle, STD_ERROR_HANDLE, STD_OUTPUT_HANDLE},
+            Pipes::{
+                ConnectNamedPipe, CreateNamedPipeW, DisconnectNamedPipe, PIPE_READMODE_BYTE,
+                PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+            },
+        },
+    };
+
+    let name = pipe_name();
+    let wide = name.encode_utf16().chain(Some(0)).collect::<Vec<_>>();
+    let error =
+        |e: std::io::Error| Error::CustomError(format!("could not listen on {}: {}", name, e));
+    let mut first = true;
+    loop {
+        // The first instance of the pipe can only be made if no other daemon
+        // has one already.
+        let open_mode = if first {
+            PIPE_ACCESS_DUPLEX | FILE_FLAG_FIRST_PIPE_INSTANCE
+        } else {
+            PIPE_ACCESS_DUPLEX
+        };
+        let handle = unsafe {
+            CreateNamedPipeW(
+                wide.as_ptr(),
+                open_mode,
+                PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+                PIPE_UNLIMITED_INSTANCES,
+                64 * 1024,
+                64 * 1024,
+                0,
+                std::ptr::null(),
+            )
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            let e = std::io::Error::last_os_error();
+            return Err(
+                if first && e.kind() == std::io::ErrorKind::PermissionDenied {
+                    Error::CustomError(format!("a daemon is already listening on {}", name))
+                } else {
+                    error(e)
+                },
+            );
+        }
+        if first {
+            eprintln!("dune daemon listening on {}", name);
+            first = false;
+        }
+        let pipe = unsafe { File::from_raw_handle(handle as _) };
+        // A client that connected before we started waiting is already connected.
+        if unsafe { ConnectNamedPipe(handle, std::ptr::null_mut()) } == 0 {
+            let e = std::io::Error::last_os_error();
+            if e.raw_os_error() != Some(ERROR_PIPE_CONNECTED as i32) {
+                eprintln!("could not accept connection: {}", e);
+                continue;
+            }
+        }
+
+        let mut script = vec![];
+        if let Err(e) = BufReader::new(&pipe).read_until(0, &mut script) {
+            eprintln!("could not read script: {}", e);
+            continue;
+        }
+        if script.last() == Some(&0) {
+            script.pop();
+        }
+
+        // Point stdout and stderr at the client while the script runs, so
+        // builtins and the programs the script runs write to the client too.
+        let _ = std::io::stdout().flush();
+        let _ = std::io::stderr().flush();
+        let (stdout, stderr): (HANDLE, HANDLE) = unsafe {
+            (
+                GetStdHandle(STD_OUTPUT_HANDLE),
+                GetStdHandle(STD_ERROR_HANDLE),
+            )
+        };
+        unsafe {
+            SetStdHandle(STD_OUTPUT_HANDLE, pipe.as_raw_handle() as _);
+            SetStdHandle(STD_ERROR_HANDLE, pipe.as_raw_handle() as _);
+        }
+
+        let script = String::from_utf8_lossy(&script);
+        if let Err(e) = crate::run_text(&script, env).and_then(|result| crate::report(result, env))
+        {
+            eprintln!("{}", dune::redact(&e.in_source(&script).to_string()));
+        }
+
+        let _ = std::io::stdout().flush();
+        let _ = std::io::stderr().flush();
+        unsafe {
+            SetStdHandle(STD_OUTPUT_HANDLE, stdout);
+            SetStdHandle(STD_ERROR_HANDLE, stderr);
+            FlushFileBuffers(handle);
+            DisconnectNamedPipe(handle);
+        }
+    }
+}
+
+/// Send a script to a running daemon, and print everything it sends back.
+#[cfg(unix)]
+pub fn eval_remote(script: &str) -> Result<(), Error> {
+    use std::{io::Write, net::Shutdown, os::unix::net::UnixStream};
+
+    let path = socket_path()?;
+    let mut stream = UnixStream::connect(&path).map_err(|e| {
+        Error::CustomError(format!(
+            "could not connect to the daemon at {} (is `dunesh --daemon` running?): {}",
+            path.display(),
+            e
+        ))
+    })?;
+
+    stream
+        .write_all(script.as_bytes())
+        .and_then(|_| stream.shutdown(Shutdown::Write))
+        .and_then(|_| std::io::copy(&mut stream, &mut std::io::stdout()))
+        .map_err(|e| Error::CustomError(format!("lost connection to the daemon: {}", e)))?;
+    Ok(())
+}
+
+/// Send a script to a running daemon, and print everything it sends back.
+#[cfg(windows)]
+pub fn eval_remote(script: &str) -> Result<(), Error> {
+    use std::io::Write;
+
+    let name = pipe_name();
+    let mut pipe = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&name)
+        .map_err(|e| {
+            Error::CustomError(format!(
+                "could not connect to the daemon at {} (is `dunesh --daemon` running?): {}",
+                name, e
+            ))
+        })?;
+
+    pipe.write_all(script.as_bytes())
+        .and_then(|_| pipe.write_all(&[0]))
+        .map_err(|e| Error::CustomError(format!("lost connection to the daemon: {}", e)))?;
+    // The daemon disconnects once the script is done, which ends the pipe.
+    match std::io::copy(&mut pipe, &mut std::io::stdout()) {
+        Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => Err(Error::CustomError(format!(
+            "lost connection to the daemon: {}",
+            e
+        ))),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn serve(_: &mut Environment) -> Result<(), Error> {
+    Err(Error::CustomError(
+        "the daemon is only supported on unix and windows".to_string(),
+    ))
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn eval_remote(_: &str) -> Result<(), Error> {
+    Err(Error::CustomError(
+        "the daemon is only supported on unix and windows".to_string(),
+    ))
+}
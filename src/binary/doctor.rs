@@ -0,0 +1,362 @@
+use crossterm::tty::IsTty;
+use std::{
+    collections::BTreeSet,
+    fs::OpenOptions,
+    path::{Path, PathBuf},
+};
+
+/// How a part of the setup turned out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Status {
+    Ok,
+    Warning,
+    Problem,
+}
+
+/// The result of checking one part of the setup, with how to fix it if it isn't ok.
+struct Check {
+    status: Status,
+    name: &'static str,
+    message: String,
+    fix: Option<String>,
+}
+
+impl Check {
+    fn ok(name: &'static str, message: impl ToString) -> Self {
+        Self {
+            status: Status::Ok,
+            name,
+            message: message.to_string(),
+            fix: None,
+        }
+    }
+
+    fn warning(name: &'static str, message: impl ToString, fix: impl ToString) -> Self {
+        Self {
+            status: Status::Warning,
+            name,
+            message: message.to_string(),
+            fix: Some(fix.to_string()),
+        }
+    }
+
+    fn problem(name: &'static str, message: impl ToString, fix: impl ToString) -> Self {
+        Self {
+            status: Status::Problem,
+            name,
+            message: message.to_string(),
+            fix: Some(fix.to_string()),
+        }
+    }
+}
+
+/// Check the user's setup, printing what's wrong and how to fix it.
+/// Returns whether there weren't any problems, though there may be warnings.
+pub fn run(history: Option<PathBuf>) -> bool {
+    let prelude = dirs::home_dir().map(|home| home.join(".dune-prelude"));
+    let mut checks = vec![history_file(history.as_deref())];
+    checks.extend(terminal());
+    checks.extend(prelude_file(prelude.as_deref()));
+    checks.extend(path());
+    checks.push(locale());
+
+    let colors = std::io::stdout().is_tty();
+    let theme = dune::theme();
+    let paint = |role: &str, text: &str| match theme.get(role) {
+        Ok(style) if colors => style.paint(text),
+        _ => text.to_string(),
+    };
+    for check in &checks {
+        let mark = match check.status {
+            Status::Ok => paint("info", "ok"),
+            Status::Warning => paint("warning", "warning"),
+            Status::Problem => paint("error", "problem"),
+        };
+        let indent = " ".repeat(12);
+        println!(
+            "{:<10}  {}: {}",
+            check.name,
+            mark,
+            check.message.replace('\n', &format!("\n{}", indent))
+        );
+        if let Some(fix) = &check.fix {
+            println!("{}{} {}", indent, paint("hint", "fix:"), fix);
+        }
+    }
+
+    let count = |status| checks.iter().filter(|c| c.status == status).count();
+    let (warnings, problems) = (count(Status::Warning), count(Status::Problem));
+    println!();
+    if warnings + problems == 0 {
+        println!("Everything looks good!");
+    } else {
+        println!("Found {} problem(s) and {} warning(s).", problems, warnings);
+    }
+    problems == 0
+}
+
+fn history_file(path: Option<&Path>) -> Check {
+    let path = match path {
+        Some(path) => path,
+        None => {
+            return Check::problem(
+                "history",
+                "could not find your home directory, so your history can't be saved",
+                "set HOME to your home directory",
+            )
+        }
+    };
+
+    let existed = path.exists();
+    match OpenOptions::new().append(true).create(true).open(path) {
+        Ok(_) => {
+            if !existed {
+                let _ = std::fs::remove_file(path);
+            }
+            Check::ok("history", format!("{} can be written", path.display()))
+        }
+        Err(e) => Check::problem(
+            "history",
+            format!("{} can't be written: {}", path.display(), e),
+            format!(
+                "make sure you own it and can write to it, like `chmod u+w {}`",
+                path.display()
+            ),
+        ),
+    }
+}
+
+fn terminal() -> Vec<Check> {
+    let mut checks = vec![];
+    if !std::io::stdin().is_tty() {
+        checks.push(Check::warning(
+            "terminal",
+            "the input isn't a terminal, so the REPL can't edit lines",
+            "run `dunesh doctor` from a terminal to check it",
+        ));
+    }
+
+    if !cfg!(windows) {
+        match std::env::var("TERM") {
+            Ok(term) if !term.is_empty() && term != "dumb" => {
+                if has_terminfo(&term) {
+                    checks.push(Check::ok(
+                        "terminal",
+                        format!("TERM is {}, and its terminfo entry was found", term),
+                    ));
+                } else {
+                    checks.push(Check::warning(
+                        "terminal",
+                        format!("no terminfo entry was found for TERM={}", term),
+                        "install your system's terminfo database, like the `ncurses-term` package, or set TERM=xterm-256color",
+                    ));
+                }
+            }
+            Ok(term) if term == "dumb" => checks.push(Check::warning(
+                "terminal",
+                "TERM is dumb, so colors and line editing won't work",
+                "set TERM to your terminal's type, like `export TERM=xterm-256color`",
+            )),
+            _ => checks.push(Check::problem(
+                "terminal",
+                "TERM isn't set",
+                "set TERM to your terminal's type, like `export TERM=xterm-256color`",
+            )),
+        }
+    }
+
+    match dune::console_width().zip(dune::console_height()) {
+        Some((width, height)) => checks.push(Check::ok(
+            "terminal",
+            format!("the console is {} columns by {} lines", width, height),
+        )),
+        None => checks.push(Check::warning(
+            "terminal",
+            "could not get the size of the console, so tables are 120 columns wide",
+            "set COLUMNS and LINES to the size of the console",
+        )),
+    }
+    checks
+}
+
+/// Whether there's a terminfo entry describing a terminal, in the places ncurses looks.
+fn has_terminfo(term: &str) -> bool {
+    let first = match term.chars().next() {
+        Some(first) => first,
+        None => return false,
+    };
+    let mut dirs = vec![];
+    if let Some(dir) = std::env::var_os("TERMINFO") {
+        dirs.push(PathBuf::from(dir));
+    }
+    if let Some(home) = dirs::home_dir() {
+        dirs.push(home.join(".terminfo"));
+    }
+    if let Some(paths) = std::env::var_os("TERMINFO_DIRS") {
+        dirs.extend(std::env::split_paths(&paths));
+    }
+    for dir in [
+        "/etc/terminfo",
+        "/lib/terminfo",
+        "/usr/share/terminfo",
+        "/usr/lib/terminfo",
+    ] {
+        dirs.push(PathBuf::from(dir));
+    }
+
+    // Entries are in a directory named after their first letter, or its
+    // code in hexadecimal on macOS.
+    dirs.iter().any(|dir| {
+        dir.join(first.to_string()).join(term).exists()
+            || dir.join(format!("{:x}", first as u32)).join(term).exists()
+    })
+}
+
+fn prelude_file(path: Option<&Path>) -> Vec<Check> {
+    let path = match path {
+        Some(path) => path,
+        None => return vec![],
+    };
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(_) if !path.exists() => {
+            return vec![Check::warning(
+                "prelude",
+                format!("there's no prelude at {}", path.display()),
+                "start `dunesh` and answer `y` to write the default prelude there",
+            )]
+        }
+        Err(e) => {
+            return vec![Check::problem(
+                "prelude",
+                format!("{} can't be read: {}", path.display(), e),
+                format!(
+                    "make sure you can read it, like `chmod u+r {}`",
+                    path.display()
+                ),
+            )]
+        }
+    };
+
+    if let Err(e) = crate::parse(&text) {
+        return vec![Check::problem(
+            "prelude",
+            format!(
+                "{} doesn't parse:\n{}",
+                path.display(),
+                e.to_string().trim_end()
+            ),
+            format!("fix the syntax error in {}", path.display()),
+        )];
+    }
+    let warnings = dune::analyze(&text);
+    if warnings.is_empty() {
+        return vec![Check::ok("prelude", format!("{} parses", path.display()))];
+    }
+    warnings
+        .into_iter()
+        .map(|warning| {
+            Check::warning(
+                "config",
+                format!("{}:\n{}", path.display(), warning.to_string().trim_end()),
+                format!("edit {}", path.display()),
+            )
+        })
+        .collect()
+}
+
+fn path() -> Vec<Check> {
+    let paths = match std::env::var_os("PATH") {
+        Some(paths) if !paths.is_empty() => paths,
+        _ => {
+            return vec![Check::problem(
+                "path",
+                "PATH isn't set, so no programs can be found",
+                "set PATH in your prelude, like `let PATH = \"/usr/local/bin:/usr/bin:/bin\"`",
+            )]
+        }
+    };
+
+    let mut checks = vec![];
+    let mut seen = BTreeSet::new();
+    let (mut missing, mut relative, mut repeated) = (vec![], vec![], vec![]);
+    for dir in std::env::split_paths(&paths) {
+        let name = dir.display().to_string();
+        if !seen.insert(dir.clone()) {
+            repeated.push(name);
+        } else if dir.is_relative() {
+            relative.push(name);
+        } else if !dir.is_dir() {
+            missing.push(name);
+        }
+    }
+    if !missing.is_empty() {
+        checks.push(Check::warning(
+            "path",
+            format!(
+                "these directories in PATH don't exist: {}",
+                missing.join(", ")
+            ),
+            "remove them from PATH, or install what was supposed to be there",
+        ));
+    }
+    if !relative.is_empty() {
+        checks.push(Check::warning(
+            "path",
+            format!(
+                "these directories in PATH are relative: {}",
+                relative.join(", ")
+            ),
+            "use absolute paths, so the programs found don't depend on the current directory",
+        ));
+    }
+    if !repeated.is_empty() {
+        checks.push(Check::warning(
+            "path",
+            format!(
+                "these directories are in PATH more than once: {}",
+                repeated.join(", ")
+            ),
+            "remove the repeats, which can happen when PATH is added to every time a prelude runs",
+        ));
+    }
+    if checks.is_empty() {
+        checks.push(Check::ok(
+            "path",
+            format!("all {} directories in PATH exist", seen.len()),
+        ));
+    }
+    checks
+}
+
+fn locale() -> Check {
+    if cfg!(windows) {
+        return Check::ok("locale", "Windows consoles support UTF-8");
+    }
+    // The first of these that's set decides how text is encoded.
+    let setting = ["LC_ALL", "LC_CTYPE", "LANG"].iter().find_map(|name| {
+        std::env::var(name)
+            .ok()
+            .filter(|value| !value.is_empty())
+            .map(|value| (name, value))
+    });
+    match setting {
+        Some((name, value)) => {
+            let lower = value.to_lowercase();
+            if lower.contains("utf-8") || lower.contains("utf8") {
+                Check::ok("locale", format!("{} is {}, which uses UTF-8", name, value))
+            } else {
+                Check::warning(
+                    "locale",
+                    format!("{} is {}, which doesn't use UTF-8", name, value),
+                    "use a UTF-8 locale so programs print symbols and other languages correctly, like `export LANG=en_US.UTF-8`",
+                )
+            }
+        }
+        None => Check::warning(
+            "locale",
+            "no locale is set, so programs may not print anything but ASCII correctly",
+            "set a UTF-8 locale, like `export LANG=en_US.UTF-8`",
+        ),
+    }
+}
@@ -0,0 +1,59 @@
+use rustyline::{line_buffer::LineBuffer, Cmd, Movement};
+use std::sync::Mutex;
+
+/// A change to the text before the cursor that a keybinding wants to make.
+///
+/// Rustyline can't replace text before the cursor and then move past it in one
+/// command, so keybindings ask for a completion instead, and the completer
+/// makes the change.
+#[derive(Default)]
+pub struct PendingEdit(Mutex<Option<Edit>>);
+
+enum Edit {
+    /// Replace everything from a position up to the cursor.
+    Replace(usize, String),
+    /// Kill text into the kill ring. Rustyline replaces the count of a movement
+    /// a keybinding returns with the count the user typed, so a kill that
+    /// needs its own count is made here.
+    Kill(Movement),
+}
+
+impl PendingEdit {
+    /// Replace everything from `start` up to the cursor with `text`.
+    pub fn replace(&self, start: usize, text: String) -> Cmd {
+        *self.0.lock().unwrap() = Some(Edit::Replace(start, text));
+        Cmd::Complete
+    }
+
+    /// Kill the text `movement` moves over, so it can be yanked again.
+    pub fn kill(&self, movement: Movement) -> Cmd {
+        *self.0.lock().unwrap() = Some(Edit::Kill(movement));
+        Cmd::Complete
+    }
+
+    /// Get the text that will be inserted, if there's an edit waiting to be made.
+    ///
+    /// Rustyline only asks the completer to make a change that makes the line
+    /// longer, so a kill inserts a placeholder, which the edit never uses.
+    pub fn text(&self) -> Option<String> {
+        self.0.lock().unwrap().as_ref().map(|edit| match edit {
+            Edit::Replace(_, text) => text.clone(),
+            Edit::Kill(_) => String::from(" "),
+        })
+    }
+
+    /// Make the waiting edit. Returns false if there was nothing to do.
+    pub fn apply(&self, line: &mut LineBuffer) -> bool {
+        match self.0.lock().unwrap().take() {
+            Some(Edit::Replace(start, text)) => {
+                line.replace(start..line.pos(), &text);
+                true
+            }
+            Some(Edit::Kill(movement)) => {
+                line.kill(&movement);
+                true
+            }
+            None => false,
+        }
+    }
+}
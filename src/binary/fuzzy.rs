@@ -0,0 +1,394 @@
+use crossterm::{
+    cursor::{Hide, MoveTo, Show},
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    queue,
+    style::Print,
+    terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use rustyline::{Cmd, ConditionalEventHandler, EventContext, RepeatCount};
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::{stdout, Write},
+    sync::{Arc, Mutex},
+};
+
+/// The most paths the file and directory pickers list.
+const MAX_PATHS: usize = 100_000;
+
+/// Score how well a query fuzzily matches some text, like fzf. Every character
+/// of the query has to be in the text, in order, but there can be others between
+/// them. Matches at the start of words and runs of matching characters score
+/// higher. Returns the score and which characters of the text matched.
+///
+/// The match ignores case, unless the query has uppercase letters.
+pub fn score(query: &str, text: &str) -> Option<(i64, Vec<usize>)> {
+    let case_sensitive = query.chars().any(char::is_uppercase);
+    let fold = |c: char| {
+        if case_sensitive {
+            c
+        } else {
+            c.to_lowercase().next().unwrap_or(c)
+        }
+    };
+    let query = query.chars().map(fold).collect::<Vec<_>>();
+    let chars = text.chars().collect::<Vec<_>>();
+    if query.is_empty() {
+        return Some((0, vec![]));
+    }
+
+    // Find where the first match ends, then where the shortest match ending
+    // there starts, so the matching characters are close together.
+    let mut matched = 0;
+    let mut end = None;
+    for (i, &c) in chars.iter().enumerate() {
+        if fold(c) == query[matched] {
+            matched += 1;
+            if matched == query.len() {
+                end = Some(i);
+                break;
+            }
+        }
+    }
+    let end = end?;
+    let mut start = end;
+    let mut remaining = query.len();
+    for i in (0..=end).rev() {
+        if fold(chars[i]) == query[remaining - 1] {
+            remaining -= 1;
+            if remaining == 0 {
+                start = i;
+                break;
+            }
+        }
+    }
+
+    let mut positions: Vec<usize> = vec![];
+    let mut score = 0;
+    for (i, &c) in chars.iter().enumerate().take(end + 1).skip(start) {
+        if positions.len() == query.len() || fold(c) != query[positions.len()] {
+            continue;
+        }
+        score += 16;
+        let boundary = i == 0
+            || matches!(chars[i - 1], ' ' | '/' | '\\' | '_' | '-' | '.' | ':')
+            || (chars[i - 1].is_lowercase() && c.is_uppercase());
+        if boundary {
+            score += 10;
+        }
+        match positions.last() {
+            Some(&last) if last + 1 == i => score += 12,
+            Some(&last) => score -= (i - last - 1).min(8) as i64,
+            None => {}
+        }
+        positions.push(i);
+    }
+    // Prefer matches near the start.
+    score -= start.min(16) as i64;
+    Some((score, positions))
+}
+
+/// Choose items from a list by typing part of them.
+pub struct Finder<'a> {
+    items: Vec<String>,
+    prompt: String,
+    multi: bool,
+    /// Get the preview of an item, shown beside the list.
+    preview: Option<Box<dyn FnMut(usize) -> String + 'a>>,
+    previews: HashMap<usize, String>,
+    query: String,
+    /// The items that match the query, best first, with the characters that matched.
+    matches: Vec<(usize, Vec<usize>)>,
+    selected: usize,
+    top: usize,
+    marked: BTreeSet<usize>,
+}
+
+impl<'a> Finder<'a> {
+    pub fn new(items: Vec<String>) -> Self {
+        let mut finder = Self {
+            // Each item is shown on one line.
+            items: items
+                .into_iter()
+                .map(|item| item.replace(['\n', '\r', '\t'], " "))
+                .collect(),
+            prompt: String::from("> "),
+            multi: false,
+            preview: None,
+            previews: HashMap::new(),
+            query: String::new(),
+            matches: vec![],
+            selected: 0,
+            top: 0,
+            marked: BTreeSet::new(),
+        };
+        finder.search();
+        finder
+    }
+
+    pub fn prompt(mut self, prompt: impl ToString) -> Self {
+        self.prompt = prompt.to_string();
+        self
+    }
+
+    /// Let several items be marked with Tab and picked together.
+    pub fn multi(mut self, multi: bool) -> Self {
+        self.multi = multi;
+        self
+    }
+
+    pub fn preview(mut self, preview: impl FnMut(usize) -> String + 'a) -> Self {
+        self.preview = Some(Box::new(preview));
+        self
+    }
+
+    /// Let the user choose items, returning their indices, or `None` if nothing was chosen.
+    pub fn run(&mut self) -> std::io::Result<Option<Vec<usize>>> {
+        let was_raw = terminal::is_raw_mode_enabled().unwrap_or(false);
+        terminal::enable_raw_mode()?;
+        let mut out = stdout();
+        queue!(out, EnterAlternateScreen)?;
+        let result = self.choose(&mut out);
+        queue!(out, Show, LeaveAlternateScreen)?;
+        out.flush()?;
+        if !was_raw {
+            terminal::disable_raw_mode()?;
+        }
+        result
+    }
+
+    fn choose(&mut self, out: &mut impl Write) -> std::io::Result<Option<Vec<usize>>> {
+        loop {
+            self.draw(out)?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                if let Some(picked) = self.handle(key) {
+                    return Ok(picked);
+                }
+            }
+        }
+    }
+
+    /// Find the items that match the query, best first.
+    fn search(&mut self) {
+        let mut matches = self
+            .items
+            .iter()
+            .enumerate()
+            .filter_map(|(i, item)| {
+                score(&self.query, item).map(|(score, positions)| (score, i, positions))
+            })
+            .collect::<Vec<_>>();
+        // Shorter items are better matches, and items stay in order otherwise.
+        matches.sort_by_key(|(score, i, _)| (-score, self.items[*i].len(), *i));
+        self.matches = matches
+            .into_iter()
+            .map(|(_, i, positions)| (i, positions))
+            .collect();
+        self.selected = 0;
+        self.top = 0;
+    }
+
+    fn draw(&mut self, out: &mut impl Write) -> std::io::Result<()> {
+        let (width, height) = terminal::size()?;
+        let (width, height) = (width as usize, height as usize);
+        // Leave room for the prompt.
+        let page = height.saturating_sub(1).max(1);
+        let list_width = if self.preview.is_some() {
+            width / 2
+        } else {
+            width
+        };
+
+        self.selected = self.selected.min(self.matches.len().saturating_sub(1));
+        if self.selected < self.top {
+            self.top = self.selected;
+        } else if self.selected >= self.top + page {
+            self.top = self.selected + 1 - page;
+        }
+
+        let matched = dune::theme().matched;
+        queue!(out, Hide, MoveTo(0, 0), Clear(ClearType::All))?;
+        for (row, (i, positions)) in self.matches.iter().enumerate().skip(self.top).take(page) {
+            let mark = if self.marked.contains(i) { "* " } else { "  " };
+            let mut line = String::from(mark);
+            for (n, c) in self.items[*i]
+                .chars()
+                .enumerate()
+                .take(list_width.saturating_sub(2))
+            {
+                if positions.contains(&n) {
+                    // Only end the style, so the reversed colors of the selection stay.
+                    line += &format!("{}{}\x1b[22;23;24;39;49m", matched.start(), c);
+                } else {
+                    line.push(c);
+                }
+            }
+            queue!(out, MoveTo(0, (row - self.top + 1) as u16))?;
+            if row == self.selected {
+                queue!(out, Print(format!("\x1b[7m{}\x1b[0m", line)))?;
+            } else {
+                queue!(out, Print(line))?;
+            }
+        }
+
+        if let Some(&(item, _)) = self.matches.get(self.selected) {
+            if let Some(preview) = self.preview.as_mut() {
+                let text = self
+                    .previews
+                    .entry(item)
+                    .or_insert_with(|| crate::strip_ansi_escapes(preview(item)));
+                for (row, line) in text.lines().take(page).enumerate() {
+                    let line = line
+                        .replace('\t', "    ")
+                        .chars()
+                        .take(width - list_width - 2)
+                        .collect::<String>();
+                    queue!(
+                        out,
+                        MoveTo(list_width as u16, (row + 1) as u16),
+                        Print(format!("│ {}", line))
+                    )?;
+                }
+                for row in text.lines().count()..page {
+                    queue!(out, MoveTo(list_width as u16, (row + 1) as u16), Print("│"))?;
+                }
+            }
+        }
+
+        let count = format!("  {}/{}", self.matches.len(), self.items.len());
+        let count = if self.marked.is_empty() {
+            count
+        } else {
+            format!("{} ({} marked)", count, self.marked.len())
+        };
+        let input = format!("{}{}", self.prompt, self.query);
+        queue!(
+            out,
+            MoveTo(0, 0),
+            Print(format!("{}\x1b[2m{}\x1b[0m", input, count)),
+            MoveTo(input.chars().count().min(width) as u16, 0),
+            Show
+        )?;
+        out.flush()
+    }
+
+    /// Handle a key press. Returns the indices of the chosen items once the finder
+    /// is closed, or `None` inside if nothing was chosen.
+    fn handle(&mut self, key: KeyEvent) -> Option<Option<Vec<usize>>> {
+        let page = terminal::size()
+            .map(|(_, height)| height.saturating_sub(1).max(1) as usize)
+            .unwrap_or(10);
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => return Some(None),
+            KeyCode::Char('c' | 'g') if ctrl => return Some(None),
+            KeyCode::Enter => {
+                return Some(if !self.marked.is_empty() {
+                    Some(self.marked.iter().copied().collect())
+                } else {
+                    self.matches.get(self.selected).map(|(i, _)| vec![*i])
+                })
+            }
+            KeyCode::Tab if self.multi => {
+                if let Some((i, _)) = self.matches.get(self.selected) {
+                    if !self.marked.remove(i) {
+                        self.marked.insert(*i);
+                    }
+                }
+                self.selected += 1;
+            }
+            KeyCode::Up => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Char('p' | 'k') if ctrl => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down => self.selected += 1,
+            KeyCode::Char('n' | 'j') if ctrl => self.selected += 1,
+            KeyCode::PageUp => self.selected = self.selected.saturating_sub(page),
+            KeyCode::PageDown => self.selected += page,
+            KeyCode::Char('u') if ctrl => {
+                self.query.clear();
+                self.search();
+            }
+            KeyCode::Char('w') if ctrl => {
+                let kept = self.query.trim_end().rfind(' ').map(|i| i + 1).unwrap_or(0);
+                self.query.truncate(kept);
+                self.search();
+            }
+            KeyCode::Backspace => {
+                self.query.pop();
+                self.search();
+            }
+            KeyCode::Char(c) if !ctrl => {
+                self.query.push(c);
+                self.search();
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+/// Quote a path so it can be typed as a string.
+fn quote(path: &str) -> String {
+    format!("\"{}\"", path.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Pick a path under the working directory.
+fn pick_path(dirs_only: bool) -> Option<String> {
+    let cwd = std::env::current_dir().ok()?;
+    let paths = super::init::walk_paths(&cwd, dirs_only, MAX_PATHS);
+    let prompt = if dirs_only { "cd> " } else { "file> " };
+    let picked = Finder::new(paths.clone()).prompt(prompt).run().ok()??;
+    picked.first().map(|&i| paths[i].clone())
+}
+
+/// Inserts a file picked with the fuzzy finder at the cursor, like Ctrl-T in fzf.
+pub struct PickFile;
+
+impl ConditionalEventHandler for PickFile {
+    fn handle(
+        &self,
+        _: &rustyline::Event,
+        _: RepeatCount,
+        _: bool,
+        _: &EventContext,
+    ) -> Option<Cmd> {
+        Some(match pick_path(false) {
+            Some(path) => Cmd::Insert(1, quote(&path)),
+            // The finder was drawn on the alternate screen, so the line is still there.
+            None => Cmd::Noop,
+        })
+    }
+}
+
+/// Changes to a directory picked with the fuzzy finder, like Alt-C in fzf.
+///
+/// A keybinding can't change the directory itself, so it replaces the line with
+/// a `cd` to the directory, which the REPL runs instead.
+pub struct PickDirectory {
+    command: Arc<Mutex<Option<String>>>,
+}
+
+impl PickDirectory {
+    pub fn new(command: Arc<Mutex<Option<String>>>) -> Self {
+        Self { command }
+    }
+}
+
+impl ConditionalEventHandler for PickDirectory {
+    fn handle(
+        &self,
+        _: &rustyline::Event,
+        _: RepeatCount,
+        _: bool,
+        _: &EventContext,
+    ) -> Option<Cmd> {
+        Some(match pick_path(true) {
+            Some(dir) => {
+                *self.command.lock().unwrap() = Some(format!("cd {}", quote(&dir)));
+                Cmd::AcceptLine
+            }
+            None => Cmd::Noop,
+        })
+    }
+}
@@ -0,0 +1,96 @@
+use super::edit::PendingEdit;
+use dune::{tokenize, TokenKind};
+use rustyline::{Cmd, ConditionalEventHandler, Event, EventContext, RepeatCount};
+use std::sync::{Arc, Mutex};
+
+/// Get the last argument of a command, like `!$` in other shells.
+pub fn last_argument(line: &str) -> Option<String> {
+    let (tokens, _) = tokenize(line);
+    tokens
+        .into_iter()
+        .rev()
+        .filter(|t| !matches!(t.kind, TokenKind::Whitespace | TokenKind::Comment))
+        .map(|t| t.range.to_str(line))
+        .find(|text| *text != ";")
+        .map(String::from)
+}
+
+/// Replace every `!$` in a line with the last argument of the previous command.
+/// Anything inside a string literal is left alone.
+pub fn expand_last_argument(line: &str, previous: Option<&str>) -> String {
+    let last = match previous.and_then(last_argument) {
+        Some(last) => last,
+        None => return line.to_string(),
+    };
+
+    let (tokens, _) = tokenize(line);
+    let mut result = String::new();
+    let mut end = 0;
+    for pair in tokens.windows(2) {
+        let (bang, dollar) = (pair[0].range, pair[1].range);
+        if bang.to_str(line) == "!" && dollar.to_str(line) == "$" && bang.end() == dollar.start() {
+            result += &line[end..bang.start()];
+            result += &last;
+            end = dollar.end();
+        }
+    }
+    result += &line[end..];
+    result
+}
+
+/// Inserts the last argument of the previous command at the cursor. Pressing it
+/// again replaces what it inserted with the last argument of the command before that.
+pub struct LastArgument {
+    history: Arc<Mutex<Vec<String>>>,
+    edit: Arc<PendingEdit>,
+    /// What the line looked like after the last insertion, the position of the
+    /// cursor, the text that was inserted, and how far back in the history it came from.
+    last: Mutex<Option<(String, usize, String, usize)>>,
+}
+
+impl LastArgument {
+    pub fn new(history: Arc<Mutex<Vec<String>>>, edit: Arc<PendingEdit>) -> Self {
+        Self {
+            history,
+            edit,
+            last: Mutex::new(None),
+        }
+    }
+}
+
+impl ConditionalEventHandler for LastArgument {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, ctx: &EventContext) -> Option<Cmd> {
+        let history = self.history.lock().unwrap();
+        let mut last = self.last.lock().unwrap();
+
+        // Is this another press right after the last one?
+        let (start, replaced) = match last.as_ref() {
+            Some((line, pos, inserted, back)) if line == ctx.line() && *pos == ctx.pos() => {
+                (back + 1, Some(inserted.clone()))
+            }
+            _ => (0, None),
+        };
+
+        let found = history
+            .iter()
+            .rev()
+            .enumerate()
+            .skip(start)
+            .find_map(|(back, line)| Some((back, last_argument(line)?)));
+        let (back, argument) = match found {
+            Some(found) => found,
+            // There's nothing further back in the history.
+            None => return Some(Cmd::Noop),
+        };
+
+        let pos = ctx.pos() - replaced.as_ref().map(String::len).unwrap_or(0);
+        let mut line = ctx.line().to_string();
+        line.replace_range(pos..ctx.pos(), &argument);
+        *last = Some((line, pos + argument.len(), argument.clone(), back));
+
+        Some(match replaced {
+            Some(_) => self.edit.replace(pos, argument),
+            None => Cmd::Insert(1, argument),
+        })
+    }
+}
@@ -1,33 +1,176 @@
+use common_macros::b_tree_map;
 use dune::{Environment, Error, Expression};
+use std::{convert::TryFrom, path::PathBuf};
 
-pub fn chess_fn(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
-    let mut won = false;
+/// The variable a game is kept in when the player quits before it's over.
+const GAME: &str = "CHESS_GAME";
+
+/// Define `chess`, which starts a game, and the builtins for saving and
+/// resuming games alongside it.
+pub fn define(env: &mut Environment) {
+    env.define_builtin(
+        "chess",
+        play,
+        "a fun builtin function for playing chess! call it with \"black\" or \"white\"",
+    );
+    env.define_builtin(
+        "chess-resume",
+        resume,
+        "resume the last game of chess that was quit, or a game returned by `chess-load`",
+    );
+    env.define_builtin(
+        "chess-save",
+        save,
+        "save the last game of chess that was quit to a file",
+    );
+    env.define_builtin(
+        "chess-load",
+        load,
+        "load a game of chess saved with `chess-save`, so it can be resumed",
+    );
+}
 
+/// Get the color the player is playing as.
+fn color(name: &str) -> Result<chess_engine::Color, Error> {
+    match name {
+        "white" => Ok(chess_engine::WHITE),
+        "black" => Ok(chess_engine::BLACK),
+        _ => Err(Error::CustomError(
+            "call chess with a color, like \"black\" or \"white\"".to_string(),
+        )),
+    }
+}
+
+fn play(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("chess", &args, 1)?;
     let player_color = match &args[0] {
-        Expression::String(color) | Expression::Symbol(color) if color == "white" => {
-            chess_engine::WHITE
-        }
-        Expression::String(color) | Expression::Symbol(color) if color == "black" => {
-            chess_engine::BLACK
-        }
+        Expression::String(color) | Expression::Symbol(color) => self::color(color)?,
+        _ => color("")?,
+    };
+    run(player_color, vec![], env)
+}
+
+fn resume(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("chess-resume", &args, 0..=1)?;
+    let game = match args.first() {
+        Some(game) => game.eval(env)?,
+        None => env.get(GAME).ok_or_else(|| {
+            Error::CustomError(String::from("there's no game of chess to resume"))
+        })?,
+    };
+
+    let (player_color, moves) = match &game {
+        Expression::Map(game) => match (game.get("color"), game.get("moves")) {
+            (Some(color), Some(Expression::List(moves))) => Some((
+                self::color(&color.to_string())?,
+                moves.iter().map(Expression::to_string).collect(),
+            )),
+            _ => None,
+        },
+        _ => None,
+    }
+    .ok_or_else(|| Error::CustomError(format!("{} is not a game of chess", game)))?;
+    run(player_color, moves, env)
+}
+
+fn save(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("chess-save", &args, 1)?;
+    let file = args[0].eval(env)?;
+    let path = PathBuf::from(env.get_cwd()).join(file.to_string());
+    let game = match env.get(GAME) {
+        Some(Expression::Map(game)) => game,
         _ => {
-            return Err(Error::CustomError(
-                "call chess with a color, like \"black\" or \"white\"".to_string(),
-            ))
+            return Err(Error::CustomError(String::from(
+                "there's no game of chess to save",
+            )))
         }
     };
 
+    let mut saved = json::JsonValue::new_object();
+    saved["color"] = game.get("color").map(|c| c.to_string()).into();
+    saved["moves"] = match game.get("moves") {
+        Some(Expression::List(moves)) => moves
+            .iter()
+            .map(Expression::to_string)
+            .collect::<Vec<_>>()
+            .into(),
+        _ => json::JsonValue::new_array(),
+    };
+
+    match std::fs::write(&path, saved.pretty(4)) {
+        Ok(()) => Ok(Expression::None),
+        Err(e) => Err(Error::CustomError(format!(
+            "could not save game to {}: {}",
+            file, e
+        ))),
+    }
+}
+
+fn load(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("chess-load", &args, 1)?;
+    let file = args[0].eval(env)?;
+    let path = PathBuf::from(env.get_cwd()).join(file.to_string());
+    let saved = std::fs::read_to_string(&path)
+        .ok()
+        .and_then(|text| json::parse(&text).ok())
+        .ok_or_else(|| {
+            Error::CustomError(format!("could not load a game of chess from {}", file))
+        })?;
+
+    let game = Expression::from(b_tree_map! {
+        String::from("color") => Expression::from(saved["color"].as_str().unwrap_or_default()),
+        String::from("moves") => Expression::List(
+            saved["moves"]
+                .members()
+                .filter_map(|m| m.as_str())
+                .map(Expression::from)
+                .collect(),
+        ),
+    });
+    env.define(GAME, game.clone());
+    Ok(game)
+}
+
+/// Play a game, starting after the moves that were already played. If the
+/// player quits before it's over, the game is kept so it can be resumed.
+fn run(
+    player_color: chess_engine::Color,
+    moves: Vec<String>,
+    env: &mut Environment,
+) -> Result<Expression, Error> {
+    use chess_engine::Evaluate;
+
+    let mut won = false;
     let mut board = chess_engine::Board::default();
     let mut history = vec![];
-    loop {
-        use chess_engine::Evaluate;
-        use std::convert::TryFrom;
+    for m in moves {
+        let next =
+            chess_engine::Move::try_from(m.clone())
+                .ok()
+                .and_then(|m| match board.play_move(m) {
+                    chess_engine::GameResult::Continuing(next) => Some((m, next)),
+                    _ => None,
+                });
+        match next {
+            Some((m, next)) => {
+                board = next;
+                history.push(m);
+            }
+            None => {
+                return Err(Error::CustomError(format!(
+                    "could not replay the move `{}` of a saved game of chess",
+                    m
+                )))
+            }
+        }
+    }
 
+    loop {
         let m = if player_color != board.get_turn_color() {
             println!("Waiting for CPU to annihilate your position...");
             board.get_best_next_move(4).0
         } else {
-            println!("Your move!\n{}", board);
+            println!("Your move!\n{}", dune::adapt_colors(&board.to_string()));
             let mut rl = crate::new_editor(env);
             let mut s = crate::readline("Enter move: ", &mut rl);
             s = s.trim().to_string();
@@ -36,7 +179,17 @@ pub fn chess_fn(args: Vec<Expression>, env: &mut Environment) -> Result<Expressi
                 eprintln!("That's not a move!");
                 continue;
             } else if s == "q" || s == "quit" || s == "exit" {
-                println!("Bye!");
+                env.define(
+                    GAME,
+                    b_tree_map! {
+                        String::from("color") => Expression::from(if player_color == chess_engine::WHITE { "white" } else { "black" }),
+                        String::from("moves") => Expression::List(history.iter().map(|m| Expression::from(m.to_string())).collect()),
+                    }
+                    .into(),
+                );
+                println!(
+                    "Bye! Resume this game with `chess-resume`, or keep it with `chess-save`."
+                );
                 break;
             } else {
                 match chess_engine::Move::try_from(s) {
@@ -56,10 +209,11 @@ pub fn chess_fn(args: Vec<Expression>, env: &mut Environment) -> Result<Expressi
             }
 
             chess_engine::GameResult::Victory(winner) => {
-                println!("{}", board);
+                println!("{}", dune::adapt_colors(&board.to_string()));
                 println!("Checkmate! {} loses. {} is victorious!", !winner, winner);
 
                 won = player_color == winner;
+                env.undefine(GAME);
                 break;
             }
 
@@ -69,6 +223,7 @@ pub fn chess_fn(args: Vec<Expression>, env: &mut Environment) -> Result<Expressi
 
             chess_engine::GameResult::Stalemate => {
                 println!("Drawn game.");
+                env.undefine(GAME);
                 break;
             }
         }
@@ -76,5 +231,3 @@ pub fn chess_fn(args: Vec<Expression>, env: &mut Environment) -> Result<Expressi
 
     Ok(Expression::Boolean(won))
 }
-
-pub const HELP: &str = "a fun builtin function for playing chess!";
@@ -1,7 +1,19 @@
+use crate::binary::{fuzzy::Finder, inspector::Inspector, notify};
 use common_macros::b_tree_map;
-use dune::{Environment, Error, Expression, Int};
-use std::io::Write;
-use terminal_size::{terminal_size, Height, Width};
+use crossterm::{
+    cursor::{Hide, MoveTo, Show},
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    queue,
+    style::Print,
+    terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use dune::{ColorSupport, Environment, Error, Expression, Int, Theme};
+use indexmap::IndexMap;
+use std::{
+    cmp::Ordering,
+    collections::BTreeSet,
+    io::{stdout, IsTerminal, Write},
+};
 
 pub fn get() -> Expression {
     (b_tree_map! {
@@ -14,7 +26,7 @@ pub fn get() -> Expression {
             std::io::stdout().flush().unwrap();
             Ok(Expression::None)
         }, "flush the console"),
-        String::from("mode") => Expression::Map(b_tree_map! {
+        String::from("mode") => Expression::from(b_tree_map! {
             String::from("raw") => Expression::builtin("raw", |_, _| {
                 match crossterm::terminal::enable_raw_mode() {
                     Ok(_) => Ok(Expression::None),
@@ -36,7 +48,7 @@ pub fn get() -> Expression {
                 Ok(Expression::None)
             }, "disable alternate screen"),
         }),
-        String::from("cursor") => Expression::Map(b_tree_map! {
+        String::from("cursor") => Expression::from(b_tree_map! {
             String::from("move-to") => Expression::builtin("move-to", |args, env| {
                 super::check_exact_args_len("move-to", &args, 2)?;
                 let x = args[0].clone().eval(env)?;
@@ -115,7 +127,24 @@ pub fn get() -> Expression {
             }, "show the cursor"),
         }),
 
-        String::from("keyboard") => Expression::Map(b_tree_map! {
+        String::from("view") => Expression::builtin("view", view,
+            "explore a list of maps in a table, returning the rows marked with space or picked with enter"),
+        String::from("fuzzy") => Expression::builtin("fuzzy", fuzzy,
+            "pick an item from a list by typing part of it, like `console@fuzzy (fs@ls \".\" | fn@map (entry -> entry@path)) {preview=fs@read, multi=False, prompt=\"> \"}`"),
+        String::from("inspect") => Expression::builtin("inspect", inspect,
+            "browse a value as a tree: fold maps and lists, search keys with `/`, copy the path to a part with `y`, and press enter to return that part"),
+        String::from("theme") => Expression::builtin("theme", theme,
+            "get the colors used across the shell, or set them to a theme like `console@theme \"light\"`, or change some of them like `console@theme {base=\"dark\", string=\"#ffaf00\", comment=\"dim\"}`"),
+        String::from("themes") => Expression::from(
+            Theme::NAMES.iter().map(|name| Expression::from(name.to_string())).collect::<Vec<_>>()
+        ),
+        String::from("colors") => Expression::builtin("colors", colors,
+            "get the colors the console can draw, one of \"none\", \"16\", \"256\", or \"truecolor\", or set them like `console@colors \"16\"`, or detect them again with \"auto\""),
+        String::from("paint") => Expression::builtin("paint", paint,
+            "draw text in the color the theme gives a role, like `console@paint \"prompt-path\" CWD`"),
+        String::from("notify-after") => Expression::builtin("notify-after", notify_after,
+            "ring the bell and show a desktop notification when a program that ran longer than some seconds or a duration finishes while the console isn't focused, like `console@notify-after \"30s\" {bell=False}`, or never with None"),
+        String::from("keyboard") => Expression::from(b_tree_map! {
             String::from("read-line") => Expression::builtin("read-line", |_, _| {
                 let mut buffer = String::new();
                 std::io::stdin().read_line(&mut buffer).unwrap();
@@ -156,7 +185,7 @@ pub fn get() -> Expression {
                     _ => Expression::None
                 })
             }, "read a key from the keyboard"),
-            String::from("keys") => Expression::Map(b_tree_map! {
+            String::from("keys") => Expression::from(b_tree_map! {
                 String::from("enter") => Expression::String("\n".to_string()),
                 String::from("backspace") => Expression::String("\x08".to_string()),
                 String::from("delete") => Expression::String("\x7f".to_string()),
@@ -191,20 +220,135 @@ pub fn get() -> Expression {
     .into()
 }
 
-fn width(_: Vec<Expression>, _: &mut Environment) -> Result<Expression, Error> {
-    Ok(match terminal_size() {
-        Some((Width(w), _)) => (w as Int).into(),
-        _ => Expression::None,
-    })
+fn theme(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("theme", &args, 0..=1)?;
+    match args.first().map(|arg| arg.eval(env)).transpose()? {
+        None | Some(Expression::None) => {
+            let theme = dune::theme();
+            let mut result = IndexMap::new();
+            result.insert(String::from("name"), Expression::from(theme.name.clone()));
+            for role in Theme::ROLES {
+                result.insert(role.to_string(), theme.get(role)?.spec().into());
+            }
+            return Ok(Expression::Map(result));
+        }
+        Some(Expression::String(name)) => dune::set_theme(Theme::named(&name)?),
+        // Change some of the colors of the theme named `base`, or the one in use.
+        Some(Expression::Map(styles)) => {
+            let mut theme = match styles.get("base") {
+                Some(base) => Theme::named(&base.to_string())?,
+                None => dune::theme(),
+            };
+            for (role, style) in styles.iter().filter(|(role, _)| *role != "base") {
+                theme.set(role, &style.to_string())?;
+            }
+            theme.name = String::from("custom");
+            dune::set_theme(theme);
+        }
+        Some(otherwise) => {
+            return Err(Error::CustomError(format!(
+                "expected the name of a theme, or a map of colors by role, but got {}",
+                otherwise
+            )))
+        }
+    }
+    Ok(Expression::None)
+}
+
+fn colors(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("colors", &args, 0..=1)?;
+    match args.first().map(|arg| arg.eval(env)).transpose()? {
+        None | Some(Expression::None) => return Ok(dune::color_support().name().into()),
+        Some(Expression::String(auto)) if auto == "auto" => dune::set_color_support(None),
+        Some(support) => dune::set_color_support(Some(ColorSupport::named(&support.to_string())?)),
+    }
+    Ok(Expression::None)
+}
+
+fn paint(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("paint", &args, 2)?;
+    let role = args[0].eval(env)?.to_string();
+    let text = args[1].eval(env)?.to_string();
+    Ok(dune::theme().get(&role)?.paint(text).into())
 }
 
-fn height(_: Vec<Expression>, _: &mut Environment) -> Result<Expression, Error> {
-    Ok(match terminal_size() {
-        Some((_, Height(h))) => (h as Int).into(),
+fn notify_after(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("notify-after", &args, 1..=2)?;
+    let mut settings = notify::settings();
+    settings.after = match args[0].eval(env)? {
+        Expression::None | Expression::Boolean(false) => None,
+        Expression::Integer(n) => Some(n as f64),
+        Expression::Float(n) => Some(n),
+        Expression::String(text) => Some(super::parse_module::duration_seconds(&text)?),
+        otherwise => {
+            return Err(Error::CustomError(format!(
+                "expected a number of seconds, a duration like \"30s\", or None, but got {}",
+                otherwise
+            )))
+        }
+    };
+    match args.get(1).map(|options| options.eval(env)).transpose()? {
+        Some(Expression::Map(options)) => {
+            for (name, value) in options {
+                let flag = match name.as_str() {
+                    "bell" => &mut settings.bell,
+                    "desktop" => &mut settings.desktop,
+                    _ => {
+                        return Err(Error::CustomError(format!(
+                            "unknown option `{}`, expected bell or desktop",
+                            name
+                        )))
+                    }
+                };
+                *flag = value.is_truthy();
+            }
+        }
+        Some(otherwise) => {
+            return Err(Error::CustomError(format!(
+                "expected options like {{bell=False}}, but got {}",
+                otherwise
+            )))
+        }
+        None => {}
+    }
+    notify::set_settings(settings.clone());
+
+    Ok(Expression::from(b_tree_map! {
+        String::from("after") => settings.after.map(Expression::Float).unwrap_or(Expression::None),
+        String::from("bell") => Expression::Boolean(settings.bell),
+        String::from("desktop") => Expression::Boolean(settings.desktop),
+    }))
+}
+
+fn width(_: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    Ok(match console_width(env) {
+        Some(w) => (w as Int).into(),
         _ => Expression::None,
     })
 }
 
+fn height(_: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    Ok(
+        match size_override(env, "LINES").or_else(dune::console_height) {
+            Some(h) => (h as Int).into(),
+            _ => Expression::None,
+        },
+    )
+}
+
+/// The width of the console, which a `COLUMNS` variable in the shell overrides.
+pub(super) fn console_width(env: &Environment) -> Option<usize> {
+    size_override(env, "COLUMNS").or_else(dune::console_width)
+}
+
+fn size_override(env: &Environment, name: &str) -> Option<usize> {
+    match env.get(name)? {
+        Expression::Integer(size) if size > 0 => Some(size as usize),
+        Expression::String(size) => size.trim().parse().ok().filter(|size| *size > 0),
+        _ => None,
+    }
+}
+
 fn write(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
     super::check_exact_args_len("write", &args, 3)?;
     match (args[0].eval(env)?, args[1].eval(env)?, args[2].eval(env)?) {
@@ -231,12 +375,443 @@ fn write(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Err
 
 fn title(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
     super::check_exact_args_len("title", &args, 1)?;
-    print!("\x1b]2;{}\x1b[0m", args[0].eval(env)?);
+    set_title(&args[0].eval(env)?.to_string());
     Ok(Expression::None)
 }
 
+/// Set the title of the console window or tab.
+pub fn set_title(title: &str) {
+    if stdout().is_terminal() {
+        // Control characters in the title would end the escape code early.
+        let title = title.replace(char::is_control, " ");
+        print!("\x1b]2;{}\x07", title);
+        let _ = stdout().flush();
+    }
+}
+
+/// Tell the console what the working directory is with an OSC 7 escape code,
+/// so it can open new tabs and windows in the same directory.
+pub fn report_cwd(cwd: &str) {
+    if stdout().is_terminal() {
+        print!("\x1b]7;{}\x1b\\", crate::binary::links::file_url(cwd));
+        let _ = stdout().flush();
+    }
+}
+
 fn clear(args: Vec<Expression>, _env: &mut Environment) -> Result<Expression, Error> {
     super::check_exact_args_len("clear", &args, 1)?;
     print!("\x1b[2J\x1b[H");
     Ok(Expression::None)
 }
+
+fn view(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("view", &args, 1)?;
+    let rows = match args[0].eval(env)? {
+        Expression::List(rows) => rows
+            .into_iter()
+            .map(|row| match row {
+                Expression::Map(row) => Ok(row),
+                otherwise => Err(Error::CustomError(format!(
+                    "expected a list of maps, but found {}",
+                    otherwise
+                ))),
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        otherwise => {
+            return Err(Error::CustomError(format!(
+                "expected a list of maps, but got {}",
+                otherwise
+            )))
+        }
+    };
+
+    let mut table = TableView::new(rows);
+    let picked = super::widget_module::interactive("table view", || {
+        let mut out = stdout();
+        queue!(out, EnterAlternateScreen, Hide)?;
+        let picked = table.run(&mut out);
+        queue!(out, Show, LeaveAlternateScreen)?;
+        out.flush()?;
+        picked
+    })?;
+    Ok(match picked {
+        Some(rows) => Expression::List(rows.into_iter().map(Expression::Map).collect()),
+        None => Expression::None,
+    })
+}
+
+fn fuzzy(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("fuzzy", &args, 1..=2)?;
+    let items = match args[0].eval(env)? {
+        Expression::List(items) => items,
+        otherwise => {
+            return Err(Error::CustomError(format!(
+                "expected a list to pick from, but got {}",
+                otherwise
+            )))
+        }
+    };
+    let options = match args.get(1).map(|options| options.eval(env)).transpose()? {
+        Some(Expression::Map(options)) => options,
+        Some(otherwise) => {
+            return Err(Error::CustomError(format!(
+                "expected a map of options like {{preview=fs@read}}, but got {}",
+                otherwise
+            )))
+        }
+        None => IndexMap::new(),
+    };
+    let multi = options
+        .get("multi")
+        .map(Expression::is_truthy)
+        .unwrap_or(false);
+
+    let mut finder = Finder::new(items.iter().map(ToString::to_string).collect()).multi(multi);
+    if let Some(prompt) = options.get("prompt") {
+        finder = finder.prompt(prompt);
+    }
+    if let Some(preview) = options.get("preview") {
+        finder = finder.preview(|i| {
+            Expression::Apply(
+                Box::new(preview.clone()),
+                vec![Expression::Quote(Box::new(items[i].clone()))],
+            )
+            .eval(env)
+            .map(|preview| preview.to_string())
+            .unwrap_or_else(|e| e.to_string())
+        });
+    }
+    let picked = finder
+        .run()
+        .map_err(|e| Error::CustomError(format!("could not run fuzzy finder: {}", e)))?;
+    drop(finder);
+
+    Ok(match picked {
+        Some(picked) if multi => {
+            Expression::List(picked.into_iter().map(|i| items[i].clone()).collect())
+        }
+        Some(picked) => picked
+            .first()
+            .map(|&i| items[i].clone())
+            .unwrap_or(Expression::None),
+        None => Expression::None,
+    })
+}
+
+fn inspect(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("inspect", &args, 1)?;
+    let value = args[0].eval(env)?;
+    if !stdout().is_terminal() {
+        return Err(Error::CustomError(String::from(
+            "inspect needs a console to draw the tree in",
+        )));
+    }
+    let picked = Inspector::new(&value)
+        .run()
+        .map_err(|e| Error::CustomError(format!("could not run the inspector: {}", e)))?;
+    Ok(picked.unwrap_or(Expression::None))
+}
+
+/// The most characters of a cell the table view shows.
+const MAX_CELL_WIDTH: usize = 30;
+
+/// The state of the table view: the rows, how they're sorted and filtered,
+/// and which of them are selected.
+struct TableView {
+    rows: Vec<IndexMap<String, Expression>>,
+    columns: Vec<String>,
+    widths: Vec<usize>,
+    /// The column the cursor is on, and the column the table is sorted by (and if it's reversed).
+    column: usize,
+    sort: Option<(usize, bool)>,
+    search: String,
+    searching: bool,
+    /// The cursor's position in the rows that are shown, and the first row on the screen.
+    selected: usize,
+    top: usize,
+    marked: BTreeSet<usize>,
+}
+
+impl TableView {
+    fn new(rows: Vec<IndexMap<String, Expression>>) -> Self {
+        let columns = rows
+            .iter()
+            .flat_map(|row| row.keys().cloned())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        let widths = columns
+            .iter()
+            .map(|column| {
+                rows.iter()
+                    .filter_map(|row| row.get(column))
+                    .map(|cell| cell.to_string().chars().count())
+                    .chain([column.chars().count() + 1])
+                    .max()
+                    .unwrap_or(0)
+                    .min(MAX_CELL_WIDTH)
+            })
+            .collect();
+
+        Self {
+            rows,
+            columns,
+            widths,
+            column: 0,
+            sort: None,
+            search: String::new(),
+            searching: false,
+            selected: 0,
+            top: 0,
+            marked: BTreeSet::new(),
+        }
+    }
+
+    /// The indices of the rows that match the search, in sorted order.
+    fn shown(&self) -> Vec<usize> {
+        let search = self.search.to_lowercase();
+        let mut shown = (0..self.rows.len())
+            .filter(|&i| {
+                self.rows[i]
+                    .values()
+                    .any(|cell| cell.to_string().to_lowercase().contains(&search))
+            })
+            .collect::<Vec<_>>();
+
+        if let Some((column, reversed)) = self.sort {
+            let column = &self.columns[column];
+            shown.sort_by(|&a, &b| {
+                let order = compare(self.rows[a].get(column), self.rows[b].get(column));
+                if reversed {
+                    order.reverse()
+                } else {
+                    order
+                }
+            });
+        }
+        shown
+    }
+
+    /// Explore the table until rows are picked, or it's closed.
+    fn run(
+        &mut self,
+        out: &mut impl Write,
+    ) -> std::io::Result<Option<Vec<IndexMap<String, Expression>>>> {
+        loop {
+            let shown = self.shown();
+            self.draw(out, &shown)?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                if let Some(picked) = self.handle(key, &shown) {
+                    return Ok(
+                        picked.map(|rows| rows.into_iter().map(|i| self.rows[i].clone()).collect())
+                    );
+                }
+            }
+        }
+    }
+
+    fn draw(&mut self, out: &mut impl Write, shown: &[usize]) -> std::io::Result<()> {
+        let (width, height) = terminal::size()?;
+        let (width, height) = (width as usize, height as usize);
+        // Leave room for the header and the status line.
+        let page = height.saturating_sub(2).max(1);
+
+        self.selected = self.selected.min(shown.len().saturating_sub(1));
+        if self.selected < self.top {
+            self.top = self.selected;
+        } else if self.selected >= self.top + page {
+            self.top = self.selected + 1 - page;
+        }
+
+        // Scroll sideways so the cursor's column is on the screen.
+        let mut first = 0;
+        while first < self.column
+            && self.widths[first..=self.column]
+                .iter()
+                .map(|w| w + 2)
+                .sum::<usize>()
+                + 2
+                > width
+        {
+            first += 1;
+        }
+
+        let line = |cells: &mut dyn Iterator<Item = (usize, String)>| {
+            let mut line = String::new();
+            for (column, cell) in cells {
+                let cell = cell
+                    .replace('\n', " ")
+                    .chars()
+                    .take(self.widths[column])
+                    .collect::<String>();
+                line += &format!("{:width$}  ", cell, width = self.widths[column]);
+            }
+            line
+        };
+
+        queue!(out, MoveTo(0, 0), Clear(ClearType::All))?;
+        let mut header = String::from("  ");
+        for column in first..self.columns.len() {
+            let arrow = match self.sort {
+                Some((sorted, false)) if sorted == column => "▲",
+                Some((sorted, true)) if sorted == column => "▼",
+                _ => " ",
+            };
+            let name = format!("{}{}", self.columns[column], arrow);
+            let name = line(&mut [(column, name)].into_iter());
+            header += &if column == self.column {
+                format!("\x1b[4m{}\x1b[24m", name)
+            } else {
+                name
+            };
+        }
+        queue!(
+            out,
+            Print(format!("\x1b[1m{}\x1b[0m", clip(&header, width)))
+        )?;
+
+        for (i, &row) in shown.iter().enumerate().skip(self.top).take(page) {
+            let mark = if self.marked.contains(&row) {
+                "* "
+            } else {
+                "  "
+            };
+            let text = mark.to_string()
+                + &line(&mut (first..self.columns.len()).map(|column| {
+                    let cell = self.rows[row].get(&self.columns[column]);
+                    (column, cell.map(ToString::to_string).unwrap_or_default())
+                }));
+            let text = clip(&text, width);
+            queue!(out, MoveTo(0, (i - self.top + 1) as u16))?;
+            if i == self.selected {
+                queue!(
+                    out,
+                    Print(format!("\x1b[7m{:width$}\x1b[0m", text, width = width))
+                )?;
+            } else {
+                queue!(out, Print(text))?;
+            }
+        }
+
+        let status = if self.searching || !self.search.is_empty() {
+            format!("/{}", self.search)
+        } else {
+            format!(
+                "{} of {} rows  ↑↓ move  ←→ column  s sort  / search  space mark  enter pick  q quit",
+                shown.len(),
+                self.rows.len()
+            )
+        };
+        queue!(
+            out,
+            MoveTo(0, height.saturating_sub(1) as u16),
+            Print(clip(&status, width))
+        )?;
+        out.flush()
+    }
+
+    /// Handle a key press. Returns the indices of the picked rows when the
+    /// table is closed, or `None` inside if nothing was picked.
+    fn handle(&mut self, key: KeyEvent, shown: &[usize]) -> Option<Option<Vec<usize>>> {
+        if self.searching {
+            match key.code {
+                KeyCode::Enter | KeyCode::Esc => self.searching = false,
+                KeyCode::Backspace => {
+                    self.search.pop();
+                }
+                KeyCode::Char(c) => {
+                    self.search.push(c);
+                    self.selected = 0;
+                }
+                _ => {}
+            }
+            return None;
+        }
+
+        let page = terminal::size()
+            .map(|(_, height)| height.saturating_sub(2).max(1) as usize)
+            .unwrap_or(10);
+        match (key.code, key.modifiers) {
+            (KeyCode::Char('q') | KeyCode::Esc, _)
+            | (KeyCode::Char('c'), KeyModifiers::CONTROL) => return Some(None),
+            (KeyCode::Enter, _) => {
+                return Some(if !self.marked.is_empty() {
+                    Some(self.marked.iter().copied().collect())
+                } else {
+                    shown.get(self.selected).map(|&row| vec![row])
+                })
+            }
+            (KeyCode::Char('/'), _) => self.searching = true,
+            (KeyCode::Char('s'), _) if !self.columns.is_empty() => {
+                self.sort = match self.sort {
+                    Some((column, false)) if column == self.column => Some((column, true)),
+                    Some((column, true)) if column == self.column => None,
+                    _ => Some((self.column, false)),
+                }
+            }
+            (KeyCode::Char(' '), _) => {
+                if let Some(&row) = shown.get(self.selected) {
+                    if !self.marked.remove(&row) {
+                        self.marked.insert(row);
+                    }
+                }
+                self.selected += 1;
+            }
+            (KeyCode::Left, _) => self.column = self.column.saturating_sub(1),
+            (KeyCode::Right, _) => {
+                self.column = (self.column + 1).min(self.columns.len().saturating_sub(1))
+            }
+            (KeyCode::Up, _) => self.selected = self.selected.saturating_sub(1),
+            (KeyCode::Down, _) => self.selected += 1,
+            (KeyCode::PageUp, _) => self.selected = self.selected.saturating_sub(page),
+            (KeyCode::PageDown, _) => self.selected += page,
+            (KeyCode::Home, _) => self.selected = 0,
+            (KeyCode::End, _) => self.selected = shown.len().saturating_sub(1),
+            _ => {}
+        }
+        None
+    }
+}
+
+/// Compare two cells, putting numbers in numeric order and missing cells last.
+fn compare(a: Option<&Expression>, b: Option<&Expression>) -> Ordering {
+    let number = |cell: &Expression| match cell {
+        Expression::Integer(n) => Some(*n as f64),
+        Expression::Float(n) => Some(*n),
+        _ => None,
+    };
+    match (a, b) {
+        (Some(a), Some(b)) => match (number(a), number(b)) {
+            (Some(x), Some(y)) => x.partial_cmp(&y).unwrap_or(Ordering::Equal),
+            _ => a.to_string().cmp(&b.to_string()),
+        },
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+/// Cut a line down to fit the width of the console, ignoring escape codes.
+fn clip(line: &str, width: usize) -> String {
+    let mut result = String::new();
+    let mut shown = 0;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            result.push(c);
+            for c in chars.by_ref() {
+                result.push(c);
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else if shown < width {
+            result.push(c);
+            shown += 1;
+        }
+    }
+    result
+}
@@ -0,0 +1,221 @@
+use common_macros::b_tree_map;
+use dune::{Environment, Error, Expression, Int};
+use indexmap::IndexMap;
+use json::JsonValue;
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+pub fn get() -> Expression {
+    (b_tree_map! {
+        String::from("open") => Expression::builtin("open", open,
+            "open a SQLite database, creating it if it doesn't exist, like `let conn = db@open \"data.db\"`"),
+        String::from("query") => Expression::builtin("query", query,
+            "get the rows a query returns as a list of maps, like `db@query conn \"SELECT * FROM users WHERE age > ?\" [30]`, with `?` or `:name` filled in from a list or map of parameters"),
+        String::from("exec") => Expression::builtin("exec", exec,
+            "run statements that change a database, like `db@exec conn \"INSERT INTO users VALUES (:name, :age)\" {name=\"Ada\", age=36}`, getting how many rows it `changes` and the `last-id` inserted"),
+    })
+    .into()
+}
+
+fn open(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("open", &args, 1)?;
+    let file = args[0].eval(env)?.to_string();
+    let path = PathBuf::from(env.get_cwd()).join(&file);
+    // Connections are only the path to the database, which each query opens
+    // again, so this is to find any problem with it early.
+    run(&path, "SELECT 1;", &[])?;
+    Ok(b_tree_map! {
+        String::from("path") => Expression::from(path.display().to_string()),
+    }
+    .into())
+}
+
+fn query(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("query", &args, 2..=3)?;
+    let (path, sql, params) = statement_args(&args, env)?;
+    let results = run(&path, &sql, &params)?;
+    Ok(Expression::List(results.into_iter().flatten().collect()))
+}
+
+fn exec(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("exec", &args, 2..=3)?;
+    let (path, sql, params) = statement_args(&args, env)?;
+    // An empty statement ends any unfinished one, so what was changed can be asked for after it.
+    let sql = format!(
+        "{}\n;\nSELECT changes() AS changes, last_insert_rowid() AS \"last-id\";",
+        sql
+    );
+    let changes = run(&path, &sql, &params)?
+        .pop()
+        .and_then(|mut rows| rows.pop())
+        .unwrap_or(Expression::None);
+    Ok(changes)
+}
+
+/// The parameters to bind to a statement, by their names like `?1` or `:name`.
+type Params = Vec<(String, Expression)>;
+
+/// Get the database, the SQL, and the parameters for a query or statement.
+fn statement_args(
+    args: &[Expression],
+    env: &mut Environment,
+) -> Result<(PathBuf, String, Params), Error> {
+    let path = match args[0].eval(env)? {
+        Expression::Map(conn) => match conn.get("path") {
+            Some(path) => PathBuf::from(path.to_string()),
+            None => {
+                return Err(Error::CustomError(String::from(
+                    "expected a connection from `db@open`, but got a map without a path",
+                )))
+            }
+        },
+        file => PathBuf::from(env.get_cwd()).join(file.to_string()),
+    };
+    let sql = args[1].eval(env)?.to_string();
+    let params = match args.get(2).map(|params| params.eval(env)).transpose()? {
+        // Positional parameters are numbered like `?1`, which is what a bare `?` is too.
+        Some(Expression::List(params)) => params
+            .into_iter()
+            .enumerate()
+            .map(|(i, param)| (format!("?{}", i + 1), param))
+            .collect(),
+        Some(Expression::Map(params)) => params
+            .into_iter()
+            .map(|(name, param)| match name.starts_with([':', '@', '$']) {
+                true => (name, param),
+                false => (format!(":{}", name), param),
+            })
+            .collect(),
+        Some(Expression::None) | None => vec![],
+        Some(otherwise) => {
+            return Err(Error::CustomError(format!(
+                "expected a list or map of parameters, but got {}",
+                otherwise
+            )))
+        }
+    };
+    Ok((path, sql, params))
+}
+
+/// Run SQL on a database with the `sqlite3` shell, getting the rows of each
+/// statement that returned any.
+fn run(
+    path: &Path,
+    sql: &str,
+    params: &[(String, Expression)],
+) -> Result<Vec<Vec<Expression>>, Error> {
+    // The shell binds the parameters in this table to every statement it runs.
+    let mut script = String::from(".parameter init\n");
+    for (name, value) in params {
+        script += &format!(
+            "INSERT INTO temp.sqlite_parameters(key, value) VALUES ({}, {});\n",
+            sql_literal(&Expression::from(name.as_str()))?,
+            sql_literal(value)?
+        );
+    }
+    script += sql;
+    script.push('\n');
+
+    let mut child = Command::new("sqlite3")
+        .args(["-json", "-bail"])
+        .arg(path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| {
+            Error::CustomError(format!(
+                "could not run sqlite3 to open {}, is SQLite's command line shell installed? {}",
+                path.display(),
+                e
+            ))
+        })?;
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(script.as_bytes());
+    }
+    let output = child.wait_with_output().map_err(|e| {
+        Error::CustomError(format!(
+            "could not run sqlite3 on {}: {}",
+            path.display(),
+            e
+        ))
+    })?;
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(&output.stderr);
+        // The line numbers count the lines binding the parameters, so they're left out.
+        let message = regex::Regex::new(r"(?m)^(Parse|Runtime) error near line \d+: ")
+            .unwrap()
+            .replace_all(message.trim(), "");
+        return Err(Error::CustomError(format!(
+            "could not run SQL on {}: {}",
+            path.display(),
+            message
+        )));
+    }
+
+    // Each statement's rows are an array, on lines of their own. Strings can't
+    // span lines in JSON, so one array ends where a line starts another.
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stdout = stdout.trim();
+    if stdout.is_empty() {
+        return Ok(vec![]);
+    }
+    let results = json::parse(&format!("[{}]", stdout.replace("]\n[", "],[")))
+        .map_err(|e| Error::CustomError(format!("could not read the rows from sqlite3: {}", e)))?;
+    Ok(results
+        .members()
+        .map(|rows| rows.members().map(from_json).collect())
+        .collect())
+}
+
+/// Write a value as SQL, to bind it to a parameter.
+fn sql_literal(value: &Expression) -> Result<String, Error> {
+    Ok(match value {
+        Expression::None => String::from("NULL"),
+        Expression::Boolean(b) => String::from(if *b { "1" } else { "0" }),
+        Expression::Integer(n) => n.to_string(),
+        Expression::Float(n) if n.is_finite() => format!("{:?}", n),
+        Expression::String(s) => format!("'{}'", s.replace('\'', "''")),
+        Expression::Bytes(bytes) => format!(
+            "X'{}'",
+            bytes
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<String>()
+        ),
+        otherwise => {
+            return Err(Error::CustomError(format!(
+                "cannot pass {} to SQL, only text, numbers, booleans, bytes, and None",
+                otherwise
+            )))
+        }
+    })
+}
+
+/// Read a row from the JSON the `sqlite3` shell writes. Unlike parsing JSON in
+/// general, whole numbers are integers, since columns of them are most likely
+/// INTEGER ones, and a whole REAL like `2.0` can't be told apart once it's parsed.
+fn from_json(value: &JsonValue) -> Expression {
+    match value {
+        JsonValue::Number(n) => match n.as_parts() {
+            (positive, mantissa, 0) if mantissa <= Int::MAX as u64 => {
+                let n = mantissa as Int;
+                Expression::Integer(if positive { n } else { -n })
+            }
+            _ => Expression::Float((*n).into()),
+        },
+        JsonValue::Object(row) => Expression::Map(
+            row.iter()
+                .map(|(column, value)| (column.to_string(), from_json(value)))
+                .collect::<IndexMap<_, _>>(),
+        ),
+        JsonValue::Array(items) => Expression::List(items.iter().map(from_json).collect()),
+        JsonValue::Null => Expression::None,
+        JsonValue::Boolean(b) => Expression::Boolean(*b),
+        JsonValue::Short(s) => Expression::from(s.as_str()),
+        JsonValue::String(s) => Expression::from(s.as_str()),
+    }
+}
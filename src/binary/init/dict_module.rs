@@ -78,7 +78,7 @@ pub fn get() -> Expression {
             let key = args[1].clone().eval(env)?;
             Ok(match expr {
                 Expression::Map(mut map) => {
-                    map.remove(&key.to_string());
+                    map.shift_remove(&key.to_string());
                     Expression::Map(map)
                 },
                 _ => Expression::None
@@ -112,7 +112,7 @@ pub fn get() -> Expression {
             let expr = args[0].clone().eval(env)?;
             Ok(match expr {
                 Expression::List(list) => {
-                    let mut map = std::collections::BTreeMap::new();
+                    let mut map = indexmap::IndexMap::new();
                     for item in list {
                         if let Expression::List(item) = item {
                             if item.len() == 2 {
@@ -148,7 +148,7 @@ pub fn get() -> Expression {
             let expr2 = args[1].clone().eval(env)?;
             Ok(match (expr1, expr2) {
                 (Expression::Map(map1), Expression::Map(map2)) => {
-                    let mut map = std::collections::BTreeMap::new();
+                    let mut map = indexmap::IndexMap::new();
                     for (key, value) in map1 {
                         if map2.contains_key(&key) {
                             map.insert(key, value);
@@ -168,7 +168,7 @@ pub fn get() -> Expression {
                 (Expression::Map(map1), Expression::Map(map2)) => {
                     let mut map = map1.clone();
                     for key in map2.keys() {
-                        map.remove(key);
+                        map.shift_remove(key);
                     }
                     Expression::Map(map)
                 },
@@ -0,0 +1,373 @@
+use common_macros::b_tree_map;
+use dune::{Environment, Error, Expression, Int};
+use std::path::PathBuf;
+
+/// How many unchanged lines are shown around each change.
+const CONTEXT: usize = 3;
+
+pub fn get() -> Expression {
+    (b_tree_map! {
+        String::from("lines") => Expression::builtin("lines", lines,
+            "compare two strings or lists line by line, returning the hunks that differ and a colored unified diff"),
+        String::from("files") => Expression::builtin("files", files,
+            "compare two files line by line, returning the hunks that differ and a colored unified diff"),
+    })
+    .into()
+}
+
+fn lines(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("lines", &args, 2)?;
+    let (old, new) = (args[0].eval(env)?, args[1].eval(env)?);
+    let split = |value: Expression| match value {
+        Expression::List(items) => items.iter().map(Expression::to_string).collect(),
+        value => value
+            .to_string()
+            .lines()
+            .map(String::from)
+            .collect::<Vec<_>>(),
+    };
+    Ok(compare(&split(old), &split(new), "a", "b"))
+}
+
+fn files(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("files", &args, 2)?;
+    let cwd = PathBuf::from(env.get_cwd());
+    let mut read = |arg: &Expression| -> Result<(String, Vec<String>), Error> {
+        let name = arg.eval(env)?.to_string();
+        match std::fs::read_to_string(cwd.join(&name)) {
+            Ok(text) => Ok((name, text.lines().map(String::from).collect())),
+            Err(e) => Err(Error::CustomError(format!(
+                "could not read file {}: {}",
+                name, e
+            ))),
+        }
+    };
+    let (old_name, old) = read(&args[0])?;
+    let (new_name, new) = read(&args[1])?;
+    Ok(compare(&old, &new, &old_name, &new_name))
+}
+
+/// A colored unified diff of two lists of lines, which is empty when they're the same.
+pub(super) fn unified(old: &[String], new: &[String], old_name: &str, new_name: &str) -> String {
+    render(&hunks(old, new), old_name, new_name)
+}
+
+/// Compare two lists of lines, returning the hunks that differ and a colored unified diff.
+fn compare(old: &[String], new: &[String], old_name: &str, new_name: &str) -> Expression {
+    let hunks = hunks(old, new);
+    b_tree_map! {
+        String::from("same") => Expression::Boolean(hunks.is_empty()),
+        String::from("unified") => Expression::from(render(&hunks, old_name, new_name)),
+        String::from("hunks") => Expression::List(
+            hunks
+                .iter()
+                .map(|hunk| {
+                    b_tree_map! {
+                        String::from("old-start") => Expression::Integer(hunk.old_start as Int),
+                        String::from("old-lines") => Expression::Integer(hunk.old_lines as Int),
+                        String::from("new-start") => Expression::Integer(hunk.new_start as Int),
+                        String::from("new-lines") => Expression::Integer(hunk.new_lines as Int),
+                        String::from("lines") => Expression::List(
+                            hunk.lines
+                                .iter()
+                                .map(|(change, text)| {
+                                    b_tree_map! {
+                                        String::from("kind") => Expression::from(match change {
+                                            Change::Keep => "context",
+                                            Change::Insert => "add",
+                                            Change::Delete => "remove",
+                                        }),
+                                        String::from("text") => Expression::from(text.clone()),
+                                    }
+                                    .into()
+                                })
+                                .collect(),
+                        ),
+                    }
+                    .into()
+                })
+                .collect(),
+        ),
+    }
+    .into()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Change {
+    Keep,
+    Insert,
+    Delete,
+}
+
+/// A run of changes, along with the unchanged lines around them. Lines count from 1.
+struct Hunk {
+    old_start: usize,
+    old_lines: usize,
+    new_start: usize,
+    new_lines: usize,
+    lines: Vec<(Change, String)>,
+}
+
+fn render(hunks: &[Hunk], old_name: &str, new_name: &str) -> String {
+    if hunks.is_empty() {
+        return String::new();
+    }
+    let theme = dune::theme();
+    let mut text = format!("\x1b[1m--- {}\n+++ {}\x1b[m\x1b[0m\n", old_name, new_name);
+    for hunk in hunks {
+        text += &theme.diff_hunk.paint(format!(
+            "@@ -{},{} +{},{} @@",
+            hunk.old_start, hunk.old_lines, hunk.new_start, hunk.new_lines
+        ));
+        text.push('\n');
+        for (change, line) in &hunk.lines {
+            text += &match change {
+                Change::Keep => format!(" {}", line),
+                Change::Delete => theme.diff_remove.paint(format!("-{}", line)),
+                Change::Insert => theme.diff_add.paint(format!("+{}", line)),
+            };
+            text.push('\n');
+        }
+    }
+    text
+}
+
+/// Group the changes between two lists of lines into hunks.
+fn hunks(old: &[String], new: &[String]) -> Vec<Hunk> {
+    let changes = changes(old, new);
+    let mut hunks: Vec<Hunk> = vec![];
+    // Where each change is in the old and new lines.
+    let mut positions = vec![];
+    let (mut i, mut j) = (0, 0);
+    for change in &changes {
+        positions.push((i, j));
+        match change {
+            Change::Keep => {
+                i += 1;
+                j += 1;
+            }
+            Change::Delete => i += 1,
+            Change::Insert => j += 1,
+        }
+    }
+
+    let mut n = 0;
+    while n < changes.len() {
+        if changes[n] == Change::Keep {
+            n += 1;
+            continue;
+        }
+        // Start a hunk a few lines before this change, and end it once there's
+        // more unchanged lines after the last change than two hunks would show.
+        let start = n.saturating_sub(CONTEXT);
+        let mut end = n;
+        let mut unchanged = 0;
+        while end < changes.len() && unchanged <= 2 * CONTEXT {
+            if changes[end] == Change::Keep {
+                unchanged += 1;
+            } else {
+                unchanged = 0;
+            }
+            end += 1;
+        }
+        let end = end - unchanged.saturating_sub(CONTEXT).min(unchanged);
+
+        let lines = changes[start..end]
+            .iter()
+            .zip(&positions[start..end])
+            .map(|(change, (i, j))| {
+                let line = match change {
+                    Change::Insert => &new[*j],
+                    _ => &old[*i],
+                };
+                (*change, line.clone())
+            })
+            .collect::<Vec<_>>();
+        let old_lines = lines.iter().filter(|(c, _)| *c != Change::Insert).count();
+        let new_lines = lines.iter().filter(|(c, _)| *c != Change::Delete).count();
+        let (i, j) = positions[start];
+        hunks.push(Hunk {
+            // Like `diff -u`, an empty side of a hunk starts at the line before it.
+            old_start: if old_lines == 0 { i } else { i + 1 },
+            old_lines,
+            new_start: if new_lines == 0 { j } else { j + 1 },
+            new_lines,
+            lines,
+        });
+        n = end;
+    }
+    hunks
+}
+
+/// Find the shortest list of changes that turns the old lines into the new
+/// lines, using Myers' algorithm. Rather than keeping every step of the search
+/// to walk back through, which takes memory for each edit times the lines, it
+/// finds the middle of the path and then each half of it the same way.
+fn changes(old: &[String], new: &[String]) -> Vec<Change> {
+    let mut changes = Vec::with_capacity(old.len() + new.len());
+    add_changes(old, new, &mut changes);
+    changes
+}
+
+fn add_changes(old: &[String], new: &[String], changes: &mut Vec<Change>) {
+    // Lines at the start and end that are the same are kept as they are.
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let (old, new) = (&old[prefix..], &new[prefix..]);
+    let suffix = old
+        .iter()
+        .rev()
+        .zip(new.iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (old, new) = (&old[..old.len() - suffix], &new[..new.len() - suffix]);
+
+    changes.resize(changes.len() + prefix, Change::Keep);
+    if old.is_empty() {
+        changes.resize(changes.len() + new.len(), Change::Insert);
+    } else if new.is_empty() {
+        changes.resize(changes.len() + old.len(), Change::Delete);
+    } else {
+        // Without the same first or last lines, there are at least two edits,
+        // so both halves around the middle have fewer than the whole.
+        let ((x, y), (u, v)) = middle_snake(old, new);
+        add_changes(&old[..x], &new[..y], changes);
+        changes.resize(changes.len() + u - x, Change::Keep);
+        add_changes(&old[u..], &new[v..], changes);
+    }
+    changes.resize(changes.len() + suffix, Change::Keep);
+}
+
+/// Find the unchanged lines in the middle of a shortest path of changes, by
+/// searching from both ends at once until the searches meet. Gives where they
+/// start and end in the old and new lines.
+fn middle_snake(old: &[String], new: &[String]) -> ((usize, usize), (usize, usize)) {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let delta = n - m;
+    let max = (n + m + 1) / 2 + 1;
+    let index = |k: isize| (k + max) as usize;
+
+    // The furthest x reached on each diagonal k = x - y, from the start, and
+    // from the end with both lists reversed.
+    let mut forward = vec![0isize; 2 * max as usize + 1];
+    let mut backward = vec![0isize; 2 * max as usize + 1];
+    for d in 0..max {
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && forward[index(k - 1)] < forward[index(k + 1)]) {
+                forward[index(k + 1)]
+            } else {
+                forward[index(k - 1)] + 1
+            };
+            let (start_x, start_y) = (x, x - k);
+            let mut y = start_y;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            forward[index(k)] = x;
+            // The reverse search is on diagonal `delta - k` of the reversed lists.
+            if delta % 2 != 0 && (delta - k).abs() < d && x + backward[index(delta - k)] >= n {
+                return (
+                    (start_x as usize, start_y as usize),
+                    (x as usize, y as usize),
+                );
+            }
+        }
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && backward[index(k - 1)] < backward[index(k + 1)]) {
+                backward[index(k + 1)]
+            } else {
+                backward[index(k - 1)] + 1
+            };
+            let (start_x, start_y) = (x, x - k);
+            let mut y = start_y;
+            while x < n && y < m && old[(n - x - 1) as usize] == new[(m - y - 1) as usize] {
+                x += 1;
+                y += 1;
+            }
+            backward[index(k)] = x;
+            if delta % 2 == 0 && (delta - k).abs() <= d && x + forward[index(delta - k)] >= n {
+                return (
+                    ((n - x) as usize, (m - y) as usize),
+                    ((n - start_x) as usize, (m - start_y) as usize),
+                );
+            }
+        }
+    }
+    unreachable!("the searches from each end always meet in the middle")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Apply the changes to the old lines, and count the edits.
+    fn apply(old: &[String], new: &[String], changes: &[Change]) -> (Vec<String>, usize) {
+        let (mut i, mut j, mut edits, mut result) = (0, 0, 0, vec![]);
+        for change in changes {
+            match change {
+                Change::Keep => {
+                    assert_eq!(old[i], new[j]);
+                    result.push(old[i].clone());
+                    i += 1;
+                    j += 1;
+                }
+                Change::Delete => {
+                    i += 1;
+                    edits += 1;
+                }
+                Change::Insert => {
+                    result.push(new[j].clone());
+                    j += 1;
+                    edits += 1;
+                }
+            }
+        }
+        assert_eq!((i, j), (old.len(), new.len()));
+        (result, edits)
+    }
+
+    /// The fewest edits between two lists, from their longest common subsequence.
+    fn fewest_edits(old: &[String], new: &[String]) -> usize {
+        let mut lcs = vec![vec![0; new.len() + 1]; old.len() + 1];
+        for i in (0..old.len()).rev() {
+            for j in (0..new.len()).rev() {
+                lcs[i][j] = if old[i] == new[j] {
+                    lcs[i + 1][j + 1] + 1
+                } else {
+                    lcs[i + 1][j].max(lcs[i][j + 1])
+                };
+            }
+        }
+        old.len() + new.len() - 2 * lcs[0][0]
+    }
+
+    #[test]
+    fn changes_are_shortest() {
+        // A small generator, so the same lists are compared every time.
+        let mut seed = 7u64;
+        let mut lines = |len: u64| {
+            (0..len)
+                .map(|_| {
+                    seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+                    ((seed >> 33) % 4).to_string()
+                })
+                .collect::<Vec<_>>()
+        };
+        for n in 0..200 {
+            let (old, new) = (lines(n % 13), lines(n % 8 + 2));
+            let (result, edits) = apply(&old, &new, &changes(&old, &new));
+            assert_eq!(result, new);
+            assert_eq!(edits, fewest_edits(&old, &new), "{:?} -> {:?}", old, new);
+        }
+    }
+
+    #[test]
+    fn changes_between_long_lists() {
+        let old = (0..3000).map(|n| n.to_string()).collect::<Vec<_>>();
+        let new = (0..3000).map(|n| (-n).to_string()).collect::<Vec<_>>();
+        let (result, edits) = apply(&old, &new, &changes(&old, &new));
+        assert_eq!(result, new);
+        // Only "0" is in both.
+        assert_eq!(edits, 5998);
+    }
+}
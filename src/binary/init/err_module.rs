@@ -1,10 +1,18 @@
 use common_macros::b_tree_map;
 use dune::{Environment, Error, Expression, Int};
+use rand::Rng;
+use std::thread;
 
 pub fn get() -> Expression {
     (b_tree_map! {
         String::from("try") => Expression::builtin("try", try_builtin,
             "try an expression or apply an error handler to an error"),
+        String::from("retry") => Expression::builtin("retry", retry,
+            "retry a command or function until it succeeds, waiting longer after each failure, like `retry {times=5, backoff=2.0} { curl $url }`"),
+        String::from("assert") => Expression::builtin("assert", assert,
+            "fail with an error unless a condition is true, like `assert (len files > 0) \"no files found\"`"),
+        String::from("assert-eq") => Expression::builtin("assert-eq", assert_eq,
+            "fail with an error showing both values, and how they differ, unless they're equal, like `assert-eq (sort xs) [1, 2, 3]`"),
         String::from("codes") => Error::codes()
     })
     .into()
@@ -20,12 +28,14 @@ fn try_builtin(args: Vec<Expression>, env: &mut Environment) -> Result<Expressio
     }
 
     match args[0].eval(env) {
+        // Ctrl-C stops the whole evaluation, so it isn't handled here.
+        Err(Error::Interrupted) => Err(Error::Interrupted),
         Err(err) => {
             let handler = args[1].clone();
 
             Expression::Apply(
                 Box::new(handler),
-                vec![Expression::Map(b_tree_map! {
+                vec![Expression::from(b_tree_map! {
                     String::from("message") => Expression::String(err.to_string()),
                     String::from("code") => Expression::Integer(Int::from(err.code())),
                     String::from("expression") => Expression::Quote(Box::new(args[0].clone()))
@@ -36,3 +46,169 @@ fn try_builtin(args: Vec<Expression>, env: &mut Environment) -> Result<Expressio
         result => result,
     }
 }
+
+fn retry(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("retry", &args, 1..=2)?;
+    let options = match args.len() {
+        2 => match args[0].eval(env)? {
+            Expression::Map(options) => options,
+            otherwise => {
+                return Err(Error::CustomError(format!(
+                    "expected a map of options like {{times=5, backoff=2.0}}, but got {}",
+                    otherwise
+                )))
+            }
+        },
+        _ => Default::default(),
+    };
+    let number = |name: &str, default: f64| match options.get(name) {
+        None => Ok(default),
+        Some(Expression::Integer(n)) => Ok(*n as f64),
+        Some(Expression::Float(n)) => Ok(*n),
+        Some(Expression::String(text)) => super::parse_module::duration_seconds(text),
+        Some(otherwise) => Err(Error::CustomError(format!(
+            "expected a number for the `{}` option of retry, but got {}",
+            name, otherwise
+        ))),
+    };
+    let times = number("times", 5.0)?.max(1.0) as Int;
+    let backoff = number("backoff", 2.0)?;
+    let mut delay = number("delay", 1.0)?;
+    let jitter = options
+        .get("jitter")
+        .map(Expression::is_truthy)
+        .unwrap_or(true);
+
+    // A program exiting unsuccessfully has to be an error, or there's nothing to retry.
+    let options = env.options().clone();
+    env.options_mut().errexit = true;
+    let mut attempt = 1;
+    let result = loop {
+        let result = match args[args.len() - 1].eval(env) {
+            Ok(f @ (Expression::Lambda(..) | Expression::Builtin(..) | Expression::Macro(..))) => {
+                Expression::Apply(Box::new(f), vec![Expression::Integer(attempt)]).eval(env)
+            }
+            Ok(Expression::Symbol(name)) => {
+                Expression::Apply(Box::new(Expression::Symbol(name)), vec![]).eval(env)
+            }
+            result => result,
+        };
+        match result {
+            Err(e) if attempt < times => {
+                // Wait somewhere between half and all of the delay, so that
+                // many retries of the same thing don't all happen at once.
+                let wait = if jitter {
+                    delay * rand::thread_rng().gen_range(0.5..=1.0)
+                } else {
+                    delay
+                };
+                eprintln!(
+                    "attempt {} of {} failed: {}\nretrying in {:.1}s...",
+                    attempt, times, e, wait
+                );
+                match super::parse_module::duration(wait.max(0.0)) {
+                    Ok(wait) => thread::sleep(wait),
+                    Err(e) => break Err(e),
+                }
+                delay *= backoff;
+                attempt += 1;
+            }
+            result => break result,
+        }
+    };
+    env.set_options(options);
+    result
+}
+
+fn assert(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("assert", &args, 1..=2)?;
+    if args[0].eval(env)?.is_truthy() {
+        return Ok(Expression::None);
+    }
+    let mut message = format!("assertion failed: `{}`{}", code(&args[0]), location());
+    if let Some(note) = args.get(1) {
+        message += &format!("\n{}", note.eval(env)?);
+    }
+    Err(Error::CustomError(message))
+}
+
+fn assert_eq(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("assert-eq", &args, 2..=3)?;
+    let (left, right) = (args[0].eval(env)?, args[1].eval(env)?);
+    if left.try_equals(&right, env)? {
+        return Ok(Expression::None);
+    }
+    let mut message = format!(
+        "assertion failed: `{}` == `{}`{}",
+        code(&args[0]),
+        code(&args[1]),
+        location()
+    );
+    if let Some(note) = args.get(2) {
+        message += &format!("\n{}", note.eval(env)?);
+    }
+    message += &format!("\n  left: {}\n right: {}", show(&left), show(&right));
+    if let (Expression::List(_) | Expression::Map(_), Expression::List(_) | Expression::Map(_)) =
+        (&left, &right)
+    {
+        let diff = super::diff_module::unified(&pretty(&left), &pretty(&right), "left", "right");
+        message += &format!("\n{}", diff.trim_end());
+    }
+    Err(Error::CustomError(message))
+}
+
+/// The code of an assertion, without the parentheses around it.
+fn code(expr: &Expression) -> String {
+    match expr {
+        Expression::Group(inner) => code(inner),
+        expr => format!("{:?}", expr),
+    }
+}
+
+/// Where an assertion is, for its failure message.
+fn location() -> String {
+    match crate::binary::scripts::current() {
+        Some(script) => format!(" in {}", script.display()),
+        None => String::new(),
+    }
+}
+
+/// Show a value the way it's written, if it can be.
+fn show(value: &Expression) -> String {
+    value.repr().unwrap_or_else(|_| format!("{:?}", value))
+}
+
+/// Write a value with every item of its lists and maps on its own line, so
+/// their differences can be shown line by line.
+fn pretty(value: &Expression) -> Vec<String> {
+    let (open, close, items) = match value {
+        Expression::List(items) => (
+            "[",
+            "]",
+            items
+                .iter()
+                .map(|item| (String::new(), item))
+                .collect::<Vec<_>>(),
+        ),
+        Expression::Map(items) => (
+            "{",
+            "}",
+            items
+                .iter()
+                .map(|(key, item)| (format!("{}=", key), item))
+                .collect(),
+        ),
+        value => return vec![show(value)],
+    };
+    let mut lines = vec![open.to_string()];
+    for (key, item) in items {
+        let mut item = pretty(item);
+        item[0] = key + &item[0];
+        if let Some(last) = item.last_mut() {
+            last.push(',');
+        }
+        lines.extend(item.into_iter().map(|line| format!("    {}", line)));
+    }
+    lines.push(close.to_string());
+    lines
+}
@@ -1,5 +1,5 @@
 use common_macros::b_tree_map;
-use dune::{Environment, Error, Expression};
+use dune::{DisplayLimits, Environment, Error, Expression, Int, Style};
 
 pub fn get() -> Expression {
     (b_tree_map! {
@@ -14,103 +14,89 @@ pub fn get() -> Expression {
         String::from("href") => Expression::builtin("href", href,
             "create a hyperlink on the console"),
 
-        String::from("bold") => Expression::builtin("bold", |args, env| {
-            Ok(format!("\x1b[1m{}\x1b[m\x1b[0m", args[0].eval(env)?).into())
-        }, "convert text to bold on the console"),
+        String::from("tree") => Expression::builtin("tree", tree,
+            "draw nested maps and lists as an indented tree instead of nested tables, up to a depth if one is given, like `fmt@tree (fs@read \"config.json\") 2`"),
 
-        String::from("faint") => Expression::builtin("faint", |args, env| {
-            Ok(format!("\x1b[2m{}\x1b[m\x1b[0m", args[0].eval(env)?).into())
-        }, "convert text to italics on the console"),
+        String::from("show") => Expression::builtin("show", show,
+            "draw a value like the REPL prints it, overriding how deep it goes, how many items of each list and map it shows, and how long strings get, like `fmt@show value {depth=3, items=20, string=80}`"),
 
-        String::from("italics") => Expression::builtin("italics", |args, env| {
-            Ok(format!("\x1b[3m{}\x1b[m\x1b[0m", args[0].eval(env)?).into())
-        }, "convert text to italics on the console"),
+        String::from("limits") => Expression::builtin("limits", limits,
+            "get how much of each value the REPL prints, or limit it with a map like `fmt@limits {depth=4, items=50, string=200}`, where None shows everything"),
 
-        String::from("underline") => Expression::builtin("underline", |args, env| {
-            Ok(format!("\x1b[4m{}\x1b[m\x1b[0m", args[0].eval(env)?).into())
-        }, "underline text on the console"),
+        String::from("duration") => Expression::builtin("duration", duration,
+            "format a number of seconds like \"1h30m\""),
 
-        String::from("blink") => Expression::builtin("blink", |args, env| {
-            Ok(format!("\x1b[5m{}\x1b[m\x1b[0m", args[0].eval(env)?).into())
-        }, "blink text on the console"),
+        String::from("size") => Expression::builtin("size", size,
+            "format a number of bytes like \"2.5GiB\""),
 
-        String::from("invert") => Expression::builtin("invert", |args, env| {
-            Ok(format!("\x1b[7m{}\x1b[m\x1b[0m", args[0].eval(env)?).into())
-        }, "invert text on the console"),
+        String::from("bytes") => Expression::builtin("bytes", bytes,
+            "format a number of bytes like \"1.5 MB\""),
 
-        String::from("strike") => Expression::builtin("strike", |args, env| {
-            Ok(format!("\x1b[9m{}\x1b[m\x1b[0m", args[0].eval(env)?).into())
-        }, "strike out text on the console"),
+        String::from("number") => Expression::builtin("number", number,
+            "format a number with thousands separators like \"1,234,567\""),
 
-        String::from("black") => Expression::builtin("black", |args, env| {
-            Ok(format!("\x1b[90m{}\x1b[m\x1b[0m", args[0].eval(env)?).into())
-        }, "convert text to black on the console"),
+        String::from("ago") => Expression::builtin("ago", ago,
+            "format a unix timestamp relative to now, like \"3 minutes ago\""),
 
-        String::from("red") => Expression::builtin("red", |args, env| {
-            Ok(format!("\x1b[91m{}\x1b[m\x1b[0m", args[0].eval(env)?).into())
-        }, "convert text to red on the console"),
+        String::from("bold") => Expression::builtin("bold", |args, env| paint("bold", args, env), "convert text to bold on the console"),
 
-        String::from("green") => Expression::builtin("green", |args, env| {
-            Ok(format!("\x1b[92m{}\x1b[m\x1b[0m", args[0].eval(env)?).into())
-        }, "convert text to green on the console"),
+        String::from("faint") => Expression::builtin("faint", |args, env| paint("dim", args, env), "convert text to faint on the console"),
 
-        String::from("yellow") => Expression::builtin("yellow", |args, env| {
-            Ok(format!("\x1b[93m{}\x1b[m\x1b[0m", args[0].eval(env)?).into())
-        }, "convert text to yellow on the console"),
+        String::from("italics") => Expression::builtin("italics", |args, env| paint("italic", args, env), "convert text to italics on the console"),
 
-        String::from("blue") => Expression::builtin("blue", |args, env| {
-            Ok(format!("\x1b[94m{}\x1b[m\x1b[0m", args[0].eval(env)?).into())
-        }, "convert text to blue on the console"),
+        String::from("underline") => Expression::builtin("underline", |args, env| paint("underline", args, env), "underline text on the console"),
 
-        String::from("magenta") => Expression::builtin("magenta", |args, env| {
-            Ok(format!("\x1b[95m{}\x1b[m\x1b[0m", args[0].eval(env)?).into())
-        }, "convert text to magenta on the console"),
+        String::from("blink") => Expression::builtin("blink", |args, env| paint("blink", args, env), "blink text on the console"),
 
-        String::from("cyan") => Expression::builtin("cyan", |args, env| {
-            Ok(format!("\x1b[96m{}\x1b[m\x1b[0m", args[0].eval(env)?).into())
-        }, "convert text to cyan on the console"),
+        String::from("invert") => Expression::builtin("invert", |args, env| paint("reverse", args, env), "invert text on the console"),
 
-        String::from("white") => Expression::builtin("white", |args, env| {
-            Ok(format!("\x1b[97m{}\x1b[m\x1b[0m", args[0].eval(env)?).into())
-        }, "convert text to white on the console"),
+        String::from("strike") => Expression::builtin("strike", |args, env| paint("strikethrough", args, env), "strike out text on the console"),
+
+        String::from("black") => Expression::builtin("black", |args, env| paint("bright-black", args, env), "convert text to black on the console"),
+
+        String::from("red") => Expression::builtin("red", |args, env| paint("bright-red", args, env), "convert text to red on the console"),
+
+        String::from("green") => Expression::builtin("green", |args, env| paint("bright-green", args, env), "convert text to green on the console"),
+
+        String::from("yellow") => Expression::builtin("yellow", |args, env| paint("bright-yellow", args, env), "convert text to yellow on the console"),
+
+        String::from("blue") => Expression::builtin("blue", |args, env| paint("bright-blue", args, env), "convert text to blue on the console"),
+
+        String::from("magenta") => Expression::builtin("magenta", |args, env| paint("bright-magenta", args, env), "convert text to magenta on the console"),
+
+        String::from("cyan") => Expression::builtin("cyan", |args, env| paint("bright-cyan", args, env), "convert text to cyan on the console"),
+
+        String::from("white") => Expression::builtin("white", |args, env| paint("bright-white", args, env), "convert text to white on the console"),
 
         String::from("dark") => b_tree_map! {
-            String::from("black") => Expression::builtin("black", |args, env| {
-                Ok(format!("\x1b[30m{}\x1b[m\x1b[0m", args[0].eval(env)?).into())
-            }, "convert text to black on the console"),
+            String::from("black") => Expression::builtin("black", |args, env| paint("black", args, env), "convert text to black on the console"),
 
-            String::from("red") => Expression::builtin("red", |args, env| {
-                Ok(format!("\x1b[31m{}\x1b[m\x1b[0m", args[0].eval(env)?).into())
-            }, "convert text to red on the console"),
+            String::from("red") => Expression::builtin("red", |args, env| paint("red", args, env), "convert text to red on the console"),
 
-            String::from("green") => Expression::builtin("green", |args, env| {
-                Ok(format!("\x1b[32m{}\x1b[m\x1b[0m", args[0].eval(env)?).into())
-            }, "convert text to green on the console"),
+            String::from("green") => Expression::builtin("green", |args, env| paint("green", args, env), "convert text to green on the console"),
 
-            String::from("yellow") => Expression::builtin("yellow", |args, env| {
-                Ok(format!("\x1b[33m{}\x1b[m\x1b[0m", args[0].eval(env)?).into())
-            }, "convert text to yellow on the console"),
+            String::from("yellow") => Expression::builtin("yellow", |args, env| paint("yellow", args, env), "convert text to yellow on the console"),
 
-            String::from("blue") => Expression::builtin("blue", |args, env| {
-                Ok(format!("\x1b[34m{}\x1b[m\x1b[0m", args[0].eval(env)?).into())
-            }, "convert text to blue on the console"),
+            String::from("blue") => Expression::builtin("blue", |args, env| paint("blue", args, env), "convert text to blue on the console"),
 
-            String::from("magenta") => Expression::builtin("magenta", |args, env| {
-                Ok(format!("\x1b[35m{}\x1b[m\x1b[0m", args[0].eval(env)?).into())
-            }, "convert text to magenta on the console"),
+            String::from("magenta") => Expression::builtin("magenta", |args, env| paint("magenta", args, env), "convert text to magenta on the console"),
 
-            String::from("cyan") => Expression::builtin("cyan", |args, env| {
-                Ok(format!("\x1b[36m{}\x1b[m\x1b[0m", args[0].eval(env)?).into())
-            }, "convert text to cyan on the console"),
+            String::from("cyan") => Expression::builtin("cyan", |args, env| paint("cyan", args, env), "convert text to cyan on the console"),
 
-            String::from("white") => Expression::builtin("white", |args, env| {
-                Ok(format!("\x1b[37m{}\x1b[m\x6b[0m", args[0].eval(env)?).into())
-            }, "convert text to white on the console"),
+            String::from("white") => Expression::builtin("white", |args, env| paint("white", args, env), "convert text to white on the console"),
         }.into()
     })
     .into()
 }
 
+/// Draw text in a style, with the colors the console has.
+fn paint(spec: &str, args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len(spec, &args, 1)?;
+    Ok(Style::parse(spec)?
+        .paint(args[0].eval(env)?.to_string())
+        .into())
+}
+
 fn wrap(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
     super::check_exact_args_len("wrap", &args, 2)?;
     match args[1].eval(env)? {
@@ -133,3 +119,294 @@ fn href(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Erro
     )
     .into())
 }
+
+/// Override display limits with the ones in a map like `{depth=3}`.
+fn override_limits(mut limits: DisplayLimits, options: Expression) -> Result<DisplayLimits, Error> {
+    let options = match options {
+        Expression::Map(options) => options,
+        otherwise => {
+            return Err(Error::CustomError(format!(
+                "expected a map of limits like {{depth=3, items=20, string=80}}, but got {}",
+                otherwise
+            )))
+        }
+    };
+    for (name, value) in options {
+        let limit = match value {
+            Expression::None => None,
+            Expression::Integer(n) if n > 0 => Some(n as usize),
+            otherwise => {
+                return Err(Error::CustomError(format!(
+                    "expected the {} limit to be a number above zero or None, but got {}",
+                    name, otherwise
+                )))
+            }
+        };
+        match name.as_str() {
+            "depth" => limits.depth = limit,
+            "items" => limits.items = limit,
+            "string" => limits.string = limit,
+            _ => {
+                return Err(Error::CustomError(format!(
+                    "unknown limit `{}`, expected depth, items, or string",
+                    name
+                )))
+            }
+        }
+    }
+    Ok(limits)
+}
+
+fn show(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("show", &args, 1..=2)?;
+    let value = args[0].eval(env)?;
+    let limits = match args.get(1) {
+        Some(options) => override_limits(dune::display_limits(), options.eval(env)?)?,
+        None => dune::display_limits(),
+    };
+    Ok(super::show(&value, &limits, env).into())
+}
+
+fn limits(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("limits", &args, 0..=1)?;
+    let mut limits = dune::display_limits();
+    match args.first().map(|arg| arg.eval(env)).transpose()? {
+        None | Some(Expression::None) => {}
+        Some(options) => {
+            limits = override_limits(limits, options)?;
+            dune::set_display_limits(limits);
+        }
+    }
+    let limit =
+        |limit: Option<usize>| limit.map_or(Expression::None, |n| Expression::Integer(n as Int));
+    Ok(Expression::from(b_tree_map! {
+        String::from("depth") => limit(limits.depth),
+        String::from("items") => limit(limits.items),
+        String::from("string") => limit(limits.string),
+    }))
+}
+
+fn tree(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("tree", &args, 1..=2)?;
+    let value = args[0].eval(env)?;
+    let depth = match args.get(1).map(|arg| arg.eval(env)).transpose()? {
+        None => None,
+        Some(Expression::Integer(depth)) if depth >= 0 => Some(depth as usize),
+        Some(otherwise) => {
+            return Err(Error::CustomError(format!(
+                "expected a depth of zero or more in tree, but got {}",
+                otherwise
+            )))
+        }
+    };
+
+    let mut lines = vec![];
+    match branches(&value) {
+        Some(_) if depth == Some(0) => lines.push(collapsed(&value)),
+        Some(members) => tree_lines(&members, "", depth, &mut lines),
+        None => lines.push(format!("{:?}", value)),
+    }
+    Ok(lines.join("\n").into())
+}
+
+/// The labeled members of a map or the items of a list, and none for anything else.
+fn branches(value: &Expression) -> Option<Vec<(String, &Expression)>> {
+    match value {
+        Expression::Map(map) => Some(map.iter().map(|(k, v)| (k.clone(), v)).collect()),
+        Expression::List(list) => Some(
+            list.iter()
+                .enumerate()
+                .map(|(i, v)| (i.to_string(), v))
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+/// A map or list past the depth of a tree, with how much is left out.
+pub(crate) fn collapsed(value: &Expression) -> String {
+    let plural = |n: usize, name: &str| format!("{} {}{}", n, name, if n == 1 { "" } else { "s" });
+    match value {
+        Expression::Map(map) if map.is_empty() => String::from("{}"),
+        Expression::List(list) if list.is_empty() => String::from("[]"),
+        Expression::Map(map) => format!("{{… {}}}", plural(map.len(), "key")),
+        Expression::List(list) => format!("[… {}]", plural(list.len(), "item")),
+        otherwise => format!("{:?}", otherwise),
+    }
+}
+
+/// Draw the branches of a tree under a prefix of guide lines, with the levels
+/// left to draw before maps and lists are collapsed.
+fn tree_lines(
+    members: &[(String, &Expression)],
+    prefix: &str,
+    levels: Option<usize>,
+    lines: &mut Vec<String>,
+) {
+    for (i, (label, value)) in members.iter().enumerate() {
+        let (branch, guide) = if i + 1 == members.len() {
+            ("└── ", "    ")
+        } else {
+            ("├── ", "│   ")
+        };
+        match branches(value) {
+            Some(inner) if !inner.is_empty() && levels != Some(1) => {
+                lines.push(format!("{}{}{}", prefix, branch, label));
+                let prefix = format!("{}{}", prefix, guide);
+                tree_lines(&inner, &prefix, levels.map(|n| n - 1), lines);
+            }
+            Some(_) => lines.push(format!(
+                "{}{}{}: {}",
+                prefix,
+                branch,
+                label,
+                collapsed(value)
+            )),
+            None => lines.push(format!("{}{}{}: {:?}", prefix, branch, label, value)),
+        }
+    }
+}
+
+/// Get the number a formatting function was called with.
+fn number_arg(name: &str, args: &[Expression], env: &mut Environment) -> Result<f64, Error> {
+    super::check_exact_args_len(name, args, 1)?;
+    match args[0].eval(env)? {
+        Expression::Integer(n) => Ok(n as f64),
+        Expression::Float(n) => Ok(n),
+        otherwise => Err(Error::CustomError(format!(
+            "expected integer or float in {}, but got {}",
+            name, otherwise
+        ))),
+    }
+}
+
+/// Format a number with at most one decimal place, dropping it if it's zero.
+pub(super) fn one_decimal(n: f64) -> String {
+    let text = format!("{:.1}", n);
+    text.strip_suffix(".0").map(String::from).unwrap_or(text)
+}
+
+fn duration(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    Ok(format_duration(number_arg("duration", &args, env)?).into())
+}
+
+/// Format a number of seconds like `1h30m`, or `250ms` under a second.
+pub(crate) fn format_duration(seconds: f64) -> String {
+    let sign = if seconds < 0.0 { "-" } else { "" };
+    let seconds = seconds.abs();
+
+    if seconds < 1.0 {
+        return format!("{}{}ms", sign, (seconds * 1000.0).round());
+    }
+
+    let mut rest = seconds.round() as u64;
+    let mut result = String::from(sign);
+    for (unit, len) in [("d", 86400), ("h", 3600), ("m", 60), ("s", 1)] {
+        if rest >= len {
+            result += &format!("{}{}", rest / len, unit);
+            rest %= len;
+        }
+    }
+    result
+}
+
+fn size(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    Ok(format_size(number_arg("size", &args, env)?).into())
+}
+
+/// Format a number of bytes with binary units, like `1.5KiB`.
+pub(crate) fn format_size(mut bytes: f64) -> String {
+    let sign = if bytes < 0.0 { "-" } else { "" };
+    bytes = bytes.abs();
+
+    for unit in ["B", "KiB", "MiB", "GiB", "TiB", "PiB"] {
+        if bytes < 1024.0 || unit == "PiB" {
+            return format!("{}{}{}", sign, one_decimal(bytes), unit);
+        }
+        bytes /= 1024.0;
+    }
+    unreachable!()
+}
+
+fn bytes(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    Ok(format_bytes(number_arg("bytes", &args, env)?).into())
+}
+
+/// Format a number of bytes with decimal units, like `1.5 MB`.
+pub(super) fn format_bytes(mut bytes: f64) -> String {
+    let sign = if bytes < 0.0 { "-" } else { "" };
+    bytes = bytes.abs();
+
+    for unit in ["B", "kB", "MB", "GB", "TB", "PB"] {
+        if bytes < 1000.0 || unit == "PB" {
+            return format!("{}{} {}", sign, one_decimal(bytes), unit);
+        }
+        bytes /= 1000.0;
+    }
+    unreachable!()
+}
+
+fn number(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("number", &args, 1)?;
+    let text = match args[0].eval(env)? {
+        Expression::Integer(n) => n.to_string(),
+        Expression::Float(n) => n.to_string(),
+        otherwise => {
+            return Err(Error::CustomError(format!(
+                "expected integer or float in number, but got {}",
+                otherwise
+            )))
+        }
+    };
+
+    let (sign, text) = match text.strip_prefix('-') {
+        Some(text) => ("-", text),
+        None => ("", text.as_str()),
+    };
+    let (whole, fraction) = match text.find('.') {
+        Some(i) => text.split_at(i),
+        None => (text, ""),
+    };
+
+    let mut result = String::from(sign);
+    for (i, digit) in whole.chars().enumerate() {
+        if i > 0 && (whole.len() - i) % 3 == 0 {
+            result.push(',');
+        }
+        result.push(digit);
+    }
+    result += fraction;
+    Ok(result.into())
+}
+
+fn ago(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    let then = number_arg("ago", &args, env)?;
+    let now = chrono::Utc::now().timestamp_millis() as f64 / 1000.0;
+    let seconds = (now - then).round() as i64;
+
+    if seconds.abs() < 1 {
+        return Ok("just now".into());
+    }
+
+    let (count, unit) = [
+        (31536000, "year"),
+        (2592000, "month"),
+        (604800, "week"),
+        (86400, "day"),
+        (3600, "hour"),
+        (60, "minute"),
+        (1, "second"),
+    ]
+    .into_iter()
+    .find(|(len, _)| seconds.abs() >= *len)
+    .map(|(len, unit)| (seconds.abs() / len, unit))
+    .unwrap();
+
+    let plural = if count == 1 { "" } else { "s" };
+    Ok(if seconds > 0 {
+        format!("{} {}{} ago", count, unit, plural)
+    } else {
+        format!("in {} {}{}", count, unit, plural)
+    }
+    .into())
+}
@@ -1,5 +1,7 @@
 use common_macros::b_tree_map;
 use dune::{Environment, Error, Expression};
+use indexmap::IndexMap;
+use std::collections::BTreeSet;
 
 pub(super) fn curry_env(
     f: Expression,
@@ -104,6 +106,12 @@ pub fn get() -> Expression {
             "reduce a function over a list of values"),
         String::from("?") => Expression::builtin("?", conditional,
         "conditionally evaluate two expressions based on the truthiness of a condition"),
+        String::from("params") => Expression::builtin("params", params,
+            "get the parameters of a function, like `[\"x\", \"y\"]` for `x -> y -> x + y`"),
+        String::from("body") => Expression::builtin("body", body,
+            "get the body of a function as a quoted expression"),
+        String::from("captured") => Expression::builtin("captured", captured,
+            "get the variables a function uses from where it was made, as a map"),
     })
     .into()
 }
@@ -238,3 +246,68 @@ fn reduce(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Er
         )))
     }
 }
+
+/// Get the parameters of a function, and the body after all of them. Curried
+/// functions like `x -> y -> x + y` have a parameter for each arrow.
+fn signature(f: &Expression) -> Result<(Vec<String>, &Expression), Error> {
+    let mut params = vec![];
+    let mut body = f;
+    loop {
+        match body {
+            Expression::Lambda(param, inner, _) | Expression::Macro(param, inner) => {
+                params.push(param.clone());
+                body = inner;
+            }
+            Expression::Group(inner) if !params.is_empty() => body = inner,
+            _ if params.is_empty() => {
+                return Err(Error::CustomError(format!(
+                    "expected a function or macro, but got {}",
+                    f
+                )))
+            }
+            _ => return Ok((params, body)),
+        }
+    }
+}
+
+fn params(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("params", &args, 1)?;
+    let (params, _) = signature(&args[0].eval(env)?)?;
+    Ok(params.into())
+}
+
+fn body(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("body", &args, 1)?;
+    let f = args[0].eval(env)?;
+    let (_, body) = signature(&f)?;
+    Ok(body.clone())
+}
+
+fn captured(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("captured", &args, 1)?;
+    let f = args[0].eval(env)?;
+    let (params, body) = signature(&f)?;
+    // Macros use the variables where they're called, so they don't capture any.
+    let captured = match &f {
+        Expression::Lambda(_, _, captured) => captured,
+        _ => return Ok(Expression::Map(IndexMap::new())),
+    };
+    let operators = match env.get("ops") {
+        Some(Expression::Map(operators)) => operators,
+        _ => IndexMap::new(),
+    };
+    let mut names = BTreeSet::new();
+    super::symbols(body, &mut names);
+    Ok(Expression::Map(
+        names
+            .into_iter()
+            .filter(|name| !params.contains(name))
+            .filter_map(|name| match captured.get(&name) {
+                // Leave out the builtin functions and operators, which every function can use.
+                None | Some(Expression::Builtin(_)) => None,
+                Some(value) if operators.get(&name) == Some(&value) => None,
+                Some(value) => Some((name, value)),
+            })
+            .collect(),
+    ))
+}
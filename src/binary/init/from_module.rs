@@ -0,0 +1,281 @@
+use common_macros::b_tree_map;
+use dune::{Environment, Error, Expression};
+use std::collections::BTreeMap;
+
+pub fn get() -> Expression {
+    (b_tree_map! {
+        String::from("table") => Expression::builtin("table", table, "parse a table of whitespace separated columns under a header, like the output of `ps aux`"),
+        String::from("ls") => Expression::builtin("ls", ls, "parse the output of `ls -l`"),
+        String::from("ps") => Expression::builtin("ps", ps, "parse the output of `ps aux`"),
+        String::from("df") => Expression::builtin("df", df, "parse the output of `df`"),
+        String::from("ip-addr") => Expression::builtin("ip-addr", ip_addr, "parse the output of `ip addr`"),
+        String::from("git-status") => Expression::builtin("git-status", git_status, "parse the output of `git status --porcelain`"),
+    })
+    .into()
+}
+
+/// Get the text a parser was called with.
+fn text_arg(name: &str, args: &[Expression], env: &mut Environment) -> Result<String, Error> {
+    super::check_exact_args_len(name, args, 1)?;
+    Ok(args[0].eval(env)?.to_string())
+}
+
+/// Turn a field into a number if it looks like one.
+pub(super) fn value(field: &str) -> Expression {
+    if let Ok(n) = field.parse() {
+        Expression::Integer(n)
+    } else if let Ok(n) = field.parse() {
+        Expression::Float(n)
+    } else {
+        Expression::String(field.to_string())
+    }
+}
+
+/// Split a line into at most `n` whitespace separated fields, where the last
+/// field is the rest of the line.
+fn fields(line: &str, n: usize) -> Vec<&str> {
+    let mut fields = vec![];
+    let mut rest = line.trim();
+    while fields.len() + 1 < n && !rest.is_empty() {
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        fields.push(&rest[..end]);
+        rest = rest[end..].trim_start();
+    }
+    if !rest.is_empty() {
+        fields.push(rest);
+    }
+    fields
+}
+
+/// Parse the rows under a header, naming each field after its column.
+fn rows(header: &[String], lines: impl Iterator<Item = impl AsRef<str>>) -> Expression {
+    Expression::List(
+        lines
+            .filter(|line| !line.as_ref().trim().is_empty())
+            .map(|line| {
+                header
+                    .iter()
+                    .cloned()
+                    .zip(fields(line.as_ref(), header.len()).into_iter().map(value))
+                    .collect::<BTreeMap<_, _>>()
+                    .into()
+            })
+            .collect(),
+    )
+}
+
+/// Name a column after its header, like `%CPU` becoming `cpu`.
+fn column_name(header: &str) -> String {
+    header.trim_matches('%').to_lowercase()
+}
+
+fn table(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    let text = text_arg("table", &args, env)?;
+    let mut lines = text.lines();
+    let header = match lines.next() {
+        Some(header) => header
+            .split_whitespace()
+            .map(column_name)
+            .collect::<Vec<_>>(),
+        None => return Ok(Expression::List(vec![])),
+    };
+    Ok(rows(&header, lines))
+}
+
+fn ps(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    table(args, env)
+}
+
+fn df(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    let text = text_arg("df", &args, env)?;
+    let mut lines = text.lines();
+    // The last column is called "Mounted on", which would otherwise be two columns.
+    let header = match lines.next() {
+        Some(header) => header
+            .replace("Mounted on", "Mounted-on")
+            .split_whitespace()
+            .map(column_name)
+            .collect::<Vec<_>>(),
+        None => return Ok(Expression::List(vec![])),
+    };
+
+    let mut result = rows(&header, lines);
+    // Make the percentage used a number.
+    if let Expression::List(rows) = &mut result {
+        for row in rows {
+            if let Expression::Map(row) = row {
+                for (name, field) in row.iter_mut() {
+                    if let (true, Expression::String(percent)) = (name.starts_with("use"), &field) {
+                        if let Some(n) = percent.strip_suffix('%') {
+                            *field = value(n);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(result)
+}
+
+fn ls(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    let text = text_arg("ls", &args, env)?;
+    let mut result = vec![];
+    for line in text.lines() {
+        let fields = fields(line, 9);
+        // Skip the `total` line, and anything else that isn't a file.
+        if fields.len() < 9 {
+            continue;
+        }
+
+        let kind = match fields[0].chars().next() {
+            Some('d') => "dir",
+            Some('l') => "link",
+            Some('-') => "file",
+            Some('c') | Some('b') => "device",
+            Some('p') => "pipe",
+            Some('s') => "socket",
+            _ => continue,
+        };
+        let mut file = b_tree_map! {
+            String::from("type") => Expression::from(kind),
+            String::from("permissions") => Expression::from(fields[0]),
+            String::from("links") => value(fields[1]),
+            String::from("owner") => Expression::from(fields[2]),
+            String::from("group") => Expression::from(fields[3]),
+            String::from("size") => value(fields[4]),
+            String::from("modified") => Expression::from(fields[5..8].join(" ")),
+        };
+        match fields[8].split_once(" -> ") {
+            Some((name, target)) if kind == "link" => {
+                file.insert(String::from("name"), Expression::from(name));
+                file.insert(String::from("target"), Expression::from(target));
+            }
+            _ => {
+                file.insert(String::from("name"), Expression::from(fields[8]));
+            }
+        }
+        result.push(file.into());
+    }
+    Ok(Expression::List(result))
+}
+
+fn ip_addr(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    let text = text_arg("ip-addr", &args, env)?;
+    let mut result = vec![];
+    let mut interface: Option<BTreeMap<String, Expression>> = None;
+
+    for line in text.lines() {
+        // Each interface starts with an unindented line like
+        // `2: eth0: <BROADCAST,MULTICAST,UP> mtu 1500 qdisc mq state UP`.
+        if !line.starts_with(char::is_whitespace) {
+            result.extend(interface.take().map(Expression::from));
+            let mut parts = line.splitn(3, ": ");
+            let (index, name, rest) = match (parts.next(), parts.next(), parts.next()) {
+                (Some(index), Some(name), Some(rest)) => (index, name, rest),
+                _ => continue,
+            };
+
+            let mut new = b_tree_map! {
+                String::from("index") => value(index),
+                String::from("name") => Expression::from(name),
+                String::from("inet") => Expression::List(vec![]),
+                String::from("inet6") => Expression::List(vec![]),
+            };
+            let mut words = rest.split_whitespace();
+            while let Some(word) = words.next() {
+                if let Some(flags) = word.strip_prefix('<').and_then(|w| w.strip_suffix('>')) {
+                    new.insert(
+                        String::from("flags"),
+                        Expression::List(flags.split(',').map(Expression::from).collect()),
+                    );
+                } else if let Some(field) = words.next() {
+                    new.insert(word.to_string(), value(field));
+                }
+            }
+            interface = Some(new);
+            continue;
+        }
+
+        let interface = match &mut interface {
+            Some(interface) => interface,
+            None => continue,
+        };
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        match words.as_slice() {
+            [link, mac, ..] if link.starts_with("link/") => {
+                interface.insert(String::from("link"), Expression::from(&link[5..]));
+                interface.insert(String::from("mac"), Expression::from(*mac));
+            }
+            [family @ ("inet" | "inet6"), address, ..] => {
+                if let Some(Expression::List(addresses)) = interface.get_mut(*family) {
+                    addresses.push(Expression::from(*address));
+                }
+            }
+            _ => {}
+        }
+    }
+    result.extend(interface.map(Expression::from));
+    Ok(Expression::List(result))
+}
+
+fn git_status(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    let text = text_arg("git-status", &args, env)?;
+    let mut result = vec![];
+    for line in text.lines() {
+        // The status is two letters and a space, which anything else isn't.
+        let (status, path) = match (line.get(..2), line.get(3..)) {
+            (Some(status), Some(path)) if !path.is_empty() => (status, path),
+            _ => continue,
+        };
+        let describe = |c: char| {
+            Expression::from(match c {
+                'M' => "modified",
+                'T' => "type-changed",
+                'A' => "added",
+                'D' => "deleted",
+                'R' => "renamed",
+                'C' => "copied",
+                'U' => "unmerged",
+                '?' => "untracked",
+                '!' => "ignored",
+                _ => "unmodified",
+            })
+        };
+
+        let mut chars = status.chars();
+        let mut file = b_tree_map! {
+            String::from("index") => describe(chars.next().unwrap_or(' ')),
+            String::from("worktree") => describe(chars.next().unwrap_or(' ')),
+        };
+        match path.split_once(" -> ") {
+            Some((from, to)) => {
+                file.insert(String::from("from"), Expression::from(from));
+                file.insert(String::from("path"), Expression::from(to));
+            }
+            None => {
+                file.insert(String::from("path"), Expression::from(path));
+            }
+        }
+        result.push(file.into());
+    }
+    Ok(Expression::List(result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn git_status_with_multibyte_text() {
+        let status =
+            |text: &str| git_status(vec![Expression::from(text)], &mut Environment::new()).unwrap();
+        assert_eq!(status("ab€cd"), Expression::List(vec![]));
+        let files = status(" M café.txt\n?? ü");
+        let Expression::List(files) = files else {
+            panic!("expected a list of files, but got {}", files);
+        };
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0]["path"], Expression::from("café.txt"));
+        assert_eq!(files[1]["worktree"], Expression::from("untracked"));
+    }
+}
@@ -2,11 +2,13 @@ use std::{
     collections::BTreeMap,
     env::current_dir,
     path::{Path, PathBuf},
+    process::Command,
 };
 
 use super::Int;
 use common_macros::b_tree_map;
 use dune::{Environment, Error, Expression};
+use indexmap::IndexMap;
 
 fn get_dir_tree(cwd: &Path, max_depth: Option<Int>) -> BTreeMap<String, Expression> {
     let mut dir_tree = b_tree_map! {};
@@ -225,13 +227,16 @@ pub fn get(env: &mut Environment) -> Expression {
             Ok(Expression::None)
         }, "remove a file or directory from the filesystem"),
         String::from("ls") => Expression::builtin("ls", |args, env| {
-            super::check_exact_args_len("ls", &args, 1)?;
+            super::check_args_len("ls", &args, 0..=1)?;
             let cwd = PathBuf::from(env.get_cwd());
-            let path = args[0].eval(env)?.to_string();
+            let path = match args.first() {
+                Some(arg) => arg.eval(env)?.to_string(),
+                None => String::from("."),
+            };
             let dir = cwd.join(&path);
 
-            list_directory(&dir, &Path::new(&path))
-        }, "get a directory's entries as a list of strings"),
+            list_directory(&dir, Path::new(&path))
+        }, "get a directory's entries as a list of maps with their `name`, `type`, `size`, `mode`, `mtime`, and `path`, like `fs@ls \"src\" | fn@map (entry -> entry@name)`"),
         String::from("exists?") => Expression::builtin("exists", |args, env| {
             super::check_exact_args_len("exists", &args, 1)?;
             let path = PathBuf::from(env.get_cwd());
@@ -292,6 +297,14 @@ pub fn get(env: &mut Environment) -> Expression {
             }
         }, "write to a file with some contents"),
 
+        String::from("bulk-rename") => Expression::builtin("bulk-rename", bulk_rename, "rename files to the paths a function gives for them, like `fs@bulk-rename (fs@ls \"photos\") (p -> string@replace p \"IMG_\" \"trip-\") {edit=True}`, where `edit` lets you change the renames in $EDITOR first, and `dry-run` only gets them"),
+        String::from("verify") => Expression::builtin("verify", verify, "check a file's checksum, like `fs@verify \"dune.tar.gz\" {sha256=\"...\"}`, returning its path if it matches and failing if it doesn't"),
+        String::from("find") => Expression::builtin("find", find, "find the files under a path that match some conditions, like `fs@find src {name=\"*.rs\", type=\"file\", size=\">1MB\", newer-than=\"1d\"}`"),
+        String::from("replace") => Expression::builtin("replace", replace, "replace text in files in place, like `fs@replace \"src/main.rs\" \"foo\" \"bar\" {regex=False, backup=\".bak\"}`, returning how many replacements were made"),
+        String::from("tree") => Expression::builtin("tree", tree, "get the tree of files under a directory as nested maps, along with a drawing of it, like `fs@tree \"src\" {depth=3, dirs-only=False, gitignore=True, hidden=False}`, where the whole tree is walked unless `depth` is given, and links to directories aren't followed"),
+        String::from("open") => Expression::builtin("open", open, "read a file into structured data, parsing it by its extension (json, toml, csv, or tsv)"),
+        String::from("save") => Expression::builtin("save", save, "write structured data to a file, formatting it by its extension (json, toml, csv, or tsv)"),
+
         String::from("append") => Expression::builtin("append", |args, env| {
             super::check_exact_args_len("append", &args, 2)?;
             let mut path = PathBuf::from(env.get_cwd());
@@ -347,7 +360,917 @@ pub fn get(env: &mut Environment) -> Expression {
     };
 
     env.define_module("fs", fs_module.clone());
-    Expression::Map(fs_module)
+    Expression::from(fs_module)
+}
+
+fn find(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("find", &args, 1..=2)?;
+    let root = args[0].eval(env)?.to_string();
+    let options = match args.get(1).map(|options| options.eval(env)).transpose()? {
+        Some(Expression::Map(options)) => options,
+        Some(otherwise) => {
+            return Err(Error::CustomError(format!(
+                "expected a map of conditions like {{name=\"*.rs\"}}, but got {}",
+                otherwise
+            )))
+        }
+        None => IndexMap::new(),
+    };
+    let cwd = PathBuf::from(env.get_cwd());
+    if !cwd.join(&root).exists() {
+        return Err(Error::CustomError(format!("{} does not exist", root)));
+    }
+
+    let pattern = |name: &str| -> Result<Option<glob::Pattern>, Error> {
+        options
+            .get(name)
+            .map(|pattern| {
+                glob::Pattern::new(&pattern.to_string())
+                    .map_err(|e| Error::CustomError(format!("invalid pattern {}: {}", pattern, e)))
+            })
+            .transpose()
+    };
+    let name = pattern("name")?;
+    let path_pattern = pattern("path")?;
+    let kind = options
+        .get("type")
+        .map(|kind| match kind.to_string().as_str() {
+            "f" => String::from("file"),
+            "d" => String::from("dir"),
+            "l" => String::from("link"),
+            kind => kind.to_string(),
+        });
+    let size = match options.get("size") {
+        Some(size) => Some(size_condition(&size.to_string())?),
+        None => None,
+    };
+    let newer_than = match options.get("newer-than") {
+        Some(time) => Some(time_condition(time, &cwd)?),
+        None => None,
+    };
+    let older_than = match options.get("older-than") {
+        Some(time) => Some(time_condition(time, &cwd)?),
+        None => None,
+    };
+    let max_depth = match options.get("max-depth") {
+        Some(Expression::Integer(n)) => Some(*n as usize),
+        Some(otherwise) => {
+            return Err(Error::CustomError(format!(
+                "expected a number for max-depth, but got {}",
+                otherwise
+            )))
+        }
+        None => None,
+    };
+    let hidden = options
+        .get("hidden")
+        .map(Expression::is_truthy)
+        .unwrap_or(true);
+    let predicate = options.get("where");
+
+    let mut found = vec![];
+    // Walk the tree depth first, in order of the names of the files.
+    let mut stack = vec![(PathBuf::from(&root), 0)];
+    while let Some((path, depth)) = stack.pop() {
+        let meta = match std::fs::symlink_metadata(cwd.join(&path)) {
+            Ok(meta) => meta,
+            Err(_) => continue,
+        };
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| path.display().to_string());
+        if depth > 0 && !hidden && file_name.starts_with('.') {
+            continue;
+        }
+
+        if meta.is_dir() && max_depth.map(|max| depth < max).unwrap_or(true) {
+            let mut children = std::fs::read_dir(cwd.join(&path))
+                .into_iter()
+                .flatten()
+                .flatten()
+                .map(|entry| path.join(entry.file_name()))
+                .collect::<Vec<_>>();
+            children.sort();
+            stack.extend(children.into_iter().rev().map(|child| (child, depth + 1)));
+        }
+
+        let file_type = if meta.file_type().is_symlink() {
+            "link"
+        } else if meta.is_dir() {
+            "dir"
+        } else {
+            "file"
+        };
+        let modified = meta.modified().ok();
+        let matches = name.as_ref().map(|p| p.matches(&file_name)).unwrap_or(true)
+            && path_pattern
+                .as_ref()
+                .map(|p| p.matches_path(&path))
+                .unwrap_or(true)
+            && kind.as_ref().map(|k| k == file_type).unwrap_or(true)
+            && size
+                .map(|(ordering, bytes)| meta.len().cmp(&bytes) == ordering)
+                .unwrap_or(true)
+            && newer_than
+                .map(|t| modified.map(|m| m > t).unwrap_or(false))
+                .unwrap_or(true)
+            && older_than
+                .map(|t| modified.map(|m| m < t).unwrap_or(false))
+                .unwrap_or(true);
+        if !matches {
+            continue;
+        }
+
+        let file = Expression::from(b_tree_map! {
+            String::from("path") => Expression::from(path.display().to_string()),
+            String::from("name") => Expression::from(file_name),
+            String::from("type") => Expression::from(file_type),
+            String::from("size") => Expression::Integer(meta.len() as Int),
+            String::from("modified") => modified
+                .and_then(|m| m.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|m| Expression::Integer(m.as_secs() as Int))
+                .unwrap_or(Expression::None),
+        });
+        if let Some(predicate) = predicate {
+            let keep = Expression::Apply(
+                Box::new(predicate.clone()),
+                vec![Expression::Quote(Box::new(file.clone()))],
+            )
+            .eval(env)?;
+            if !keep.is_truthy() {
+                continue;
+            }
+        }
+        found.push(file);
+    }
+    Ok(Expression::List(found))
+}
+
+fn bulk_rename(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("bulk-rename", &args, 2..=3)?;
+    // Entries from `fs@ls` and `fs@find` are renamed by their paths.
+    let files = match args[0].eval(env)? {
+        Expression::List(files) => files
+            .into_iter()
+            .map(|file| match file {
+                Expression::Map(entry) if entry.contains_key("path") => entry["path"].to_string(),
+                file => file.to_string(),
+            })
+            .collect::<Vec<_>>(),
+        otherwise => {
+            return Err(Error::CustomError(format!(
+                "expected a list of files to rename, but got {}",
+                otherwise
+            )))
+        }
+    };
+    let f = args[1].eval(env)?;
+    let options = match args.get(2).map(|options| options.eval(env)).transpose()? {
+        Some(Expression::Map(options)) => options,
+        Some(otherwise) => {
+            return Err(Error::CustomError(format!(
+                "expected a map of options like {{edit=True}}, but got {}",
+                otherwise
+            )))
+        }
+        None => IndexMap::new(),
+    };
+    let option = |name: &str| {
+        options
+            .get(name)
+            .map(Expression::is_truthy)
+            .unwrap_or(false)
+    };
+
+    // A file the function gives `None` or the same path for stays where it is.
+    let mut renames = vec![];
+    for file in files {
+        let new = Expression::Apply(Box::new(f.clone()), vec![Expression::from(file.as_str())])
+            .eval(env)?;
+        if new != Expression::None && new.to_string() != file {
+            renames.push((file, new.to_string()));
+        }
+    }
+    if option("edit") && !renames.is_empty() {
+        renames = match edit_renames(&renames, env)? {
+            Some(renames) => renames,
+            None => return Ok(Expression::List(vec![])),
+        };
+    }
+
+    let cwd = PathBuf::from(env.get_cwd());
+    check_renames(&renames, &cwd)?;
+    if !option("dry-run") {
+        apply_renames(&renames, &cwd)?;
+    }
+    Ok(Expression::List(
+        renames
+            .into_iter()
+            .map(|(from, to)| {
+                b_tree_map! {
+                    String::from("from") => Expression::from(from),
+                    String::from("to") => Expression::from(to),
+                }
+                .into()
+            })
+            .collect(),
+    ))
+}
+
+/// Write text to a new file in the temp dir that only this user can read.
+/// The name is random and the file must not exist yet, so no one can make it
+/// ahead of time, or link it to another file to have that written instead.
+fn write_temp_file(prefix: &str, text: &str) -> Result<PathBuf, Error> {
+    #[cfg(unix)]
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::{fs::OpenOptions, io::Write};
+
+    loop {
+        let file = std::env::temp_dir().join(format!(
+            "{}-{}-{:016x}.txt",
+            prefix,
+            std::process::id(),
+            rand::random::<u64>()
+        ));
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let error = |e: std::io::Error| {
+            Error::CustomError(format!("could not write {}: {}", file.display(), e))
+        };
+        match options.open(&file) {
+            Ok(mut opened) => {
+                opened.write_all(text.as_bytes()).map_err(|e| {
+                    let _ = std::fs::remove_file(&file);
+                    error(e)
+                })?;
+                return Ok(file);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(error(e)),
+        }
+    }
+}
+
+/// Let the user change the renames in their editor, like `vidir`, getting
+/// the renames they kept, or `None` if they cancelled.
+fn edit_renames(
+    renames: &[(String, String)],
+    env: &Environment,
+) -> Result<Option<Vec<(String, String)>>, Error> {
+    const ARROW: &str = " -> ";
+    let mut text = String::from(
+        "# Change where files go after the arrows, or delete a line to leave a file alone.\n\
+         # Saving applies the renames, and an empty file cancels them.\n",
+    );
+    for (from, to) in renames {
+        text += &format!("{}{}{}\n", from, ARROW, to);
+    }
+
+    // Editors like `code --wait` take arguments of their own.
+    let editor = ["VISUAL", "EDITOR"]
+        .iter()
+        .filter_map(|name| match env.get(name) {
+            Some(Expression::String(editor)) => Some(editor),
+            _ => std::env::var(name).ok(),
+        })
+        .find(|editor| !editor.trim().is_empty());
+    let edited = match editor {
+        Some(editor) => {
+            let file = write_temp_file("dune-rename", &text)?;
+            let mut words = editor.split_whitespace();
+            let status = Command::new(words.next().unwrap_or_default())
+                .args(words)
+                .arg(&file)
+                .current_dir(env.get_cwd())
+                .status();
+            let edited = std::fs::read_to_string(&file);
+            let _ = std::fs::remove_file(&file);
+            match status {
+                Ok(status) if status.success() => edited.ok(),
+                Ok(_) => None,
+                Err(e) => {
+                    return Err(Error::CustomError(format!(
+                        "could not run the editor `{}`: {}",
+                        editor, e
+                    )))
+                }
+            }
+        }
+        None => super::widget_module::edit_text("rename", &text)?,
+    };
+    let edited = match edited {
+        Some(edited) if !edited.trim().is_empty() => edited,
+        _ => return Ok(None),
+    };
+
+    let mut kept = vec![];
+    for line in edited.lines() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_once(ARROW) {
+            Some((from, to)) if renames.iter().any(|(file, _)| file == from) => {
+                if !to.trim().is_empty() && to != from {
+                    kept.push((from.to_string(), to.to_string()));
+                }
+            }
+            _ => {
+                return Err(Error::CustomError(format!(
+                    "expected a line like `old{}new` for one of the files to rename, but got `{}`",
+                    ARROW, line
+                )))
+            }
+        }
+    }
+    Ok(Some(kept))
+}
+
+/// Make sure renames can all be done before doing any of them: every file is
+/// renamed once, no two go to the same place, and nothing is overwritten,
+/// unless it's renamed out of the way too.
+fn check_renames(renames: &[(String, String)], cwd: &Path) -> Result<(), Error> {
+    let mut sources = BTreeMap::new();
+    let mut targets = BTreeMap::new();
+    for (from, to) in renames {
+        let source = cwd.join(from);
+        if std::fs::symlink_metadata(&source).is_err() {
+            return Err(Error::CustomError(format!("{} does not exist", from)));
+        }
+        if sources.insert(source, from).is_some() {
+            return Err(Error::CustomError(format!("{} is renamed twice", from)));
+        }
+        if let Some((other, _)) = targets.insert(cwd.join(to), (from, to)) {
+            return Err(Error::CustomError(format!(
+                "{} and {} would both be renamed to {}",
+                other, from, to
+            )));
+        }
+    }
+    for (target, (from, to)) in &targets {
+        if target.exists() && !sources.contains_key(target) {
+            return Err(Error::CustomError(format!(
+                "cannot rename {} to {}, which already exists",
+                from, to
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Do renames that `check_renames` allowed. When a file is renamed to where
+/// another one is, like when swapping names, everything is first moved to a
+/// temporary name next to it.
+fn apply_renames(renames: &[(String, String)], cwd: &Path) -> Result<(), Error> {
+    let overlaps = renames.iter().any(|(_, to)| {
+        renames
+            .iter()
+            .any(|(from, _)| cwd.join(from) == cwd.join(to))
+    });
+    let mut moves = vec![];
+    for (i, (from, to)) in renames.iter().enumerate() {
+        let (from, to) = (cwd.join(from), cwd.join(to));
+        if overlaps {
+            let mut temp = from.clone().into_os_string();
+            temp.push(format!(".{}-{}.renaming", std::process::id(), i));
+            let temp = PathBuf::from(temp);
+            move_path(&from, &temp)?;
+            moves.push((temp, to));
+        } else {
+            moves.push((from, to));
+        }
+    }
+    for (from, to) in moves {
+        move_path(&from, &to)?;
+    }
+    Ok(())
+}
+
+fn verify(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("verify", &args, 2)?;
+    let file = args[0].eval(env)?.to_string();
+    let expected = match args[1].eval(env)? {
+        Expression::Map(options) => match options.get("sha256") {
+            Some(digest) => digest.to_string(),
+            None => {
+                return Err(Error::CustomError(format!(
+                    "expected a checksum to verify {} with, like {{sha256=\"...\"}}",
+                    file
+                )))
+            }
+        },
+        otherwise => {
+            return Err(Error::CustomError(format!(
+                "expected a map of checksums like {{sha256=\"...\"}}, but got {}",
+                otherwise
+            )))
+        }
+    };
+    let contents = std::fs::read(PathBuf::from(env.get_cwd()).join(&file))
+        .map_err(|e| Error::CustomError(format!("could not read file {}: {}", file, e)))?;
+    super::check_sha256(&file, &contents, &expected)?;
+    Ok(Expression::from(file))
+}
+
+/// Parse a size condition like `>1MB`, `<10k`, or `=0` into the ordering of
+/// a file's size with the size, and the size in bytes.
+fn size_condition(text: &str) -> Result<(std::cmp::Ordering, u64), Error> {
+    let text = text.trim();
+    let (ordering, size) = match text.chars().next() {
+        Some('>') => (std::cmp::Ordering::Greater, &text[1..]),
+        Some('<') => (std::cmp::Ordering::Less, &text[1..]),
+        Some('=') => (std::cmp::Ordering::Equal, &text[1..]),
+        _ => (std::cmp::Ordering::Equal, text),
+    };
+    Ok((
+        ordering,
+        super::parse_module::size_bytes(size.trim())?.max(0) as u64,
+    ))
+}
+
+/// Get the time a condition like `newer-than` compares against: either a number of
+/// seconds or a duration like `"2h"` ago, or when a path was last modified.
+fn time_condition(time: &Expression, cwd: &Path) -> Result<std::time::SystemTime, Error> {
+    let seconds = match time {
+        Expression::Integer(n) => *n as f64,
+        Expression::Float(n) => *n,
+        time => {
+            let text = time.to_string();
+            if let Ok(modified) = std::fs::metadata(cwd.join(&text)).and_then(|m| m.modified()) {
+                return Ok(modified);
+            }
+            super::parse_module::duration_seconds(&text)?
+        }
+    };
+    let ago = super::parse_module::duration(seconds.max(0.0))?;
+    std::time::SystemTime::now()
+        .checked_sub(ago)
+        .ok_or_else(|| {
+            Error::CustomError(format!(
+                "{} seconds ago is before any time a file could have",
+                seconds
+            ))
+        })
+}
+
+fn replace(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("replace", &args, 3..=4)?;
+    let paths = match args[0].eval(env)? {
+        Expression::List(paths) => paths,
+        path => vec![path],
+    };
+    let pattern = args[1].eval(env)?.to_string();
+    let replacement = args[2].eval(env)?.to_string();
+    let options = match args.get(3).map(|options| options.eval(env)).transpose()? {
+        Some(Expression::Map(options)) => options,
+        Some(otherwise) => {
+            return Err(Error::CustomError(format!(
+                "expected a map of options like {{regex=True, backup=\".bak\"}}, but got {}",
+                otherwise
+            )))
+        }
+        None => IndexMap::new(),
+    };
+    // Without `regex`, the pattern and replacement are plain text.
+    let use_regex = options
+        .get("regex")
+        .map(Expression::is_truthy)
+        .unwrap_or(false);
+    let (pattern, replacement) = if use_regex {
+        (pattern, replacement)
+    } else {
+        (regex::escape(&pattern), replacement.replace('$', "$$"))
+    };
+    let regex = regex::Regex::new(&pattern)
+        .map_err(|e| Error::CustomError(format!("invalid pattern: {}", e)))?;
+    let backup = match options.get("backup") {
+        Some(Expression::None) | None => None,
+        Some(suffix) => Some(suffix.to_string()),
+    };
+
+    let cwd = PathBuf::from(env.get_cwd());
+    let mut count = 0;
+    for file in paths {
+        let path = cwd.join(file.to_string());
+        let text = std::fs::read_to_string(&path)
+            .map_err(|e| Error::CustomError(format!("could not read file {}: {}", file, e)))?;
+        let found = regex.find_iter(&text).count();
+        if found == 0 {
+            continue;
+        }
+        let replaced = regex.replace_all(&text, replacement.as_str());
+
+        let error = |e: std::io::Error| {
+            Error::CustomError(format!("could not replace text in {}: {}", file, e))
+        };
+        if let Some(suffix) = &backup {
+            let mut backup = path.clone().into_os_string();
+            backup.push(suffix);
+            std::fs::copy(&path, backup).map_err(error)?;
+        }
+        // Write to a temporary file next to the original and rename it over the
+        // original, so the file is never left half written.
+        let mut temp = path.clone().into_os_string();
+        temp.push(format!(".{}.tmp", std::process::id()));
+        let temp = PathBuf::from(temp);
+        let written = std::fs::write(&temp, replaced.as_bytes())
+            .and_then(|_| std::fs::metadata(&path))
+            .and_then(|meta| std::fs::set_permissions(&temp, meta.permissions()))
+            .and_then(|_| std::fs::rename(&temp, &path));
+        if let Err(e) = written {
+            let _ = std::fs::remove_file(&temp);
+            return Err(error(e));
+        }
+        count += found;
+    }
+    Ok(Expression::Integer(count as Int))
+}
+
+fn tree(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("tree", &args, 1..=2)?;
+    let root = args[0].eval(env)?.to_string();
+    let options = match args.get(1).map(|options| options.eval(env)).transpose()? {
+        Some(Expression::Map(options)) => options,
+        Some(otherwise) => {
+            return Err(Error::CustomError(format!(
+                "expected a map of options like {{depth=3}}, but got {}",
+                otherwise
+            )))
+        }
+        None => IndexMap::new(),
+    };
+    let flag = |name: &str| {
+        options
+            .get(name)
+            .map(Expression::is_truthy)
+            .unwrap_or(false)
+    };
+    let walk = TreeWalk {
+        depth: match options.get("depth") {
+            Some(Expression::Integer(n)) => Some(*n as usize),
+            Some(otherwise) => {
+                return Err(Error::CustomError(format!(
+                    "expected a number for depth, but got {}",
+                    otherwise
+                )))
+            }
+            None => None,
+        },
+        dirs_only: flag("dirs-only"),
+        gitignore: flag("gitignore"),
+        hidden: flag("hidden"),
+    };
+
+    let path = PathBuf::from(env.get_cwd()).join(&root);
+    if !path.is_dir() {
+        return Err(Error::CustomError(format!("{} is not a directory", root)));
+    }
+    let mut text = root.clone();
+    let tree = walk.walk(&path, 0, &[], "", &mut text);
+    Ok(b_tree_map! {
+        String::from("tree") => tree,
+        String::from("text") => Expression::from(text),
+    }
+    .into())
+}
+
+/// What to include in a tree of files.
+struct TreeWalk {
+    depth: Option<usize>,
+    dirs_only: bool,
+    gitignore: bool,
+    hidden: bool,
+}
+
+impl TreeWalk {
+    /// Get the files under a directory as a map, where directories are maps, files
+    /// are their sizes, and links are where they point, while drawing the tree. `ignored` are the patterns in the
+    /// `.gitignore` files of the directories above.
+    fn walk(
+        &self,
+        dir: &Path,
+        depth: usize,
+        ignored: &[(glob::Pattern, bool)],
+        indent: &str,
+        text: &mut String,
+    ) -> Expression {
+        let mut ignored = ignored.to_vec();
+        if self.gitignore {
+            ignored.extend(gitignore_patterns(dir));
+        }
+
+        let mut entries = std::fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|entry| {
+                let name = entry.file_name().to_string_lossy().to_string();
+                // Links aren't followed, so a link to a directory above can't
+                // make the walk go around in circles.
+                let link = entry
+                    .file_type()
+                    .ok()
+                    .filter(std::fs::FileType::is_symlink)
+                    .map(|_| std::fs::read_link(entry.path()).unwrap_or_default());
+                let is_dir = link.is_none() && entry.path().is_dir();
+                let skip = (!self.hidden && name.starts_with('.'))
+                    || (self.dirs_only && !entry.path().is_dir())
+                    || (self.gitignore
+                        && (name == ".git"
+                            || ignored.iter().any(|(pattern, dirs_only)| {
+                                (is_dir || !dirs_only) && pattern.matches(&name)
+                            })));
+                if skip {
+                    None
+                } else {
+                    Some((name, entry.path(), is_dir, link))
+                }
+            })
+            .collect::<Vec<_>>();
+        entries.sort();
+
+        let mut tree = BTreeMap::new();
+        for (i, (name, path, is_dir, link)) in entries.iter().enumerate() {
+            let last = i + 1 == entries.len();
+            *text += &format!("\n{}{}{}", indent, if last { "└── " } else { "├── " }, name);
+            // Links are shown with where they point, like `tree` does.
+            if let Some(target) = link {
+                *text += &format!(" -> {}", target.display());
+            }
+            let value = if let Some(target) = link {
+                Expression::from(target.display().to_string())
+            } else if *is_dir {
+                if self.depth.map(|max| depth + 1 < max).unwrap_or(true) {
+                    let indent = format!("{}{}", indent, if last { "    " } else { "│   " });
+                    self.walk(path, depth + 1, &ignored, &indent, text)
+                } else {
+                    Expression::Map(IndexMap::new())
+                }
+            } else {
+                std::fs::metadata(path)
+                    .map(|meta| Expression::Integer(meta.len() as Int))
+                    .unwrap_or(Expression::None)
+            };
+            tree.insert(name.clone(), value);
+        }
+        Expression::from(tree)
+    }
+}
+
+/// List the paths under a directory, relative to it, for pickers like the ones bound to
+/// Ctrl-T and Alt-C. Hidden and gitignored files are left out, and shallower paths come
+/// first, so the listing can stop at `limit` paths in huge trees.
+pub fn walk_paths(root: &Path, dirs_only: bool, limit: usize) -> Vec<String> {
+    let mut paths = vec![];
+    let mut queue = std::collections::VecDeque::from([(root.to_path_buf(), vec![])]);
+    while let Some((dir, ignored)) = queue.pop_front() {
+        let mut ignored: Vec<(glob::Pattern, bool)> = ignored;
+        ignored.extend(gitignore_patterns(&dir));
+        let mut entries = std::fs::read_dir(&dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| {
+                let link = entry.file_type().map(|t| t.is_symlink()).unwrap_or(false);
+                (
+                    entry.file_name().to_string_lossy().to_string(),
+                    entry.path(),
+                    link,
+                )
+            })
+            .collect::<Vec<_>>();
+        entries.sort();
+        for (name, path, link) in entries {
+            let is_dir = path.is_dir();
+            if name.starts_with('.')
+                || ignored
+                    .iter()
+                    .any(|(pattern, dirs_only)| (is_dir || !dirs_only) && pattern.matches(&name))
+            {
+                continue;
+            }
+            if is_dir || !dirs_only {
+                if paths.len() == limit {
+                    return paths;
+                }
+                if let Ok(relative) = path.strip_prefix(root) {
+                    paths.push(relative.to_string_lossy().to_string());
+                }
+            }
+            // Links to directories can be picked, but aren't followed, in
+            // case they link back to a directory above.
+            if is_dir && !link {
+                queue.push_back((path, ignored.clone()));
+            }
+        }
+    }
+    paths
+}
+
+/// Read the patterns in a directory's `.gitignore`, and whether each only matches directories.
+fn gitignore_patterns(dir: &Path) -> Vec<(glob::Pattern, bool)> {
+    std::fs::read_to_string(dir.join(".gitignore"))
+        .unwrap_or_default()
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#') && !line.starts_with('!'))
+        .filter_map(|line| {
+            let dirs_only = line.ends_with('/');
+            let line = line.trim_matches('/');
+            // Patterns for a path, like `target/debug`, are matched by the last part.
+            let name = line.rsplit('/').next().unwrap_or(line);
+            glob::Pattern::new(name)
+                .ok()
+                .map(|pattern| (pattern, dirs_only))
+        })
+        .collect()
+}
+
+/// Get the lowercase extension of a file, like `json` for `data.JSON`.
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+fn open(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("open", &args, 1)?;
+    let file = args[0].eval(env)?;
+    let path = PathBuf::from(env.get_cwd()).join(file.to_string());
+
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        // Files that aren't text are opened as bytes.
+        Err(_) => {
+            return match std::fs::read(&path) {
+                Ok(contents) => Ok(Expression::Bytes(contents)),
+                Err(e) => Err(Error::CustomError(format!(
+                    "could not open file {}: {}",
+                    file, e
+                ))),
+            }
+        }
+    };
+
+    match extension(&path).as_str() {
+        "json" => match json::parse(&contents) {
+            Ok(val) => Ok(super::parse_module::json_to_expr(val)),
+            Err(e) => Err(Error::CustomError(format!(
+                "could not parse {} as JSON: {}",
+                file, e
+            ))),
+        },
+        "toml" => match contents.parse::<toml::Value>() {
+            Ok(val) => Ok(super::parse_module::toml_to_expr(val)),
+            Err(e) => Err(Error::CustomError(format!(
+                "could not parse {} as TOML: {}",
+                file, e
+            ))),
+        },
+        "csv" => Ok(super::parse_module::csv_to_expr(&contents, ',')),
+        "tsv" => Ok(super::parse_module::csv_to_expr(&contents, '\t')),
+        _ => Ok(contents.into()),
+    }
+}
+
+fn save(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("save", &args, 2)?;
+    let file = args[0].eval(env)?;
+    let path = PathBuf::from(env.get_cwd()).join(file.to_string());
+    let value = args[1].eval(env)?;
+
+    let result = match (extension(&path).as_str(), value) {
+        ("json", value) => std::fs::write(&path, json::stringify_pretty(to_json(&value), 4) + "\n"),
+        ("toml", value) => {
+            let text = match to_toml(&value) {
+                Some(toml @ toml::Value::Table(_)) => toml.to_string(),
+                _ => {
+                    return Err(Error::CustomError(format!(
+                        "could not save {} as TOML: only maps can be saved as TOML",
+                        file
+                    )))
+                }
+            };
+            std::fs::write(&path, text)
+        }
+        (ext @ ("csv" | "tsv"), value) => {
+            let separator = if ext == "csv" { ',' } else { '\t' };
+            match to_csv(&value, separator) {
+                Some(text) => std::fs::write(&path, text),
+                None => {
+                    return Err(Error::CustomError(format!(
+                        "could not save {} as {}: only lists of maps can be saved as tables",
+                        file,
+                        ext.to_uppercase()
+                    )))
+                }
+            }
+        }
+        (_, Expression::Bytes(bytes)) => std::fs::write(&path, bytes),
+        (_, value) => std::fs::write(&path, value.to_string()),
+    };
+
+    match result {
+        Ok(()) => Ok(Expression::None),
+        Err(e) => Err(Error::CustomError(format!(
+            "could not save file {}: {}",
+            file, e
+        ))),
+    }
+}
+
+fn to_json(value: &Expression) -> json::JsonValue {
+    match value {
+        Expression::None => json::JsonValue::Null,
+        Expression::Boolean(b) => (*b).into(),
+        Expression::Integer(n) => (*n).into(),
+        Expression::Float(n) => (*n).into(),
+        Expression::String(s) => s.as_str().into(),
+        Expression::Bytes(bytes) => bytes.as_slice().into(),
+        Expression::List(list) => json::JsonValue::Array(list.iter().map(to_json).collect()),
+        Expression::Map(map) => {
+            let mut object = json::object::Object::new();
+            for (key, value) in map {
+                object.insert(key, to_json(value));
+            }
+            json::JsonValue::Object(object)
+        }
+        other => other.to_string().into(),
+    }
+}
+
+/// Convert a value to TOML, which has no way to write `None`.
+fn to_toml(value: &Expression) -> Option<toml::Value> {
+    Some(match value {
+        Expression::None => return None,
+        Expression::Boolean(b) => toml::Value::Boolean(*b),
+        Expression::Integer(n) => toml::Value::Integer(*n),
+        Expression::Float(n) => toml::Value::Float(*n),
+        Expression::String(s) => toml::Value::String(s.clone()),
+        Expression::Bytes(bytes) => toml::Value::Array(
+            bytes
+                .iter()
+                .map(|b| toml::Value::Integer(*b as i64))
+                .collect(),
+        ),
+        Expression::List(list) => toml::Value::Array(list.iter().filter_map(to_toml).collect()),
+        Expression::Map(map) => toml::Value::Table(
+            map.iter()
+                .filter_map(|(key, value)| Some((key.clone(), to_toml(value)?)))
+                .collect(),
+        ),
+        other => toml::Value::String(other.to_string()),
+    })
+}
+
+/// Write a list of maps as a table, with a column for every key in any of the maps.
+fn to_csv(value: &Expression, separator: char) -> Option<String> {
+    let rows = match value {
+        Expression::List(rows) => rows
+            .iter()
+            .map(|row| match row {
+                Expression::Map(row) => Some(row),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?,
+        _ => return None,
+    };
+
+    let mut header: Vec<&String> = vec![];
+    for key in rows.iter().flat_map(|row| row.keys()) {
+        if !header.contains(&key) {
+            header.push(key);
+        }
+    }
+
+    let field = |text: &str| {
+        if text.contains([separator, '"', '\n', '\r']) {
+            format!("\"{}\"", text.replace('"', "\"\""))
+        } else {
+            text.to_string()
+        }
+    };
+    let separator = separator.to_string();
+    let mut text = header
+        .iter()
+        .map(|key| field(key))
+        .collect::<Vec<_>>()
+        .join(&separator)
+        + "\n";
+    for row in rows {
+        text += &header
+            .iter()
+            .map(|key| match row.get(*key) {
+                Some(Expression::None) | None => String::new(),
+                Some(value) => field(&value.to_string()),
+            })
+            .collect::<Vec<_>>()
+            .join(&separator);
+        text += "\n";
+    }
+    Some(text)
 }
 
 /// Copy one path to another path.
@@ -458,50 +1381,161 @@ fn remove_path(path: &Path) -> Result<(), Error> {
 
 /// Returns the paths of entries in a directory as a list of strings.
 fn list_directory(dir: &Path, short: &Path) -> Result<Expression, Error> {
-    if dir.is_dir() {
-        // The list of paths (as strings) in the directory we will return.
-        let mut result = vec![];
+    let meta = std::fs::symlink_metadata(dir)
+        .map_err(|_| Error::CustomError(format!("{} does not exist", dir.display())))?;
+    if !meta.is_dir() {
+        // If the path is a file, return the file's entry in a list.
+        let name = short
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| short.display().to_string());
+        return Ok(Expression::List(vec![directory_entry(name, short, &meta)]));
+    }
 
-        // Read the directory's items
-        if let Ok(entries) = std::fs::read_dir(dir) {
-            for entry in entries {
-                // For every valid entry in the directory,
-                // add it's filename as a string to the result list.
-                if let Ok(entry) = entry {
-                    let file_name_osstring = entry.file_name();
-                    result.push(match file_name_osstring.into_string() {
-                        Ok(file_name) => short.join(file_name).to_string_lossy().to_string(),
-                        // If we cannot directly convert the filename to a string,
-                        // it's probably an invalid UTF-8 string.
-                        // In this case, we remove the invalid bytes and try again.
-                        Err(file_name) => file_name.to_string_lossy().to_string(),
-                    });
-                } else {
-                    // If an entry is invalid, throw an error.
-                    return Err(Error::CustomError(format!(
-                        "could not read entries in {}",
-                        dir.display()
-                    )));
-                }
+    let entries = std::fs::read_dir(dir).map_err(|e| {
+        Error::CustomError(format!("could not read directory {}: {}", dir.display(), e))
+    })?;
+    let mut result = vec![];
+    for entry in entries {
+        let entry = entry.map_err(|e| {
+            Error::CustomError(format!(
+                "could not read entries in {}: {}",
+                dir.display(),
+                e
+            ))
+        })?;
+        // Names that aren't valid UTF-8 have the invalid bytes replaced.
+        let name = entry.file_name().to_string_lossy().to_string();
+        let meta = match std::fs::symlink_metadata(entry.path()) {
+            Ok(meta) => meta,
+            // The entry was removed while the directory was read.
+            Err(_) => continue,
+        };
+        result.push((
+            name.clone(),
+            directory_entry(name.clone(), &short.join(&name), &meta),
+        ));
+    }
+    result.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(Expression::List(
+        result.into_iter().map(|(_, entry)| entry).collect(),
+    ))
+}
+
+/// Describe an entry of a directory for `fs@ls`, with the columns in the
+/// order the REPL shows them in.
+fn directory_entry(name: String, path: &Path, meta: &std::fs::Metadata) -> Expression {
+    let file_type = if meta.file_type().is_symlink() {
+        "link"
+    } else if meta.is_dir() {
+        "dir"
+    } else {
+        "file"
+    };
+    let mut entry = IndexMap::new();
+    entry.insert(String::from("name"), Expression::from(name));
+    entry.insert(String::from("type"), Expression::from(file_type));
+    entry.insert(String::from("size"), Expression::Integer(meta.len() as Int));
+    entry.insert(String::from("mode"), Expression::from(file_mode(meta)));
+    entry.insert(
+        String::from("mtime"),
+        meta.modified()
+            .ok()
+            .and_then(|m| m.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|m| Expression::Integer(m.as_secs() as Int))
+            .unwrap_or(Expression::None),
+    );
+    entry.insert(
+        String::from("path"),
+        Expression::from(path.display().to_string()),
+    );
+    Expression::from(entry)
+}
+
+/// The permissions of a file like `ls -l` shows them, like `rwxr-xr-x`.
+/// Without Unix permissions, only whether the file is read-only is known.
+fn file_mode(meta: &std::fs::Metadata) -> String {
+    #[cfg(unix)]
+    let bits = {
+        use std::os::unix::fs::PermissionsExt;
+        meta.permissions().mode()
+    };
+    #[cfg(not(unix))]
+    let bits = if meta.permissions().readonly() {
+        0o444
+    } else {
+        0o666
+    };
+    "rwxrwxrwx"
+        .chars()
+        .enumerate()
+        .map(|(i, c)| if bits & (0o400 >> i) != 0 { c } else { '-' })
+        .collect()
+}
+
+/// List the entries of directories like `ls`, for systems without it. Hidden
+/// entries are only listed with `-a`.
+pub(super) fn ls(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    let cwd = PathBuf::from(env.get_cwd());
+    let mut all = false;
+    let mut paths = vec![];
+    for arg in args {
+        match arg.eval(env)?.to_string() {
+            flag if flag.starts_with('-') => all = all || flag.contains('a'),
+            path => paths.push(path),
+        }
+    }
+    if paths.is_empty() {
+        paths.push(String::from("."));
+    }
+
+    for (i, path) in paths.iter().enumerate() {
+        let dir = cwd.join(path);
+        if !dir.is_dir() {
+            if dir.exists() {
+                println!("{}", path);
+                continue;
             }
-        } else {
-            // If we cannot read the directory's entries, throw an error.
-            return Err(Error::CustomError(format!(
-                "could not read directory {}",
-                dir.display()
-            )));
+            return Err(Error::CustomError(format!("{} does not exist", path)));
+        }
+        if paths.len() > 1 {
+            println!("{}{}:", if i > 0 { "\n" } else { "" }, path);
         }
 
-        // Return the list of paths as a list.
-        Ok(result.into())
-    } else if dir.is_file() {
-        // If the path is a file, return the file's name as a string in a list.
-        return Ok(Expression::List(vec![format!("{}", dir.display()).into()]));
-    } else {
-        // Otherwise, the path is neither a file nor a directory, so throw an error.
-        return Err(Error::CustomError(format!(
-            "{} does not exist",
-            dir.display()
-        )));
+        let mut entries = std::fs::read_dir(&dir)
+            .map_err(|e| Error::CustomError(format!("could not read directory {}: {}", path, e)))?
+            .flatten()
+            .map(|entry| {
+                let is_dir = entry.path().is_dir();
+                (entry.file_name().to_string_lossy().to_string(), is_dir)
+            })
+            .filter(|(name, _)| all || !name.starts_with('.'))
+            .collect::<Vec<_>>();
+        entries.sort();
+        for (name, is_dir) in entries {
+            if is_dir {
+                println!(
+                    "{}",
+                    dune::theme()
+                        .directory
+                        .paint(format!("{}{}", name, std::path::MAIN_SEPARATOR))
+                );
+            } else {
+                println!("{}", name);
+            }
+        }
+    }
+    Ok(Expression::None)
+}
+
+/// Print the contents of files like `cat`, for systems without it.
+pub(super) fn cat(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    let cwd = PathBuf::from(env.get_cwd());
+    for arg in args {
+        let file = arg.eval(env)?.to_string();
+        let contents = std::fs::read(cwd.join(&file))
+            .map_err(|e| Error::CustomError(format!("could not read file {}: {}", file, e)))?;
+        print!("{}", String::from_utf8_lossy(&contents));
     }
+    Ok(Expression::None)
 }
@@ -0,0 +1,589 @@
+use super::format_size;
+use common_macros::b_tree_map;
+use dune::{Environment, Error, Expression, Int};
+use indexmap::IndexMap;
+use std::{
+    io::{BufRead, BufReader, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How often the progress of a download is reported.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+pub fn get() -> Expression {
+    (b_tree_map! {
+        String::from("download") => Expression::builtin("download", download,
+            "download a url to a file, like `http@download url \"out.tar.gz\" {resume=True, sha256=\"...\", progress=(p -> echo p@downloaded)}`, where the file is only written once it's downloaded and matches `sha256`, and `resume` continues an unfinished download of it"),
+        String::from("fetch-verified") => Expression::builtin("fetch-verified", fetch_verified,
+            "download a url to a file only if its SHA-256 checksum matches, replacing the file all at once, like `http@fetch-verified url \"...\" \"bin/tool\"`"),
+        String::from("serve") => Expression::builtin("serve", serve,
+            "serve the files in a directory over HTTP until q is pressed, like `http@serve \".\" {port=8080, host=\"127.0.0.1\", hidden=False}`, only to this computer unless `host` is \"0.0.0.0\", and without hidden files unless `hidden` is set"),
+    })
+    .into()
+}
+
+fn download(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("download", &args, 2..=3)?;
+    let url = args[0].eval(env)?.to_string();
+    let dest = args[1].eval(env)?.to_string();
+    let options = match args.get(2).map(|options| options.eval(env)).transpose()? {
+        Some(Expression::Map(options)) => options,
+        Some(otherwise) => {
+            return Err(Error::CustomError(format!(
+                "expected a map of options like {{resume=True}}, but got {}",
+                otherwise
+            )))
+        }
+        None => IndexMap::new(),
+    };
+    let resume = options
+        .get("resume")
+        .map(Expression::is_truthy)
+        .unwrap_or(false);
+    let progress = options.get("progress").cloned();
+    let path = PathBuf::from(env.get_cwd()).join(&dest);
+
+    // Download next to the destination, and only move the file there once it's
+    // complete and verified. An unfinished download is kept to be resumed.
+    let mut temp = path.clone().into_os_string();
+    temp.push(".part");
+    let temp = PathBuf::from(temp);
+
+    let started = Instant::now();
+    if let Err(e) = fetch(&url, &temp, resume, &progress, env) {
+        if !resume {
+            let _ = std::fs::remove_file(&temp);
+        }
+        return Err(e);
+    }
+    let verified = std::fs::read(&temp)
+        .map_err(|e| Error::CustomError(format!("could not read {}: {}", dest, e)))
+        .and_then(|contents| {
+            let digest = match options.get("sha256") {
+                Some(expected) => check_sha256(&dest, &contents, &expected.to_string())?,
+                None => sha256(&contents),
+            };
+            std::fs::rename(&temp, &path)
+                .map_err(|e| Error::CustomError(format!("could not write {}: {}", dest, e)))?;
+            Ok((contents.len(), digest))
+        });
+    // A download that doesn't match can't be finished by resuming it.
+    let (bytes, digest) = verified.inspect_err(|_| {
+        let _ = std::fs::remove_file(&temp);
+    })?;
+
+    Ok(b_tree_map! {
+        String::from("path") => Expression::from(dest),
+        String::from("bytes") => Expression::Integer(bytes as Int),
+        String::from("seconds") => Expression::from(started.elapsed().as_secs_f64()),
+        String::from("sha256") => Expression::from(digest),
+    }
+    .into())
+}
+
+fn fetch_verified(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("fetch-verified", &args, 3)?;
+    let url = args[0].eval(env)?.to_string();
+    let expected = args[1].eval(env)?.to_string();
+    let dest = args[2].eval(env)?.to_string();
+    let path = PathBuf::from(env.get_cwd()).join(&dest);
+
+    // Download next to the destination, so that renaming it over the
+    // destination can't leave a partial or unverified file behind.
+    let mut temp = path.clone().into_os_string();
+    temp.push(format!(".{}.part", std::process::id()));
+    let temp = PathBuf::from(temp);
+
+    let started = Instant::now();
+    let verified = fetch(&url, &temp, false, &None, env).and_then(|_| {
+        let contents = std::fs::read(&temp)
+            .map_err(|e| Error::CustomError(format!("could not read {}: {}", url, e)))?;
+        let digest = check_sha256(&url, &contents, &expected)?;
+        std::fs::rename(&temp, &path)
+            .map_err(|e| Error::CustomError(format!("could not write {}: {}", dest, e)))?;
+        Ok((contents.len(), digest))
+    });
+    let (bytes, digest) = verified.inspect_err(|_| {
+        let _ = std::fs::remove_file(&temp);
+    })?;
+
+    Ok(b_tree_map! {
+        String::from("path") => Expression::from(dest),
+        String::from("bytes") => Expression::Integer(bytes as Int),
+        String::from("seconds") => Expression::from(started.elapsed().as_secs_f64()),
+        String::from("sha256") => Expression::from(digest),
+    }
+    .into())
+}
+
+/// Download a url to a path, reporting its progress as it goes.
+fn fetch(
+    url: &str,
+    path: &Path,
+    resume: bool,
+    progress: &Option<Expression>,
+    env: &mut Environment,
+) -> Result<(), Error> {
+    // We leave speaking HTTP to curl, and keep an eye on the file it writes.
+    let total = content_length(url);
+    let mut curl = Command::new("curl");
+    curl.args(["--fail", "--silent", "--show-error", "--location"]);
+    if resume && path.exists() {
+        curl.args(["--continue-at", "-"]);
+    }
+    let mut child = curl
+        .arg("--output")
+        .arg(path)
+        // A url starting with `-` is still a url, not an option.
+        .arg("--url")
+        .arg(url)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| {
+            Error::CustomError(format!("could not run curl to download {}: {}", url, e))
+        })?;
+
+    let status = loop {
+        if let Some(status) = child.try_wait().map_err(|e| {
+            Error::CustomError(format!("could not wait for the download of {}: {}", url, e))
+        })? {
+            break status;
+        }
+        let downloaded = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+        report_progress(progress, downloaded, total, env)?;
+        thread::sleep(PROGRESS_INTERVAL);
+    };
+    let downloaded = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    if status.success() {
+        report_progress(progress, downloaded, total.or(Some(downloaded)), env)?;
+    }
+    if progress.is_none() {
+        eprintln!();
+    }
+
+    if !status.success() {
+        let mut message = String::new();
+        if let Some(mut stderr) = child.stderr.take() {
+            let _ = stderr.read_to_string(&mut message);
+        }
+        return Err(Error::CustomError(format!(
+            "could not download {}: {}",
+            url,
+            message.trim()
+        )));
+    }
+    Ok(())
+}
+
+/// Ask the server how big a download will be, if it knows.
+fn content_length(url: &str) -> Option<u64> {
+    let output = Command::new("curl")
+        .args(["--silent", "--head", "--location", "--url", url])
+        .stdin(Stdio::null())
+        .output()
+        .ok()?;
+    // After redirects, the last response is the one for the file itself.
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .filter(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .filter_map(|(_, value)| value.trim().parse().ok())
+        .next_back()
+}
+
+/// Pass the progress of a download to the `progress` hook, or draw a progress bar without one.
+fn report_progress(
+    hook: &Option<Expression>,
+    downloaded: u64,
+    total: Option<u64>,
+    env: &mut Environment,
+) -> Result<(), Error> {
+    match hook {
+        Some(hook) => {
+            let progress = Expression::from(b_tree_map! {
+                String::from("downloaded") => Expression::Integer(downloaded as Int),
+                String::from("total") => total.map(|t| Expression::Integer(t as Int)).unwrap_or(Expression::None),
+            });
+            Expression::Apply(
+                Box::new(hook.clone()),
+                vec![Expression::Quote(Box::new(progress))],
+            )
+            .eval(env)?;
+        }
+        None => {
+            let size = format_size(downloaded as f64);
+            let line = match total {
+                Some(total) if total > 0 => {
+                    let fraction = (downloaded as f64 / total as f64).min(1.0);
+                    let filled = (fraction * 30.0) as usize;
+                    format!(
+                        "[{}{}] {:>3}% {} / {}",
+                        "=".repeat(filled),
+                        " ".repeat(30 - filled),
+                        (fraction * 100.0) as u8,
+                        size,
+                        format_size(total as f64)
+                    )
+                }
+                _ => size,
+            };
+            eprint!("\r\x1b[K{}", line);
+            let _ = std::io::stderr().flush();
+        }
+    }
+    Ok(())
+}
+
+fn serve(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("serve", &args, 1..=2)?;
+    let dir = args[0].eval(env)?.to_string();
+    let options = match args.get(1).map(|options| options.eval(env)).transpose()? {
+        Some(Expression::Map(options)) => options,
+        Some(otherwise) => {
+            return Err(Error::CustomError(format!(
+                "expected a map of options like {{port=8080}}, but got {}",
+                otherwise
+            )))
+        }
+        None => Default::default(),
+    };
+    let port = match options.get("port") {
+        Some(Expression::Integer(port)) if (0..=65535).contains(port) => *port as u16,
+        Some(otherwise) => {
+            return Err(Error::CustomError(format!(
+                "expected a port number, but got {}",
+                otherwise
+            )))
+        }
+        None => 8080,
+    };
+    // Only this computer can see the files, unless they're shared on purpose.
+    let host = match options.get("host") {
+        Some(Expression::String(host)) => host.clone(),
+        Some(otherwise) => {
+            return Err(Error::CustomError(format!(
+                "expected an address to listen on, like \"0.0.0.0\", but got {}",
+                otherwise
+            )))
+        }
+        None => String::from("127.0.0.1"),
+    };
+    let hidden = options
+        .get("hidden")
+        .map(Expression::is_truthy)
+        .unwrap_or(false);
+    let root = dunce::canonicalize(PathBuf::from(env.get_cwd()).join(&dir))
+        .map_err(|e| Error::CustomError(format!("could not serve {}: {}", dir, e)))?;
+    if !root.is_dir() {
+        return Err(Error::CustomError(format!("{} is not a directory", dir)));
+    }
+
+    let error = |e: std::io::Error| Error::CustomError(format!("could not serve {}: {}", dir, e));
+    let listener = TcpListener::bind((host.as_str(), port)).map_err(error)?;
+    listener.set_nonblocking(true).map_err(error)?;
+    let address = listener.local_addr().map_err(error)?;
+    println!(
+        "Serving {} at http://{}/ (press q to stop)",
+        root.display(),
+        if address.ip().is_unspecified() {
+            format!("localhost:{}", address.port())
+        } else {
+            address.to_string()
+        }
+    );
+
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let root = root.clone();
+                thread::spawn(move || {
+                    let _ = stream.set_nonblocking(false);
+                    let _ = respond(stream, &root, hidden);
+                });
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                if super::time_module::stop_pressed(Duration::from_millis(50)).map_err(error)? {
+                    return Ok(Expression::None);
+                }
+            }
+            Err(e) => return Err(error(e)),
+        }
+    }
+}
+
+/// Answer a single request for a file or directory under `root`. Hidden files,
+/// and the files in hidden directories, aren't found unless `hidden` is set.
+fn respond(mut stream: TcpStream, root: &Path, hidden: bool) -> std::io::Result<()> {
+    let mut request = String::new();
+    BufReader::new(&stream).read_line(&mut request)?;
+    let mut parts = request.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method.to_string(), target.to_string()),
+        _ => return Ok(()),
+    };
+    let raw_path = target.split(['?', '#']).next().unwrap_or("/");
+    let path = percent_decode(raw_path);
+
+    let (status, content_type, body) = if method != "GET" && method != "HEAD" {
+        (405, "text/plain", b"method not allowed".to_vec())
+    } else {
+        // Don't let `..` or symlinks escape the directory being served.
+        match dunce::canonicalize(root.join(path.trim_start_matches('/')))
+            .ok()
+            .filter(|file| file.starts_with(root))
+            .filter(|file| hidden || !is_hidden(file.strip_prefix(root).unwrap_or(file)))
+        {
+            Some(file) if file.is_dir() && !path.ends_with('/') => {
+                let location = format!("{}/", raw_path);
+                write!(
+                    stream,
+                    "HTTP/1.1 301 Moved Permanently\r\nLocation: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    location
+                )?;
+                println!("{} {} 301\r", method, path);
+                return Ok(());
+            }
+            Some(file) if file.is_dir() && file.join("index.html").is_file() => (
+                200,
+                "text/html; charset=utf-8",
+                std::fs::read(file.join("index.html"))?,
+            ),
+            Some(file) if file.is_dir() => (
+                200,
+                "text/html; charset=utf-8",
+                listing(&file, &path, hidden).into_bytes(),
+            ),
+            Some(file) => match std::fs::read(&file) {
+                Ok(contents) => (200, mime_type(&file), contents),
+                Err(_) => (403, "text/plain", b"forbidden".to_vec()),
+            },
+            None => (404, "text/plain", b"not found".to_vec()),
+        }
+    };
+
+    let reason = match status {
+        200 => "OK",
+        403 => "Forbidden",
+        404 => "Not Found",
+        _ => "Method Not Allowed",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        reason,
+        content_type,
+        body.len()
+    )?;
+    if method != "HEAD" {
+        stream.write_all(&body)?;
+    }
+    // The terminal might be in raw mode while we wait for a key, so end the line ourselves.
+    println!("{} {} {}\r", method, path, status);
+    Ok(())
+}
+
+/// Decode the `%20`-style escapes in a URL path.
+fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        match (bytes[i], path.get(i + 1..i + 3)) {
+            (b'%', Some(hex)) if u8::from_str_radix(hex, 16).is_ok() => {
+                decoded.push(u8::from_str_radix(hex, 16).unwrap());
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// Escape the characters in a file name that can't be in a URL path.
+fn percent_encode(name: &str) -> String {
+    name.bytes()
+        .map(|byte| match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (byte as char).to_string()
+            }
+            byte => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// Is a file, or a directory it's in, hidden, like `.git` or `.env`?
+fn is_hidden(path: &Path) -> bool {
+    path.components()
+        .any(|part| part.as_os_str().to_string_lossy().starts_with('.'))
+}
+
+/// An HTML page listing the files in a directory.
+fn listing(dir: &Path, path: &str, hidden: bool) -> String {
+    let escape = |text: &str| {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+    };
+    let mut names = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| hidden || !is_hidden(Path::new(&entry.file_name())))
+        .map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if entry.path().is_dir() {
+                name + "/"
+            } else {
+                name
+            }
+        })
+        .collect::<Vec<_>>();
+    names.sort();
+
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Index of {0}</title></head>\n<body>\n<h1>Index of {0}</h1>\n<ul>\n",
+        escape(path)
+    );
+    if path != "/" {
+        html += "<li><a href=\"../\">../</a></li>\n";
+    }
+    for name in names {
+        html += &format!(
+            "<li><a href=\"{}\">{}</a></li>\n",
+            escape(&percent_encode(&name)),
+            escape(&name)
+        );
+    }
+    html + "</ul>\n</body>\n</html>\n"
+}
+
+/// Guess the MIME type of a file from its extension.
+fn mime_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "txt" | "md" | "rs" | "toml" | "yaml" | "yml" | "csv" | "log" => {
+            "text/plain; charset=utf-8"
+        }
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "ico" => "image/x-icon",
+        "webp" => "image/webp",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "tar" => "application/x-tar",
+        "mp3" => "audio/mpeg",
+        "wav" => "audio/wav",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Check the SHA-256 digest of some bytes against the one expected, in hex
+/// of either case, getting the digest if it matches.
+pub(crate) fn check_sha256(name: &str, bytes: &[u8], expected: &str) -> Result<String, Error> {
+    let digest = sha256(bytes);
+    let expected = expected.trim().to_lowercase();
+    if expected != digest {
+        return Err(Error::CustomError(format!(
+            "the checksum of {} is {}, but expected {}",
+            name, digest, expected
+        )));
+    }
+    Ok(digest)
+}
+
+/// The SHA-256 digest of some bytes, as hex.
+pub(crate) fn sha256(bytes: &[u8]) -> String {
+    const K: [u32; 64] = [
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4,
+        0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe,
+        0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f,
+        0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7,
+        0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc,
+        0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b,
+        0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116,
+        0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+        0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7,
+        0xc67178f2,
+    ];
+    let mut h: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+
+    // Pad the message to a multiple of 64 bytes, ending with its length in bits.
+    let mut message = bytes.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((bytes.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut hh] = h;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = hh
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            hh = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (h, x) in h.iter_mut().zip([a, b, c, d, e, f, g, hh]) {
+            *h = h.wrapping_add(x);
+        }
+    }
+    h.iter().map(|word| format!("{:08x}", word)).collect()
+}
@@ -26,6 +26,16 @@ pub fn get() -> Expression {
             "fold a list from the left"),
         String::from("foldr") => Expression::builtin("foldr", foldr,
             "fold a list from the right"),
+        String::from("fold") => Expression::builtin("fold", fold,
+            "combine the items of a list into one value, starting from an initial value, like `list@fold (acc -> x -> acc + x) 0 [1, 2, 3]`, stopping early at `list@stop value`"),
+        String::from("reduce") => Expression::builtin("reduce", reduce,
+            "combine the items of a list into one value, starting from the first item, like `list@reduce (a -> b -> a * b) [1, 2, 3]`, stopping early at `list@stop value`"),
+        String::from("scan") => Expression::builtin("scan", scan,
+            "like `list@fold`, but get the list of every value along the way, like `list@scan (acc -> x -> acc + x) 0 [1, 2, 3]` is `[1, 3, 6]`"),
+        String::from("find") => Expression::builtin("find", find,
+            "get the first item of a list a function returns True for, or None, like `list@find (x -> x > 2) [1, 5, 3]`"),
+        String::from("stop") => Expression::builtin("stop", stop,
+            "return `list@stop value` from the function given to `list@fold`, `list@reduce`, or `list@scan` to stop early with that value"),
         String::from("zip") => Expression::builtin("zip", zip,
             "zip two lists together"),
         String::from("unzip") => Expression::builtin("unzip", unzip,
@@ -226,6 +236,114 @@ fn foldl(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Err
     }
 }
 
+/// The key of the map `list@stop` wraps a value in, to stop folding early.
+const STOP: &str = "__stop";
+
+fn stop(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("stop", &args, 1)?;
+    Ok(Expression::Map(
+        [(STOP.to_string(), args[0].eval(env)?)]
+            .into_iter()
+            .collect(),
+    ))
+}
+
+/// Get the value wrapped by `list@stop`, if this is one.
+fn stopped(value: &Expression) -> Option<Expression> {
+    match value {
+        Expression::Map(map) if map.len() == 1 => map.get(STOP).cloned(),
+        _ => None,
+    }
+}
+
+/// Get the function and the list of a fold, checking that the list is one.
+fn fold_args(
+    name: &str,
+    f: &Expression,
+    list: &Expression,
+    env: &mut Environment,
+) -> Result<(Expression, Vec<Expression>), Error> {
+    let f = f.eval(env)?;
+    match list.eval(env)? {
+        Expression::List(list) => Ok((f, list)),
+        otherwise => Err(Error::CustomError(format!(
+            "{} expects a list, but got {}",
+            name, otherwise
+        ))),
+    }
+}
+
+/// Fold the items of a list into an accumulator, calling `step` with each
+/// value along the way, until the list runs out or `list@stop` is returned.
+fn fold_with(
+    f: &Expression,
+    mut acc: Expression,
+    list: Vec<Expression>,
+    env: &mut Environment,
+    mut step: impl FnMut(&Expression),
+) -> Result<Expression, Error> {
+    for item in list {
+        acc = Expression::Apply(
+            Box::new(f.clone()),
+            vec![
+                Expression::Quote(Box::new(acc)),
+                Expression::Quote(Box::new(item)),
+            ],
+        )
+        .eval(env)?;
+        if let Some(value) = stopped(&acc) {
+            step(&value);
+            return Ok(value);
+        }
+        step(&acc);
+    }
+    Ok(acc)
+}
+
+fn fold(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("fold", &args, 3)?;
+    let init = args[1].eval(env)?;
+    let (f, list) = fold_args("fold", &args[0], &args[2], env)?;
+    fold_with(&f, init, list, env, |_| {})
+}
+
+fn reduce(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("reduce", &args, 2)?;
+    let (f, mut list) = fold_args("reduce", &args[0], &args[1], env)?;
+    if list.is_empty() {
+        return Err(Error::CustomError(
+            "cannot reduce an empty list, because it has no first item to start from".to_string(),
+        ));
+    }
+    let first = list.remove(0);
+    fold_with(&f, first, list, env, |_| {})
+}
+
+fn scan(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("scan", &args, 3)?;
+    let init = args[1].eval(env)?;
+    let (f, list) = fold_args("scan", &args[0], &args[2], env)?;
+    let mut values = vec![];
+    fold_with(&f, init, list, env, |value| values.push(value.clone()))?;
+    Ok(Expression::List(values))
+}
+
+fn find(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("find", &args, 2)?;
+    let (f, list) = fold_args("find", &args[0], &args[1], env)?;
+    for item in list {
+        let found = Expression::Apply(
+            Box::new(f.clone()),
+            vec![Expression::Quote(Box::new(item.clone()))],
+        )
+        .eval(env)?;
+        if found.is_truthy() {
+            return Ok(item);
+        }
+    }
+    Ok(Expression::None)
+}
+
 fn foldr(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
     if args.len() != 3 {
         return Err(Error::CustomError(
@@ -23,7 +23,7 @@ fn is_log_level_enabled(level: Int) -> bool {
 
 pub fn get() -> Expression {
     (b_tree_map! {
-        String::from("level") => Expression::Map(b_tree_map! {
+        String::from("level") => Expression::from(b_tree_map! {
             String::from("none") => Expression::Integer(NONE),
             String::from("trace") => Expression::Integer(TRACE),
             String::from("debug") => Expression::Integer(DEBUG),
@@ -71,9 +71,9 @@ pub fn get() -> Expression {
 
             // Like `echo`, but with green formatting and an `[INFO]` prefix on new lines.
             let mut last_was_newline = true;
-            let prefix = "\x1b[92m[INFO]\x1b[m ";
+            let prefix = dune::theme().info.paint("[INFO]") + " ";
             for (i, arg) in args.iter().enumerate() {
-                let x = arg.clone().eval(env)?.to_string();
+                let x = dune::redact(&arg.clone().eval(env)?.to_string());
                 // Split into lines and print each line with a green `[INFO]` prefix.
                 let lines = x.split('\n').collect::<Vec<&str>>();
                 if lines.len() > 1 {
@@ -108,9 +108,9 @@ pub fn get() -> Expression {
 
             // Like `echo`, but with green formatting and an `[INFO]` prefix on new lines.
             let mut last_was_newline = true;
-            let prefix = "\x1b[93m[WARN]\x1b[m ";
+            let prefix = dune::theme().warning.paint("[WARN]") + " ";
             for (i, arg) in args.iter().enumerate() {
-                let x = arg.clone().eval(env)?.to_string();
+                let x = dune::redact(&arg.clone().eval(env)?.to_string());
                 // Split into lines and print each line with a green `[INFO]` prefix.
                 let lines = x.split('\n').collect::<Vec<&str>>();
                 if lines.len() > 1 {
@@ -146,9 +146,9 @@ pub fn get() -> Expression {
 
             // Like `echo`, but with green formatting and an `[INFO]` prefix on new lines.
             let mut last_was_newline = true;
-            let prefix = "\x1b[94m[DEBUG]\x1b[m ";
+            let prefix = dune::theme().debug.paint("[DEBUG]") + " ";
             for (i, arg) in args.iter().enumerate() {
-                let x = arg.clone().eval(env)?.to_string();
+                let x = dune::redact(&arg.clone().eval(env)?.to_string());
                 // Split into lines and print each line with a green `[INFO]` prefix.
                 let lines = x.split('\n').collect::<Vec<&str>>();
                 if lines.len() > 1 {
@@ -183,9 +183,9 @@ pub fn get() -> Expression {
 
             // Like `echo`, but with green formatting and an `[INFO]` prefix on new lines.
             let mut last_was_newline = true;
-            let prefix = "\x1b[91m[ERROR]\x1b[m ";
+            let prefix = dune::theme().error.paint("[ERROR]") + " ";
             for (i, arg) in args.iter().enumerate() {
-                let x = arg.clone().eval(env)?.to_string();
+                let x = dune::redact(&arg.clone().eval(env)?.to_string());
                 // Split into lines and print each line with a green `[INFO]` prefix.
                 let lines = x.split('\n').collect::<Vec<&str>>();
                 if lines.len() > 1 {
@@ -219,9 +219,9 @@ pub fn get() -> Expression {
 
             // Like `echo`, but with green formatting and an `[INFO]` prefix on new lines.
             let mut last_was_newline = true;
-            let prefix = "\x1b[95m[TRACE]\x1b[m ";
+            let prefix = dune::theme().trace.paint("[TRACE]") + " ";
             for (i, arg) in args.iter().enumerate() {
-                let x = arg.clone().eval(env)?.to_string();
+                let x = dune::redact(&arg.clone().eval(env)?.to_string());
                 // Split into lines and print each line with a green `[INFO]` prefix.
                 let lines = x.split('\n').collect::<Vec<&str>>();
                 if lines.len() > 1 {
@@ -252,7 +252,7 @@ pub fn get() -> Expression {
         String::from("echo") => Expression::builtin("echo", |args, env| {
             // Like `echo`, but with no formatting.
             for (i, arg) in args.iter().enumerate() {
-                let x = arg.clone().eval(env)?.to_string();
+                let x = dune::redact(&arg.clone().eval(env)?.to_string());
                 if i < args.len() - 1 {
                     print!("{} ", x)
                 } else {
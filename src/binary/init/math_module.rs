@@ -77,16 +77,16 @@ pub fn get(env: &mut Environment) -> Expression {
 
 
         String::from("sum") => Expression::builtin("sum", |args, env| {
-            let mut int_sum = 0;
+            let mut int_sum = Expression::Integer(0);
             let mut float_sum = 0.0;
             for arg in &args {
                 match arg.eval(env)? {
-                    Expression::Integer(i) => int_sum += i,
+                    Expression::Integer(i) => int_sum = (int_sum + Expression::Integer(i))?,
                     Expression::Float(f) => float_sum += f,
                     Expression::List(list) => {
                         for item in list {
                             match item.eval(env)? {
-                                Expression::Integer(i) => int_sum += i,
+                                Expression::Integer(i) => int_sum = (int_sum + Expression::Integer(i))?,
                                 Expression::Float(f) => float_sum += f,
                                 e => return Err(Error::CustomError(format!("invalid sum argument {:?}", e)))
                             }
@@ -97,24 +97,24 @@ pub fn get(env: &mut Environment) -> Expression {
             }
 
             if float_sum == 0.0 {
-                Ok(int_sum.into())
+                Ok(int_sum)
             } else {
-                Ok((int_sum as f64 + float_sum).into())
+                int_sum + Expression::Float(float_sum)
             }
         }, "sum a list of numbers"),
 
         String::from("product") => Expression::builtin("product", |args, env| {
-            let mut int_product = 1;
+            let mut int_product = Expression::Integer(1);
             let mut float_product = 1.0;
 
             for arg in &args {
                 match arg.eval(env)? {
-                    Expression::Integer(i) => int_product *= i,
+                    Expression::Integer(i) => int_product = (int_product * Expression::Integer(i))?,
                     Expression::Float(f) => float_product *= f,
                     Expression::List(list) => {
                         for item in list {
                             match item.eval(env)? {
-                                Expression::Integer(i) => int_product *= i,
+                                Expression::Integer(i) => int_product = (int_product * Expression::Integer(i))?,
                                 Expression::Float(f) => float_product *= f,
                                 e => return Err(Error::CustomError(format!("invalid product argument {:?}", e)))
                             }
@@ -125,9 +125,9 @@ pub fn get(env: &mut Environment) -> Expression {
             }
 
             if float_product == 1.0 {
-                Ok(int_product.into())
+                Ok(int_product)
             } else {
-                Ok((int_product as f64 * float_product).into())
+                int_product * Expression::Float(float_product)
             }
         }, "multiply a list of numbers"),
 
@@ -138,11 +138,11 @@ pub fn get(env: &mut Environment) -> Expression {
                     if i < 0 {
                         Err(Error::CustomError("cannot take the factorial of a negative number".to_string()))
                     } else {
-                        let mut result = 1;
+                        let mut result = Expression::Integer(1);
                         for n in 1..=i {
-                            result *= n;
+                            result = (result * Expression::Integer(n))?;
                         }
-                        Ok(result.into())
+                        Ok(result)
                     }
                 },
                 Expression::Float(f) => {
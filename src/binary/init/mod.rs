@@ -1,30 +1,45 @@
-use dune::{Builtin, Environment, Error, Expression, Int};
+use dune::{Builtin, DisplayLimits, Environment, Error, Expression, Int};
 
 use common_macros::b_tree_map;
+use indexmap::IndexMap;
+use std::{collections::BTreeSet, io::IsTerminal, time::Duration};
 
 #[cfg(feature = "chess-engine")]
 mod chess_module;
 mod console_module;
+mod db_module;
 mod dict_module;
+mod diff_module;
 mod err_module;
 mod fmt_module;
 mod fn_module;
+mod from_module;
 use fn_module::{curry, reverse_curry};
 mod fs_module;
+mod http_module;
 mod list_module;
 use list_module::*;
 mod log_module;
 mod math_module;
+mod monitor_module;
+mod net_module;
 mod operator_module;
 mod os_module;
 mod parse_module;
 mod rand_module;
 mod shell_module;
+mod store_module;
 mod string_module;
 mod sys_module;
 mod time_module;
+mod units_module;
 mod widget_module;
 
+pub use console_module::{report_cwd, set_title};
+pub(super) use fmt_module::{collapsed, format_duration, format_size};
+pub use fs_module::walk_paths;
+pub(super) use http_module::{check_sha256, sha256};
+
 pub fn init(env: &mut Environment) {
     let fs = fs_module::get(env);
     let ops = operator_module::get(env);
@@ -43,11 +58,19 @@ pub fn init(env: &mut Environment) {
         "console" => console_module::get(),
         "fmt" => fmt_module::get(),
         "parse" => parse_module::get(),
+        "from" => from_module::get(),
+        "diff" => diff_module::get(),
+        "net" => net_module::get(),
+        "http" => http_module::get(),
         "fs" => fs,
         "ops" => ops,
         "string" => string_module::get(),
         "list" => list_module::get(),
         "sys" => sys_module::get(),
+        "units" => units_module::get(),
+        "monitor" => monitor_module::get(),
+        "store" => store_module::get(),
+        "db" => db_module::get(),
     };
 
     env.define_module("std", standard_module.clone());
@@ -58,8 +81,42 @@ pub fn init(env: &mut Environment) {
 
     env.define("exit", env.get("os").unwrap()["exit"].clone());
     env.define("cd", env.get("os").unwrap()["cd"].clone());
+    env.define("shopt", env.get("sys").unwrap()["shopt"].clone());
+    env.define("watch", env.get("time").unwrap()["watch"].clone());
+    env.define("retry", env.get("err").unwrap()["retry"].clone());
+    env.define("cache", env.get("store").unwrap()["cache"].clone());
+    env.define("assert", env.get("err").unwrap()["assert"].clone());
+    env.define("assert-eq", env.get("err").unwrap()["assert-eq"].clone());
+    env.define("each", env.get("shell").unwrap()["each"].clone());
+    env.define("inspect", env.get("console").unwrap()["inspect"].clone());
+    env.define("open", env.get("fs").unwrap()["open"].clone());
+    env.define("save", env.get("fs").unwrap()["save"].clone());
     env.define("quit", env.get("exit").unwrap());
 
+    // Windows doesn't come with programs like `ls`, so use Dune's own
+    // versions of them, unless something like Git for Windows provides them.
+    if cfg!(windows) {
+        let fs = env.get("fs").unwrap();
+        for (name, fallback) in [
+            (
+                "ls",
+                Expression::builtin("ls", fs_module::ls, "list the entries of directories"),
+            ),
+            (
+                "cat",
+                Expression::builtin("cat", fs_module::cat, "print the contents of files"),
+            ),
+            ("mkdir", fs["mkdir"].clone()),
+            ("rm", fs["rm"].clone()),
+            ("cp", fs["cp"].clone()),
+            ("mv", fs["mv"].clone()),
+        ] {
+            if dune::find_program(name, env).is_none() {
+                env.define(name, fallback);
+            }
+        }
+    }
+
     env.define_builtin(
         "help",
         |args, env| {
@@ -86,11 +143,30 @@ pub fn init(env: &mut Environment) {
                     Expression::Symbol(name) if name == "syntax" => {
                         println!(include_str!("../help/syntax.txt"));
                     }
-                    otherwise => {
-                        if let Expression::Builtin(Builtin { help, .. }) = otherwise.eval(env)? {
-                            println!("{}", help)
+                    otherwise => match otherwise.eval(env)? {
+                        Expression::Builtin(Builtin { help, .. }) => println!("{}", help),
+                        // List the public members of a module, with what each one is.
+                        Expression::Map(members) => {
+                            let members = members
+                                .iter()
+                                .filter(|(name, _)| !dune::is_private(name))
+                                .collect::<Vec<_>>();
+                            let width = members
+                                .iter()
+                                .map(|(name, _)| name.chars().count())
+                                .max()
+                                .unwrap_or(0);
+                            for (name, value) in members {
+                                println!(
+                                    "{:width$}  {}",
+                                    name,
+                                    super::completion::describe_value(value),
+                                    width = width
+                                );
+                            }
                         }
-                    }
+                        _ => {}
+                    },
                 }
             }
             Ok(Expression::None)
@@ -101,12 +177,13 @@ pub fn init(env: &mut Environment) {
     env.define_builtin(
         "print",
         |args, env| {
+            let width = console_module::console_width(env).unwrap_or(120);
             for (i, arg) in args.iter().enumerate() {
                 let x = arg.clone().eval(env)?;
                 if i < args.len() - 1 {
-                    print!("{} ", x)
+                    print!("{:width$} ", x, width = width)
                 } else {
-                    print!("{}", x)
+                    print!("{:width$}", x, width = width)
                 }
             }
 
@@ -135,12 +212,13 @@ pub fn init(env: &mut Environment) {
     env.define_builtin(
         "println",
         |args, env| {
+            let width = console_module::console_width(env).unwrap_or(120);
             for (i, arg) in args.iter().enumerate() {
                 let x = arg.clone().eval(env)?;
                 if i < args.len() - 1 {
-                    print!("{} ", x)
+                    print!("{:width$} ", x, width = width)
                 } else {
-                    println!("{}", x)
+                    println!("{:width$}", x, width = width)
                 }
             }
 
@@ -150,6 +228,37 @@ pub fn init(env: &mut Environment) {
     );
     env.define("echo", env.get("println").unwrap());
 
+    env.define_builtin(
+        "abbr",
+        |args, env| {
+            let mut abbreviations = match env.get(super::abbreviations::ABBREVIATIONS) {
+                Some(Expression::Map(abbreviations)) => abbreviations,
+                _ => IndexMap::new(),
+            };
+            match args.as_slice() {
+                [] | [Expression::None] => return Ok(Expression::Map(abbreviations)),
+                [name] => {
+                    abbreviations.shift_remove(&name.to_string());
+                }
+                [name, expansion] => {
+                    let expansion = expansion.eval(env)?.to_string();
+                    abbreviations.insert(name.to_string(), Expression::String(expansion));
+                }
+                _ => {
+                    return Err(Error::CustomError(
+                        "too many arguments to function abbr".to_string(),
+                    ))
+                }
+            }
+            env.define(
+                super::abbreviations::ABBREVIATIONS,
+                Expression::Map(abbreviations),
+            );
+            Ok(Expression::None)
+        },
+        "define an abbreviation that expands as you type it, like `abbr gco \"git checkout\"`",
+    );
+
     env.define_builtin(
         "input",
         |args, env| {
@@ -195,6 +304,42 @@ pub fn init(env: &mut Environment) {
         "format an expression to a string",
     );
 
+    env.define_builtin(
+        "repr",
+        |args, env| {
+            check_exact_args_len("repr", &args, 1)?;
+            Ok(Expression::String(args[0].eval(env)?.repr()?))
+        },
+        "write a value as source code that reads back as the same value, like `repr {name=\"dune\", tags=[1, 2.5]}`",
+    );
+
+    env.define_builtin(
+        "type",
+        |args, env| {
+            check_exact_args_len("type", &args, 1)?;
+            Ok(describe(&args[0].eval(env)?))
+        },
+        "describe the type of a value as a map, like `{kind=\"list\", len=5}` or `{kind=\"lambda\", param=\"x\", captured=[\"y\"]}`",
+    );
+
+    env.define_builtin(
+        "is-a",
+        |args, env| {
+            check_exact_args_len("is-a", &args, 2)?;
+            let value = args[0].eval(env)?;
+            let kind = args[1].eval(env)?.to_string().to_lowercase();
+            Ok(Expression::Boolean(match kind.as_str() {
+                "number" => matches!(value, Expression::Integer(_) | Expression::Float(_)),
+                "function" => matches!(
+                    value,
+                    Expression::Lambda(..) | Expression::Macro(..) | Expression::Builtin(_)
+                ),
+                kind => kind == kind_of(&value),
+            }))
+        },
+        "check whether a value is of a kind, like `is-a x \"list\"`, where \"number\" and \"function\" cover the kinds of numbers and functions",
+    );
+
     env.define_builtin(
         "int",
         |args, env| match args[0].eval(env)? {
@@ -288,8 +433,8 @@ pub fn init(env: &mut Environment) {
 
     env.define_builtin(
         "vars",
-        |_, env| Ok(env.bindings.clone().into()),
-        "get a table of the defined variables",
+        |_, env| Ok(sys_module::redact_value(env.bindings().clone().into())),
+        "get a table of the defined variables, with their secrets masked",
     );
 
     env.define_builtin(
@@ -394,6 +539,171 @@ pub fn init(env: &mut Environment) {
         "evaluate an expression without changing the environment",
     );
 
+    env.define_builtin(
+        "source",
+        |args, env| {
+            check_exact_args_len("source", &args, 1)?;
+            let path = args[0].eval(env)?.to_string();
+            super::scripts::source(&path, env)
+        },
+        "evaluate a file in the current environment, like `source \"tasks/build.dn\"`, where relative paths are relative to the script running `source`",
+    );
+
+    env.define_builtin(
+        "eval-in",
+        |args, env| {
+            check_exact_args_len("eval-in", &args, 2)?;
+            let bindings = match args[0].eval(env)? {
+                Expression::Map(bindings) => bindings,
+                otherwise => {
+                    return Err(Error::CustomError(format!(
+                        "expected a map of bindings to evaluate in, but got {}",
+                        otherwise
+                    )))
+                }
+            };
+            let expr = args[1].eval(env)?;
+
+            // The expression can use the builtins, the operators, and the standard
+            // library, but none of the other bindings outside of the map.
+            let mut builtins = Environment::new();
+            for (name, value) in env.bindings() {
+                if let Expression::Builtin(_) = value {
+                    builtins.define(name, value.clone());
+                }
+            }
+            if let Some(Expression::Map(std)) = env.get("std") {
+                if let Some(Expression::Map(ops)) = std.get("ops") {
+                    builtins.extend_bindings(ops.clone());
+                }
+                builtins.extend_bindings(std);
+            }
+            builtins.set_cwd(env.get_cwd());
+            let mut sandbox = Environment::new();
+            sandbox.set_options(env.options().clone());
+            sandbox.set_parent(builtins);
+            sandbox.extend_bindings(bindings);
+
+            let result = expr.eval(&mut sandbox)?;
+            Ok(Expression::from(b_tree_map! {
+                String::from("result") => result,
+                String::from("env") => Expression::from(sandbox),
+            }))
+        },
+        "evaluate an expression with only the bindings in a map and the standard library, like `eval-in {x=1} '(x + 1)`, getting a map of its result and the bindings it ends with",
+    );
+
+    env.define_builtin(
+        "with-scope",
+        |args, env| {
+            check_exact_args_len("with-scope", &args, 1)?;
+            let outer = env.clone();
+            let result = args[0].eval(env);
+            // Go back to the directory the scope started in, if it changed.
+            if env.get_cwd() != outer.get_cwd() {
+                let _ = std::env::set_current_dir(outer.get_cwd());
+                report_cwd(&outer.get_cwd());
+            }
+            *env = outer;
+            result
+        },
+        "evaluate an expression, then undo the variables, options, and directory it changed, like `with-scope { let PATH = \"bin\"; shopt \"errexit\" True; make }`",
+    );
+
+    env.define_builtin(
+        "module",
+        |args, env| {
+            check_exact_args_len("module", &args, 1)?;
+            // The block can use everything outside of it, but only what it
+            // binds itself ends up in the module.
+            let mut scope = Environment::new();
+            scope.set_options(env.options().clone());
+            scope.set_parent(env.clone());
+            args[0].eval(&mut scope)?;
+            Ok(Expression::from(scope))
+        },
+        "evaluate a block in its own scope, getting a map of what it binds, like `let greet = module { let _punctuation = \"!\"; let hello = name -> \"hello \" + name + _punctuation }`, where names starting with `_` are private",
+    );
+
+    env.define_builtin(
+        "use",
+        |args, env| {
+            check_args_len("use", &args, 1..)?;
+            for arg in args {
+                // Packages are used by name, like `use greet` once `greet` is installed.
+                let module = match arg {
+                    Expression::Symbol(name) if !env.is_defined(&name) => use_package(&name, env)?,
+                    arg => match arg.eval(env)? {
+                        Expression::String(name) => use_package(&name, env)?,
+                        module => module,
+                    },
+                };
+                match module {
+                    Expression::Map(members) => {
+                        for (name, value) in members {
+                            if !dune::is_private(&name) {
+                                env.define(&name, value);
+                            }
+                        }
+                    }
+                    otherwise => {
+                        return Err(Error::CustomError(format!(
+                            "expected a module to use, but got {}",
+                            otherwise
+                        )))
+                    }
+                }
+            }
+            Ok(Expression::None)
+        },
+        "bind the public members of modules, or installed packages, in the current scope, like `use greet` to call `hello \"world\"` instead of `greet@hello \"world\"`",
+    );
+
+    env.define_builtin(
+        "expand",
+        |args, env| {
+            check_exact_args_len("expand", &args, 1)?;
+            // The parentheses around what to expand, or a quote, only pass it whole.
+            let expr = match &args[0] {
+                Expression::Group(inner) | Expression::Quote(inner) => inner.as_ref(),
+                expr => expr,
+            };
+            Ok(Expression::String(dune::to_source(&dune::expand_macros(
+                expr, env,
+            ))))
+        },
+        "show the code an expression runs as without running it, with its macros applied and its operators written as the functions they call, like `expand (unless False '(echo \"hi\"))`",
+    );
+
+    env.define_builtin(
+        "quasiquote",
+        |args, env| {
+            check_exact_args_len("quasiquote", &args, 1)?;
+            quasiquote(args[0].clone(), env)
+        },
+        "quote an expression, filling in the parts marked with `unquote` or `unquote-splice` with their values, like `` `(echo (unquote x)) ``",
+    );
+
+    env.define_builtin(
+        "unquote",
+        |_, _| {
+            Err(Error::CustomError(String::from(
+                "unquote can only be used inside of a quasiquote",
+            )))
+        },
+        "fill in part of a quasiquote with the value of an expression",
+    );
+
+    env.define_builtin(
+        "unquote-splice",
+        |_, _| {
+            Err(Error::CustomError(String::from(
+                "unquote-splice can only be used inside of a quasiquote",
+            )))
+        },
+        "fill in part of a list or application in a quasiquote with the items of a list",
+    );
+
     env.define_builtin(
         "exec",
         |args, env| args[0].clone().eval(env)?.eval(env),
@@ -419,17 +729,18 @@ pub fn init(env: &mut Environment) {
     );
 
     #[cfg(feature = "chess-engine")]
-    env.define_builtin("chess", chess_module::chess_fn, chess_module::HELP);
+    chess_module::define(env);
 
     env.define_builtin(
         "report",
         |args, env| {
             let val = args[0].eval(env)?;
             match val {
-                Expression::Map(_) => println!("{}", val),
-                Expression::String(s) => println!("{}", s),
+                Expression::String(s) => super::pager::print(&s, env),
                 Expression::None => {}
-                otherwise => println!("{}", otherwise),
+                otherwise => {
+                    super::pager::print(&show(&otherwise, &dune::display_limits(), env), env)
+                }
             }
 
             Ok(Expression::None)
@@ -438,6 +749,246 @@ pub fn init(env: &mut Environment) {
     );
 }
 
+/// Draw a value the way the REPL prints it, with only what's within the limits.
+fn show(value: &Expression, limits: &DisplayLimits, env: &Environment) -> String {
+    let width = console_module::console_width(env).unwrap_or(120);
+    let value = limits.apply(value);
+    // Lists of records, like the entries of a directory, are drawn a row each.
+    let text = dune::records_table(&value, width).unwrap_or_else(|| format!("{:width$}", value));
+    // Tables are drawn in the theme's colors, unless they're going somewhere else.
+    if std::io::stdout().is_terminal() {
+        dune::theme().paint_borders(&text)
+    } else {
+        text
+    }
+}
+
+/// Fill in the parts of a quasiquoted expression marked with `unquote`. Quasiquotes
+/// inside of it are left for when they're evaluated themselves.
+/// Import an installed package to `use` it.
+fn use_package(name: &str, env: &mut Environment) -> Result<Expression, Error> {
+    match crate::binary::packages::entry(name) {
+        Some(entry) => crate::binary::scripts::import(&entry.display().to_string(), env),
+        None => Err(Error::CustomError(format!(
+            "no package named `{}` is installed, install it with `dunesh pkg install <name-or-git-url>`",
+            name
+        ))),
+    }
+}
+
+fn quasiquote(expr: Expression, env: &mut Environment) -> Result<Expression, Error> {
+    // Fill in a list of expressions, splicing in the items of any `unquote-splice`.
+    fn fill(exprs: Vec<Expression>, env: &mut Environment) -> Result<Vec<Expression>, Error> {
+        let mut result = vec![];
+        for expr in exprs {
+            match unquoted(&expr) {
+                Some(("unquote-splice", value)) => match value.eval(env)? {
+                    Expression::List(items) => result.extend(items),
+                    otherwise => {
+                        return Err(Error::CustomError(format!(
+                            "expected a list to splice into a quasiquote, but got {}",
+                            otherwise
+                        )))
+                    }
+                },
+                _ => result.push(quasiquote(expr, env)?),
+            }
+        }
+        Ok(result)
+    }
+
+    match unquoted(&expr) {
+        Some(("unquote", value)) => return value.eval(env),
+        Some((name, _)) => {
+            return Err(Error::CustomError(format!(
+                "{} can only be used in a list or an application",
+                name
+            )))
+        }
+        None => {}
+    }
+    let mut fill_one = |expr: Box<Expression>| quasiquote(*expr, env).map(Box::new);
+
+    Ok(match expr {
+        Expression::Apply(f, args) => match f.as_ref() {
+            Expression::Symbol(name) if name == "quasiquote" => Expression::Apply(f, args),
+            _ => {
+                let f = fill_one(f)?;
+                Expression::Apply(f, fill(args, env)?)
+            }
+        },
+        Expression::List(items) => Expression::List(fill(items, env)?),
+        Expression::Do(exprs) => Expression::Do(fill(exprs, env)?),
+        Expression::Map(map) => Expression::Map(
+            map.into_iter()
+                .map(|(key, value)| Ok((key, quasiquote(value, env)?)))
+                .collect::<Result<_, Error>>()?,
+        ),
+        Expression::Group(inner) => Expression::Group(fill_one(inner)?),
+        Expression::Quote(inner) => Expression::Quote(fill_one(inner)?),
+        Expression::Yield(inner) => Expression::Yield(fill_one(inner)?),
+        Expression::Assign(name, value) => Expression::Assign(name, fill_one(value)?),
+        Expression::Local(name, value) => Expression::Local(name, fill_one(value)?),
+        Expression::Const(name, value) => Expression::Const(name, fill_one(value)?),
+        Expression::Lambda(param, body, captured) => {
+            Expression::Lambda(param, fill_one(body)?, captured)
+        }
+        Expression::Macro(param, body) => Expression::Macro(param, fill_one(body)?),
+        Expression::For(names, list, body) => {
+            let list = fill_one(list)?;
+            Expression::For(names, list, fill_one(body)?)
+        }
+        Expression::If(cond, then, otherwise) => {
+            let cond = fill_one(cond)?;
+            let then = fill_one(then)?;
+            Expression::If(cond, then, fill_one(otherwise)?)
+        }
+        expr => expr,
+    })
+}
+
+/// If an expression is `(unquote x)` or `(unquote-splice x)`, get which one it
+/// is and the `x` whose value fills it in.
+fn unquoted(expr: &Expression) -> Option<(&str, &Expression)> {
+    match expr {
+        Expression::Group(inner) => unquoted(inner),
+        Expression::Apply(f, args) if args.len() == 1 => match f.as_ref() {
+            Expression::Symbol(name) if name == "unquote" || name == "unquote-splice" => {
+                Some((name, &args[0]))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The name of the kind of a value, like `list` or `lambda`.
+fn kind_of(value: &Expression) -> &'static str {
+    match value {
+        Expression::Integer(_) => "integer",
+        Expression::Float(_) => "float",
+        Expression::String(_) => "string",
+        Expression::Bytes(_) => "bytes",
+        Expression::Boolean(_) => "boolean",
+        Expression::None => "none",
+        Expression::List(_) => "list",
+        Expression::Map(_) => "map",
+        Expression::Lambda(..) => "lambda",
+        Expression::Macro(..) => "macro",
+        Expression::Builtin(_) => "builtin",
+        Expression::Symbol(_) => "symbol",
+        Expression::Generator(_) => "generator",
+        Expression::Quote(_) => "quote",
+        // Only quoted code can evaluate to these.
+        _ => "expression",
+    }
+}
+
+/// The line the REPL prints under a result with `shopt annotate True`, with
+/// its type, size, and how long it took, like `list · 3 items · 12ms`.
+pub fn annotation(value: &Expression, elapsed: Duration) -> String {
+    let count = |n: usize, name: &str| format!("{} {}{}", n, name, if n == 1 { "" } else { "s" });
+    let mut parts = vec![kind_of(value).to_string()];
+    match value {
+        Expression::String(s) => parts.push(count(s.chars().count(), "char")),
+        Expression::Bytes(bytes) => parts.push(format_size(bytes.len() as f64)),
+        Expression::List(items) => parts.push(count(items.len(), "item")),
+        Expression::Map(map) => parts.push(count(map.len(), "key")),
+        _ => {}
+    }
+    parts.push(fmt_module::format_duration(elapsed.as_secs_f64()));
+    dune::theme().annotation.paint(parts.join(" · "))
+}
+
+/// Describe the type of a value, along with the details that matter for its kind.
+fn describe(value: &Expression) -> Expression {
+    let mut description = b_tree_map! {
+        String::from("kind") => Expression::from(kind_of(value)),
+    };
+    let mut detail = |name: &str, value: Expression| {
+        description.insert(name.to_string(), value);
+    };
+    match value {
+        Expression::String(s) => detail("len", Expression::Integer(s.chars().count() as Int)),
+        Expression::Bytes(bytes) => detail("len", Expression::Integer(bytes.len() as Int)),
+        Expression::List(items) => detail("len", Expression::Integer(items.len() as Int)),
+        Expression::Map(map) => {
+            detail("len", Expression::Integer(map.len() as Int));
+            detail(
+                "keys",
+                Expression::List(map.keys().cloned().map(Expression::from).collect()),
+            );
+        }
+        Expression::Lambda(param, body, captured) => {
+            detail("param", Expression::from(param.clone()));
+            // The variables the body uses from where the lambda was made,
+            // leaving out the builtin functions.
+            let mut names = BTreeSet::new();
+            symbols(body, &mut names);
+            detail(
+                "captured",
+                Expression::List(
+                    names
+                        .into_iter()
+                        .filter(|name| {
+                            name != param
+                                && !matches!(
+                                    captured.get(name),
+                                    None | Some(Expression::Builtin(_))
+                                )
+                        })
+                        .map(Expression::from)
+                        .collect(),
+                ),
+            );
+        }
+        Expression::Macro(param, _) => detail("param", Expression::from(param.clone())),
+        Expression::Builtin(Builtin { name, help, .. }) => {
+            detail("name", Expression::from(name.clone()));
+            detail("help", Expression::from(help.clone()));
+        }
+        Expression::Symbol(name) => detail("name", Expression::from(name.clone())),
+        Expression::Quote(expr) => detail("quoted", describe(expr)),
+        _ => {}
+    }
+    Expression::from(description)
+}
+
+/// Collect the names of the symbols used in some code.
+fn symbols(expr: &Expression, names: &mut BTreeSet<String>) {
+    match expr {
+        Expression::Symbol(name) => {
+            names.insert(name.clone());
+        }
+        Expression::List(exprs) | Expression::Do(exprs) => {
+            exprs.iter().for_each(|expr| symbols(expr, names))
+        }
+        Expression::Map(map) => map.values().for_each(|expr| symbols(expr, names)),
+        Expression::Group(expr)
+        | Expression::Quote(expr)
+        | Expression::Yield(expr)
+        | Expression::Assign(_, expr)
+        | Expression::Local(_, expr)
+        | Expression::Const(_, expr)
+        | Expression::Lambda(_, expr, _)
+        | Expression::Macro(_, expr) => symbols(expr, names),
+        Expression::For(_, list, body) => {
+            symbols(list, names);
+            symbols(body, names);
+        }
+        Expression::If(cond, then, otherwise) => {
+            symbols(cond, names);
+            symbols(then, names);
+            symbols(otherwise, names);
+        }
+        Expression::Apply(f, args) => {
+            symbols(f, names);
+            args.iter().for_each(|arg| symbols(arg, names));
+        }
+        _ => {}
+    }
+}
+
 fn check_args_len(
     name: impl ToString,
     args: &[Expression],
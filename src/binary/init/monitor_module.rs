@@ -0,0 +1,248 @@
+use common_macros::b_tree_map;
+use dune::{Environment, Error, Expression, Int};
+use std::{
+    collections::BTreeMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+pub fn get() -> Expression {
+    (b_tree_map! {
+        String::from("cpu") => Expression::builtin("cpu", cpu,
+            "get the load averages, and how busy the CPU and each of its cores have been since the last call as percentages, like `watch 1.0 { let cpu = monitor@cpu (); echo cpu@usage \"%\" }`"),
+        String::from("memory") => Expression::builtin("memory", memory,
+            "get the total, used, free, and available memory in bytes"),
+        String::from("swap") => Expression::builtin("swap", swap,
+            "get the total, used, and free swap space in bytes"),
+        String::from("temperatures") => Expression::builtin("temperatures", temperatures,
+            "get the temperature of each sensor in degrees Celsius"),
+        String::from("network") => Expression::builtin("network", network,
+            "get the bytes each network interface has received and sent, and how many per second since the last call"),
+        String::from("all") => Expression::builtin("all", all,
+            "get the cpu, memory, swap, temperatures, and network all at once"),
+    })
+    .into()
+}
+
+/// The busy and total time of the CPU and each core, when `cpu` was last called.
+static CPU_TIMES: Mutex<Option<Vec<(u64, u64)>>> = Mutex::new(None);
+
+/// The bytes each network interface has received and sent.
+type NetworkBytes = BTreeMap<String, (u64, u64)>;
+
+/// The bytes each interface had received and sent, when `network` was last called.
+static NETWORK_BYTES: Mutex<Option<(Instant, NetworkBytes)>> = Mutex::new(None);
+
+/// Read a file from `/proc` or `/sys`.
+fn read(path: &str) -> Result<String, Error> {
+    std::fs::read_to_string(path).map_err(|e| {
+        Error::CustomError(format!(
+            "could not read {}, system monitoring only works on Linux: {}",
+            path, e
+        ))
+    })
+}
+
+/// Round a percentage to one decimal place.
+fn percent(part: f64, whole: f64) -> Expression {
+    if whole <= 0.0 {
+        return Expression::Float(0.0);
+    }
+    Expression::Float((part / whole * 1000.0).round() / 10.0)
+}
+
+/// The busy and total time of the CPU, followed by each core.
+fn cpu_times() -> Result<Vec<(u64, u64)>, Error> {
+    Ok(read("/proc/stat")?
+        .lines()
+        .filter(|line| line.starts_with("cpu"))
+        .map(|line| {
+            let times = line
+                .split_whitespace()
+                .skip(1)
+                .map(|n| n.parse::<u64>().unwrap_or(0))
+                .collect::<Vec<_>>();
+            // The guest times are already counted in the user times.
+            let total = times.iter().take(8).sum::<u64>();
+            let idle = times.iter().skip(3).take(2).sum::<u64>();
+            (total - idle, total)
+        })
+        .collect())
+}
+
+fn cpu(args: Vec<Expression>, _env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("cpu", &args, 0..=1)?;
+    let mut last = CPU_TIMES.lock().unwrap();
+    // The first time, there's nothing to compare to, so wait a moment.
+    let before = match last.take() {
+        Some(before) => before,
+        None => {
+            let before = cpu_times()?;
+            std::thread::sleep(Duration::from_millis(100));
+            before
+        }
+    };
+    let after = cpu_times()?;
+    let usage = before
+        .iter()
+        .zip(&after)
+        .map(|((busy, total), (busy_now, total_now))| {
+            percent(
+                busy_now.saturating_sub(*busy) as f64,
+                total_now.saturating_sub(*total) as f64,
+            )
+        })
+        .collect::<Vec<_>>();
+    *last = Some(after);
+
+    let load = read("/proc/loadavg")?
+        .split_whitespace()
+        .take(3)
+        .map(|n| Expression::Float(n.parse().unwrap_or(0.0)))
+        .collect::<Vec<_>>();
+    let cores = usage.iter().skip(1).cloned().collect::<Vec<_>>();
+    Ok(Expression::from(b_tree_map! {
+        String::from("usage") => usage.first().cloned().unwrap_or(Expression::Float(0.0)),
+        String::from("cores") => Expression::List(cores.clone()),
+        String::from("count") => Expression::Integer(cores.len() as Int),
+        String::from("load") => Expression::List(load),
+    }))
+}
+
+/// The fields of `/proc/meminfo`, in bytes.
+fn meminfo() -> Result<BTreeMap<String, Int>, Error> {
+    Ok(read("/proc/meminfo")?
+        .lines()
+        .filter_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            let kib = value.split_whitespace().next()?.parse::<Int>().ok()?;
+            Some((name.to_string(), kib * 1024))
+        })
+        .collect())
+}
+
+fn memory(args: Vec<Expression>, _env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("memory", &args, 0..=1)?;
+    let info = meminfo()?;
+    let field = |name: &str| info.get(name).copied().unwrap_or(0);
+    let (total, available) = (field("MemTotal"), field("MemAvailable"));
+    Ok(Expression::from(b_tree_map! {
+        String::from("total") => Expression::Integer(total),
+        String::from("used") => Expression::Integer(total - available),
+        String::from("free") => Expression::Integer(field("MemFree")),
+        String::from("available") => Expression::Integer(available),
+        String::from("usage") => percent((total - available) as f64, total as f64),
+    }))
+}
+
+fn swap(args: Vec<Expression>, _env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("swap", &args, 0..=1)?;
+    let info = meminfo()?;
+    let field = |name: &str| info.get(name).copied().unwrap_or(0);
+    let (total, free) = (field("SwapTotal"), field("SwapFree"));
+    Ok(Expression::from(b_tree_map! {
+        String::from("total") => Expression::Integer(total),
+        String::from("used") => Expression::Integer(total - free),
+        String::from("free") => Expression::Integer(free),
+        String::from("usage") => percent((total - free) as f64, total as f64),
+    }))
+}
+
+fn temperatures(args: Vec<Expression>, _env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("temperatures", &args, 0..=1)?;
+    let mut zones = std::fs::read_dir("/sys/class/thermal")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with("thermal_zone"))
+        })
+        .collect::<Vec<_>>();
+    zones.sort();
+
+    let mut result = BTreeMap::new();
+    for zone in zones {
+        let millidegrees = match std::fs::read_to_string(zone.join("temp")) {
+            Ok(temp) => match temp.trim().parse::<f64>() {
+                Ok(temp) => temp,
+                Err(_) => continue,
+            },
+            Err(_) => continue,
+        };
+        let kind = std::fs::read_to_string(zone.join("type"))
+            .map(|kind| kind.trim().to_string())
+            .unwrap_or_else(|_| zone.display().to_string());
+        // Some machines have several sensors of the same type.
+        let mut name = kind.clone();
+        let mut n = 1;
+        while result.contains_key(&name) {
+            n += 1;
+            name = format!("{}-{}", kind, n);
+        }
+        result.insert(name, Expression::Float(millidegrees / 1000.0));
+    }
+    Ok(Expression::from(result))
+}
+
+fn network(args: Vec<Expression>, _env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("network", &args, 0..=1)?;
+    let now = Instant::now();
+    let bytes = read("/proc/net/dev")?
+        .lines()
+        .skip(2)
+        .filter_map(|line| {
+            let (name, counters) = line.split_once(':')?;
+            let counters = counters
+                .split_whitespace()
+                .map(|n| n.parse::<u64>().unwrap_or(0))
+                .collect::<Vec<_>>();
+            Some((
+                name.trim().to_string(),
+                (*counters.first()?, *counters.get(8)?),
+            ))
+        })
+        .collect::<NetworkBytes>();
+
+    let mut last = NETWORK_BYTES.lock().unwrap();
+    let result = bytes
+        .iter()
+        .map(|(name, (received, sent))| {
+            let (received_rate, sent_rate) = match &*last {
+                Some((then, before)) => {
+                    let seconds = now.duration_since(*then).as_secs_f64().max(1e-3);
+                    let (received_before, sent_before) =
+                        before.get(name).copied().unwrap_or((*received, *sent));
+                    (
+                        received.saturating_sub(received_before) as f64 / seconds,
+                        sent.saturating_sub(sent_before) as f64 / seconds,
+                    )
+                }
+                None => (0.0, 0.0),
+            };
+            (
+                name.clone(),
+                Expression::from(b_tree_map! {
+                    String::from("received") => Expression::Integer(*received as Int),
+                    String::from("sent") => Expression::Integer(*sent as Int),
+                    String::from("received-rate") => Expression::Float(received_rate.round()),
+                    String::from("sent-rate") => Expression::Float(sent_rate.round()),
+                }),
+            )
+        })
+        .collect::<BTreeMap<_, _>>();
+    *last = Some((now, bytes));
+    Ok(Expression::from(result))
+}
+
+fn all(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("all", &args, 0..=1)?;
+    Ok(Expression::from(b_tree_map! {
+        String::from("cpu") => cpu(vec![], env)?,
+        String::from("memory") => memory(vec![], env)?,
+        String::from("swap") => swap(vec![], env)?,
+        String::from("temperatures") => temperatures(vec![], env)?,
+        String::from("network") => network(vec![], env)?,
+    }))
+}
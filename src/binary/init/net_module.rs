@@ -0,0 +1,323 @@
+use common_macros::b_tree_map;
+use dune::{Environment, Error, Expression, Int};
+use indexmap::IndexMap;
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs},
+    thread,
+    time::{Duration, Instant},
+};
+
+pub fn get() -> Expression {
+    (b_tree_map! {
+        String::from("ping") => Expression::builtin("ping", ping,
+            "time connecting to a host a few times, like `net@ping \"example.com\" {count=3, port=443, timeout=1}`"),
+        String::from("port-open") => Expression::builtin("port-open", port_open,
+            "check whether a port on a host accepts connections, like `net@port-open \"localhost\" 8080`"),
+        String::from("resolve") => Expression::builtin("resolve", resolve,
+            "look up the addresses of a host name"),
+        String::from("interfaces") => Expression::builtin("interfaces", interfaces,
+            "get the IPv4 and IPv6 addresses, MAC address, and whether it's up of each network interface"),
+    })
+    .into()
+}
+
+/// Get the options a builtin was optionally called with.
+fn options(
+    args: &[Expression],
+    index: usize,
+    env: &mut Environment,
+) -> Result<IndexMap<String, Expression>, Error> {
+    match args
+        .get(index)
+        .map(|options| options.eval(env))
+        .transpose()?
+    {
+        Some(Expression::Map(options)) => Ok(options),
+        Some(otherwise) => Err(Error::CustomError(format!(
+            "expected a map of options like {{timeout=1}}, but got {}",
+            otherwise
+        ))),
+        None => Ok(IndexMap::new()),
+    }
+}
+
+/// Get how long to wait for a connection, in seconds or as a duration like `"500ms"`.
+fn timeout(options: &IndexMap<String, Expression>) -> Result<Duration, Error> {
+    let seconds = match options.get("timeout") {
+        None => 1.0,
+        Some(Expression::Integer(n)) => *n as f64,
+        Some(Expression::Float(n)) => *n,
+        Some(Expression::String(text)) => super::parse_module::duration_seconds(text)?,
+        Some(otherwise) => {
+            return Err(Error::CustomError(format!(
+                "expected a timeout in seconds, but got {}",
+                otherwise
+            )))
+        }
+    };
+    super::parse_module::duration(seconds.max(0.001))
+}
+
+fn port(value: &Expression) -> Result<u16, Error> {
+    match value {
+        Expression::Integer(n) if (0..=65535).contains(n) => Ok(*n as u16),
+        otherwise => Err(Error::CustomError(format!(
+            "expected a port number, but got {}",
+            otherwise
+        ))),
+    }
+}
+
+/// Look up the addresses of a host, and how long it took in milliseconds.
+fn lookup(host: &str, port: u16) -> Result<(Vec<SocketAddr>, f64), Error> {
+    let started = Instant::now();
+    let addresses = (host, port)
+        .to_socket_addrs()
+        .map_err(|e| Error::CustomError(format!("could not resolve {}: {}", host, e)))?
+        .collect::<Vec<_>>();
+    Ok((addresses, milliseconds(started)))
+}
+
+fn milliseconds(started: Instant) -> f64 {
+    started.elapsed().as_secs_f64() * 1000.0
+}
+
+fn resolve(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("resolve", &args, 1)?;
+    let host = args[0].eval(env)?.to_string();
+    let (addresses, ms) = lookup(&host, 0)?;
+
+    let (v4, v6): (Vec<_>, Vec<_>) = addresses.iter().partition(|address| address.is_ipv4());
+    let ips = |addresses: Vec<&SocketAddr>| {
+        Expression::List(
+            addresses
+                .iter()
+                .map(|address| Expression::from(address.ip().to_string()))
+                .collect(),
+        )
+    };
+    Ok(b_tree_map! {
+        String::from("host") => Expression::from(host),
+        String::from("ipv4") => ips(v4),
+        String::from("ipv6") => ips(v6),
+        String::from("ms") => Expression::from(ms),
+    }
+    .into())
+}
+
+fn port_open(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("port-open", &args, 2..=3)?;
+    let host = args[0].eval(env)?.to_string();
+    let port = port(&args[1].eval(env)?)?;
+    let timeout = timeout(&options(&args, 2, env)?)?;
+
+    let (addresses, _) = lookup(&host, port)?;
+    let started = Instant::now();
+    let connected = addresses
+        .iter()
+        .find(|address| TcpStream::connect_timeout(address, timeout).is_ok());
+    Ok(b_tree_map! {
+        String::from("host") => Expression::from(host),
+        String::from("port") => Expression::Integer(port as Int),
+        String::from("open") => Expression::Boolean(connected.is_some()),
+        String::from("address") => connected
+            .map(|address| Expression::from(address.ip().to_string()))
+            .unwrap_or(Expression::None),
+        String::from("ms") => Expression::from(milliseconds(started)),
+    }
+    .into())
+}
+
+/// Ping a host by timing TCP connections to it, which unlike ICMP doesn't
+/// need special privileges.
+fn ping(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("ping", &args, 1..=2)?;
+    let host = args[0].eval(env)?.to_string();
+    let options = options(&args, 1, env)?;
+    let timeout = timeout(&options)?;
+    let port = match options.get("port") {
+        Some(value) => port(value)?,
+        None => 80,
+    };
+    let count = match options.get("count") {
+        Some(Expression::Integer(n)) if *n > 0 => *n as usize,
+        Some(otherwise) => {
+            return Err(Error::CustomError(format!(
+                "expected a positive count, but got {}",
+                otherwise
+            )))
+        }
+        None => 3,
+    };
+
+    let (addresses, _) = lookup(&host, port)?;
+    let address = *addresses
+        .first()
+        .ok_or_else(|| Error::CustomError(format!("could not resolve {}", host)))?;
+    let mut times = vec![];
+    for i in 0..count {
+        if i > 0 {
+            thread::sleep(Duration::from_millis(200));
+        }
+        let started = Instant::now();
+        if TcpStream::connect_timeout(&address, timeout).is_ok() {
+            times.push(milliseconds(started));
+        }
+    }
+
+    let mut result = b_tree_map! {
+        String::from("host") => Expression::from(host),
+        String::from("address") => Expression::from(address.ip().to_string()),
+        String::from("port") => Expression::Integer(port as Int),
+        String::from("sent") => Expression::Integer(count as Int),
+        String::from("received") => Expression::Integer(times.len() as Int),
+        String::from("times") => Expression::List(times.iter().map(|ms| Expression::from(*ms)).collect()),
+    };
+    if !times.is_empty() {
+        let min = times.iter().cloned().fold(f64::INFINITY, f64::min);
+        let max = times.iter().cloned().fold(0.0, f64::max);
+        let avg = times.iter().sum::<f64>() / times.len() as f64;
+        result.insert(String::from("min"), min.into());
+        result.insert(String::from("max"), max.into());
+        result.insert(String::from("avg"), avg.into());
+    }
+    Ok(result.into())
+}
+
+/// A network interface's addresses, and its flags like whether it's up.
+#[derive(Default)]
+struct Interface {
+    addresses: Vec<IpAddr>,
+    mac: Option<String>,
+    up: bool,
+    running: bool,
+    loopback: bool,
+}
+
+fn interfaces(args: Vec<Expression>, _env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("interfaces", &args, 0..=1)?;
+    Ok(list_interfaces()?
+        .into_iter()
+        .map(|(name, interface)| {
+            let ips = |v4: bool| {
+                Expression::List(
+                    interface
+                        .addresses
+                        .iter()
+                        .filter(|address| address.is_ipv4() == v4)
+                        .map(|address| Expression::from(address.to_string()))
+                        .collect(),
+                )
+            };
+            let value = Expression::from(b_tree_map! {
+                String::from("ipv4") => ips(true),
+                String::from("ipv6") => ips(false),
+                String::from("mac") => interface.mac.clone().map(Expression::from).unwrap_or(Expression::None),
+                String::from("up") => Expression::Boolean(interface.up),
+                String::from("running") => Expression::Boolean(interface.running),
+                String::from("loopback") => Expression::Boolean(interface.loopback),
+            });
+            (name, value)
+        })
+        .collect::<BTreeMap<_, _>>()
+        .into())
+}
+
+/// List the interfaces with `getifaddrs`, which gives an entry for each
+/// address of each interface, and one with its hardware address.
+#[cfg(unix)]
+fn list_interfaces() -> Result<BTreeMap<String, Interface>, Error> {
+    use std::{
+        ffi::CStr,
+        net::{Ipv4Addr, Ipv6Addr},
+    };
+
+    let mut first: *mut libc::ifaddrs = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut first) } != 0 {
+        return Err(Error::CustomError(format!(
+            "could not list the network interfaces: {}",
+            std::io::Error::last_os_error()
+        )));
+    }
+
+    let mut interfaces = BTreeMap::<String, Interface>::new();
+    let mut next = first;
+    while let Some(entry) = unsafe { next.as_ref() } {
+        next = entry.ifa_next;
+        let name = unsafe { CStr::from_ptr(entry.ifa_name) }
+            .to_string_lossy()
+            .to_string();
+        let interface = interfaces.entry(name).or_default();
+        let flag = |flag: libc::c_int| entry.ifa_flags & flag as libc::c_uint != 0;
+        interface.up |= flag(libc::IFF_UP);
+        interface.running |= flag(libc::IFF_RUNNING);
+        interface.loopback |= flag(libc::IFF_LOOPBACK);
+
+        let address = match unsafe { entry.ifa_addr.as_ref() } {
+            Some(address) => address,
+            None => continue,
+        };
+        let hardware = match address.sa_family as libc::c_int {
+            libc::AF_INET => {
+                let address = unsafe { &*(entry.ifa_addr as *const libc::sockaddr_in) };
+                let ip = Ipv4Addr::from(u32::from_be(address.sin_addr.s_addr));
+                interface.addresses.push(IpAddr::V4(ip));
+                continue;
+            }
+            libc::AF_INET6 => {
+                let address = unsafe { &*(entry.ifa_addr as *const libc::sockaddr_in6) };
+                let ip = Ipv6Addr::from(address.sin6_addr.s6_addr);
+                interface.addresses.push(IpAddr::V6(ip));
+                continue;
+            }
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            libc::AF_PACKET => {
+                let address = unsafe { &*(entry.ifa_addr as *const libc::sockaddr_ll) };
+                let length = (address.sll_halen as usize).min(address.sll_addr.len());
+                address.sll_addr[..length].to_vec()
+            }
+            #[cfg(any(
+                target_os = "macos",
+                target_os = "ios",
+                target_os = "freebsd",
+                target_os = "openbsd",
+                target_os = "netbsd",
+                target_os = "dragonfly"
+            ))]
+            libc::AF_LINK => {
+                // The hardware address comes after the name, and can run past
+                // the end of the array it's declared as.
+                let address = unsafe { &*(entry.ifa_addr as *const libc::sockaddr_dl) };
+                let start = address.sdl_data.as_ptr() as *const u8;
+                unsafe {
+                    std::slice::from_raw_parts(
+                        start.add(address.sdl_nlen as usize),
+                        address.sdl_alen as usize,
+                    )
+                }
+                .to_vec()
+            }
+            _ => continue,
+        };
+        // Loopback and tunnel interfaces have no hardware address.
+        if hardware.iter().any(|byte| *byte != 0) {
+            interface.mac = Some(
+                hardware
+                    .iter()
+                    .map(|byte| format!("{:02x}", byte))
+                    .collect::<Vec<_>>()
+                    .join(":"),
+            );
+        }
+    }
+    unsafe { libc::freeifaddrs(first) };
+    Ok(interfaces)
+}
+
+#[cfg(not(unix))]
+fn list_interfaces() -> Result<BTreeMap<String, Interface>, Error> {
+    Err(Error::CustomError(String::from(
+        "listing the network interfaces is only supported on Unix",
+    )))
+}
@@ -1,8 +1,9 @@
 use super::{curry, reverse_curry};
-use dune::{Environment, Error, Expression};
+use dune::{status_code, was_interrupted, Environment, Error, Expression, Foreground, Int};
 use std::{
+    cmp::Ordering,
     io::Write,
-    process::{Command, Stdio},
+    process::{Command, ExitStatus, Stdio},
 };
 
 fn add_builtin(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
@@ -19,19 +20,84 @@ fn add_builtin(args: Vec<Expression>, env: &mut Environment) -> Result<Expressio
     }
 
     for arg in &args[1..] {
-        let old_result = result.clone();
-        result = result.eval(env)? + arg.clone().eval(env)?;
-
-        if let Expression::None = result {
-            return Err(Error::CustomError(format!(
-                "cannot add {:?} and {:?}",
-                old_result, arg
-            )));
-        }
+        result = arithmetic("+", result.eval(env)?, arg.clone().eval(env)?, env)?;
     }
     Ok(result)
 }
 
+/// Apply an arithmetic operator to two values, and report what went wrong
+/// instead of giving `None` when it can't be done. With the `wrapping`
+/// option, Integers wrap around instead of overflowing.
+fn arithmetic(
+    op: &str,
+    a: Expression,
+    b: Expression,
+    env: &mut Environment,
+) -> Result<Expression, Error> {
+    // A map's function for the operator is called where it's used, and its
+    // errors are the operator's errors.
+    let hook = match op {
+        "+" => Some("__add"),
+        "-" => Some("__sub"),
+        "*" => Some("__mul"),
+        "/" | "//" => Some("__div"),
+        "**" => Some("__pow"),
+        _ => None,
+    };
+    if let Some(result) = hook
+        .map(|hook| a.try_overload(hook, &b, env))
+        .transpose()?
+        .flatten()
+    {
+        return Ok(result);
+    }
+
+    if env.options().wrapping {
+        if let (Expression::Integer(m), Expression::Integer(n)) = (&a, &b) {
+            let (m, n) = (*m, *n);
+            let wrapped = match op {
+                "+" => Some(m.wrapping_add(n)),
+                "-" => Some(m.wrapping_sub(n)),
+                "*" => Some(m.wrapping_mul(n)),
+                "//" if n != 0 => Some(m.wrapping_div(n)),
+                "%" if n != 0 => Some(m.wrapping_rem(n)),
+                "**" => u32::try_from(n).ok().map(|n| m.wrapping_pow(n)),
+                _ => None,
+            };
+            if let Some(i) = wrapped {
+                return Ok(Expression::Integer(i));
+            }
+        }
+    }
+
+    let result = match op {
+        "+" => a.clone() + b.clone(),
+        "-" => a.clone() - b.clone(),
+        "*" => a.clone() * b.clone(),
+        "/" => a.clone().true_div(b.clone()),
+        "//" => a.clone() / b.clone(),
+        "%" => a.clone() % b.clone(),
+        _ => a.clone().pow(b.clone()),
+    }?;
+    if result != Expression::None {
+        return Ok(result);
+    }
+
+    Err(Error::CustomError(match op {
+        "**" => format!("cannot raise {:?} to the power of {:?}", a, b),
+        _ => {
+            let verb = match op {
+                "+" => "add",
+                "-" => "subtract",
+                "*" => "multiply",
+                "%" => "get the remainder of",
+                _ => "divide",
+            };
+            format!("cannot {} {:?} and {:?}", verb, a, b)
+        }
+    }))
+}
+
 pub fn get(env: &mut Environment) -> Expression {
     let mut tmp = Environment::new();
 
@@ -50,19 +116,17 @@ pub fn get(env: &mut Environment) -> Expression {
 
             if args.len() > 1 {
                 for arg in &args[1..] {
-                    let old_result = result.clone();
-                    result = result.eval(env)? - arg.clone().eval(env)?;
-                    if let Expression::None = result {
-                        return Err(Error::CustomError(format!(
-                            "cannot subtract {:?} and {:?}",
-                            old_result, arg
-                        )));
-                    }
+                    result = arithmetic("-", result.eval(env)?, arg.clone().eval(env)?, env)?;
                 }
                 Ok(result)
             } else {
                 // Return the negated original
-                Ok(-result)
+                match result {
+                    Expression::Integer(n) if env.options().wrapping => {
+                        Ok(Expression::Integer(n.wrapping_neg()))
+                    }
+                    result => -result,
+                }
             }
         },
         "subtract two expressions",
@@ -76,15 +140,8 @@ pub fn get(env: &mut Environment) -> Expression {
                 |args, env| {
                     let mut result = args[0].clone().eval(env)?;
                     for arg in &args[1..] {
-                        let old_result = result.clone();
-                        result = result.eval(env)? * arg.clone().eval(env)?;
-
-                        if let Expression::None = result {
-                            return Err(Error::CustomError(format!(
-                                "cannot multiply {:?} and {:?}",
-                                old_result, arg
-                            )));
-                        }
+                        let arg = arg.clone().eval(env)?;
+                        result = arithmetic("*", result.eval(env)?, arg, env)?;
                     }
                     Ok(result)
                 },
@@ -102,15 +159,8 @@ pub fn get(env: &mut Environment) -> Expression {
                 |args, env| {
                     let mut result = args[0].clone().eval(env)?;
                     for arg in &args[1..] {
-                        let old_result = result.clone();
-                        result = result.eval(env)? / arg.clone().eval(env)?;
-
-                        if let Expression::None = result {
-                            return Err(Error::CustomError(format!(
-                                "cannot divide {:?} and {:?}",
-                                old_result, arg
-                            )));
-                        }
+                        let arg = arg.clone().eval(env)?;
+                        result = arithmetic("//", result.eval(env)?, arg, env)?;
                     }
                     Ok(result)
                 },
@@ -120,6 +170,41 @@ pub fn get(env: &mut Environment) -> Expression {
         ),
     );
 
+    tmp.define(
+        "/",
+        curry(
+            Expression::builtin(
+                "/",
+                |args, env| {
+                    let mut result = args[0].clone().eval(env)?;
+                    for arg in &args[1..] {
+                        let arg = arg.clone().eval(env)?;
+                        result = arithmetic("/", result.eval(env)?, arg, env)?;
+                    }
+                    Ok(result)
+                },
+                "divide two expressions, giving a float even for two integers",
+            ),
+            2,
+        ),
+    );
+
+    tmp.define(
+        "**",
+        curry(
+            Expression::builtin(
+                "**",
+                |args, env| {
+                    let base = args[0].clone().eval(env)?;
+                    let exponent = args[1].clone().eval(env)?;
+                    arithmetic("**", base, exponent, env)
+                },
+                "raise an expression to a power",
+            ),
+            2,
+        ),
+    );
+
     tmp.define(
         "%",
         curry(
@@ -128,15 +213,8 @@ pub fn get(env: &mut Environment) -> Expression {
                 |args, env| {
                     let mut result = args[0].clone().eval(env)?;
                     for arg in &args[1..] {
-                        let old_result = result.clone();
-                        result = result.eval(env)? % arg.clone().eval(env)?;
-
-                        if let Expression::None = result {
-                            return Err(Error::CustomError(format!(
-                                "cannot remainder {:?} and {:?}",
-                                old_result, arg
-                            )));
-                        }
+                        let arg = arg.clone().eval(env)?;
+                        result = arithmetic("%", result.eval(env)?, arg, env)?;
                     }
                     Ok(result)
                 },
@@ -163,13 +241,19 @@ pub fn get(env: &mut Environment) -> Expression {
     tmp.define_builtin(
         "||",
         |args, env| {
-            Ok(Expression::Boolean(
-                args.into_iter()
-                    .map(|x| x.eval(env))
-                    .collect::<Result<Vec<Expression>, Error>>()?
-                    .iter()
-                    .any(|item| item.is_truthy()),
-            ))
+            // Like `set -e` in other shells, a failing program doesn't stop a
+            // script when it's followed by an alternative.
+            // The operands after the first truthy one aren't run at all.
+            let errexit = env.options().errexit;
+            for (i, arg) in args.iter().enumerate() {
+                env.options_mut().errexit = errexit && i + 1 == args.len();
+                let result = arg.eval(env);
+                env.options_mut().errexit = errexit;
+                if result?.is_truthy() {
+                    return Ok(Expression::Boolean(true));
+                }
+            }
+            Ok(Expression::Boolean(false))
         },
         "perform a boolean or for a list of truthy values",
     );
@@ -191,9 +275,8 @@ pub fn get(env: &mut Environment) -> Expression {
     tmp.define_builtin(
         "==",
         |args, env| {
-            Ok(Expression::Boolean(
-                args[0].eval(env)? == args[1].eval(env)?,
-            ))
+            let (a, b) = (args[0].eval(env)?, args[1].eval(env)?);
+            Ok(Expression::Boolean(a.try_equals(&b, env)?))
         },
         "compare two values for equality",
     );
@@ -201,9 +284,8 @@ pub fn get(env: &mut Environment) -> Expression {
     tmp.define_builtin(
         "!=",
         |args, env| {
-            Ok(Expression::Boolean(
-                args[0].eval(env)? != args[1].eval(env)?,
-            ))
+            let (a, b) = (args[0].eval(env)?, args[1].eval(env)?);
+            Ok(Expression::Boolean(!a.try_equals(&b, env)?))
         },
         "compare two values for inequality",
     );
@@ -211,9 +293,11 @@ pub fn get(env: &mut Environment) -> Expression {
     tmp.define_builtin(
         "<",
         |args, env| {
-            Ok(Expression::Boolean(
-                args[0].clone().eval(env)? < args[1].clone().eval(env)?,
-            ))
+            let (a, b) = (args[0].eval(env)?, args[1].eval(env)?);
+            Ok(Expression::Boolean(matches!(
+                a.try_cmp(&b, env)?,
+                Some(Ordering::Less)
+            )))
         },
         "determine the order of two values",
     );
@@ -221,9 +305,11 @@ pub fn get(env: &mut Environment) -> Expression {
     tmp.define_builtin(
         "<=",
         |args, env| {
-            Ok(Expression::Boolean(
-                args[0].clone().eval(env)? <= args[1].clone().eval(env)?,
-            ))
+            let (a, b) = (args[0].eval(env)?, args[1].eval(env)?);
+            Ok(Expression::Boolean(matches!(
+                a.try_cmp(&b, env)?,
+                Some(Ordering::Less | Ordering::Equal)
+            )))
         },
         "determine the order of two values",
     );
@@ -231,9 +317,11 @@ pub fn get(env: &mut Environment) -> Expression {
     tmp.define_builtin(
         ">",
         |args, env| {
-            Ok(Expression::Boolean(
-                args[0].clone().eval(env)? > args[1].clone().eval(env)?,
-            ))
+            let (a, b) = (args[0].eval(env)?, args[1].eval(env)?);
+            Ok(Expression::Boolean(matches!(
+                a.try_cmp(&b, env)?,
+                Some(Ordering::Greater)
+            )))
         },
         "determine the order of two values",
     );
@@ -241,9 +329,11 @@ pub fn get(env: &mut Environment) -> Expression {
     tmp.define_builtin(
         ">=",
         |args, env| {
-            Ok(Expression::Boolean(
-                args[0].clone().eval(env)? >= args[1].clone().eval(env)?,
-            ))
+            let (a, b) = (args[0].eval(env)?, args[1].eval(env)?);
+            Ok(Expression::Boolean(matches!(
+                a.try_cmp(&b, env)?,
+                Some(Ordering::Greater | Ordering::Equal)
+            )))
         },
         "determine the order of two values",
     );
@@ -265,7 +355,7 @@ pub fn get(env: &mut Environment) -> Expression {
     );
 
     let mut new_tmp = env.clone();
-    for (name, val) in &tmp.bindings {
+    for (name, val) in tmp.bindings() {
         new_tmp.define(name, val.clone());
     }
 
@@ -288,13 +378,13 @@ pub fn get(env: &mut Environment) -> Expression {
             .unwrap(),
     );
 
-    let bindings = tmp.bindings.clone();
+    let bindings = tmp.bindings().clone();
 
     for (name, val) in &bindings {
         env.define(name, val.clone());
     }
 
-    Expression::Map(tmp.bindings)
+    Expression::from(tmp)
 }
 
 fn pipe_builtin(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
@@ -314,6 +404,10 @@ fn pipe_builtin(args: Vec<Expression>, env: &mut Environment) -> Result<Expressi
 
     // A dummy value to hold where `expr_to_command` stores its resulting command.
     let mut x = Command::new("dummy");
+    // The exit status of each program in the pipe, and `None` for everything else.
+    let mut statuses = vec![];
+    // The programs in the pipe run in the foreground together.
+    let mut foreground = Foreground::new();
 
     // For every command, pipe in the previous output buffer,
     // and get the result.
@@ -346,7 +440,7 @@ fn pipe_builtin(args: Vec<Expression>, env: &mut Environment) -> Result<Expressi
                 }
 
                 // Try to execute the command.
-                if let Ok(mut child_handler) = cmd.spawn() {
+                if let Ok(mut child_handler) = foreground.spawn(cmd) {
                     // If we need to pipe in STDIN:
                     if !is_first {
                         // Attempt to grab the STDIN of the process from the handler.
@@ -377,11 +471,17 @@ fn pipe_builtin(args: Vec<Expression>, env: &mut Environment) -> Result<Expressi
                     if is_last {
                         // If this is the last command in the pipe, then simply
                         // wait for it to finish without piping in any input.
-                        if child_handler.wait().is_err() {
-                            return Err(Error::CustomError(format!(
-                                "error when waiting for process `{}`",
-                                expr
-                            )));
+                        match foreground.wait(&mut child_handler) {
+                            Ok(status) if was_interrupted(&status) => {
+                                return Err(interrupted(expr))
+                            }
+                            Ok(status) => statuses.push(exit_code(status)),
+                            Err(_) => {
+                                return Err(Error::CustomError(format!(
+                                    "error when waiting for process `{}`",
+                                    expr
+                                )))
+                            }
                         }
                     } else {
                         // If it is not the last command, then we need
@@ -389,7 +489,11 @@ fn pipe_builtin(args: Vec<Expression>, env: &mut Environment) -> Result<Expressi
                         // in the pipe.
 
                         // Attempt to grab the STDOUT of the process from the handler.
-                        if let Ok(output) = child_handler.wait_with_output() {
+                        if let Ok(output) = foreground.wait_with_output(child_handler) {
+                            if was_interrupted(&output.status) {
+                                return Err(interrupted(expr));
+                            }
+                            statuses.push(exit_code(output.status));
                             // Store the contents of the STDOUT into the buffer
                             // for the next process.
                             buf = output.stdout.clone();
@@ -423,6 +527,7 @@ fn pipe_builtin(args: Vec<Expression>, env: &mut Environment) -> Result<Expressi
             // treat this as an application of that expression to the result
             // of the last command.
             None => {
+                statuses.push(Expression::None);
                 result_of_last_cmd = if is_first {
                     // If this is the first command, don't pipe in anything.
                     expr.clone()
@@ -444,8 +549,58 @@ fn pipe_builtin(args: Vec<Expression>, env: &mut Environment) -> Result<Expressi
             }
         }
     }
-    // Return the accumulated Dune expression.
-    Ok(result_of_last_cmd)
+
+    // With `pipefail`, the pipe fails with the rightmost program that failed.
+    // Otherwise, only the last part of the pipe decides whether it failed.
+    // Either way, its status is kept in `LAST_STATUS`, and it's only an error
+    // with `errexit`.
+    let failed = if env.options().pipefail {
+        statuses
+            .iter()
+            .zip(args.iter())
+            .rev()
+            .find(|(status, _)| !matches!(status, Expression::None | Expression::Integer(0)))
+    } else {
+        statuses
+            .last()
+            .zip(args.last())
+            .filter(|(status, _)| !matches!(status, Expression::None | Expression::Integer(0)))
+    };
+    let status = match failed {
+        Some((Expression::Integer(status), _)) => *status,
+        _ => 0,
+    };
+    let error = match failed {
+        Some((Expression::Integer(status), expr)) if env.options().errexit => Some(match expr {
+            Expression::Apply(f, args) => {
+                Error::CommandExited(f.to_string(), args.clone(), *status)
+            }
+            _ => Error::CommandExited(expr.to_string(), vec![], *status),
+        }),
+        _ => None,
+    };
+
+    env.define("PIPESTATUS", Expression::List(statuses));
+    env.define("LAST_STATUS", Expression::Integer(status));
+    match error {
+        Some(error) => Err(error),
+        // Return the accumulated Dune expression.
+        None => Ok(result_of_last_cmd),
+    }
+}
+
+/// Interrupting a program in a pipe with Ctrl-C stops the rest of the line or
+/// script too, like shells report it as 130.
+fn interrupted(expr: &Expression) -> Error {
+    match expr {
+        Expression::Apply(f, args) => Error::CommandExited(f.to_string(), args.clone(), 130),
+        _ => Error::CommandExited(expr.to_string(), vec![], 130),
+    }
+}
+
+/// Get the exit code of a program, or 128 plus the signal that killed it.
+fn exit_code(status: ExitStatus) -> Expression {
+    Expression::Integer(status_code(&status) as Int)
 }
 
 /// Interpret a Dune expression as a program to be executed.
@@ -459,16 +614,7 @@ fn expr_to_command<'a>(
     expr: &Expression,
     env: &mut Environment,
 ) -> Result<Option<&'a mut Command>, Error> {
-    let bindings = env
-        .bindings
-        .clone()
-        .into_iter()
-        .map(|(k, v)| (k, v.to_string()))
-        // This is to prevent environment variables from getting too large.
-        // This causes some strange bugs on Linux: mainly it becomes
-        // impossible to execute any program because `the argument
-        // list is too long`.
-        .filter(|(_, s)| s.len() <= 1024);
+    let bindings = env.exported();
 
     Ok(match expr {
         // If the command is quoted or in parentheses, try to get the inner command.
@@ -486,7 +632,7 @@ fn expr_to_command<'a>(
                 };
                 *cmd = Command::new(cmd_name);
                 Some(
-                    cmd.current_dir(env.get_cwd()).envs(bindings).args(
+                    cmd.current_dir(env.get_cwd()).envs(&*bindings).args(
                         args.iter()
                             .filter(|&x| x != &Expression::None)
                             .map(|x| Ok(format!("{}", x.eval(env)?)))
@@ -498,7 +644,7 @@ fn expr_to_command<'a>(
                 Expression::String(cmd_name) | Expression::Symbol(cmd_name) => {
                     *cmd = Command::new(cmd_name);
                     Some(
-                        cmd.current_dir(env.get_cwd()).envs(bindings).args(
+                        cmd.current_dir(env.get_cwd()).envs(&*bindings).args(
                             args.iter()
                                 .filter(|&x| x != &Expression::None)
                                 .map(|x| Ok(format!("{}", x.eval(env)?)))
@@ -516,14 +662,14 @@ fn expr_to_command<'a>(
             // If the symbol is an alias, then execute the alias.
             Some(Expression::Symbol(name)) => {
                 *cmd = Command::new(name);
-                Some(cmd.current_dir(env.get_cwd()).envs(bindings))
+                Some(cmd.current_dir(env.get_cwd()).envs(&*bindings))
             }
             // If the symbol is bound to something like `5`, this isn't a command.
             Some(_) => None,
             // If the symbol is not defined, use the symbol as the program name.
             None => {
                 *cmd = Command::new(name);
-                Some(cmd.current_dir(env.get_cwd()).envs(bindings))
+                Some(cmd.current_dir(env.get_cwd()).envs(&*bindings))
             }
         },
 
@@ -531,3 +677,92 @@ fn expr_to_command<'a>(
         _ => None,
     })
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn sh(script: &str) -> Expression {
+        Expression::Apply(
+            Box::new(Expression::Symbol(String::from("sh"))),
+            vec![Expression::from("-c"), Expression::from(script)],
+        )
+    }
+
+    fn pipe(
+        pipefail: bool,
+        errexit: bool,
+        args: Vec<Expression>,
+    ) -> (Result<Expression, Error>, Environment) {
+        let mut env = Environment::new();
+        env.options_mut().pipefail = pipefail;
+        env.options_mut().errexit = errexit;
+        (pipe_builtin(args, &mut env), env)
+    }
+
+    #[test]
+    fn pipefail_sets_the_status() {
+        let (result, env) = pipe(true, false, vec![sh("exit 3"), sh("cat")]);
+        assert!(result.is_ok());
+        assert_eq!(env.get("LAST_STATUS"), Some(Expression::Integer(3)));
+        assert_eq!(
+            env.get("PIPESTATUS"),
+            Some(Expression::List(vec![
+                Expression::Integer(3),
+                Expression::Integer(0)
+            ]))
+        );
+
+        let (result, env) = pipe(false, false, vec![sh("exit 3"), sh("cat")]);
+        assert!(result.is_ok());
+        assert_eq!(env.get("LAST_STATUS"), Some(Expression::Integer(0)));
+    }
+
+    #[test]
+    fn errexit_fails_with_the_last_program() {
+        let (result, env) = pipe(false, true, vec![sh("exit 3"), sh("cat")]);
+        assert!(result.is_ok());
+        assert_eq!(env.get("LAST_STATUS"), Some(Expression::Integer(0)));
+
+        let (result, env) = pipe(false, true, vec![sh("true"), sh("cat; exit 4")]);
+        assert!(matches!(result, Err(Error::CommandExited(_, _, 4))));
+        assert_eq!(env.get("LAST_STATUS"), Some(Expression::Integer(4)));
+    }
+
+    #[test]
+    fn pipefail_and_errexit_fail_with_the_rightmost_program() {
+        let (result, env) = pipe(true, true, vec![sh("exit 3"), sh("exit 5"), sh("cat")]);
+        assert!(matches!(result, Err(Error::CommandExited(_, _, 5))));
+        assert_eq!(env.get("LAST_STATUS"), Some(Expression::Integer(5)));
+    }
+
+    #[test]
+    fn or_stops_at_the_first_truthy_operand() {
+        let mut env = Environment::new();
+        env.options_mut().errexit = true;
+        let or = match get(&mut env) {
+            Expression::Map(ops) => ops["||"].clone(),
+            _ => unreachable!(),
+        };
+        let marker = std::env::temp_dir().join(format!("dune-or-{}", std::process::id()));
+        let touch = sh(&format!("touch {}", marker.display()));
+
+        let result = Expression::Apply(
+            Box::new(or.clone()),
+            vec![sh("exit 1"), Expression::Boolean(true), touch],
+        )
+        .eval(&mut env);
+        assert_eq!(result, Ok(Expression::Boolean(true)));
+        assert!(!marker.exists());
+
+        let result =
+            Expression::Apply(Box::new(or), vec![sh("exit 1"), sh("exit 2")]).eval(&mut env);
+        assert!(matches!(result, Err(Error::CommandExited(_, _, 2))));
+    }
+
+    #[test]
+    fn signals_are_reported_like_shells() {
+        let (_, env) = pipe(true, false, vec![sh("kill -9 $$"), sh("cat")]);
+        assert_eq!(env.get("LAST_STATUS"), Some(Expression::Integer(128 + 9)));
+    }
+}
@@ -1,6 +1,10 @@
 use common_macros::b_tree_map;
 use dune::{Environment, Error, Expression};
-use std::path::PathBuf;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    process::Command,
+};
 
 pub fn get() -> Expression {
     let os = os_info::get();
@@ -27,6 +31,10 @@ pub fn get() -> Expression {
             "exit the shell",
         ),
         String::from("cd") => Expression::builtin("cd", cd, "change directories"),
+        String::from("load-dotenv") => Expression::builtin("load-dotenv", load_dotenv,
+            "load the variables in a `.env` file (by default, the one in the current directory) so programs can use them, except for those that would replace a function or module"),
+        String::from("battery") => Expression::builtin("battery", battery,
+            "get the battery's charge as a percentage, its state, whether it's plugged in, and the seconds until it's empty or full, or None if there isn't one (on Linux and macOS)"),
     })
     .into()
 }
@@ -36,7 +44,7 @@ fn cd(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, dune::
 
     match args[0].eval(env)? {
         Expression::Symbol(path) | Expression::String(path) => {
-            let abs_path = PathBuf::from(env.get_cwd()).join(path);
+            let abs_path = PathBuf::from(env.get_cwd()).join(drive_root(path));
 
             let new_cwd = dunce::canonicalize(&abs_path).map_err(|e| {
                 dune::Error::CustomError(match format!("{:?}", e.kind()).as_str() {
@@ -68,7 +76,12 @@ fn cd(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, dune::
                 })
             })?;
 
+            let dotenv = new_cwd.join(".env");
             env.set_cwd(new_cwd.into_os_string().into_string().unwrap());
+            super::report_cwd(&env.get_cwd());
+            if env.options().dotenv && dotenv.is_file() {
+                load_dotenv(vec![Expression::String(".env".to_string())], env)?;
+            }
             Ok(Expression::None)
         }
 
@@ -79,3 +92,454 @@ fn cd(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, dune::
         }
     }
 }
+
+/// On Windows, a drive on its own like `D:` means the root of that drive.
+/// Otherwise, it would be relative to a working directory Dune doesn't keep
+/// track of for each drive. UNC paths like `\\server\share` are left alone,
+/// and `dunce` keeps them readable when they're canonicalized.
+fn drive_root(path: String) -> String {
+    let bytes = path.as_bytes();
+    if cfg!(windows) && bytes.len() == 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' {
+        path + "\\"
+    } else {
+        path
+    }
+}
+
+fn load_dotenv(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("load-dotenv", &args, 0..=1)?;
+    let file = match args.first().map(|file| file.eval(env)).transpose()? {
+        None | Some(Expression::None) => String::from(".env"),
+        Some(file) => file.to_string(),
+    };
+    let path = PathBuf::from(env.get_cwd()).join(&file);
+    let text = std::fs::read_to_string(&path)
+        .map_err(|e| Error::CustomError(format!("could not read {}: {}", file, e)))?;
+
+    let variables = parse_dotenv(&text, env)
+        .map_err(|e| Error::CustomError(format!("could not load {}: {}", file, e)))?;
+    // A `.env` file can be loaded just by changing to its directory, so it only
+    // sets variables, and never replaces a command like `cd` or `echo`.
+    let (variables, kept): (Vec<_>, Vec<_>) = variables.into_iter().partition(|(name, _)| {
+        !matches!(
+            env.get(name),
+            Some(
+                Expression::Builtin(_)
+                    | Expression::Lambda(..)
+                    | Expression::Macro(..)
+                    | Expression::Map(_)
+            )
+        )
+    });
+    for (name, _) in kept {
+        eprintln!(
+            "{}: not loading `{}`, which would replace a function or module",
+            file, name
+        );
+    }
+    // Every variable is passed along to the programs the shell runs.
+    for (name, value) in &variables {
+        env.define(name, Expression::String(value.clone()));
+    }
+    Ok(variables
+        .into_iter()
+        .map(|(name, value)| (name, Expression::String(value)))
+        .collect::<BTreeMap<_, _>>()
+        .into())
+}
+
+/// Parse the `NAME=value` lines of a `.env` file. Values can be quoted: single quotes
+/// keep everything as it's written, and double quotes allow escapes like `\n` and can
+/// span several lines. Variables like `${NAME}` are expanded outside of single quotes.
+fn parse_dotenv(text: &str, env: &Environment) -> Result<Vec<(String, String)>, String> {
+    let mut variables: Vec<(String, String)> = vec![];
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+
+    loop {
+        // Skip blank lines and comments.
+        while let Some(c) = chars.peek() {
+            match c {
+                '\n' => line += 1,
+                c if c.is_whitespace() => {}
+                '#' => {
+                    while chars.peek().map(|c| *c != '\n').unwrap_or(false) {
+                        chars.next();
+                    }
+                    continue;
+                }
+                _ => break,
+            }
+            chars.next();
+        }
+        if chars.peek().is_none() {
+            break;
+        }
+
+        let mut name = String::new();
+        while let Some(c) = chars.peek() {
+            if *c == '=' || *c == '\n' {
+                break;
+            }
+            name.push(*c);
+            chars.next();
+        }
+        let name = name.trim();
+        let name = name
+            .strip_prefix("export ")
+            .unwrap_or(name)
+            .trim()
+            .to_string();
+        if chars.next() != Some('=') || name.is_empty() {
+            return Err(format!("expected `NAME=value` on line {}", line));
+        }
+        if !is_variable_name(&name) {
+            return Err(format!(
+                "`{}` on line {} is not a variable name, which is letters, digits, and `_`",
+                name, line
+            ));
+        }
+        while chars
+            .peek()
+            .map(|c| *c == ' ' || *c == '\t')
+            .unwrap_or(false)
+        {
+            chars.next();
+        }
+
+        let lookup = |name: &str| {
+            variables
+                .iter()
+                .rev()
+                .find(|(n, _)| n == name)
+                .map(|(_, value)| value.clone())
+                .or_else(|| env.get(name).map(|value| value.to_string()))
+                .unwrap_or_default()
+        };
+        let value = match chars.peek() {
+            Some('\'') => {
+                chars.next();
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            value.push(c)
+                        }
+                        None => return Err(format!("unterminated quote on line {}", line)),
+                    }
+                }
+                value
+            }
+            Some('"') => {
+                chars.next();
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => value.push('\n'),
+                            Some('t') => value.push('\t'),
+                            Some('r') => value.push('\r'),
+                            Some(c) => value.push(c),
+                            None => return Err(format!("unterminated quote on line {}", line)),
+                        },
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            value.push(c)
+                        }
+                        None => return Err(format!("unterminated quote on line {}", line)),
+                    }
+                }
+                expand(&value, lookup)
+            }
+            _ => {
+                let mut value = String::new();
+                while let Some(c) = chars.peek() {
+                    // A comment has to be separated from an unquoted value by a space.
+                    if *c == '\n' || (*c == '#' && value.ends_with([' ', '\t'])) {
+                        break;
+                    }
+                    value.push(*c);
+                    chars.next();
+                }
+                expand(value.trim(), lookup)
+            }
+        };
+        // Ignore anything after a quoted value, like a comment.
+        while chars.peek().map(|c| *c != '\n').unwrap_or(false) {
+            chars.next();
+        }
+        variables.push((name, value));
+    }
+    Ok(variables)
+}
+
+/// Can a name be set in a `.env` file, like `PATH` or `api_key_2`?
+fn is_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Expand the `$NAME` and `${NAME}` variables in a value.
+fn expand(value: &str, lookup: impl Fn(&str) -> String) -> String {
+    let mut result = String::new();
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            result.push(c);
+            continue;
+        }
+        let braced = chars.peek() == Some(&'{');
+        if braced {
+            chars.next();
+        }
+        let mut name = String::new();
+        while let Some(c) = chars.peek() {
+            if c.is_alphanumeric() || *c == '_' {
+                name.push(*c);
+                chars.next();
+            } else {
+                break;
+            }
+        }
+        if braced && chars.peek() == Some(&'}') {
+            chars.next();
+        }
+        if name.is_empty() {
+            result.push('$');
+            if braced {
+                result.push('{');
+            }
+        } else {
+            result += &lookup(&name);
+        }
+    }
+    result
+}
+
+/// The state of the battery, and how long until it's empty or full if that's known.
+struct Battery {
+    percent: f64,
+    state: String,
+    plugged: bool,
+    remaining: Option<i64>,
+}
+
+fn battery(args: Vec<Expression>, _env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("battery", &args, 0..=1)?;
+    let battery = if cfg!(target_os = "macos") {
+        pmset_battery()
+    } else {
+        sysfs_battery(Path::new("/sys/class/power_supply"))
+    };
+    Ok(match battery {
+        Some(battery) => Expression::from(b_tree_map! {
+            String::from("percent") => Expression::Float((battery.percent * 10.0).round() / 10.0),
+            String::from("state") => Expression::String(battery.state),
+            String::from("plugged") => Expression::Boolean(battery.plugged),
+            String::from("time-remaining") => battery.remaining.map(Expression::Integer).unwrap_or(Expression::None),
+        }),
+        None => Expression::None,
+    })
+}
+
+/// Read the batteries Linux lists in `/sys/class/power_supply`, adding
+/// them up if there's more than one.
+fn sysfs_battery(dir: &Path) -> Option<Battery> {
+    let read = |path: &Path, name: &str| {
+        std::fs::read_to_string(path.join(name))
+            .ok()
+            .map(|text| text.trim().to_string())
+    };
+    let number = |path: &Path, name: &str| read(path, name)?.parse::<f64>().ok();
+
+    let mut supplies = std::fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .collect::<Vec<_>>();
+    supplies.sort();
+    // Mice and keyboards have batteries too, but they're scoped to a device.
+    let batteries = supplies
+        .iter()
+        .filter(|path| read(path, "type").as_deref() == Some("Battery"))
+        .filter(|path| read(path, "scope").as_deref() != Some("Device"))
+        .collect::<Vec<_>>();
+    if batteries.is_empty() {
+        return None;
+    }
+    let plugged = supplies
+        .iter()
+        .filter(|path| read(path, "type").is_some_and(|kind| kind != "Battery"))
+        .any(|path| read(path, "online").as_deref() == Some("1"));
+
+    // Batteries report energy in µWh and power in µW, or charge in µAh and
+    // current in µA, which work out the same way.
+    let (mut now, mut full, mut rate, mut capacities) = (0.0, 0.0, 0.0, vec![]);
+    let mut states = vec![];
+    for path in &batteries {
+        if let Some(energy) = number(path, "energy_now").or_else(|| number(path, "charge_now")) {
+            now += energy;
+            full += number(path, "energy_full")
+                .or_else(|| number(path, "charge_full"))
+                .unwrap_or(0.0);
+            rate += number(path, "power_now")
+                .or_else(|| number(path, "current_now"))
+                .unwrap_or(0.0)
+                .abs();
+        }
+        if let Some(capacity) = number(path, "capacity") {
+            capacities.push(capacity);
+        }
+        if let Some(status) = read(path, "status") {
+            states.push(status.to_lowercase().replace(' ', "-"));
+        }
+    }
+
+    let percent = if full > 0.0 {
+        now / full * 100.0
+    } else if !capacities.is_empty() {
+        capacities.iter().sum::<f64>() / capacities.len() as f64
+    } else {
+        return None;
+    };
+    let state = ["discharging", "charging", "not-charging", "full"]
+        .iter()
+        .find(|state| states.iter().any(|s| s == *state))
+        .map(|state| state.to_string())
+        .unwrap_or_else(|| String::from("unknown"));
+    let hours = match state.as_str() {
+        "discharging" if rate > 0.0 => Some(now / rate),
+        "charging" if rate > 0.0 => Some((full - now).max(0.0) / rate),
+        _ => None,
+    };
+    Some(Battery {
+        percent: percent.min(100.0),
+        plugged: plugged || state == "charging",
+        state,
+        remaining: hours.map(|hours| (hours * 3600.0).round() as i64),
+    })
+}
+
+/// Read the battery from `pmset -g batt` on macOS, which prints a line like
+/// `-InternalBattery-0 (id=1234)` and then `85%; discharging; 4:20 remaining`
+/// after a tab.
+fn pmset_battery() -> Option<Battery> {
+    let output = Command::new("pmset").args(["-g", "batt"]).output().ok()?;
+    let text = String::from_utf8_lossy(&output.stdout).to_string();
+    let plugged = text.contains("'AC Power'");
+    let line = text.lines().find(|line| line.contains("InternalBattery"))?;
+    let fields = line
+        .split_once('\t')?
+        .1
+        .split(';')
+        .map(str::trim)
+        .collect::<Vec<_>>();
+
+    let percent = fields.first()?.trim_end_matches('%').parse::<f64>().ok()?;
+    let state = match fields.get(1).copied().unwrap_or_default() {
+        "charged" => "full",
+        "finishing charge" => "charging",
+        "AC attached" => "not-charging",
+        state @ ("charging" | "discharging") => state,
+        _ => "unknown",
+    };
+    let remaining = fields.get(2).and_then(|field| {
+        let (hours, minutes) = field.split_whitespace().next()?.split_once(':')?;
+        Some(hours.parse::<i64>().ok()? * 3600 + minutes.parse::<i64>().ok()? * 60)
+    });
+    Some(Battery {
+        percent,
+        state: state.to_string(),
+        plugged,
+        remaining: remaining.filter(|_| state == "charging" || state == "discharging"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Result<Vec<(String, String)>, String> {
+        parse_dotenv(text, &Environment::new())
+    }
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn dotenv_quotes() {
+        assert_eq!(
+            parse("A='$HOME \\n'\nB=\"x\\ty\"\nC=\"$B-${A}\"\nexport D = plain value \n"),
+            Ok(vars(&[
+                ("A", "$HOME \\n"),
+                ("B", "x\ty"),
+                ("C", "x\ty-$HOME \\n"),
+                ("D", "plain value"),
+            ]))
+        );
+    }
+
+    #[test]
+    fn dotenv_comments() {
+        assert_eq!(
+            parse("# a comment\n\nA=1 # not part of it\nB=a#b\nC=\"2\" # after quotes\n  # indented\n"),
+            Ok(vars(&[("A", "1"), ("B", "a#b"), ("C", "2")]))
+        );
+    }
+
+    #[test]
+    fn dotenv_multiline_values() {
+        assert_eq!(
+            parse("KEY=\"-----BEGIN-----\nabc\n-----END-----\"\nNEXT='one\ntwo'\nLAST=3"),
+            Ok(vars(&[
+                ("KEY", "-----BEGIN-----\nabc\n-----END-----"),
+                ("NEXT", "one\ntwo"),
+                ("LAST", "3"),
+            ]))
+        );
+        assert!(parse("A=\"never closed\nB=1").is_err());
+    }
+
+    #[test]
+    fn dotenv_names() {
+        assert!(parse("foo bar=1").is_err());
+        assert!(parse("1A=1").is_err());
+        assert!(parse("A-B=1").is_err());
+        assert!(parse("=1").is_err());
+        assert_eq!(parse("_a1=1"), Ok(vars(&[("_a1", "1")])));
+    }
+
+    #[test]
+    fn dotenv_keeps_functions() {
+        let dir = std::env::temp_dir().join(format!("dune-dotenv-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join(".env"), "echo=1\nTOKEN=abc\n").unwrap();
+
+        let mut env = Environment::new();
+        env.set_cwd(dir.to_str().unwrap());
+        env.define_builtin("echo", |_, _| Ok(Expression::None), "print");
+        let loaded = load_dotenv(vec![], &mut env);
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert_eq!(
+            loaded.unwrap(),
+            Expression::from(b_tree_map! {
+                String::from("TOKEN") => Expression::from("abc"),
+            })
+        );
+        assert!(matches!(env.get("echo"), Some(Expression::Builtin(_))));
+        assert_eq!(env.get("TOKEN"), Some(Expression::from("abc")));
+    }
+}
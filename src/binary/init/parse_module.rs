@@ -1,13 +1,18 @@
 use common_macros::b_tree_map;
 use dune::{parse_script, Environment, Error, Expression, SyntaxError};
+use indexmap::IndexMap;
 use json::JsonValue;
-use std::collections::BTreeMap;
+use std::time::Duration;
 
 pub fn get() -> Expression {
     (b_tree_map! {
         String::from("toml") => Expression::builtin("toml", parse_toml, "parse a TOML value into a Dune expression"),
         String::from("json") => Expression::builtin("json", parse_json, "parse a JSON value into a Dune expression"),
+        String::from("csv") => Expression::builtin("csv", parse_csv, "parse CSV with a header row into a list of maps"),
         String::from("expr") => Expression::builtin("expr", parse_expr, "parse a Dune script"),
+        String::from("duration") => Expression::builtin("duration", parse_duration, "parse a duration like \"1h30m\" into a number of seconds"),
+        String::from("size") => Expression::builtin("size", parse_size, "parse a size like \"2.5GiB\" into a number of bytes"),
+        String::from("scan") => Expression::builtin("scan", parse_scan, "extract fields from text with a pattern like \"took {d}ms status={w}\""),
     })
     .into()
 }
@@ -47,7 +52,7 @@ fn parse_json(args: Vec<Expression>, env: &mut Environment) -> Result<Expression
     }
 }
 
-fn json_to_expr(val: JsonValue) -> Expression {
+pub(super) fn json_to_expr(val: JsonValue) -> Expression {
     match val {
         JsonValue::Null => Expression::None,
         JsonValue::Boolean(b) => Expression::Boolean(b),
@@ -62,7 +67,7 @@ fn json_to_expr(val: JsonValue) -> Expression {
             Expression::List(v)
         }
         JsonValue::Object(o) => {
-            let mut m = BTreeMap::new();
+            let mut m = IndexMap::new();
             for (k, v) in o.iter() {
                 m.insert(k.to_string(), json_to_expr(v.clone()));
             }
@@ -84,7 +89,7 @@ fn parse_toml(args: Vec<Expression>, env: &mut Environment) -> Result<Expression
     }
 }
 
-fn toml_to_expr(val: toml::Value) -> Expression {
+pub(super) fn toml_to_expr(val: toml::Value) -> Expression {
     match val {
         toml::Value::Boolean(b) => Expression::Boolean(b),
         toml::Value::Float(n) => Expression::Float(n),
@@ -99,7 +104,7 @@ fn toml_to_expr(val: toml::Value) -> Expression {
             Expression::List(v)
         }
         toml::Value::Table(o) => {
-            let mut m = BTreeMap::new();
+            let mut m = IndexMap::new();
             for (k, v) in o.iter() {
                 m.insert(k.to_string(), toml_to_expr(v.clone()));
             }
@@ -107,3 +112,361 @@ fn toml_to_expr(val: toml::Value) -> Expression {
         }
     }
 }
+
+fn parse_csv(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("csv", &args, 1)?;
+    let text = args[0].eval(env)?.to_string();
+    Ok(csv_to_expr(&text, ','))
+}
+
+/// Parse delimited text with a header row into a list of maps, one for each row.
+pub(super) fn csv_to_expr(text: &str, separator: char) -> Expression {
+    let mut records = csv_records(text, separator).into_iter();
+    let header = match records.next() {
+        Some(header) => header,
+        None => return Expression::List(vec![]),
+    };
+    Expression::List(
+        records
+            .map(|record| {
+                header
+                    .iter()
+                    .cloned()
+                    .zip(record.iter().map(|field| super::from_module::value(field)))
+                    .collect::<IndexMap<_, _>>()
+                    .into()
+            })
+            .collect(),
+    )
+}
+
+/// Split delimited text into records of fields. Fields can be quoted to contain
+/// the separator, newlines, or quotes written twice like `""`.
+fn csv_records(text: &str, separator: char) -> Vec<Vec<String>> {
+    let mut records = vec![];
+    let mut record = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => quoted = true,
+            c if quoted => field.push(c),
+            c if c == separator => record.push(std::mem::take(&mut field)),
+            '\r' => {}
+            '\n' => {
+                record.push(std::mem::take(&mut field));
+                if record.iter().any(|field| !field.is_empty()) {
+                    records.push(std::mem::take(&mut record));
+                }
+                record.clear();
+            }
+            c => field.push(c),
+        }
+    }
+    record.push(field);
+    if record.iter().any(|field| !field.is_empty()) {
+        records.push(record);
+    }
+    records
+}
+
+fn parse_duration(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("duration", &args, 1)?;
+    let seconds = duration_seconds(&args[0].eval(env)?.to_string())?;
+    if seconds.fract() == 0.0 {
+        Ok(Expression::Integer(seconds as i64))
+    } else {
+        Ok(Expression::Float(seconds))
+    }
+}
+
+fn parse_size(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("size", &args, 1)?;
+    Ok(Expression::Integer(size_bytes(
+        &args[0].eval(env)?.to_string(),
+    )?))
+}
+
+/// Split text like `1h30m` or `2.5 GiB` into its numbers and the units after them.
+fn quantities(text: &str) -> Option<Vec<(f64, String)>> {
+    let mut result = Vec::new();
+    let mut rest = text.trim();
+    while !rest.is_empty() {
+        let len = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        let number = rest[..len].parse::<f64>().ok()?;
+        rest = rest[len..].trim_start();
+
+        let len = rest
+            .find(|c: char| !c.is_alphabetic())
+            .unwrap_or(rest.len());
+        result.push((number, rest[..len].to_lowercase()));
+        rest = rest[len..].trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+    }
+
+    if result.is_empty() {
+        None
+    } else {
+        Some(result)
+    }
+}
+
+/// The number of seconds in a duration like `1h30m`, `90s`, or `1.5 days`.
+/// A number without a unit is a number of seconds.
+pub(super) fn duration_seconds(text: &str) -> Result<f64, Error> {
+    let error = || Error::CustomError(format!("could not parse `{}` as a duration", text));
+
+    let mut seconds = 0.0;
+    for (number, unit) in quantities(text).ok_or_else(error)? {
+        seconds += number
+            * match unit.as_str() {
+                "ns" | "nanosecond" | "nanoseconds" => 1e-9,
+                "us" | "µs" | "microsecond" | "microseconds" => 1e-6,
+                "ms" | "millisecond" | "milliseconds" => 1e-3,
+                "" | "s" | "sec" | "secs" | "second" | "seconds" => 1.0,
+                "m" | "min" | "mins" | "minute" | "minutes" => 60.0,
+                "h" | "hr" | "hrs" | "hour" | "hours" => 3600.0,
+                "d" | "day" | "days" => 86400.0,
+                "w" | "wk" | "wks" | "week" | "weeks" => 604800.0,
+                _ => return Err(error()),
+            };
+    }
+    Ok(seconds)
+}
+
+/// Turn a number of seconds into a `Duration` to wait for, which is an error
+/// rather than a panic if it's negative, not a number, or far too long.
+pub(super) fn duration(seconds: f64) -> Result<Duration, Error> {
+    Duration::try_from_secs_f64(seconds).map_err(|_| {
+        Error::CustomError(format!(
+            "{} seconds is not a duration that can be waited for",
+            seconds
+        ))
+    })
+}
+
+/// The number of bytes in a size like `512`, `10KB`, or `2.5GiB`.
+/// Single letter units like `G` are powers of 1024, like the sizes `ls -h` and `du -h` print.
+pub(super) fn size_bytes(text: &str) -> Result<i64, Error> {
+    let error = || Error::CustomError(format!("could not parse `{}` as a size", text));
+
+    let mut bytes = 0.0;
+    for (number, unit) in quantities(text).ok_or_else(error)? {
+        let unit = unit.strip_suffix("bytes").unwrap_or(&unit);
+        let (prefix, base) = match unit.strip_suffix("ib") {
+            Some(prefix) => (prefix, 1024.0),
+            None => match unit.strip_suffix('b') {
+                Some(prefix) => (prefix, 1000.0),
+                None => (unit, 1024.0),
+            },
+        };
+
+        let power = match prefix {
+            "" => 0,
+            "k" => 1,
+            "m" => 2,
+            "g" => 3,
+            "t" => 4,
+            "p" => 5,
+            "e" => 6,
+            _ => return Err(error()),
+        };
+        bytes += number * f64::powi(base, power);
+    }
+    Ok(bytes.round() as i64)
+}
+
+/// A single piece of a `scan` pattern.
+#[derive(Debug, Clone, PartialEq)]
+enum ScanPart {
+    /// Text that must appear verbatim.
+    Literal(String),
+    /// A run of whitespace in the pattern, which matches any amount of whitespace.
+    Space,
+    /// A field to capture, with an optional name and its kind (`d`, `f`, `w`, or `s`).
+    Field(Option<String>, char),
+}
+
+fn parse_scan(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("scan", &args, 2)?;
+    let pattern = args[0].eval(env)?.to_string();
+    let text = args[1].eval(env)?.to_string();
+    let parts = scan_pattern(&pattern)?;
+
+    let fields = (0..=text.len())
+        .filter(|i| text.is_char_boundary(*i))
+        .find_map(|i| scan_at(&parts, &text[i..]))
+        .ok_or_else(|| {
+            Error::CustomError(format!(
+                "`{}` does not match the pattern `{}`",
+                text, pattern
+            ))
+        })?;
+
+    if parts
+        .iter()
+        .any(|part| matches!(part, ScanPart::Field(Some(_), _)))
+    {
+        Ok(Expression::Map(
+            fields
+                .into_iter()
+                .filter_map(|(name, value)| Some((name?, value)))
+                .collect(),
+        ))
+    } else {
+        Ok(Expression::List(
+            fields.into_iter().map(|(_, value)| value).collect(),
+        ))
+    }
+}
+
+/// Split a `scan` pattern into literals, whitespace, and fields.
+fn scan_pattern(pattern: &str) -> Result<Vec<ScanPart>, Error> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut chars = pattern.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                literal.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                literal.push('}');
+            }
+            '{' => {
+                let mut spec = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => spec.push(c),
+                        None => {
+                            return Err(Error::CustomError(format!(
+                                "unclosed field in scan pattern `{}`",
+                                pattern
+                            )))
+                        }
+                    }
+                }
+
+                let (name, kind) = match spec.rsplit_once(':') {
+                    Some((name, kind)) => (Some(name.trim().to_string()), kind.trim()),
+                    None => (None, spec.trim()),
+                };
+                let kind = match kind {
+                    "d" | "f" | "w" | "s" => kind.chars().next().unwrap(),
+                    _ => {
+                        return Err(Error::CustomError(format!(
+                            "unknown field `{{{}}}` in scan pattern, expected one of {{d}}, {{f}}, {{w}}, or {{s}}",
+                            spec
+                        )))
+                    }
+                };
+
+                if !literal.is_empty() {
+                    parts.push(ScanPart::Literal(std::mem::take(&mut literal)));
+                }
+                parts.push(ScanPart::Field(name, kind));
+            }
+            c if c.is_whitespace() => {
+                while chars.peek().map(|c| c.is_whitespace()) == Some(true) {
+                    chars.next();
+                }
+                if !literal.is_empty() {
+                    parts.push(ScanPart::Literal(std::mem::take(&mut literal)));
+                }
+                parts.push(ScanPart::Space);
+            }
+            c => literal.push(c),
+        }
+    }
+
+    if !literal.is_empty() {
+        parts.push(ScanPart::Literal(literal));
+    }
+    Ok(parts)
+}
+
+/// Match the pattern against the start of `text`, returning the captured fields.
+fn scan_at(parts: &[ScanPart], text: &str) -> Option<Vec<(Option<String>, Expression)>> {
+    let (part, rest) = match parts.split_first() {
+        Some(split) => split,
+        None => return Some(Vec::new()),
+    };
+
+    match part {
+        ScanPart::Literal(literal) => scan_at(rest, text.strip_prefix(literal.as_str())?),
+        ScanPart::Space => scan_at(rest, text.trim_start()),
+        // A string field is as short as it can be while the rest of the pattern
+        // still matches, and never runs past the end of the line.
+        ScanPart::Field(name, 's') => {
+            let line = text.find('\n').unwrap_or(text.len());
+            let mut ends = text[..line]
+                .char_indices()
+                .map(|(i, c)| i + c.len_utf8())
+                .collect::<Vec<_>>();
+            if rest.is_empty() {
+                ends = ends.pop().into_iter().collect();
+            }
+
+            ends.into_iter().find_map(|end| {
+                let mut fields = vec![(name.clone(), Expression::String(text[..end].to_string()))];
+                fields.extend(scan_at(rest, &text[end..])?);
+                Some(fields)
+            })
+        }
+        ScanPart::Field(name, kind) => {
+            let len = match kind {
+                'd' => number_len(text, false),
+                'f' => number_len(text, true),
+                _ => text.find(char::is_whitespace).unwrap_or(text.len()),
+            };
+            if len == 0 {
+                return None;
+            }
+
+            let (field, text) = text.split_at(len);
+            let value = match kind {
+                'd' => Expression::Integer(field.parse().ok()?),
+                'f' => Expression::Float(field.parse().ok()?),
+                _ => Expression::String(field.to_string()),
+            };
+            let mut fields = vec![(name.clone(), value)];
+            fields.extend(scan_at(rest, text)?);
+            Some(fields)
+        }
+    }
+}
+
+/// The length of the number at the start of `text`, or zero if there isn't one.
+fn number_len(text: &str, float: bool) -> usize {
+    let bytes = text.as_bytes();
+    let digits = |start: usize| {
+        bytes[start..]
+            .iter()
+            .take_while(|b| b.is_ascii_digit())
+            .count()
+    };
+
+    let sign = matches!(bytes.first(), Some(b'-') | Some(b'+')) as usize;
+    let whole = digits(sign);
+    let mut frac = 0;
+    if float && bytes.get(sign + whole) == Some(&b'.') {
+        frac = digits(sign + whole + 1);
+    }
+
+    match (whole, frac) {
+        (0, 0) => 0,
+        (_, 0) => sign + whole,
+        _ => sign + whole + 1 + frac,
+    }
+}
@@ -1,12 +1,20 @@
 use common_macros::b_tree_map;
-use dune::{Environment, Error, Expression};
-use rand::{distributions::Uniform, prelude::SliceRandom, Rng};
+use dune::{Environment, Error, Expression, Int};
+use rand::{
+    distributions::{Uniform, WeightedIndex},
+    prelude::SliceRandom,
+    Rng,
+};
 
 pub fn get() -> Expression {
     (b_tree_map! {
         String::from("int") => Expression::builtin("int", int, "get a random integer between two numbers (exclusive)"),
         String::from("choose") => Expression::builtin("choose", choose, "choose a random item in a list"),
         String::from("shuffle") => Expression::builtin("shuffle", shuffle, "shuffle a list randomly"),
+        String::from("choose-weighted") => Expression::builtin("choose-weighted", choose_weighted,
+            "choose a random item from a list of [item, weight] pairs or a map of items to weights, where items with larger weights are more likely"),
+        String::from("sample") => Expression::builtin("sample", sample, "choose a number of different items in a list, in a random order"),
+        String::from("gaussian") => Expression::builtin("gaussian", gaussian, "get a random number from a normal distribution with a mean and standard deviation"),
     })
     .into()
 }
@@ -55,3 +63,101 @@ fn shuffle(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, E
         ))),
     }
 }
+
+/// Get a number as a float, for weights and the parameters of distributions.
+fn number(value: &Expression) -> Option<f64> {
+    match value {
+        Expression::Integer(n) => Some(*n as f64),
+        Expression::Float(n) => Some(*n),
+        _ => None,
+    }
+}
+
+fn choose_weighted(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("choose-weighted", &args, 1)?;
+    let pairs = match args[0].eval(env)? {
+        Expression::Map(map) => map
+            .into_iter()
+            .map(|(item, weight)| (Expression::String(item), weight))
+            .collect::<Vec<_>>(),
+        Expression::List(list) => list
+            .into_iter()
+            .map(|pair| match pair {
+                Expression::List(pair) if pair.len() == 2 => Ok((pair[0].clone(), pair[1].clone())),
+                otherwise => Err(Error::CustomError(format!(
+                    "expected an [item, weight] pair, but got {}",
+                    otherwise
+                ))),
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        otherwise => {
+            return Err(Error::CustomError(format!(
+                "expected a list of [item, weight] pairs or a map of items to weights, but got {}",
+                otherwise
+            )))
+        }
+    };
+
+    let mut weights = vec![];
+    for (_, weight) in &pairs {
+        match number(weight) {
+            Some(n) if n >= 0.0 && n.is_finite() => weights.push(n),
+            _ => {
+                return Err(Error::CustomError(format!(
+                    "expected a weight that's a number of at least 0, but got {}",
+                    weight
+                )))
+            }
+        }
+    }
+    let distribution = WeightedIndex::new(&weights).map_err(|_| {
+        Error::CustomError(String::from(
+            "expected at least one item with a weight above 0",
+        ))
+    })?;
+    Ok(pairs[rand::thread_rng().sample(distribution)].0.clone())
+}
+
+fn sample(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("sample", &args, 2)?;
+    match (args[0].eval(env)?, args[1].eval(env)?) {
+        (Expression::Integer(k), Expression::List(list))
+            if (0..=list.len() as Int).contains(&k) =>
+        {
+            let mut rng = rand::thread_rng();
+            Ok(rand::seq::index::sample(&mut rng, list.len(), k as usize)
+                .into_iter()
+                .map(|i| list[i].clone())
+                .collect::<Vec<_>>()
+                .into())
+        }
+        (Expression::Integer(k), Expression::List(list)) => Err(Error::CustomError(format!(
+            "can't sample {} items from a list of {} without repeating any",
+            k,
+            list.len()
+        ))),
+        (k, list) => Err(Error::CustomError(format!(
+            "expected a number of items and a list, but got {} and {}",
+            k, list
+        ))),
+    }
+}
+
+fn gaussian(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("gaussian", &args, 2)?;
+    let (mean, stddev) = (args[0].eval(env)?, args[1].eval(env)?);
+    match (number(&mean), number(&stddev)) {
+        (Some(mean), Some(stddev)) if stddev >= 0.0 => {
+            let mut rng = rand::thread_rng();
+            // The Box-Muller transform, which turns two uniform numbers into a normal one.
+            let u = 1.0 - rng.gen::<f64>();
+            let v = rng.gen::<f64>();
+            let z = (-2.0 * u.ln()).sqrt() * (std::f64::consts::TAU * v).cos();
+            Ok(Expression::Float(mean + stddev * z))
+        }
+        _ => Err(Error::CustomError(format!(
+            "expected a mean and a standard deviation of at least 0, but got {} and {}",
+            mean, stddev
+        ))),
+    }
+}
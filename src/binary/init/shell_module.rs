@@ -1,8 +1,18 @@
 use rand::seq::SliceRandom;
-use std::env::current_exe;
+use std::{
+    collections::BTreeMap,
+    env::current_exe,
+    io::{BufRead, BufReader, Read, Write},
+    path::PathBuf,
+    process::{Command, ExitStatus, Stdio},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
 
 use common_macros::b_tree_map;
-use dune::{Expression, VERSION};
+use dune::{Deadline, Environment, Error, Expression, Int, Style, VERSION};
+use indexmap::IndexMap;
 
 pub fn get() -> Expression {
     (b_tree_map! {
@@ -24,6 +34,14 @@ pub fn get() -> Expression {
             let suggestion = suggestions.choose(&mut rand::thread_rng()).unwrap();
             Expression::String(suggestion.to_string())
         },
+        String::from("run") => Expression::builtin("run", run,
+            "run a command with its own directory and environment variables, like `shell@run [\"make\"] {cwd=\"lib\", env={CC=\"clang\"}, clear-env=False, timeout=\"5m\"}`, returning its status, and killing it if it runs longer than the timeout"),
+        String::from("parallel") => Expression::builtin("parallel", parallel,
+            "run a list of commands at the same time, like `shell@parallel [\"make a\", \"make b\"] {jobs=4}`, returning the status of each, with the same `cwd`, `env`, `clear-env`, and `timeout` options as `shell@run`"),
+        String::from("each") => Expression::builtin("each", each,
+            "run a command or function for each item of a list, like `each \"gzip {}\" files {jobs=4, chunk=10}`, where commands take the same `cwd`, `env`, `clear-env`, and `timeout` options as `shell@run`"),
+        String::from("last-command") => Expression::builtin("last-command", last_command,
+            "get the exit code and seconds taken of the last line entered in the REPL, like a prompt can show, or None before the first"),
         String::from("license") => Expression::String("APACHE-2.0".to_string()),
         String::from("prelude") => {
             // Home directory + .dune-prelude
@@ -43,3 +61,421 @@ pub fn get() -> Expression {
     })
     .into()
 }
+
+/// The colors the output of each command running in parallel is prefixed with.
+const PREFIX_COLORS: &[&str] = &[
+    "bright-cyan",
+    "bright-magenta",
+    "bright-yellow",
+    "bright-green",
+    "bright-blue",
+    "bright-red",
+];
+
+fn parallel(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("parallel", &args, 1..=2)?;
+    let commands = match args[0].eval(env)? {
+        Expression::List(commands) => commands,
+        otherwise => {
+            return Err(Error::CustomError(format!(
+                "expected a list of commands, but got {}",
+                otherwise
+            )))
+        }
+    };
+    let options = options(&args, 1, env)?;
+    let jobs = positive(&options, "jobs", default_jobs())?;
+    let setup = Setup::new(&options, env)?;
+
+    let commands = commands
+        .into_iter()
+        .map(command)
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(run_all(commands, jobs, &setup))
+}
+
+fn last_command(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("last-command", &args, 0..=1)?;
+    Ok(match env.last_command() {
+        Some(last) => Expression::from(b_tree_map! {
+            String::from("code") => Expression::Integer(last.code),
+            String::from("success") => Expression::Boolean(last.code == 0),
+            String::from("seconds") => Expression::Float(last.seconds),
+        }),
+        None => Expression::None,
+    })
+}
+
+fn run(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("run", &args, 1..=2)?;
+    let (name, mut cmd) = command(args[0].eval(env)?)?;
+    let options = options(&args, 1, env)?;
+    let setup = Setup::new(&options, env)?;
+    let started = Instant::now();
+    let status = match setup.timeout {
+        Some(timeout) => dune::run_foreground_for(setup.apply(&mut cmd), timeout),
+        None => dune::run_foreground(setup.apply(&mut cmd)),
+    };
+    Ok(status_of(name, started, status))
+}
+
+fn each(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("each", &args, 2..=3)?;
+    let f = args[0].eval(env)?;
+    let items = match args[1].eval(env)? {
+        Expression::List(items) => items,
+        otherwise => {
+            return Err(Error::CustomError(format!(
+                "expected a list of items, but got {}",
+                otherwise
+            )))
+        }
+    };
+    let options = options(&args, 2, env)?;
+    let jobs = positive(&options, "jobs", 1)?;
+    let chunk = positive(&options, "chunk", 1)?;
+    let setup = Setup::new(&options, env)?;
+    let chunks = items.chunks(chunk);
+
+    match f {
+        // Commands get the items in place of each `{}`, or after their other arguments.
+        Expression::String(template) => {
+            let commands = chunks
+                .map(|items| {
+                    let items = items
+                        .iter()
+                        .map(|item| shell_quote(&item.to_string()))
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    command(Expression::String(if template.contains("{}") {
+                        template.replace("{}", &items)
+                    } else {
+                        format!("{} {}", template, items)
+                    }))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(run_all(commands, jobs, &setup))
+        }
+        Expression::List(_) | Expression::Symbol(_) => {
+            let template = match f {
+                Expression::List(words) => words.iter().map(Expression::to_string).collect(),
+                other => vec![other.to_string()],
+            };
+            let has_placeholder = template.iter().any(|word| word.contains("{}"));
+            let commands = chunks
+                .map(|items| {
+                    let items = items.iter().map(Expression::to_string).collect::<Vec<_>>();
+                    let mut words = vec![];
+                    for word in &template {
+                        if word == "{}" {
+                            words.extend(items.iter().cloned().map(Expression::String));
+                        } else {
+                            words.push(Expression::String(word.replace("{}", &items.join(" "))));
+                        }
+                    }
+                    if !has_placeholder {
+                        words.extend(items.into_iter().map(Expression::String));
+                    }
+                    command(Expression::List(words))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(run_all(commands, jobs, &setup))
+        }
+        // Functions can't run in parallel, so they're applied to each item in turn.
+        f => {
+            let mut results = vec![];
+            for items in chunks {
+                let arg = match items {
+                    [item] if chunk == 1 => item.clone(),
+                    items => Expression::List(items.to_vec()),
+                };
+                results.push(
+                    Expression::Apply(Box::new(f.clone()), vec![Expression::Quote(Box::new(arg))])
+                        .eval(env)?,
+                );
+            }
+            Ok(Expression::List(results))
+        }
+    }
+}
+
+/// Get the map of options a builtin was optionally called with.
+fn options(
+    args: &[Expression],
+    index: usize,
+    env: &mut Environment,
+) -> Result<IndexMap<String, Expression>, Error> {
+    match args
+        .get(index)
+        .map(|options| options.eval(env))
+        .transpose()?
+    {
+        Some(Expression::Map(options)) => Ok(options),
+        Some(otherwise) => Err(Error::CustomError(format!(
+            "expected a map of options like {{jobs=4}}, but got {}",
+            otherwise
+        ))),
+        None => Ok(IndexMap::new()),
+    }
+}
+
+/// Get an option that has to be a positive number.
+fn positive(
+    options: &IndexMap<String, Expression>,
+    name: &str,
+    default: usize,
+) -> Result<usize, Error> {
+    match options.get(name) {
+        Some(Expression::Integer(n)) if *n > 0 => Ok(*n as usize),
+        Some(otherwise) => Err(Error::CustomError(format!(
+            "expected a positive number of {}, but got {}",
+            name, otherwise
+        ))),
+        None => Ok(default),
+    }
+}
+
+/// Where commands run, and the environment variables they get: the shell's
+/// variables, unless the `clear-env` option is set, along with the `env` option.
+struct Setup {
+    cwd: PathBuf,
+    vars: BTreeMap<String, String>,
+    clear_env: bool,
+    /// How long a command may run before it's killed.
+    timeout: Option<Duration>,
+}
+
+impl Setup {
+    fn new(options: &IndexMap<String, Expression>, env: &mut Environment) -> Result<Self, Error> {
+        let mut cwd = PathBuf::from(env.get_cwd());
+        if let Some(dir) = options.get("cwd") {
+            cwd = cwd.join(dir.to_string());
+            if !cwd.is_dir() {
+                return Err(Error::CustomError(format!(
+                    "cannot run commands in {}, because it isn't a directory",
+                    cwd.display()
+                )));
+            }
+        }
+        let clear_env = options
+            .get("clear-env")
+            .map(Expression::is_truthy)
+            .unwrap_or(false);
+        let mut vars = if clear_env {
+            BTreeMap::new()
+        } else {
+            (*env.exported()).clone()
+        };
+        match options.get("env") {
+            Some(Expression::Map(overrides)) => {
+                vars.extend(overrides.iter().map(|(k, v)| (k.clone(), v.to_string())))
+            }
+            Some(otherwise) => {
+                return Err(Error::CustomError(format!(
+                    "expected a map of environment variables like {{CC=\"clang\"}}, but got {}",
+                    otherwise
+                )))
+            }
+            None => {}
+        }
+        let timeout = match options.get("timeout") {
+            None | Some(Expression::None) => None,
+            Some(Expression::Integer(n)) => Some(*n as f64),
+            Some(Expression::Float(n)) => Some(*n),
+            Some(Expression::String(text)) => Some(super::parse_module::duration_seconds(text)?),
+            Some(otherwise) => {
+                return Err(Error::CustomError(format!(
+                    "expected a timeout in seconds or a duration like \"30s\", but got {}",
+                    otherwise
+                )))
+            }
+        }
+        .map(super::parse_module::duration)
+        .transpose()?;
+        Ok(Self {
+            cwd,
+            vars,
+            clear_env,
+            timeout,
+        })
+    }
+
+    fn apply<'a>(&self, cmd: &'a mut Command) -> &'a mut Command {
+        if self.clear_env {
+            cmd.env_clear();
+        }
+        cmd.current_dir(&self.cwd).envs(&self.vars)
+    }
+}
+
+/// The status of a command that ran, or couldn't be run.
+fn status_of(name: String, started: Instant, status: std::io::Result<ExitStatus>) -> Expression {
+    let mut result = b_tree_map! {
+        String::from("command") => Expression::from(name.clone()),
+        String::from("seconds") => Expression::from(started.elapsed().as_secs_f64()),
+    };
+    match status {
+        Ok(status) => {
+            result.insert(String::from("success"), status.success().into());
+            result.insert(
+                String::from("code"),
+                status
+                    .code()
+                    .map(|c| Expression::Integer(c as Int))
+                    .unwrap_or(Expression::None),
+            );
+        }
+        Err(e) => {
+            if e.kind() == std::io::ErrorKind::TimedOut {
+                eprintln!("{}: {}", name, e);
+            } else {
+                eprintln!("{}: could not run: {}", name, e);
+            }
+            result.insert(String::from("success"), false.into());
+            result.insert(String::from("code"), Expression::None);
+        }
+    }
+    Expression::from(result)
+}
+
+/// Quote a word so the system shell passes it along as it is.
+fn shell_quote(word: &str) -> String {
+    if !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_alphanumeric() || "-_./=:,+@%".contains(c))
+    {
+        word.to_string()
+    } else if cfg!(windows) {
+        format!("\"{}\"", word.replace('"', "\"\""))
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
+/// Make a command to run. Strings are run by the system shell, and lists are
+/// a program and its arguments.
+fn command(command: Expression) -> Result<(String, Command), Error> {
+    match command {
+        Expression::List(words) if !words.is_empty() => {
+            let words = words.iter().map(Expression::to_string).collect::<Vec<_>>();
+            let mut cmd = Command::new(&words[0]);
+            cmd.args(&words[1..]);
+            Ok((words.join(" "), cmd))
+        }
+        Expression::String(line) | Expression::Symbol(line) => {
+            let mut cmd = if cfg!(windows) {
+                Command::new("cmd")
+            } else {
+                Command::new("sh")
+            };
+            cmd.arg(if cfg!(windows) { "/C" } else { "-c" }).arg(&line);
+            Ok((line, cmd))
+        }
+        otherwise => Err(Error::CustomError(format!(
+            "expected a command as a string or a list of words, but got {}",
+            otherwise
+        ))),
+    }
+}
+
+/// Run commands with at most `jobs` of them at once, prefixing everything
+/// they print with their names. Returns the status of each command.
+fn run_all(commands: Vec<(String, Command)>, jobs: usize, setup: &Setup) -> Expression {
+    let width = commands
+        .iter()
+        .map(|(name, _)| name.chars().count().min(20))
+        .max()
+        .unwrap_or(0);
+    // The commands are taken off the end of the queue, so the first one goes last.
+    let queue = Arc::new(Mutex::new(
+        commands.into_iter().enumerate().rev().collect::<Vec<_>>(),
+    ));
+    let results = Arc::new(Mutex::new(BTreeMap::new()));
+
+    thread::scope(|scope| {
+        for _ in 0..jobs {
+            let (queue, results) = (queue.clone(), results.clone());
+            scope.spawn(move || loop {
+                let (i, (name, mut cmd)) = match queue.lock().unwrap().pop() {
+                    Some(next) => next,
+                    None => break,
+                };
+                let prefix = format!(
+                    "{}{:width$}\x1b[m | ",
+                    Style::parse(PREFIX_COLORS[i % PREFIX_COLORS.len()])
+                        .unwrap_or_default()
+                        .start(),
+                    name.chars().take(20).collect::<String>(),
+                    width = width
+                );
+                // A command with a timeout gets a process group of its own, so
+                // everything it started is killed, which could otherwise hold its
+                // output open.
+                #[cfg(unix)]
+                if setup.timeout.is_some() {
+                    std::os::unix::process::CommandExt::process_group(&mut cmd, 0);
+                }
+                let started = Instant::now();
+                let status = setup
+                    .apply(&mut cmd)
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::piped())
+                    .spawn()
+                    .and_then(|mut child| {
+                        let stdout = stream(child.stdout.take(), prefix.clone(), false);
+                        let stderr = stream(child.stderr.take(), prefix, true);
+                        let deadline = setup
+                            .timeout
+                            .map(|timeout| Deadline::new(-(child.id() as i32), timeout));
+                        let status = child.wait();
+                        let _ = (stdout.join(), stderr.join());
+                        match deadline.map(Deadline::finish) {
+                            Some(true) => Err(std::io::Error::new(
+                                std::io::ErrorKind::TimedOut,
+                                format!("killed after running for {:?}", setup.timeout.unwrap()),
+                            )),
+                            _ => status,
+                        }
+                    });
+                results
+                    .lock()
+                    .unwrap()
+                    .insert(i, status_of(name, started, status));
+            });
+        }
+    });
+
+    let results = std::mem::take(&mut *results.lock().unwrap());
+    Expression::List(results.into_values().collect())
+}
+
+/// Use as many jobs as there are processors.
+fn default_jobs() -> usize {
+    thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(4)
+}
+
+/// Print every line a command writes with a prefix naming the command, so the
+/// output of commands running at the same time can be told apart.
+fn stream(
+    pipe: Option<impl Read + Send + 'static>,
+    prefix: String,
+    to_stderr: bool,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let pipe = match pipe {
+            Some(pipe) => pipe,
+            None => return,
+        };
+        for line in BufReader::new(pipe).lines().map_while(Result::ok) {
+            if to_stderr {
+                let _ = writeln!(std::io::stderr().lock(), "{}{}", prefix, line);
+            } else {
+                let _ = writeln!(std::io::stdout().lock(), "{}{}", prefix, line);
+            }
+        }
+    })
+}
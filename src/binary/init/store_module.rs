@@ -0,0 +1,367 @@
+use common_macros::b_tree_map;
+use dune::{parse_script, Environment, Error, Expression};
+use indexmap::IndexMap;
+use lazy_static::lazy_static;
+use std::{
+    collections::HashMap,
+    fs::OpenOptions,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+/// The names of the files values are remembered in, under `~/.dune`.
+const STORE: &str = "store";
+const CACHE: &str = "cache";
+
+lazy_static! {
+    /// The values `cache` made in this session, and when they expire. Each line
+    /// the REPL runs is evaluated on a thread of its own, so they're shared
+    /// between every thread.
+    static ref CACHED: Mutex<HashMap<String, (SystemTime, Expression)>> = Mutex::new(HashMap::new());
+}
+
+/// How long to wait for another session to finish writing the store.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+/// How old a lock has to be to have been left behind by a session that died.
+const STALE_LOCK: Duration = Duration::from_secs(30);
+
+pub fn get() -> Expression {
+    (b_tree_map! {
+        String::from("set") => Expression::builtin("set", set,
+            "remember a value across sessions, like `store@set \"last-sync\" (time@now ())`"),
+        String::from("get") => Expression::builtin("get", get_value,
+            "get a value remembered with `store@set`, or a default if there isn't one, like `store@get \"runs\" 0`"),
+        String::from("update") => Expression::builtin("update", update_value,
+            "change a remembered value with a function, without another session changing it in between, like `store@update \"runs\" (n -> n + 1) 0`, getting the new value"),
+        String::from("remove") => Expression::builtin("remove", remove,
+            "forget a value remembered with `store@set`"),
+        String::from("list") => Expression::builtin("list", list,
+            "get a map of every value remembered with `store@set`"),
+        String::from("cache") => Expression::builtin("cache", cache,
+            "get the value of an expression or function made in the last while, or make it again, like `cache \"weather\" \"10m\" { curl \"wttr.in?format=3\" }`, or `cache {disk=True} key ttl f` to keep it across sessions"),
+    })
+    .into()
+}
+
+fn set(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("set", &args, 2)?;
+    let key = args[0].eval(env)?.to_string();
+    let value = args[1].eval(env)?;
+    check_data(&key, &value)?;
+    update(&path(STORE)?, |store| {
+        store.insert(key, value);
+        Ok(())
+    })?;
+    Ok(Expression::None)
+}
+
+fn update_value(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("update", &args, 2..=3)?;
+    let key = args[0].eval(env)?.to_string();
+    let f = args[1].eval(env)?;
+    let default = match args.get(2) {
+        Some(default) => default.eval(env)?,
+        None => Expression::None,
+    };
+    let mut result = Expression::None;
+    update(&path(STORE)?, |store| {
+        let old = store.get(&key).cloned().unwrap_or(default);
+        let new =
+            Expression::Apply(Box::new(f), vec![Expression::Quote(Box::new(old))]).eval(env)?;
+        check_data(&key, &new)?;
+        store.insert(key, new.clone());
+        result = new;
+        Ok(())
+    })?;
+    Ok(result)
+}
+
+fn get_value(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("get", &args, 1..=2)?;
+    let key = args[0].eval(env)?.to_string();
+    match load(&path(STORE)?)?.swap_remove(&key) {
+        Some(value) => Ok(value),
+        None => match args.get(1) {
+            Some(default) => default.eval(env),
+            None => Ok(Expression::None),
+        },
+    }
+}
+
+fn remove(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("remove", &args, 1)?;
+    let key = args[0].eval(env)?.to_string();
+    update(&path(STORE)?, |store| {
+        store.shift_remove(&key);
+        Ok(())
+    })?;
+    Ok(Expression::None)
+}
+
+fn list(_: Vec<Expression>, _: &mut Environment) -> Result<Expression, Error> {
+    Ok(Expression::Map(load(&path(STORE)?)?))
+}
+
+fn cache(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("cache", &args, 3..=4)?;
+    let (options, args) = match args.len() {
+        4 => match args[0].eval(env)? {
+            Expression::Map(options) => (options, &args[1..]),
+            otherwise => {
+                return Err(Error::CustomError(format!(
+                    "expected a map of options like {{disk=True}}, but got {}",
+                    otherwise
+                )))
+            }
+        },
+        _ => (IndexMap::new(), &args[..]),
+    };
+    let key = args[0].eval(env)?.to_string();
+    let ttl = match args[1].eval(env)? {
+        Expression::Integer(n) => n as f64,
+        Expression::Float(n) => n,
+        Expression::String(text) => super::parse_module::duration_seconds(&text)?,
+        otherwise => {
+            return Err(Error::CustomError(format!(
+                "expected how long to keep `{}` for, like 60 or \"10m\", but got {}",
+                key, otherwise
+            )))
+        }
+    };
+    let disk = options
+        .get("disk")
+        .map(Expression::is_truthy)
+        .unwrap_or(false);
+
+    // Values on disk expire at a number of seconds since 1970.
+    let now = SystemTime::now();
+    let seconds = |time: SystemTime| {
+        time.duration_since(SystemTime::UNIX_EPOCH)
+            .map(|since| since.as_secs_f64())
+            .unwrap_or(0.0)
+    };
+    let cached = if disk {
+        match load(&path(CACHE)?)?.swap_remove(&key) {
+            Some(Expression::Map(mut entry)) => match entry.get("expires") {
+                Some(Expression::Float(expires)) if *expires > seconds(now) => {
+                    entry.swap_remove("value")
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    } else {
+        CACHED
+            .lock()
+            .unwrap()
+            .get(&key)
+            .filter(|(expires, _)| *expires > now)
+            .map(|(_, value)| value.clone())
+    };
+    if let Some(value) = cached {
+        return Ok(value);
+    }
+
+    // Like with `retry`, a function is called, and anything else is evaluated.
+    let value = match args[2].eval(env)? {
+        f @ (Expression::Lambda(..) | Expression::Builtin(..) | Expression::Macro(..)) => {
+            Expression::Apply(Box::new(f), vec![Expression::from(key.as_str())]).eval(env)?
+        }
+        Expression::Symbol(name) => {
+            Expression::Apply(Box::new(Expression::Symbol(name)), vec![]).eval(env)?
+        }
+        value => value,
+    };
+    let expires = now
+        .checked_add(super::parse_module::duration(ttl.max(0.0))?)
+        .ok_or_else(|| Error::CustomError(format!("cannot keep `{}` for {} seconds", key, ttl)))?;
+    if disk {
+        check_data(&key, &value)?;
+        update(&path(CACHE)?, |cache| {
+            // Expired values are cleaned up whenever another is cached.
+            cache.retain(|_, entry| match entry {
+                Expression::Map(entry) => {
+                    matches!(entry.get("expires"), Some(Expression::Float(t)) if *t > seconds(now))
+                }
+                _ => false,
+            });
+            cache.insert(
+                key,
+                b_tree_map! {
+                    String::from("expires") => Expression::Float(seconds(expires)),
+                    String::from("value") => value.clone(),
+                }
+                .into(),
+            );
+            Ok(())
+        })?;
+    } else {
+        CACHED.lock().unwrap().insert(key, (expires, value.clone()));
+    }
+    Ok(value)
+}
+
+/// The file the store is kept in, `~/.dune/store.dn`, or the one `cache` keeps
+/// values on disk in, `~/.dune/cache.dn`.
+fn path(name: &str) -> Result<PathBuf, Error> {
+    dirs::home_dir()
+        .map(|home| home.join(".dune").join(format!("{}.dn", name)))
+        .ok_or_else(|| {
+            Error::CustomError(String::from(
+                "could not find your home directory to keep the store in",
+            ))
+        })
+}
+
+/// Read the values in the store. A store that was never written is empty.
+fn load(path: &Path) -> Result<IndexMap<String, Expression>, Error> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(IndexMap::new()),
+        Err(e) => {
+            return Err(Error::CustomError(format!(
+                "could not read the store in {}: {}",
+                path.display(),
+                e
+            )))
+        }
+    };
+    let corrupt = || {
+        Error::CustomError(format!(
+            "the store in {} is not a map of values, fix or remove it",
+            path.display()
+        ))
+    };
+    // The store is kept as a map literal, which is only read, never run.
+    match parse_script(&text).map_err(|_| corrupt())? {
+        Expression::Do(mut exprs) if exprs.len() == 1 => match exprs.pop() {
+            Some(Expression::Map(store)) if store.values().all(is_data) => Ok(store),
+            _ => Err(corrupt()),
+        },
+        Expression::Map(store) if store.values().all(is_data) => Ok(store),
+        _ => Err(corrupt()),
+    }
+}
+
+/// Whether a value is plain data, which can be written down and read back.
+fn is_data(value: &Expression) -> bool {
+    match value {
+        Expression::None
+        | Expression::Boolean(_)
+        | Expression::Integer(_)
+        | Expression::String(_) => true,
+        Expression::Float(n) => n.is_finite(),
+        Expression::List(items) => items.iter().all(is_data),
+        Expression::Map(map) => map.values().all(is_data),
+        _ => false,
+    }
+}
+
+fn check_data(key: &str, value: &Expression) -> Result<(), Error> {
+    if is_data(value) {
+        return Ok(());
+    }
+    Err(Error::CustomError(format!(
+        "cannot remember {} for `{}`, only text, numbers, booleans, None, and lists and maps of them",
+        value, key
+    )))
+}
+
+/// Change the store while no other session can, and write it back all at once.
+fn update(
+    path: &Path,
+    change: impl FnOnce(&mut IndexMap<String, Expression>) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let write_error = |e: std::io::Error| {
+        Error::CustomError(format!(
+            "could not write the store in {}: {}",
+            path.display(),
+            e
+        ))
+    };
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(write_error)?;
+    }
+
+    let _lock = Lock::acquire(path.with_extension("lock"))?;
+    let mut store = load(path)?;
+    change(&mut store)?;
+    let text = Expression::Map(store).repr()? + "\n";
+    let temp = path.with_extension(format!("{}.tmp", std::process::id()));
+    std::fs::write(&temp, text)
+        .and_then(|_| std::fs::rename(&temp, path))
+        .map_err(|e| {
+            let _ = std::fs::remove_file(&temp);
+            write_error(e)
+        })
+}
+
+/// A lock on the store, held by the session whose lock file exists, and
+/// released when it's dropped.
+struct Lock(PathBuf);
+
+impl Lock {
+    fn acquire(path: PathBuf) -> Result<Self, Error> {
+        let started = SystemTime::now();
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => return Ok(Self(path)),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+                Err(e) => {
+                    return Err(Error::CustomError(format!(
+                        "could not lock the store with {}: {}",
+                        path.display(),
+                        e
+                    )))
+                }
+            }
+
+            let age = std::fs::metadata(&path)
+                .and_then(|meta| meta.modified())
+                .ok()
+                .and_then(|modified| modified.elapsed().ok());
+            if age.is_some_and(|age| age > STALE_LOCK) {
+                let _ = std::fs::remove_file(&path);
+                continue;
+            }
+            if started.elapsed().is_ok_and(|waited| waited > LOCK_TIMEOUT) {
+                return Err(Error::CustomError(format!(
+                    "the store is locked by another session, remove {} if none is running",
+                    path.display()
+                )));
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_is_shared_between_threads() {
+        let cached = |value: i64| {
+            thread::spawn(move || {
+                let args = vec![
+                    Expression::from("shared-between-threads"),
+                    Expression::from("10m"),
+                    Expression::Integer(value),
+                ];
+                cache(args, &mut Environment::new()).unwrap()
+            })
+            .join()
+            .unwrap()
+        };
+        assert_eq!(cached(1), Expression::Integer(1));
+        assert_eq!(cached(2), Expression::Integer(1));
+    }
+}
@@ -1,6 +1,8 @@
 use super::fn_module::curry_env;
 use common_macros::b_tree_map;
-use dune::{Environment, Error, Expression};
+use dune::{Environment, Error, Expression, Int};
+use regex::Regex;
+use std::path::{Path, PathBuf};
 
 fn split(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
     if args.len() != 2 {
@@ -27,13 +29,332 @@ fn split(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Err
     }
 }
 
+/// Find the matches of a regular expression in some files, searching directories recursively.
+fn grep(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    // With only a pattern, search the text piped into it.
+    if args.len() == 1 {
+        return curry_env(Expression::builtin("grep", grep_text, ""), 2, env)?
+            .eval(env)?
+            .apply(args)
+            .eval(env);
+    }
+    super::check_exact_args_len("grep", &args, 2)?;
+    let pattern = pattern(&args[0].eval(env)?)?;
+    let paths = match args[1].eval(env)? {
+        Expression::List(paths) => paths,
+        path => vec![path],
+    };
+
+    let cwd = PathBuf::from(env.get_cwd());
+    let mut files = vec![];
+    for path in paths {
+        let path = PathBuf::from(path.to_string());
+        if !cwd.join(&path).exists() {
+            return Err(Error::CustomError(format!(
+                "{} does not exist",
+                path.display()
+            )));
+        }
+        files_under(&cwd, path, &mut files);
+    }
+
+    let mut result = vec![];
+    for file in files {
+        // Skip anything that isn't text.
+        let text = match std::fs::read(cwd.join(&file)) {
+            Ok(bytes) if !bytes.iter().take(8000).any(|b| *b == 0) => {
+                match String::from_utf8(bytes) {
+                    Ok(text) => text,
+                    Err(_) => continue,
+                }
+            }
+            _ => continue,
+        };
+        result.extend(matches(
+            &pattern,
+            &text,
+            Expression::from(file.display().to_string()),
+        ));
+    }
+    Ok(Expression::List(result))
+}
+
+fn grep_text(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("grep", &args, 2)?;
+    let pattern = pattern(&args[0].eval(env)?)?;
+    let text = match args[1].eval(env)? {
+        Expression::Bytes(bytes) => String::from_utf8_lossy(&bytes).to_string(),
+        text => text.to_string(),
+    };
+    Ok(Expression::List(matches(&pattern, &text, Expression::None)))
+}
+
+fn pattern(pattern: &Expression) -> Result<Regex, Error> {
+    Regex::new(&pattern.to_string())
+        .map_err(|e| Error::CustomError(format!("invalid pattern {}: {}", pattern, e)))
+}
+
+/// Collect the files under a path, skipping hidden directories like `.git`.
+fn files_under(cwd: &Path, path: PathBuf, files: &mut Vec<PathBuf>) {
+    if !cwd.join(&path).is_dir() {
+        files.push(path);
+        return;
+    }
+    let mut children = std::fs::read_dir(cwd.join(&path))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+        .map(|entry| path.join(entry.file_name()))
+        .collect::<Vec<_>>();
+    children.sort();
+    for child in children {
+        files_under(cwd, child, files);
+    }
+}
+
+/// Describe every match of a pattern in some text, with lines and columns counting from 1.
+fn matches(pattern: &Regex, text: &str, file: Expression) -> Vec<Expression> {
+    let mut result = vec![];
+    for (i, line) in text.lines().enumerate() {
+        for captures in pattern.captures_iter(line) {
+            let whole = captures.get(0).unwrap();
+            result.push(
+                b_tree_map! {
+                    String::from("file") => file.clone(),
+                    String::from("line") => Expression::Integer(i as Int + 1),
+                    String::from("column") => Expression::Integer(line[..whole.start()].chars().count() as Int + 1),
+                    String::from("text") => Expression::from(line),
+                    String::from("match") => Expression::from(whole.as_str()),
+                    String::from("captures") => Expression::List(
+                        captures
+                            .iter()
+                            .skip(1)
+                            .map(|group| group.map(|g| Expression::from(g.as_str())).unwrap_or(Expression::None))
+                            .collect(),
+                    ),
+                }
+                .into(),
+            );
+        }
+    }
+    result
+}
+
+/// The characters Windows-1252 has in place of the C1 control codes `0x80` to `0x9F`.
+/// The five bytes it leaves undefined decode to the control codes, like in browsers.
+const WINDOWS_1252: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8d}', 'Ž', '\u{8f}',
+    '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9d}', 'ž', 'Ÿ',
+];
+
+/// The text encodings that can be detected, decoded, and encoded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Ascii,
+    Latin1,
+    Windows1252,
+}
+
+impl Encoding {
+    fn named(name: &str) -> Result<Self, Error> {
+        let normalized = name.to_lowercase().replace(['-', '_', ' '], "");
+        Ok(match normalized.as_str() {
+            "utf8" => Self::Utf8,
+            "utf16" | "utf16le" => Self::Utf16Le,
+            "utf16be" => Self::Utf16Be,
+            "ascii" | "usascii" => Self::Ascii,
+            "latin1" | "iso88591" => Self::Latin1,
+            "windows1252" | "cp1252" => Self::Windows1252,
+            _ => {
+                return Err(Error::CustomError(format!(
+                    "unknown encoding {:?}, expected one of utf-8, utf-16le, utf-16be, ascii, latin1, or windows-1252",
+                    name
+                )))
+            }
+        })
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Utf8 => "utf-8",
+            Self::Utf16Le => "utf-16le",
+            Self::Utf16Be => "utf-16be",
+            Self::Ascii => "ascii",
+            Self::Latin1 => "latin1",
+            Self::Windows1252 => "windows-1252",
+        }
+    }
+
+    /// Guess the encoding of some bytes from their byte order mark, the zero
+    /// bytes UTF-16 puts in ASCII text, or whether they're valid UTF-8.
+    fn detect(bytes: &[u8]) -> Self {
+        if bytes.starts_with(&[0xEF, 0xBB, 0xBF]) {
+            return Self::Utf8;
+        } else if bytes.starts_with(&[0xFF, 0xFE]) {
+            return Self::Utf16Le;
+        } else if bytes.starts_with(&[0xFE, 0xFF]) {
+            return Self::Utf16Be;
+        }
+
+        if bytes.chunks_exact(2).remainder().is_empty() && bytes.contains(&0) {
+            let pairs = bytes.len() / 2;
+            let zeros_at = |parity| {
+                bytes
+                    .iter()
+                    .skip(parity)
+                    .step_by(2)
+                    .filter(|b| **b == 0)
+                    .count()
+            };
+            let (even, odd) = (zeros_at(0), zeros_at(1));
+            if odd * 3 > pairs && even * 10 < pairs {
+                return Self::Utf16Le;
+            } else if even * 3 > pairs && odd * 10 < pairs {
+                return Self::Utf16Be;
+            }
+        }
+
+        if bytes.is_ascii() {
+            Self::Ascii
+        } else if std::str::from_utf8(bytes).is_ok() {
+            Self::Utf8
+        } else if bytes.iter().any(|b| (0x80..0xA0).contains(b)) {
+            // Latin-1 only has control codes there, so this is most likely Windows-1252.
+            Self::Windows1252
+        } else {
+            Self::Latin1
+        }
+    }
+
+    fn decode(self, bytes: &[u8]) -> Result<String, Error> {
+        let error = |reason: String| {
+            Error::CustomError(format!(
+                "could not decode bytes as {}: {}",
+                self.name(),
+                reason
+            ))
+        };
+        match self {
+            Self::Utf8 => {
+                let bytes = bytes.strip_prefix(&[0xEF, 0xBB, 0xBF]).unwrap_or(bytes);
+                String::from_utf8(bytes.to_vec()).map_err(|e| error(e.to_string()))
+            }
+            Self::Utf16Le | Self::Utf16Be => {
+                let pairs = bytes.chunks_exact(2);
+                if !pairs.remainder().is_empty() {
+                    return Err(error(String::from("there is an odd number of bytes")));
+                }
+                let units = pairs.map(|pair| match self {
+                    Self::Utf16Le => u16::from_le_bytes([pair[0], pair[1]]),
+                    _ => u16::from_be_bytes([pair[0], pair[1]]),
+                });
+                let text = char::decode_utf16(units)
+                    .collect::<Result<String, _>>()
+                    .map_err(|e| error(e.to_string()))?;
+                Ok(text
+                    .strip_prefix('\u{FEFF}')
+                    .map(String::from)
+                    .unwrap_or(text))
+            }
+            Self::Ascii => match bytes.iter().position(|b| !b.is_ascii()) {
+                Some(i) => Err(error(format!(
+                    "byte {:#04x} at {} isn't ASCII",
+                    bytes[i], i
+                ))),
+                None => Ok(bytes.iter().map(|b| *b as char).collect()),
+            },
+            Self::Latin1 => Ok(bytes.iter().map(|b| *b as char).collect()),
+            Self::Windows1252 => Ok(bytes
+                .iter()
+                .map(|b| match b {
+                    0x80..=0x9F => WINDOWS_1252[*b as usize - 0x80],
+                    _ => *b as char,
+                })
+                .collect()),
+        }
+    }
+
+    fn encode(self, text: &str) -> Result<Vec<u8>, Error> {
+        let byte = |c: char| -> Result<u8, Error> {
+            let code = c as u32;
+            let found = match self {
+                Self::Ascii if code < 0x80 => Some(code as u8),
+                Self::Latin1 if code < 0x100 => Some(code as u8),
+                Self::Windows1252 => match WINDOWS_1252.iter().position(|d| *d == c) {
+                    Some(i) => Some(0x80 + i as u8),
+                    None if code < 0x80 || (0xA0..0x100).contains(&code) => Some(code as u8),
+                    None => None,
+                },
+                _ => None,
+            };
+            found.ok_or_else(|| {
+                Error::CustomError(format!("cannot encode {:?} as {}", c, self.name()))
+            })
+        };
+        match self {
+            Self::Utf8 => Ok(text.as_bytes().to_vec()),
+            Self::Utf16Le => Ok(text.encode_utf16().flat_map(u16::to_le_bytes).collect()),
+            Self::Utf16Be => Ok(text.encode_utf16().flat_map(u16::to_be_bytes).collect()),
+            _ => text.chars().map(byte).collect(),
+        }
+    }
+}
+
+/// Get the bytes a function was called with. Strings are their UTF-8 bytes.
+fn bytes_arg(name: &str, value: Expression) -> Result<Vec<u8>, Error> {
+    match value {
+        Expression::Bytes(bytes) => Ok(bytes),
+        Expression::String(text) => Ok(text.into_bytes()),
+        otherwise => Err(Error::CustomError(format!(
+            "expected bytes in {}, but got {}",
+            name, otherwise
+        ))),
+    }
+}
+
+fn detect_encoding(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("detect-encoding", &args, 1)?;
+    let bytes = bytes_arg("detect-encoding", args[0].eval(env)?)?;
+    Ok(Encoding::detect(&bytes).name().into())
+}
+
+fn decode(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("decode", &args, 1..=2)?;
+    let bytes = bytes_arg("decode", args[0].eval(env)?)?;
+    let encoding = match args.get(1) {
+        Some(name) => Encoding::named(&name.eval(env)?.to_string())?,
+        None => Encoding::detect(&bytes),
+    };
+    Ok(encoding.decode(&bytes)?.into())
+}
+
+fn encode(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("encode", &args, 2)?;
+    let text = args[0].eval(env)?.to_string();
+    let encoding = Encoding::named(&args[1].eval(env)?.to_string())?;
+    Ok(Expression::Bytes(encoding.encode(&text)?))
+}
+
 pub fn get() -> Expression {
     (b_tree_map! {
+        String::from("grep") => Expression::builtin("grep", grep,
+            "find the matches of a regular expression in files or directories, like `string@grep \"fn (\\w+)\" \"src\"`, or in the text piped into it"),
         String::from("to-string") => Expression::builtin("to-string", |args, env| {
             super::check_exact_args_len("to-string", &args, 1)?;
             Ok(Expression::String(args[0].clone().eval(env)?.to_string()))
         }, "convert a value to a string"),
 
+        String::from("detect-encoding") => Expression::builtin("detect-encoding", detect_encoding,
+            "guess the text encoding of some bytes, like \"utf-8\", \"utf-16le\", or \"windows-1252\""),
+        String::from("decode") => Expression::builtin("decode", decode,
+            "decode bytes into a string, like `string@decode (fs@read \"old.log\") \"latin1\"`, detecting the encoding when it isn't given"),
+        String::from("encode") => Expression::builtin("encode", encode,
+            "encode a string into bytes, like `string@encode \"café\" \"utf-16le\"`"),
+
         String::from("caesar") => Expression::builtin("caesar-cipher", |args, env| {
             super::check_args_len("caesar-cipher", &args, 1..=2)?;
 
@@ -1,10 +1,12 @@
 use std::path::PathBuf;
 
+use indexmap::IndexMap;
+
 use common_macros::b_tree_map;
-use dune::{Error, Expression};
+use dune::{Environment, Error, Expression, Options};
 
 pub fn get() -> Expression {
-    Expression::Map(b_tree_map! {
+    Expression::from(b_tree_map! {
         String::from("parse") => Expression::builtin("parse", |args, env| {
             super::check_exact_args_len("parse", &args, 1)?;
             let expr = args[0].clone().eval(env)?;
@@ -31,23 +33,9 @@ pub fn get() -> Expression {
         // Evaluate a file in the current environment.
         String::from("include") => Expression::builtin("include", |args, env| {
             super::check_exact_args_len("include", &args, 1)?;
-
-            let cwd = PathBuf::from(env.get_cwd());
-            let path = cwd.join(args[0].eval(env)?.to_string());
-
-            if let Ok(canon_path) = dunce::canonicalize(&path) {
-                // Read the file.
-                let contents = std::fs::read_to_string(canon_path.clone()).map_err(|e| Error::CustomError(format!("could not read file {}: {}", canon_path.display(), e)))?;
-                // Evaluate the file.
-                if let Ok(expr) = crate::parse(&contents) {
-                    expr.eval(env)
-                } else {
-                    Err(Error::CustomError(format!("could not parse file {}", canon_path.display())))
-                }
-            } else {
-                Err(Error::CustomError(format!("could not canonicalize path {}", path.display())))
-            }
-        }, "evaluate a file in the current environment"),
+            let path = args[0].eval(env)?.to_string();
+            crate::binary::scripts::source(&path, env)
+        }, "evaluate a file in the current environment, relative to the script that includes it"),
 
         // Change the current working directory.
         String::from("cd") => Expression::builtin("cd", |args, env| {
@@ -71,23 +59,9 @@ pub fn get() -> Expression {
         // Import a file (evaluate it in a new environment).
         String::from("import") => Expression::builtin("import", |args, env| {
             super::check_exact_args_len("import", &args, 1)?;
-            let cwd = PathBuf::from(env.get_cwd());
-            let path = cwd.join(args[0].eval(env)?.to_string());
-
-            if let Ok(canon_path) = dunce::canonicalize(&path) {
-                // Read the file.
-                let contents = std::fs::read_to_string(canon_path.clone()).map_err(|e| Error::CustomError(format!("could not read file {}: {}", canon_path.display(), e)))?;
-                // Evaluate the file.
-                if let Ok(expr) = crate::parse(&contents) {
-                    let mut new_env = env.clone();
-                    expr.eval(&mut new_env)
-                } else {
-                    Err(Error::CustomError(format!("could not parse file {}", canon_path.display())))
-                }
-            } else {
-                Err(Error::CustomError(format!("could not canonicalize path {}", path.display())))
-            }
-        }, "import a file (evaluate it in a new environment)"),
+            let path = args[0].eval(env)?.to_string();
+            crate::binary::scripts::import(&path, env)
+        }, "import a file (evaluate it in a new environment), or an installed package by name"),
 
         String::from("env") => Expression::builtin("env", |_args, env| {
             Ok(Expression::from(env.clone()))
@@ -113,5 +87,290 @@ pub fn get() -> Expression {
             let name = args[0].to_string();
             Ok(Expression::Boolean(env.is_defined(&name)))
         }, "check if a variable is defined in the current environment"),
+
+        String::from("strict") => Expression::builtin("strict", |args, env| {
+            super::check_exact_args_len("strict", &args, 1)?;
+            match args[0].eval(env)? {
+                Expression::Boolean(strict) => env.options_mut().strict = strict,
+                otherwise => return Err(Error::CustomError(format!(
+                    "expected True or False in strict, but got {}",
+                    otherwise
+                ))),
+            }
+            Ok(Expression::None)
+        }, "make using an undefined variable an error, instead of evaluating to its name"),
+
+        String::from("shopt") => Expression::builtin("shopt", shopt,
+            "get all the shell options, get one option, or set an option to True or False"),
+
+        String::from("argv") => Expression::from(crate::binary::scripts::args()),
+
+        String::from("parse-args") => Expression::builtin("parse-args", parse_args,
+            "parse `sys@argv` into a map, like `sys@parse-args {flags={verbose=\"print more\"}, options={out=\"where to write\"}, positional={file=\"the file to read\"}}`, with a `--help` flag"),
+
+        String::from("redact") => Expression::builtin("redact", redact,
+            "mask a secret value, or everything matching a regex with `sys@redact --pattern \"ghp_[0-9a-zA-Z]+\"`, in the history, traces, logs, errors, and `vars`"),
     })
 }
+
+fn redact(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    match args.as_slice() {
+        [Expression::Symbol(flag), pattern] if flag == "--pattern" => {
+            let pattern = pattern.eval(env)?.to_string();
+            dune::add_secret_pattern(&pattern).map_err(|e| {
+                Error::CustomError(format!("invalid pattern {:?}: {}", pattern, e))
+            })?;
+        }
+        [value] => match value.eval(env)? {
+            Expression::List(values) => values
+                .iter()
+                .for_each(|value| dune::add_secret(&value.to_string())),
+            value => dune::add_secret(&value.to_string()),
+        },
+        _ => {
+            return Err(Error::CustomError(String::from(
+                "call redact with a secret, like `sys@redact token`, or a pattern, like `sys@redact --pattern \"ghp_[0-9a-zA-Z]+\"`",
+            )))
+        }
+    }
+    Ok(Expression::None)
+}
+
+/// Mask the secrets in the strings of a value.
+pub(super) fn redact_value(value: Expression) -> Expression {
+    match value {
+        Expression::String(text) => Expression::String(dune::redact(&text)),
+        Expression::List(values) => {
+            Expression::List(values.into_iter().map(redact_value).collect())
+        }
+        Expression::Map(values) => Expression::Map(
+            values
+                .into_iter()
+                .map(|(name, value)| (name, redact_value(value)))
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+fn shopt(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    match args.as_slice() {
+        [] | [Expression::None] => Ok(Expression::Map(
+            Options::NAMES
+                .iter()
+                .map(|name| Ok((name.to_string(), env.options().get(name)?.into())))
+                .collect::<Result<_, Error>>()?,
+        )),
+        [name] => Ok(env.options().get(&name.to_string())?.into()),
+        [name, value] => match value.eval(env)? {
+            Expression::Boolean(value) => {
+                env.options_mut().set(&name.to_string(), value)?;
+                Ok(Expression::None)
+            }
+            otherwise => Err(Error::CustomError(format!(
+                "expected True or False for option `{}`, but got {}",
+                name, otherwise
+            ))),
+        },
+        _ => Err(Error::CustomError(String::from(
+            "too many arguments to function shopt",
+        ))),
+    }
+}
+
+/// Parse the arguments of a script, as described by a map of `flags`, which
+/// are True or False, `options`, which take a value, and `positional` arguments.
+/// Each maps a name to its help text, or for options, to a map like
+/// `{help="where to write", default="out.txt"}`. A `name` and `about` go at the
+/// top of the `--help` text, and `args` are parsed instead of `sys@argv`.
+fn parse_args(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("parse-args", &args, 1)?;
+    let spec = match args[0].eval(env)? {
+        Expression::Map(spec) => spec,
+        otherwise => {
+            return Err(Error::CustomError(format!(
+                "expected a map like {{flags={{...}}, options={{...}}, positional={{...}}}}, but got {}",
+                otherwise
+            )))
+        }
+    };
+    let section = |name: &str| -> Result<IndexMap<String, Expression>, Error> {
+        match spec.get(name) {
+            None | Some(Expression::None) => Ok(IndexMap::new()),
+            Some(Expression::Map(section)) => Ok(section.clone()),
+            // Positional arguments can be listed without help text.
+            Some(Expression::List(names)) => Ok(names
+                .iter()
+                .map(|name| (name.to_string(), Expression::None))
+                .collect()),
+            Some(otherwise) => Err(Error::CustomError(format!(
+                "expected a map of names to help text for `{}`, but got {}",
+                name, otherwise
+            ))),
+        }
+    };
+    let (flags, options, positional) = (
+        section("flags")?,
+        section("options")?,
+        section("positional")?,
+    );
+    let argv = match spec.get("args") {
+        Some(Expression::List(args)) => args.iter().map(Expression::to_string).collect(),
+        _ => crate::binary::scripts::args(),
+    };
+
+    let mut result = IndexMap::new();
+    for name in flags.keys() {
+        result.insert(name.clone(), Expression::Boolean(false));
+    }
+    for (name, option) in &options {
+        let default = match option {
+            Expression::Map(option) => option.get("default").cloned(),
+            _ => None,
+        };
+        result.insert(name.clone(), default.unwrap_or(Expression::None));
+    }
+
+    let mut values = vec![];
+    let mut argv = argv.into_iter();
+    while let Some(arg) = argv.next() {
+        if arg == "--" {
+            values.extend(argv.by_ref());
+            break;
+        }
+        if arg == "--help" || arg == "-h" && !flags.contains_key("h") {
+            println!("{}", args_help(&spec, &flags, &options, &positional));
+            std::process::exit(0);
+        }
+        let name = match arg.strip_prefix("--").or_else(|| arg.strip_prefix('-')) {
+            Some(name) if !name.is_empty() && arg.parse::<f64>().is_err() => name,
+            _ => {
+                values.push(arg);
+                continue;
+            }
+        };
+        let (name, value) = match name.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_string())),
+            None => (name, None),
+        };
+        if flags.contains_key(name) {
+            if value.is_some() {
+                return Err(Error::CustomError(format!(
+                    "the flag `{}` doesn't take a value",
+                    arg
+                )));
+            }
+            result.insert(name.to_string(), Expression::Boolean(true));
+        } else if options.contains_key(name) {
+            let value = value
+                .or_else(|| argv.next())
+                .ok_or_else(|| Error::CustomError(format!("the option `{}` needs a value", arg)))?;
+            result.insert(name.to_string(), Expression::String(value));
+        } else {
+            return Err(Error::CustomError(format!(
+                "unknown flag `{}`, see `--help` for the flags this script takes",
+                arg
+            )));
+        }
+    }
+
+    let mut values = values.into_iter();
+    for name in positional.keys() {
+        match values.next() {
+            Some(value) => result.insert(name.clone(), Expression::String(value)),
+            None => {
+                return Err(Error::CustomError(format!(
+                    "missing the argument `{}`, see `--help` for the arguments this script takes",
+                    name
+                )))
+            }
+        };
+    }
+    result.insert(
+        String::from("rest"),
+        Expression::List(values.map(Expression::String).collect()),
+    );
+    Ok(Expression::Map(result))
+}
+
+/// The `--help` text of a script's arguments.
+fn args_help(
+    spec: &IndexMap<String, Expression>,
+    flags: &IndexMap<String, Expression>,
+    options: &IndexMap<String, Expression>,
+    positional: &IndexMap<String, Expression>,
+) -> String {
+    let dash = |name: &str| if name.len() == 1 { "-" } else { "--" };
+    let help = |value: &Expression| match value {
+        Expression::Map(option) => option
+            .get("help")
+            .map(Expression::to_string)
+            .unwrap_or_default(),
+        Expression::None => String::new(),
+        value => value.to_string(),
+    };
+
+    let program = spec
+        .get("name")
+        .map(Expression::to_string)
+        .or_else(|| {
+            crate::binary::scripts::current().map(|script| {
+                script
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+        })
+        .unwrap_or_else(|| String::from("script"));
+    let mut usage = program;
+    if !flags.is_empty() || !options.is_empty() {
+        usage += " [OPTIONS]";
+    }
+    for name in positional.keys() {
+        usage += &format!(" <{}>", name);
+    }
+
+    let mut text = String::new();
+    if let Some(about) = spec.get("about") {
+        text += &format!("{}\n\n", about);
+    }
+    text += &format!("Usage: {}\n", usage);
+
+    let mut rows = positional
+        .iter()
+        .map(|(name, value)| (format!("<{}>", name), help(value)))
+        .collect::<Vec<_>>();
+    if !rows.is_empty() {
+        text += "\nArguments:\n";
+        text += &table(&rows);
+    }
+    rows = flags
+        .iter()
+        .map(|(name, value)| (format!("{}{}", dash(name), name), help(value)))
+        .chain(options.iter().map(|(name, value)| {
+            let mut help = help(value);
+            if let Expression::Map(option) = value {
+                if let Some(default) = option.get("default") {
+                    help += &format!(" (default: {})", default);
+                }
+            }
+            (
+                format!("{}{} <{}>", dash(name), name, name.to_uppercase()),
+                help,
+            )
+        }))
+        .chain([(String::from("-h, --help"), String::from("print this help"))])
+        .collect();
+    text += "\nOptions:\n";
+    text += &table(&rows);
+    text.trim_end().to_string()
+}
+
+/// Line up the help text of arguments in a column.
+fn table(rows: &[(String, String)]) -> String {
+    let width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    rows.iter()
+        .map(|(name, help)| format!("  {:width$}  {}\n", name, help, width = width))
+        .collect()
+}
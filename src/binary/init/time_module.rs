@@ -1,15 +1,39 @@
-use std::{thread, time::Duration};
+use std::{
+    collections::BTreeMap,
+    io::{stdout, Write},
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
 
-use chrono::{Datelike, Timelike};
+use chrono::{Datelike, TimeZone, Timelike};
 use common_macros::b_tree_map;
+use crossterm::{
+    cursor::MoveTo,
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute,
+    terminal::{self, Clear, ClearType},
+};
 use dune::{Environment, Error, Expression};
 
+use crate::binary::timezone::Zone;
+
 pub fn get() -> Expression {
     (b_tree_map! {
         String::from("sleep") => Expression::builtin("sleep", sleep,
-            "sleep for a given number of milliseconds"),
+            "sleep for a given number of milliseconds, or a duration like \"1m30s\""),
         String::from("now") => Expression::builtin("now", now,
             "get information about the current time"),
+        String::from("now-in") => Expression::builtin("now-in", now_in,
+            "get the current time in a timezone, like `time@now-in \"Europe/Berlin\"`"),
+        String::from("convert") => Expression::builtin("convert", convert,
+            "convert a time like \"2024-03-01 12:00\" on the clocks of one timezone to another, like `time@convert \"2024-03-01 12:00\" \"America/New_York\" \"Asia/Tokyo\"`"),
+        String::from("to-utc") => Expression::builtin("to-utc", to_utc,
+            "convert a time on the local clock to UTC"),
+        String::from("to-local") => Expression::builtin("to-local", to_local,
+            "convert a time in UTC to the local clock"),
+        String::from("watch") => Expression::builtin("watch", watch,
+            "run a block every few seconds, like `watch 2.0 { cargo build }`, or whenever a path changes, like `watch --on-change src { ... }`, until q is pressed"),
     })
     .into()
 }
@@ -17,33 +41,319 @@ pub fn get() -> Expression {
 fn sleep(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
     super::check_exact_args_len("sleep", &args, 1)?;
 
-    match args[0].eval(env)? {
-        Expression::Float(n) => thread::sleep(Duration::from_millis(n as u64)),
-        Expression::Integer(n) => thread::sleep(Duration::from_millis(n as u64)),
+    let duration = match args[0].eval(env)? {
+        Expression::Float(n) => Duration::from_millis(n as u64),
+        Expression::Integer(n) => Duration::from_millis(n as u64),
+        Expression::String(text) => {
+            super::parse_module::duration(super::parse_module::duration_seconds(&text)?)?
+        }
         otherwise => {
             return Err(Error::CustomError(format!(
-                "expected integer or float, but got {}",
+                "expected integer, float, or duration, but got {}",
                 otherwise
             )))
         }
-    }
+    };
 
+    // Sleep a little at a time, so Ctrl-C can stop it.
+    let end = Instant::now() + duration;
+    while let Some(left) = end.checked_duration_since(Instant::now()) {
+        if dune::is_canceled() {
+            return Err(Error::Interrupted);
+        }
+        thread::sleep(left.min(Duration::from_millis(50)));
+    }
     Ok(Expression::None)
 }
 
 fn now(_: Vec<Expression>, _: &mut Environment) -> Result<Expression, Error> {
     let now = chrono::Local::now();
 
-    Ok(Expression::Map(b_tree_map! {
+    Ok(Expression::from(b_tree_map! {
+        String::from("stamp") => Expression::Integer(now.timestamp()),
         String::from("year") => Expression::Integer(now.year() as i64),
         String::from("month") => Expression::Integer(now.month() as i64),
         String::from("day") => Expression::Integer(now.day() as i64),
         String::from("hour") => Expression::Integer(now.hour() as i64),
-        String::from("time") => Expression::Map(b_tree_map! {
+        String::from("time") => Expression::from(b_tree_map! {
             String::from("str") => Expression::String(now.time().format("%-I:%M %p").to_string()),
         }),
-        String::from("date") => Expression::Map(b_tree_map! {
+        String::from("date") => Expression::from(b_tree_map! {
             String::from("str") => Expression::String(now.format("%D").to_string()),
         }),
     }))
 }
+
+/// A moment as it is on the clocks of a timezone.
+fn moment(stamp: i64, zone: &Zone) -> Result<Expression, Error> {
+    let offset = zone.offset_at(stamp);
+    let time = chrono::FixedOffset::east_opt(offset.seconds as i32)
+        .and_then(|fixed| fixed.timestamp_opt(stamp, 0).single())
+        .ok_or_else(|| Error::CustomError(format!("the time {} is out of range", stamp)))?;
+
+    Ok(Expression::from(b_tree_map! {
+        String::from("stamp") => Expression::Integer(stamp),
+        String::from("year") => Expression::Integer(time.year() as i64),
+        String::from("month") => Expression::Integer(time.month() as i64),
+        String::from("day") => Expression::Integer(time.day() as i64),
+        String::from("hour") => Expression::Integer(time.hour() as i64),
+        String::from("minute") => Expression::Integer(time.minute() as i64),
+        String::from("second") => Expression::Integer(time.second() as i64),
+        String::from("zone") => Expression::String(zone.name.clone()),
+        String::from("abbreviation") => Expression::String(offset.abbreviation),
+        String::from("offset") => Expression::Integer(offset.seconds),
+        String::from("dst") => Expression::Boolean(offset.dst),
+        String::from("str") => Expression::String(time.format("%Y-%m-%d %H:%M:%S %:z").to_string()),
+        String::from("time") => Expression::from(b_tree_map! {
+            String::from("str") => Expression::String(time.format("%-I:%M %p").to_string()),
+        }),
+        String::from("date") => Expression::from(b_tree_map! {
+            String::from("str") => Expression::String(time.format("%D").to_string()),
+        }),
+    }))
+}
+
+/// Get the timezone named by an argument.
+fn zone(arg: &Expression, env: &mut Environment) -> Result<Zone, Error> {
+    match arg.eval(env)? {
+        Expression::String(name) | Expression::Symbol(name) => Zone::named(&name),
+        otherwise => Err(Error::CustomError(format!(
+            "expected the name of a timezone, but got {}",
+            otherwise
+        ))),
+    }
+}
+
+/// Get the UNIX timestamp of a time, which is either a timestamp, a map with
+/// one like `time@now` returns, a time with an offset like
+/// "2024-03-01T12:00:00+01:00", or a time like "2024-03-01 12:00" on the clocks
+/// of a timezone.
+fn stamp(time: Expression, zone: &Zone) -> Result<i64, Error> {
+    match time {
+        Expression::Integer(stamp) => Ok(stamp),
+        Expression::Float(stamp) => Ok(stamp.floor() as i64),
+        Expression::Map(ref map) => match map.get("stamp") {
+            Some(Expression::Integer(stamp)) => Ok(*stamp),
+            _ => Err(Error::CustomError(format!(
+                "expected a time with a stamp, but got {}",
+                time
+            ))),
+        },
+        Expression::String(text) => {
+            let text = text.trim();
+            if let Ok(time) = chrono::DateTime::parse_from_rfc3339(text) {
+                return Ok(time.timestamp());
+            }
+            let local = [
+                "%Y-%m-%d %H:%M:%S",
+                "%Y-%m-%dT%H:%M:%S",
+                "%Y-%m-%d %H:%M",
+                "%Y-%m-%dT%H:%M",
+            ]
+            .iter()
+            .find_map(|format| chrono::NaiveDateTime::parse_from_str(text, format).ok())
+            .or_else(|| {
+                chrono::NaiveDate::parse_from_str(text, "%Y-%m-%d")
+                    .ok()
+                    .and_then(|date| date.and_hms_opt(0, 0, 0))
+            })
+            .ok_or_else(|| {
+                Error::CustomError(format!(
+                    "expected a time like \"2024-03-01 12:00\", but got {:?}",
+                    text
+                ))
+            })?;
+            Ok(zone.to_stamp(chrono::Utc.from_utc_datetime(&local).timestamp()))
+        }
+        otherwise => Err(Error::CustomError(format!(
+            "expected a time, but got {}",
+            otherwise
+        ))),
+    }
+}
+
+fn now_in(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("now-in", &args, 1)?;
+    let zone = zone(&args[0], env)?;
+    moment(chrono::Utc::now().timestamp(), &zone)
+}
+
+fn convert(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("convert", &args, 3)?;
+    let time = args[0].eval(env)?;
+    let (from, to) = (zone(&args[1], env)?, zone(&args[2], env)?);
+    moment(stamp(time, &from)?, &to)
+}
+
+fn to_utc(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("to-utc", &args, 1)?;
+    let time = args[0].eval(env)?;
+    moment(stamp(time, &Zone::local())?, &Zone::named("UTC")?)
+}
+
+fn to_local(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("to-local", &args, 1)?;
+    let time = args[0].eval(env)?;
+    moment(stamp(time, &Zone::named("UTC")?)?, &Zone::local())
+}
+
+/// How often to check whether the files being watched have changed.
+const WATCH_POLL: Duration = Duration::from_millis(250);
+
+fn watch(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("watch", &args, 2..=3)?;
+    let block = args[args.len() - 1].clone();
+
+    // Either re-run the block every so often, or whenever a path changes.
+    let (interval, watched) = match &args[0] {
+        Expression::Symbol(flag) if flag == "--on-change" && args.len() == 3 => {
+            let name = args[1].eval(env)?.to_string();
+            let path = PathBuf::from(env.get_cwd()).join(&name);
+            if !path.exists() {
+                return Err(Error::CustomError(format!(
+                    "cannot watch {}, it doesn't exist",
+                    name
+                )));
+            }
+            (None, Some((name, path)))
+        }
+        _ if args.len() == 2 => {
+            let seconds = match args[0].eval(env)? {
+                Expression::Float(n) => n,
+                Expression::Integer(n) => n as f64,
+                Expression::String(text) => super::parse_module::duration_seconds(&text)?,
+                otherwise => {
+                    return Err(Error::CustomError(format!(
+                        "expected a number of seconds or a duration to watch, but got {}",
+                        otherwise
+                    )))
+                }
+            };
+            (Some(super::parse_module::duration(seconds.max(0.1))?), None)
+        }
+        _ => {
+            return Err(Error::CustomError(String::from(
+                "call watch with an interval, like `watch 2.0 { ... }`, or a path, like `watch --on-change src { ... }`",
+            )))
+        }
+    };
+
+    let header = match (&interval, &watched) {
+        (Some(interval), _) => format!("Every {}s: {}", interval.as_secs_f64(), block),
+        (_, Some((name, _))) => format!("On changes to {}: {}", name, block),
+        _ => unreachable!(),
+    };
+    let mut modified = watched.as_ref().map(|(_, path)| modification_times(path));
+    loop {
+        let _ = execute!(stdout(), Clear(ClearType::All), MoveTo(0, 0));
+        println!(
+            "{}    {}    (press q to stop)\n",
+            header,
+            chrono::Local::now().format("%H:%M:%S")
+        );
+        // Show the result like the REPL would, running a lone command name.
+        let result = match block.eval(env) {
+            Ok(Expression::Symbol(name)) => {
+                Expression::Apply(Box::new(Expression::Symbol(name)), vec![]).eval(env)
+            }
+            Ok(Expression::None) => Ok(Expression::None),
+            Ok(value) => {
+                crate::report(Expression::Quote(Box::new(value)), env).map(|_| Expression::None)
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            eprintln!("{}", e);
+        }
+        let _ = stdout().flush();
+
+        let started = Instant::now();
+        loop {
+            if stop_pressed(WATCH_POLL)
+                .map_err(|e| Error::CustomError(format!("could not run watch: {}", e)))?
+            {
+                return Ok(Expression::None);
+            }
+            let changed = match (&interval, &watched) {
+                (Some(interval), _) => started.elapsed() >= *interval,
+                (_, Some((_, path))) => {
+                    let now = modification_times(path);
+                    let changed = modified.as_ref() != Some(&now);
+                    modified = Some(now);
+                    changed
+                }
+                _ => unreachable!(),
+            };
+            if changed {
+                break;
+            }
+        }
+    }
+}
+
+/// Wait a little while for the user to press q, Escape, or Ctrl-C. Without a
+/// terminal to read keys from, this just waits.
+pub(super) fn stop_pressed(timeout: Duration) -> std::io::Result<bool> {
+    let was_raw = terminal::is_raw_mode_enabled().unwrap_or(false);
+    if terminal::enable_raw_mode().is_err() {
+        thread::sleep(timeout);
+        return Ok(false);
+    }
+    let mut stop = false;
+    let deadline = Instant::now() + timeout;
+    let mut result = Ok(());
+    while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+        match event::poll(left).and_then(|ready| {
+            if ready {
+                event::read().map(Some)
+            } else {
+                Ok(None)
+            }
+        }) {
+            Ok(Some(Event::Key(KeyEvent {
+                code,
+                modifiers,
+                kind: KeyEventKind::Press,
+                ..
+            }))) => {
+                if matches!(code, KeyCode::Char('q') | KeyCode::Esc)
+                    || (code == KeyCode::Char('c') && modifiers.contains(KeyModifiers::CONTROL))
+                {
+                    stop = true;
+                    break;
+                }
+            }
+            Ok(Some(_)) => {}
+            Ok(None) => break,
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        }
+    }
+    if !was_raw {
+        terminal::disable_raw_mode()?;
+    }
+    result.map(|_| stop)
+}
+
+/// Get when every file under a path was last modified, skipping hidden files.
+fn modification_times(path: &Path) -> BTreeMap<PathBuf, SystemTime> {
+    let mut times = BTreeMap::new();
+    let mut paths = vec![path.to_path_buf()];
+    while let Some(path) = paths.pop() {
+        if let Ok(meta) = std::fs::metadata(&path) {
+            if let Ok(time) = meta.modified() {
+                times.insert(path.clone(), time);
+            }
+            if meta.is_dir() {
+                for entry in std::fs::read_dir(&path).into_iter().flatten().flatten() {
+                    if !entry.file_name().to_string_lossy().starts_with('.') {
+                        paths.push(entry.path());
+                    }
+                }
+            }
+        }
+    }
+    times
+}
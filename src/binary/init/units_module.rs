@@ -0,0 +1,345 @@
+use super::{
+    fmt_module::{format_bytes, format_duration, format_size, one_decimal},
+    parse_module::{duration_seconds, size_bytes},
+};
+use common_macros::b_tree_map;
+use dune::{Environment, Error, Expression};
+use std::collections::BTreeMap;
+
+pub fn get() -> Expression {
+    (b_tree_map! {
+        String::from("convert") => Expression::builtin("convert", convert,
+            "convert a quantity to another unit of the same kind, like `units@convert \"5km\" \"mi\"` or `units@convert 72 \"F\" \"C\"`"),
+        String::from("parse") => Expression::builtin("parse", parse,
+            "parse a quantity like \"2.5GiB\", \"1h30m\", or \"5ft 11in\" into a number of the base unit of its kind"),
+        String::from("format") => Expression::builtin("format", format,
+            "format a quantity in a readable unit, or the unit given, like `units@format 1536 \"size\"` or `units@format \"1500m\" \"km\"`"),
+        String::from("kind") => Expression::builtin("kind", kind,
+            "get the kind of a unit or quantity, like `units@kind \"MB/s\"` is \"rate\""),
+        String::from("kinds") => Expression::from(
+            KINDS
+                .iter()
+                .map(|(kind, base)| (kind.to_string(), Expression::from(base.to_string())))
+                .collect::<BTreeMap<_, _>>()
+        ),
+    })
+    .into()
+}
+
+/// The kinds of quantities, and the base unit each one is parsed into.
+const KINDS: &[(&str, &str)] = &[
+    ("duration", "s"),
+    ("length", "m"),
+    ("rate", "B/s"),
+    ("size", "B"),
+    ("temperature", "°C"),
+];
+
+/// The names of the units of length, and how many meters each one is.
+const LENGTHS: &[(&str, f64)] = &[
+    ("nm nanometer nanometers", 1e-9),
+    ("um µm micrometer micrometers", 1e-6),
+    ("mm millimeter millimeters", 1e-3),
+    ("cm centimeter centimeters", 1e-2),
+    ("m meter meters", 1.0),
+    ("km kilometer kilometers", 1e3),
+    ("in inch inches", 0.0254),
+    ("ft foot feet", 0.3048),
+    ("yd yard yards", 0.9144),
+    ("mi mile miles", 1609.344),
+    ("nmi nautical-mile nautical-miles", 1852.0),
+];
+
+/// A unit, as how to turn a number of it into the base unit of its kind.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Unit {
+    kind: &'static str,
+    /// How many of the base unit one of this unit is.
+    scale: f64,
+    /// What's added after scaling, for temperatures.
+    offset: f64,
+}
+
+impl Unit {
+    fn linear(kind: &'static str, scale: f64) -> Self {
+        Self {
+            kind,
+            scale,
+            offset: 0.0,
+        }
+    }
+
+    /// A number of this unit in the base unit.
+    fn in_base(self, value: f64) -> f64 {
+        value * self.scale + self.offset
+    }
+
+    /// A number of the base unit in this unit.
+    fn in_unit(self, value: f64) -> f64 {
+        (value - self.offset) / self.scale
+    }
+}
+
+/// Look up a unit by name, ignoring case. Durations and sizes are named the
+/// same way `parse@duration` and `parse@size` read them, except that `m` is
+/// meters instead of minutes outside of durations like `1h30m`, and sizes need
+/// a `B` like `MB` or `MiB`.
+fn unit(name: &str) -> Option<Unit> {
+    let name = name.trim().to_lowercase().replace("metre", "meter");
+    let name = name.as_str();
+    if name.is_empty() {
+        return None;
+    }
+
+    if let Some((_, scale)) = LENGTHS
+        .iter()
+        .find(|(names, _)| names.split_whitespace().any(|n| n == name))
+    {
+        return Some(Unit::linear("length", *scale));
+    }
+    match name {
+        "c" | "°c" | "celsius" => return Some(Unit::linear("temperature", 1.0)),
+        "f" | "°f" | "fahrenheit" => {
+            return Some(Unit {
+                kind: "temperature",
+                scale: 5.0 / 9.0,
+                offset: -160.0 / 9.0,
+            })
+        }
+        "k" | "kelvin" => {
+            return Some(Unit {
+                kind: "temperature",
+                scale: 1.0,
+                offset: -273.15,
+            })
+        }
+        _ => {}
+    }
+
+    // Rates are bits per second like `Mbps`, or sizes per second like `MB/s`.
+    if let Some(prefix) = name.strip_suffix("bps") {
+        let power = ["", "k", "m", "g", "t"].iter().position(|p| *p == prefix)?;
+        return Some(Unit::linear("rate", 1000f64.powi(power as i32) / 8.0));
+    }
+    if let Some(size) = name.strip_suffix("/s") {
+        return match unit(size)? {
+            Unit {
+                kind: "size",
+                scale,
+                ..
+            } => Some(Unit::linear("rate", scale)),
+            _ => None,
+        };
+    }
+
+    if let Ok(seconds) = duration_seconds(&format!("1{}", name)) {
+        return Some(Unit::linear("duration", seconds));
+    }
+    if name.ends_with('b') || name.ends_with("byte") || name.ends_with("bytes") {
+        if let Ok(bytes) = size_bytes(&format!("1{}", name)) {
+            return Some(Unit::linear("size", bytes as f64));
+        }
+    }
+    None
+}
+
+/// Get a unit, or an error saying it isn't one.
+fn unit_named(name: &str) -> Result<Unit, Error> {
+    unit(name).ok_or_else(|| Error::CustomError(format!("unknown unit `{}`", name)))
+}
+
+/// Parse a quantity like `5km`, `-40 F`, or `1h30m` into a number of the base
+/// unit of its kind, and that kind. The parts of a quantity must be the same kind.
+fn quantity(text: &str) -> Result<(f64, &'static str), Error> {
+    let error = || {
+        Error::CustomError(format!(
+            "could not parse `{}` as a quantity like \"5km\"",
+            text
+        ))
+    };
+
+    let mut parts = vec![];
+    let mut rest = text.trim();
+    while !rest.is_empty() {
+        let len = rest
+            .char_indices()
+            .find(|&(i, c)| !(c.is_ascii_digit() || c == '.' || (i == 0 && c == '-')))
+            .map(|(i, _)| i)
+            .unwrap_or(rest.len());
+        let number = rest[..len].parse::<f64>().map_err(|_| error())?;
+        rest = rest[len..].trim_start();
+
+        let len = rest
+            .find(|c: char| c.is_ascii_digit() || c.is_whitespace() || c == ',')
+            .unwrap_or(rest.len());
+        parts.push((number, &rest[..len]));
+        rest = rest[len..].trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+    }
+
+    let mut units = parts
+        .iter()
+        .map(|(_, name)| unit_named(name).map_err(|_| error()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let kind = match units.first() {
+        Some(unit) => unit.kind,
+        None => return Err(error()),
+    };
+    // `m` is minutes in durations like `1h30m`, like `fmt@duration` writes them.
+    if units.iter().any(|unit| unit.kind != kind)
+        && units.iter().any(|unit| unit.kind == "duration")
+    {
+        if let Ok(durations) = parts
+            .iter()
+            .map(|(_, name)| duration_seconds(&format!("1{}", name)))
+            .collect::<Result<Vec<_>, _>>()
+        {
+            units = durations
+                .into_iter()
+                .map(|seconds| Unit::linear("duration", seconds))
+                .collect();
+        }
+    }
+    let kind = units[0].kind;
+    if units.iter().any(|unit| unit.kind != kind) {
+        return Err(Error::CustomError(format!(
+            "cannot add up `{}`, because its parts are different kinds of quantities",
+            text
+        )));
+    }
+    if kind == "temperature" && parts.len() > 1 {
+        return Err(error());
+    }
+    Ok((
+        parts
+            .iter()
+            .zip(units)
+            .map(|((number, _), unit)| unit.in_base(*number))
+            .sum(),
+        kind,
+    ))
+}
+
+/// Get a number from an argument.
+fn number_of(name: &str, value: Expression) -> Result<f64, Error> {
+    match value {
+        Expression::Integer(n) => Ok(n as f64),
+        Expression::Float(n) => Ok(n),
+        otherwise => Err(Error::CustomError(format!(
+            "expected integer or float in {}, but got {}",
+            name, otherwise
+        ))),
+    }
+}
+
+/// A converted number, as an integer when it's whole. Conversions like
+/// `1609.344 / 0.3048` leave floating point noise in the last digits, so the
+/// number is rounded to 12 significant digits first.
+fn number(n: f64) -> Expression {
+    let n = format!("{:.11e}", n).parse::<f64>().unwrap_or(n);
+    if n.fract() == 0.0 && n.abs() < 1e15 {
+        Expression::Integer(n as i64)
+    } else {
+        Expression::Float(n)
+    }
+}
+
+/// Check that a unit is the kind of quantity it's used with.
+fn same_kind(unit_name: &str, unit: Unit, kind: &str) -> Result<(), Error> {
+    if unit.kind == kind {
+        Ok(())
+    } else {
+        Err(Error::CustomError(format!(
+            "cannot use `{}` for a {}, because it's a unit of {}",
+            unit_name, kind, unit.kind
+        )))
+    }
+}
+
+fn convert(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("convert", &args, 2..=3)?;
+    let (base, kind) = match args[0].eval(env)? {
+        Expression::String(text) if args.len() == 2 => quantity(&text)?,
+        value if args.len() == 3 => {
+            let from = args[1].eval(env)?.to_string();
+            let unit = unit_named(&from)?;
+            (unit.in_base(number_of("convert", value)?), unit.kind)
+        }
+        otherwise => {
+            return Err(Error::CustomError(format!(
+                "expected a quantity like \"5km\" to convert, or a number and its unit, but got {}",
+                otherwise
+            )))
+        }
+    };
+    let to = args[args.len() - 1].eval(env)?.to_string();
+    let unit = unit_named(&to)?;
+    same_kind(&to, unit, kind)?;
+    Ok(number(unit.in_unit(base)))
+}
+
+fn parse(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("parse", &args, 1)?;
+    Ok(number(quantity(&args[0].eval(env)?.to_string())?.0))
+}
+
+fn kind(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("kind", &args, 1)?;
+    let text = args[0].eval(env)?.to_string();
+    match unit(&text) {
+        Some(unit) => Ok(unit.kind.into()),
+        None => Ok(quantity(&text)?.1.into()),
+    }
+}
+
+fn format(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_args_len("format", &args, 1..=2)?;
+    let value = args[0].eval(env)?;
+    let to = match args.get(1) {
+        Some(arg) => Some(arg.eval(env)?.to_string()),
+        None => None,
+    };
+
+    // A number is in the base unit of the kind it's formatted as.
+    let kind_named = |name: &str| {
+        KINDS
+            .iter()
+            .find(|(kind, _)| *kind == name)
+            .map(|(kind, _)| *kind)
+    };
+    let (base, kind) = match (value, &to) {
+        (Expression::String(text), _) => quantity(&text)?,
+        (value, Some(to)) => (
+            number_of("format", value)?,
+            match kind_named(to) {
+                Some(kind) => kind,
+                None => unit_named(to)?.kind,
+            },
+        ),
+        (otherwise, None) => {
+            return Err(Error::CustomError(format!(
+                "expected a quantity like \"5km\" to format, or a number and its kind, but got {}",
+                otherwise
+            )))
+        }
+    };
+
+    if let Some(to) = to.filter(|to| kind_named(to).is_none()) {
+        let unit = unit_named(&to)?;
+        same_kind(&to, unit, kind)?;
+        return Ok(format!("{}{}", one_decimal(unit.in_unit(base)), to).into());
+    }
+    Ok(match kind {
+        "size" => format_size(base),
+        "duration" => format_duration(base),
+        "rate" => format_bytes(base) + "/s",
+        "temperature" => format!("{}°C", one_decimal(base)),
+        _ => {
+            let (name, scale) = [("km", 1e3), ("m", 1.0), ("cm", 1e-2), ("mm", 1e-3)]
+                .into_iter()
+                .find(|(_, scale)| base.abs() >= *scale)
+                .unwrap_or(("m", 1.0));
+            format!("{}{}", one_decimal(base / scale), name)
+        }
+    }
+    .into())
+}
@@ -1,11 +1,27 @@
 use common_macros::b_tree_map;
+use crossterm::{
+    cursor::{MoveTo, MoveUp},
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    queue,
+    style::Print,
+    terminal,
+};
 use dune::{Environment, Error, Expression};
+use std::{
+    collections::BTreeSet,
+    io::{stdout, Write},
+    path::PathBuf,
+};
 
 pub fn get() -> Expression {
     (b_tree_map! {
         String::from("create") => Expression::builtin("create", create, "create a text widget"),
         String::from("joinx") => Expression::builtin("joinx", joinx, "join two widgets horizontally"),
-        String::from("joiny") => Expression::builtin("joiny", joiny, "join two widgets vertically")
+        String::from("joiny") => Expression::builtin("joiny", joiny, "join two widgets vertically"),
+        String::from("editor") => Expression::builtin("editor", editor,
+            "edit text in a widget, returning the text when ctrl+s or esc is pressed (ctrl+c cancels)"),
+        String::from("file-picker") => Expression::builtin("file-picker", file_picker,
+            "browse for files starting in a directory, returning the path picked with enter, or the paths marked with space")
     })
     .into()
 }
@@ -187,3 +203,504 @@ fn joiny(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Err
         .join("\n")
         .into())
 }
+
+fn editor(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("editor", &args, 4)?;
+    let title = args[0].eval(env)?.to_string();
+    let text = match args[1].eval(env)? {
+        Expression::None => String::new(),
+        other => other.to_string(),
+    };
+
+    let width = match args[2].eval(env)? {
+        Expression::Integer(n) if n > 4 => n as usize,
+        otherwise => {
+            return Err(Error::CustomError(format!(
+                "expected width argument to be integer greater than 4, but got {}",
+                otherwise
+            )))
+        }
+    };
+    let height = match args[3].eval(env)? {
+        Expression::Integer(n) if n >= 3 => n as usize,
+        otherwise => {
+            return Err(Error::CustomError(format!(
+                "expected height argument to be an integer greater than 2, but got {}",
+                otherwise
+            )))
+        }
+    };
+    if width - 2 < title.chars().count() {
+        return Err(Error::CustomError(String::from(
+            "width is less than title length",
+        )));
+    }
+
+    let mut area = TextArea::new(&text, width - 2, height - 2);
+    if interactive("editor", || area.run(&title))? {
+        Ok(area.text().into())
+    } else {
+        Ok(text.into())
+    }
+}
+
+/// Edit text in the editor widget, as wide as the console and as tall as the
+/// text, getting the edited text, or `None` if the edit was cancelled.
+pub(super) fn edit_text(title: &str, text: &str) -> Result<Option<String>, Error> {
+    let (width, height) = terminal::size().unwrap_or((80, 24));
+    let width = (width as usize).clamp(title.chars().count() + 4, 120);
+    let height = (text.lines().count() + 3).clamp(3, (height as usize).max(4) - 1);
+    let mut area = TextArea::new(text, width - 2, height - 2);
+    Ok(interactive("editor", || area.run(title))?.then(|| area.text()))
+}
+
+fn file_picker(args: Vec<Expression>, env: &mut Environment) -> Result<Expression, Error> {
+    super::check_exact_args_len("file-picker", &args, 1)?;
+    let start = PathBuf::from(env.get_cwd()).join(args[0].eval(env)?.to_string());
+    let dir = match dunce::canonicalize(&start) {
+        Ok(dir) if dir.is_dir() => dir,
+        _ => {
+            return Err(Error::CustomError(format!(
+                "{} is not a directory",
+                start.display()
+            )))
+        }
+    };
+
+    let width = match terminal::size() {
+        Ok((width, _)) if width > 0 => (width as usize).min(72),
+        _ => 72,
+    };
+    let mut picker = FilePicker::new(dir, width - 2, 10);
+    Ok(match interactive("file picker", || picker.run())? {
+        None => Expression::None,
+        Some(mut paths) if paths.len() == 1 => paths.remove(0).display().to_string().into(),
+        Some(paths) => Expression::List(
+            paths
+                .into_iter()
+                .map(|path| path.display().to_string().into())
+                .collect(),
+        ),
+    })
+}
+
+/// Run an interactive widget in raw mode, leaving the console the way it was afterwards.
+pub(super) fn interactive<T>(
+    name: &str,
+    run: impl FnOnce() -> std::io::Result<T>,
+) -> Result<T, Error> {
+    let was_raw = terminal::is_raw_mode_enabled().unwrap_or(false);
+    let result = terminal::enable_raw_mode().and_then(|_| run());
+    if !was_raw {
+        let _ = terminal::disable_raw_mode();
+    }
+    result.map_err(|e| Error::CustomError(format!("could not run {}: {}", name, e)))
+}
+
+/// Make room for an interactive widget below the cursor, scrolling the console if we
+/// have to. Returns where the top left corner of the widget goes.
+fn make_room(out: &mut impl Write, height: usize) -> std::io::Result<(u16, u16)> {
+    queue!(
+        out,
+        Print("\r\n".repeat(height - 1)),
+        MoveUp(height as u16 - 1)
+    )?;
+    out.flush()?;
+    crossterm::cursor::position()
+}
+
+/// The top border of an interactive widget, with its title in the middle.
+fn top_border(title: &str, width: usize) -> String {
+    let title_len = title.chars().count();
+    let left = (width - title_len) / 2;
+    format!(
+        "┌{}{}{}┐",
+        "─".repeat(left),
+        title,
+        "─".repeat(width - title_len - left)
+    )
+}
+
+/// The state of the editor widget: the text being edited, where the cursor is
+/// in it, and which part of it is scrolled into view.
+struct TextArea {
+    lines: Vec<Vec<char>>,
+    row: usize,
+    col: usize,
+    top: usize,
+    left: usize,
+    width: usize,
+    height: usize,
+}
+
+impl TextArea {
+    fn new(text: &str, width: usize, height: usize) -> Self {
+        let mut lines = text
+            .split('\n')
+            .map(|line| line.replace('\r', "").chars().collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        if lines.is_empty() {
+            lines.push(vec![]);
+        }
+        Self {
+            lines,
+            row: 0,
+            col: 0,
+            top: 0,
+            left: 0,
+            width,
+            height,
+        }
+    }
+
+    fn text(&self) -> String {
+        self.lines
+            .iter()
+            .map(|line| line.iter().collect::<String>())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Edit the text until it's saved or the edit is cancelled.
+    /// Returns whether the text was saved.
+    fn run(&mut self, title: &str) -> std::io::Result<bool> {
+        let mut out = stdout();
+        let (x, y) = make_room(&mut out, self.height + 2)?;
+
+        loop {
+            self.draw(&mut out, title, x, y)?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                if let Some(saved) = self.handle(key) {
+                    queue!(out, MoveTo(0, y + self.height as u16 + 2))?;
+                    out.flush()?;
+                    return Ok(saved);
+                }
+            }
+        }
+    }
+
+    fn draw(&mut self, out: &mut impl Write, title: &str, x: u16, y: u16) -> std::io::Result<()> {
+        // Scroll so the cursor is always in view.
+        if self.row < self.top {
+            self.top = self.row;
+        } else if self.row >= self.top + self.height {
+            self.top = self.row + 1 - self.height;
+        }
+        if self.col < self.left {
+            self.left = self.col;
+        } else if self.col >= self.left + self.width {
+            self.left = self.col + 1 - self.width;
+        }
+
+        queue!(out, MoveTo(x, y), Print(top_border(title, self.width)))?;
+        for i in 0..self.height {
+            let visible = self
+                .lines
+                .get(self.top + i)
+                .map(|line| {
+                    line.iter()
+                        .skip(self.left)
+                        .take(self.width)
+                        .collect::<String>()
+                })
+                .unwrap_or_default();
+            queue!(
+                out,
+                MoveTo(x, y + 1 + i as u16),
+                Print(format!(" {:width$} ", visible, width = self.width))
+            )?;
+        }
+        queue!(
+            out,
+            MoveTo(x, y + 1 + self.height as u16),
+            Print(format!("└{}┘", "─".repeat(self.width))),
+            MoveTo(
+                x + 1 + (self.col - self.left) as u16,
+                y + 1 + (self.row - self.top) as u16
+            )
+        )?;
+        out.flush()
+    }
+
+    /// Handle a key press. Returns whether the text was saved, if editing is finished.
+    fn handle(&mut self, key: KeyEvent) -> Option<bool> {
+        match (key.code, key.modifiers) {
+            (KeyCode::Char('s'), KeyModifiers::CONTROL) | (KeyCode::Esc, _) => return Some(true),
+            (KeyCode::Char('c'), KeyModifiers::CONTROL) => return Some(false),
+            (KeyCode::Char(c), modifiers) if !modifiers.contains(KeyModifiers::CONTROL) => {
+                self.lines[self.row].insert(self.col, c);
+                self.col += 1;
+            }
+            (KeyCode::Tab, _) => {
+                for _ in 0..4 {
+                    self.lines[self.row].insert(self.col, ' ');
+                    self.col += 1;
+                }
+            }
+            (KeyCode::Enter, _) => {
+                let rest = self.lines[self.row].split_off(self.col);
+                self.lines.insert(self.row + 1, rest);
+                self.row += 1;
+                self.col = 0;
+            }
+            (KeyCode::Backspace, _) => {
+                if self.col > 0 {
+                    self.col -= 1;
+                    self.lines[self.row].remove(self.col);
+                } else if self.row > 0 {
+                    let line = self.lines.remove(self.row);
+                    self.row -= 1;
+                    self.col = self.lines[self.row].len();
+                    self.lines[self.row].extend(line);
+                }
+            }
+            (KeyCode::Delete, _) => {
+                if self.col < self.lines[self.row].len() {
+                    self.lines[self.row].remove(self.col);
+                } else if self.row + 1 < self.lines.len() {
+                    let line = self.lines.remove(self.row + 1);
+                    self.lines[self.row].extend(line);
+                }
+            }
+            (KeyCode::Left, _) => {
+                if self.col > 0 {
+                    self.col -= 1;
+                } else if self.row > 0 {
+                    self.row -= 1;
+                    self.col = self.lines[self.row].len();
+                }
+            }
+            (KeyCode::Right, _) => {
+                if self.col < self.lines[self.row].len() {
+                    self.col += 1;
+                } else if self.row + 1 < self.lines.len() {
+                    self.row += 1;
+                    self.col = 0;
+                }
+            }
+            (KeyCode::Up, _) => self.row = self.row.saturating_sub(1),
+            (KeyCode::Down, _) => self.row = (self.row + 1).min(self.lines.len() - 1),
+            (KeyCode::PageUp, _) => self.row = self.row.saturating_sub(self.height),
+            (KeyCode::PageDown, _) => self.row = (self.row + self.height).min(self.lines.len() - 1),
+            (KeyCode::Home, _) => self.col = 0,
+            (KeyCode::End, _) => self.col = self.lines[self.row].len(),
+            _ => {}
+        }
+        // Moving up or down can leave the cursor past the end of a shorter line.
+        self.col = self.col.min(self.lines[self.row].len());
+        None
+    }
+}
+
+/// The state of the file picker widget: the directory being browsed, what's
+/// been typed to filter it, and the files marked so far.
+struct FilePicker {
+    dir: PathBuf,
+    /// The names of the files in the directory, and whether each is a directory.
+    entries: Vec<(String, bool)>,
+    filter: String,
+    selected: usize,
+    top: usize,
+    marked: BTreeSet<PathBuf>,
+    width: usize,
+    height: usize,
+}
+
+impl FilePicker {
+    fn new(dir: PathBuf, width: usize, height: usize) -> Self {
+        let mut picker = Self {
+            dir,
+            entries: vec![],
+            filter: String::new(),
+            selected: 0,
+            top: 0,
+            marked: BTreeSet::new(),
+            width,
+            height,
+        };
+        picker.open(picker.dir.clone());
+        picker
+    }
+
+    /// Start browsing another directory.
+    fn open(&mut self, dir: PathBuf) {
+        let mut entries = std::fs::read_dir(&dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| {
+                (
+                    entry.file_name().to_string_lossy().to_string(),
+                    entry.path().is_dir(),
+                )
+            })
+            .collect::<Vec<_>>();
+        // Directories first, then files, each in alphabetical order.
+        entries.sort_by(|(a, a_dir), (b, b_dir)| b_dir.cmp(a_dir).then(a.cmp(b)));
+        entries.insert(0, (String::from(".."), true));
+
+        self.dir = dir;
+        self.entries = entries;
+        self.filter.clear();
+        self.selected = 0;
+        self.top = 0;
+    }
+
+    /// The entries whose names contain the filter.
+    fn shown(&self) -> Vec<&(String, bool)> {
+        let filter = self.filter.to_lowercase();
+        self.entries
+            .iter()
+            .filter(|(name, _)| name.to_lowercase().contains(&filter))
+            .collect()
+    }
+
+    /// Browse until a path is picked, or the picker is cancelled.
+    fn run(&mut self) -> std::io::Result<Option<Vec<PathBuf>>> {
+        let mut out = stdout();
+        let (x, y) = make_room(&mut out, self.height + 3)?;
+
+        loop {
+            self.draw(&mut out, x, y)?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                if let Some(picked) = self.handle(key) {
+                    queue!(out, MoveTo(0, y + self.height as u16 + 3))?;
+                    out.flush()?;
+                    return Ok(picked);
+                }
+            }
+        }
+    }
+
+    fn draw(&mut self, out: &mut impl Write, x: u16, y: u16) -> std::io::Result<()> {
+        let shown = self.shown();
+        let selected = self.selected.min(shown.len().saturating_sub(1));
+        let mut top = self.top.min(selected);
+        if selected >= top + self.height {
+            top = selected + 1 - self.height;
+        }
+
+        // Show the end of the path if it's too long to fit.
+        let mut title = self.dir.display().to_string();
+        while title.chars().count() > self.width {
+            title.remove(0);
+        }
+        queue!(out, MoveTo(x, y), Print(top_border(&title, self.width)))?;
+        for i in 0..self.height {
+            let row = match shown.get(top + i) {
+                Some((name, is_dir)) => {
+                    let mark = if self.marked.contains(&self.dir.join(name)) {
+                        '*'
+                    } else {
+                        ' '
+                    };
+                    let name = if *is_dir {
+                        format!("{}/", name)
+                    } else {
+                        name.clone()
+                    };
+                    format!("{}{}", mark, name)
+                }
+                None => String::new(),
+            };
+            let row = row.chars().take(self.width).collect::<String>();
+            queue!(out, MoveTo(x, y + 1 + i as u16))?;
+            if top + i == selected {
+                queue!(
+                    out,
+                    Print(format!(
+                        " \x1b[7m{:width$}\x1b[0m ",
+                        row,
+                        width = self.width
+                    ))
+                )?;
+            } else {
+                queue!(out, Print(format!(" {:width$} ", row, width = self.width)))?;
+            }
+        }
+        let filter = format!("/{}", self.filter)
+            .chars()
+            .take(self.width)
+            .collect::<String>();
+        queue!(
+            out,
+            MoveTo(x, y + 1 + self.height as u16),
+            Print(format!(" {:width$} ", filter, width = self.width)),
+            MoveTo(x, y + 2 + self.height as u16),
+            Print(format!("└{}┘", "─".repeat(self.width))),
+            MoveTo(
+                x + 2 + self.filter.chars().count() as u16,
+                y + 1 + self.height as u16
+            )
+        )?;
+
+        self.selected = selected;
+        self.top = top;
+        out.flush()
+    }
+
+    /// Handle a key press. Returns the picked paths when browsing is finished,
+    /// or `None` inside if the picker was cancelled.
+    fn handle(&mut self, key: KeyEvent) -> Option<Option<Vec<PathBuf>>> {
+        let shown = self.shown();
+        let current = shown
+            .get(self.selected)
+            .map(|(name, is_dir)| (self.dir.join(name), *is_dir));
+
+        match (key.code, key.modifiers) {
+            (KeyCode::Esc, _) | (KeyCode::Char('c'), KeyModifiers::CONTROL) => return Some(None),
+            (KeyCode::Enter, _) => {
+                if !self.marked.is_empty() {
+                    return Some(Some(self.marked.iter().cloned().collect()));
+                }
+                match current {
+                    Some((path, true)) => self.open(dunce::canonicalize(&path).unwrap_or(path)),
+                    Some((path, false)) => return Some(Some(vec![path])),
+                    None => {}
+                }
+            }
+            (KeyCode::Right, _) => {
+                if let Some((path, true)) = current {
+                    self.open(dunce::canonicalize(&path).unwrap_or(path))
+                }
+            }
+            (KeyCode::Left, _) => {
+                if let Some(parent) = self.dir.parent() {
+                    self.open(parent.to_path_buf())
+                }
+            }
+            (KeyCode::Backspace, _) if self.filter.is_empty() => {
+                if let Some(parent) = self.dir.parent() {
+                    self.open(parent.to_path_buf())
+                }
+            }
+            (KeyCode::Backspace, _) => {
+                self.filter.pop();
+                self.selected = 0;
+            }
+            (KeyCode::Char(' '), _) => {
+                if let Some((path, _)) = current {
+                    if !self.marked.remove(&path) {
+                        self.marked.insert(path);
+                    }
+                }
+            }
+            (KeyCode::Char(c), modifiers) if !modifiers.contains(KeyModifiers::CONTROL) => {
+                self.filter.push(c);
+                self.selected = 0;
+            }
+            (KeyCode::Up, _) => self.selected = self.selected.saturating_sub(1),
+            (KeyCode::Down, _) => self.selected += 1,
+            (KeyCode::PageUp, _) => self.selected = self.selected.saturating_sub(self.height),
+            (KeyCode::PageDown, _) => self.selected += self.height,
+            _ => {}
+        }
+        None
+    }
+}
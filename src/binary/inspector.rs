@@ -0,0 +1,459 @@
+use super::init::collapsed;
+use crossterm::{
+    cursor::{Hide, MoveTo},
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    queue,
+    style::Print,
+    terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use dune::Expression;
+use std::{
+    collections::BTreeSet,
+    io::{stdout, Write},
+};
+
+/// A node of the tree, by the position of each branch taken from the root to
+/// get to it. Ordering these paths puts the nodes in the order they're drawn.
+type Path = Vec<usize>;
+
+/// Browse a value as a tree, expanding and collapsing its maps and lists.
+pub struct Inspector<'a> {
+    root: &'a Expression,
+    expanded: BTreeSet<Path>,
+    /// The nodes that are shown, in order.
+    rows: Vec<Path>,
+    selected: usize,
+    top: usize,
+    /// The search being typed after `/`.
+    typing: Option<String>,
+    /// The last search, which `n` and `N` go through the matches of.
+    search: String,
+    /// Shown at the bottom until the next key is pressed.
+    message: Option<String>,
+}
+
+impl<'a> Inspector<'a> {
+    pub fn new(root: &'a Expression) -> Self {
+        let mut inspector = Self {
+            root,
+            expanded: BTreeSet::from([vec![]]),
+            rows: vec![],
+            selected: 0,
+            top: 0,
+            typing: None,
+            search: String::new(),
+            message: None,
+        };
+        inspector.refresh();
+        inspector
+    }
+
+    /// Let the user browse the value, returning the part of it they picked,
+    /// or `None` if they quit without picking anything.
+    pub fn run(&mut self) -> std::io::Result<Option<Expression>> {
+        let was_raw = terminal::is_raw_mode_enabled().unwrap_or(false);
+        terminal::enable_raw_mode()?;
+        let mut out = stdout();
+        queue!(out, EnterAlternateScreen, Hide)?;
+        let result = self.browse(&mut out);
+        queue!(out, crossterm::cursor::Show, LeaveAlternateScreen)?;
+        out.flush()?;
+        if !was_raw {
+            terminal::disable_raw_mode()?;
+        }
+        result
+    }
+
+    fn browse(&mut self, out: &mut impl Write) -> std::io::Result<Option<Expression>> {
+        loop {
+            self.draw(out)?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                self.message = None;
+                if let Some(picked) = self.handle(key, out) {
+                    return Ok(picked);
+                }
+            }
+        }
+    }
+
+    fn node(&self, path: &[usize]) -> Option<&'a Expression> {
+        let mut node = self.root;
+        for &i in path {
+            node = member(node, i)?.1;
+        }
+        Some(node)
+    }
+
+    /// The key or index a node is under in its parent.
+    fn label(&self, path: &[usize]) -> String {
+        match path.split_last() {
+            Some((&i, parent)) => self
+                .node(parent)
+                .and_then(|parent| member(parent, i))
+                .map(|(label, _)| label)
+                .unwrap_or_default(),
+            None => String::new(),
+        }
+    }
+
+    /// Whether a node is a map key, rather than a list item or the root.
+    fn is_key(&self, path: &[usize]) -> bool {
+        match path.split_last() {
+            Some((_, parent)) => matches!(self.node(parent), Some(Expression::Map(_))),
+            None => false,
+        }
+    }
+
+    /// The indices that get a node from the value, like `@users@0@name`.
+    fn path_text(&self, path: &[usize]) -> String {
+        let mut text = String::new();
+        for end in 1..=path.len() {
+            let label = self.label(&path[..end]);
+            let plain = label.starts_with(|c: char| c.is_alphabetic() || c == '_')
+                && label
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || c == '_' || c == '-');
+            if !self.is_key(&path[..end]) || plain {
+                text += &format!("@{}", label);
+            } else {
+                text += &format!("@{:?}", label);
+            }
+        }
+        text
+    }
+
+    /// Find the nodes that are shown, keeping the same one selected.
+    fn refresh(&mut self) {
+        let selected = self.rows.get(self.selected).cloned();
+        self.rows.clear();
+        let mut stack = vec![vec![]];
+        while let Some(path) = stack.pop() {
+            if self.expanded.contains(&path) {
+                let count = self.node(&path).map_or(0, count);
+                for i in (0..count).rev() {
+                    let mut child = path.clone();
+                    child.push(i);
+                    stack.push(child);
+                }
+            }
+            self.rows.push(path);
+        }
+        if let Some(selected) = selected {
+            self.select(&selected);
+        }
+    }
+
+    fn select(&mut self, path: &[usize]) {
+        if let Some(row) = self.rows.iter().position(|row| row == path) {
+            self.selected = row;
+        }
+    }
+
+    /// Whether a node is a map or list with something in it.
+    fn is_branch(&self, path: &[usize]) -> bool {
+        self.node(path).is_some_and(|node| count(node) > 0)
+    }
+
+    /// Expand a node and everything in it.
+    fn expand_all(&mut self, path: &[usize]) {
+        let mut stack = vec![path.to_vec()];
+        while let Some(path) = stack.pop() {
+            let count = self.node(&path).map_or(0, count);
+            if count > 0 {
+                for i in 0..count {
+                    let mut child = path.clone();
+                    child.push(i);
+                    stack.push(child);
+                }
+                self.expanded.insert(path);
+            }
+        }
+        self.refresh();
+    }
+
+    /// Whether a key matches the search, ignoring case unless the search has
+    /// uppercase letters, like the fuzzy finder.
+    fn matches(search: &str, key: &str) -> bool {
+        if search.chars().any(char::is_uppercase) {
+            key.contains(search)
+        } else {
+            key.to_lowercase().contains(&search.to_lowercase())
+        }
+    }
+
+    /// Go to the next key matching the search, or the previous one, expanding
+    /// the maps and lists it's in. Keys in collapsed nodes are found too.
+    fn find(&mut self, forward: bool, including_selected: bool) {
+        if self.search.is_empty() {
+            return;
+        }
+        let mut found = vec![];
+        let mut stack = vec![vec![]];
+        while let Some(path) = stack.pop() {
+            if self.is_key(&path) && Self::matches(&self.search, &self.label(&path)) {
+                found.push(path.clone());
+            }
+            let count = self.node(&path).map_or(0, count);
+            for i in (0..count).rev() {
+                let mut child = path.clone();
+                child.push(i);
+                stack.push(child);
+            }
+        }
+
+        let current = self.rows.get(self.selected).cloned().unwrap_or_default();
+        let next = if forward {
+            found
+                .iter()
+                .find(|path| **path > current || (including_selected && **path == current))
+                .or(found.first())
+        } else {
+            found
+                .iter()
+                .rev()
+                .find(|path| **path < current)
+                .or(found.last())
+        };
+        match next.cloned() {
+            Some(path) => {
+                for end in 0..path.len() {
+                    self.expanded.insert(path[..end].to_vec());
+                }
+                self.refresh();
+                self.select(&path);
+                self.message = Some(format!("{} keys match `{}`", found.len(), self.search));
+            }
+            None => self.message = Some(format!("no keys match `{}`", self.search)),
+        }
+    }
+
+    /// Copy the path to the selected node, with OSC 52 so it works over SSH too.
+    fn copy(&mut self, out: &mut impl Write) -> std::io::Result<()> {
+        let path = self.path_text(&self.rows[self.selected]);
+        queue!(
+            out,
+            Print(format!("\x1b]52;c;{}\x07", base64(path.as_bytes())))
+        )?;
+        self.message = Some(if path.is_empty() {
+            String::from("this is the value itself, there's no path to copy")
+        } else {
+            format!("copied {}", path)
+        });
+        Ok(())
+    }
+
+    fn draw(&mut self, out: &mut impl Write) -> std::io::Result<()> {
+        let (width, height) = terminal::size()?;
+        let (width, height) = (width as usize, height as usize);
+        // Leave room for the status line.
+        let page = height.saturating_sub(1).max(1);
+
+        self.selected = self.selected.min(self.rows.len().saturating_sub(1));
+        if self.selected < self.top {
+            self.top = self.selected;
+        } else if self.selected >= self.top + page {
+            self.top = self.selected + 1 - page;
+        }
+
+        let matched = dune::theme().matched;
+        queue!(out, MoveTo(0, 0), Clear(ClearType::All))?;
+        for (row, path) in self.rows.iter().enumerate().skip(self.top).take(page) {
+            let node = self.node(path).unwrap_or(&Expression::None);
+            let marker = match self.is_branch(path) {
+                true if self.expanded.contains(path) => "▾ ",
+                true => "▸ ",
+                false => "  ",
+            };
+            let indent = format!("{}{}", "  ".repeat(path.len()), marker);
+            let label = self.label(path);
+            let mut rest = if self.expanded.contains(path) && self.is_branch(path) {
+                let count = count(node);
+                let (one, many) = match node {
+                    Expression::Map(_) => ("key", "keys"),
+                    _ => ("item", "items"),
+                };
+                let count = format!("{} {}", count, if count == 1 { one } else { many });
+                if path.is_empty() {
+                    count
+                } else {
+                    format!("  \x1b[2m{}\x1b[22m", count)
+                }
+            } else {
+                let value = collapsed(node).replace(['\n', '\r', '\t'], " ");
+                if path.is_empty() {
+                    value
+                } else {
+                    format!(": {}", value)
+                }
+            };
+
+            // Only the label and the value are cut off to fit, never the styles.
+            let room = width.saturating_sub(indent.chars().count());
+            let label = label.chars().take(room).collect::<String>();
+            let room = room.saturating_sub(label.chars().count());
+            if crate::strip_ansi_escapes(&rest).chars().count() > room {
+                rest = crate::strip_ansi_escapes(&rest)
+                    .chars()
+                    .take(room)
+                    .collect();
+            }
+            let label = if !self.search.is_empty()
+                && self.is_key(path)
+                && Self::matches(&self.search, &label)
+            {
+                // Only end the style, so the reversed colors of the selection stay.
+                format!("{}{}\x1b[22;23;24;39;49m", matched.start(), label)
+            } else {
+                label
+            };
+
+            let line = format!("{}{}{}", indent, label, rest);
+            queue!(out, MoveTo(0, (row - self.top) as u16))?;
+            if row == self.selected {
+                queue!(out, Print(format!("\x1b[7m{}\x1b[0m", line)))?;
+            } else {
+                queue!(out, Print(line))?;
+            }
+        }
+
+        let status = match (&self.typing, &self.message) {
+            (Some(search), _) => format!("/{}", search),
+            (None, Some(message)) => message.clone(),
+            (None, None) => {
+                let path = self.path_text(&self.rows[self.selected]);
+                format!(
+                    "{}  \x1b[2m←→ fold  * expand all  / search  y copy path  enter pick  q quit\x1b[0m",
+                    if path.is_empty() { "@" } else { &path }
+                )
+            }
+        };
+        let status = if crate::strip_ansi_escapes(&status).chars().count() > width {
+            crate::strip_ansi_escapes(&status)
+                .chars()
+                .take(width)
+                .collect()
+        } else {
+            status
+        };
+        queue!(out, MoveTo(0, page as u16), Print(status))?;
+        out.flush()
+    }
+
+    /// Handle a key press. Returns the picked part of the value once the
+    /// inspector is closed, or `None` inside if nothing was picked.
+    fn handle(&mut self, key: KeyEvent, out: &mut impl Write) -> Option<Option<Expression>> {
+        let page = terminal::size()
+            .map(|(_, height)| height.saturating_sub(1).max(1) as usize)
+            .unwrap_or(10);
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        if ctrl && matches!(key.code, KeyCode::Char('c' | 'g')) {
+            return Some(None);
+        }
+
+        if let Some(search) = self.typing.as_mut() {
+            match key.code {
+                KeyCode::Esc => self.typing = None,
+                KeyCode::Enter => {
+                    self.typing = None;
+                    self.find(true, true);
+                }
+                KeyCode::Backspace => {
+                    search.pop();
+                }
+                KeyCode::Char(c) => search.push(c),
+                _ => {}
+            }
+            // Jump to the first match while the search is typed.
+            if let Some(search) = self.typing.clone() {
+                self.search = search;
+                self.find(true, true);
+                self.message = None;
+            }
+            return None;
+        }
+
+        let path = self.rows[self.selected].clone();
+        match key.code {
+            KeyCode::Esc | KeyCode::Char('q') => return Some(None),
+            KeyCode::Enter => return Some(self.node(&path).cloned()),
+            KeyCode::Up | KeyCode::Char('k') => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => self.selected += 1,
+            KeyCode::PageUp => self.selected = self.selected.saturating_sub(page),
+            KeyCode::PageDown => self.selected += page,
+            KeyCode::Home | KeyCode::Char('g') => self.selected = 0,
+            KeyCode::End | KeyCode::Char('G') => self.selected = self.rows.len() - 1,
+            KeyCode::Right | KeyCode::Char('l') if self.is_branch(&path) => {
+                if self.expanded.insert(path) {
+                    self.refresh();
+                } else {
+                    self.selected += 1;
+                }
+            }
+            KeyCode::Left | KeyCode::Char('h') => {
+                if self.expanded.remove(&path) && self.is_branch(&path) {
+                    self.refresh();
+                } else if let Some((_, parent)) = path.split_last() {
+                    self.select(parent);
+                }
+            }
+            KeyCode::Char(' ') | KeyCode::Tab if self.is_branch(&path) => {
+                if !self.expanded.remove(&path) {
+                    self.expanded.insert(path);
+                }
+                self.refresh();
+            }
+            KeyCode::Char('*') => self.expand_all(&path),
+            KeyCode::Char('/') => self.typing = Some(String::new()),
+            KeyCode::Char('n') => self.find(true, false),
+            KeyCode::Char('N') => self.find(false, false),
+            KeyCode::Char('y' | 'c') => {
+                if let Err(e) = self.copy(out) {
+                    self.message = Some(format!("could not copy the path: {}", e));
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+/// The key or index of a member of a map or list, and the member.
+fn member(value: &Expression, i: usize) -> Option<(String, &Expression)> {
+    match value {
+        Expression::Map(map) => map.get_index(i).map(|(k, v)| (k.clone(), v)),
+        Expression::List(list) => list.get(i).map(|v| (i.to_string(), v)),
+        _ => None,
+    }
+}
+
+/// How many members a map or list has, and zero for anything else.
+fn count(value: &Expression) -> usize {
+    match value {
+        Expression::Map(map) => map.len(),
+        Expression::List(list) => list.len(),
+        _ => 0,
+    }
+}
+
+/// Encode bytes as base64, which is how OSC 52 sends text to the clipboard.
+fn base64(bytes: &[u8]) -> String {
+    const DIGITS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(DIGITS[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
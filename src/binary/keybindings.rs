@@ -0,0 +1,244 @@
+use super::edit::PendingEdit;
+use dune::{Environment, Expression};
+use rustyline::{
+    Anchor, At, CharSearch, Cmd, ConditionalEventHandler, Event, EventContext, KeyCode, KeyEvent,
+    Modifiers, Movement, RepeatCount, Word,
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+/// The variable the prelude binds keys to editing commands in, like
+/// `let KEYBINDINGS = {ctrl-w="unix-word-rubout", alt-left="shell-backward-word"}`.
+pub const KEYBINDINGS: &str = "KEYBINDINGS";
+
+/// An editing command a key can be bound to, named like the readline
+/// command it works like.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    BackwardWord,
+    ForwardWord,
+    ShellBackwardWord,
+    ShellForwardWord,
+    BackwardKillWord,
+    KillWord,
+    UnixWordRubout,
+    UnixFilenameRubout,
+    KillLine,
+    UnixLineDiscard,
+    Yank,
+    YankPop,
+    BeginningOfLine,
+    EndOfLine,
+}
+
+const ACTIONS: &[(&str, Action)] = &[
+    ("backward-word", Action::BackwardWord),
+    ("forward-word", Action::ForwardWord),
+    ("shell-backward-word", Action::ShellBackwardWord),
+    ("shell-forward-word", Action::ShellForwardWord),
+    ("backward-kill-word", Action::BackwardKillWord),
+    ("kill-word", Action::KillWord),
+    ("unix-word-rubout", Action::UnixWordRubout),
+    ("unix-filename-rubout", Action::UnixFilenameRubout),
+    ("kill-line", Action::KillLine),
+    ("unix-line-discard", Action::UnixLineDiscard),
+    ("yank", Action::Yank),
+    ("yank-pop", Action::YankPop),
+    ("beginning-of-line", Action::BeginningOfLine),
+    ("end-of-line", Action::EndOfLine),
+];
+
+/// The keys bound without a `KEYBINDINGS` in the prelude. Everything else
+/// works like in readline already, except that Ctrl-W stops at the slashes
+/// in a path, instead of deleting the whole path.
+const DEFAULTS: &[(&str, Action)] = &[("ctrl-w", Action::UnixFilenameRubout)];
+
+impl Action {
+    pub fn named(name: &str) -> Option<Self> {
+        ACTIONS
+            .iter()
+            .find(|(action, _)| *action == name)
+            .map(|(_, action)| *action)
+    }
+
+    fn command(self, n: RepeatCount, ctx: &EventContext, edit: &PendingEdit) -> Cmd {
+        match self {
+            Self::BackwardWord => Cmd::Move(Movement::BackwardWord(n, Word::Emacs)),
+            Self::ForwardWord => Cmd::Move(Movement::ForwardWord(n, At::AfterEnd, Word::Emacs)),
+            Self::ShellBackwardWord => Cmd::Move(Movement::BackwardWord(n, Word::Big)),
+            Self::ShellForwardWord => Cmd::Move(Movement::ForwardWord(n, At::AfterEnd, Word::Big)),
+            Self::BackwardKillWord => Cmd::Kill(Movement::BackwardWord(n, Word::Emacs)),
+            Self::KillWord => Cmd::Kill(Movement::ForwardWord(n, At::AfterEnd, Word::Emacs)),
+            Self::UnixWordRubout => Cmd::Kill(Movement::BackwardWord(n, Word::Big)),
+            Self::UnixFilenameRubout => filename_rubout(ctx.line(), ctx.pos(), n, edit),
+            Self::KillLine => Cmd::Kill(Movement::EndOfLine),
+            Self::UnixLineDiscard => Cmd::Kill(Movement::BeginningOfLine),
+            Self::Yank => Cmd::Yank(n, Anchor::Before),
+            Self::YankPop => Cmd::YankPop,
+            Self::BeginningOfLine => Cmd::Move(Movement::BeginningOfLine),
+            Self::EndOfLine => Cmd::Move(Movement::EndOfLine),
+        }
+    }
+}
+
+/// Read a key like `ctrl-w`, `alt-b`, or `alt-backspace`.
+pub fn parse_key(name: &str) -> Option<KeyEvent> {
+    let mut modifiers = Modifiers::NONE;
+    let mut key = name;
+    loop {
+        key = if let Some(rest) = key.strip_prefix("ctrl-") {
+            modifiers |= Modifiers::CTRL;
+            rest
+        } else if let Some(rest) = key.strip_prefix("alt-") {
+            modifiers |= Modifiers::ALT;
+            rest
+        } else if let Some(rest) = key.strip_prefix("shift-") {
+            modifiers |= Modifiers::SHIFT;
+            rest
+        } else {
+            break;
+        };
+    }
+    let code = match key {
+        "backspace" => KeyCode::Backspace,
+        "delete" => KeyCode::Delete,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        _ => {
+            let mut chars = key.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => KeyCode::Char(c),
+                _ => return None,
+            }
+        }
+    };
+    Some(KeyEvent::normalize(KeyEvent(code, modifiers)))
+}
+
+/// Get the keybindings of an environment, on top of the defaults. Keys or
+/// commands that aren't known are left out, and `problems` reports them.
+pub fn get(env: &Environment) -> HashMap<KeyEvent, Action> {
+    let mut bindings = DEFAULTS
+        .iter()
+        .filter_map(|(key, action)| Some((parse_key(key)?, *action)))
+        .collect::<HashMap<_, _>>();
+    for (key, action) in configured(env) {
+        if let (Some(key), Some(action)) = (parse_key(&key), Action::named(&action)) {
+            bindings.insert(key, action);
+        }
+    }
+    bindings
+}
+
+/// The keys bound in the `KEYBINDINGS` of an environment, and the names of
+/// the commands they're bound to.
+pub fn configured(env: &Environment) -> Vec<(String, String)> {
+    match env.get(KEYBINDINGS) {
+        Some(Expression::Map(bindings)) => bindings
+            .into_iter()
+            .map(|(key, action)| (key, action.to_string()))
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// Describe the keys and commands in `KEYBINDINGS` that aren't known.
+pub fn problems(env: &Environment) -> Vec<String> {
+    let mut problems = vec![];
+    for (key, action) in configured(env) {
+        if parse_key(&key).is_none() {
+            problems.push(format!(
+                "unknown key `{}` in {}, expected one like `ctrl-w`, `alt-b`, or `alt-backspace`",
+                key, KEYBINDINGS
+            ));
+        }
+        if Action::named(&action).is_none() {
+            problems.push(format!(
+                "unknown editing command `{}` for `{}` in {}, expected one of {}",
+                action,
+                key,
+                KEYBINDINGS,
+                ACTIONS
+                    .iter()
+                    .map(|(name, _)| *name)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+    }
+    problems
+}
+
+/// Where `unix-filename-rubout` kills back to: past any slashes and spaces
+/// before the cursor, then to the slash or space before that.
+fn filename_rubout_start(line: &str, pos: usize) -> usize {
+    let is_boundary = |c: char| c == '/' || c.is_whitespace();
+    let word = line[..pos].trim_end_matches(is_boundary);
+    word.len()
+        - word
+            .chars()
+            .rev()
+            .take_while(|c| !is_boundary(*c))
+            .map(char::len_utf8)
+            .sum::<usize>()
+}
+
+/// Kill back to the start of the last part of a path, like `unix-filename-rubout`
+/// in readline, so the killed text can be yanked again.
+fn filename_rubout(line: &str, pos: usize, n: RepeatCount, edit: &PendingEdit) -> Cmd {
+    let mut start = pos;
+    for _ in 0..n.max(1) {
+        start = filename_rubout_start(line, start);
+    }
+    if start == pos {
+        return Cmd::Noop;
+    }
+    // Killing by a character search puts the text in the kill ring, where
+    // deleting a number of characters doesn't.
+    edit.kill(match line[..start].chars().next_back() {
+        Some(boundary) => {
+            let count = line[start - boundary.len_utf8()..pos]
+                .chars()
+                .filter(|c| *c == boundary)
+                .count();
+            Movement::ViCharSearch(count, CharSearch::BackwardAfter(boundary))
+        }
+        None => Movement::BeginningOfLine,
+    })
+}
+
+/// Runs the editing command bound to a key in `KEYBINDINGS`, which can be
+/// changed while the REPL runs.
+pub struct BoundKey {
+    key: KeyEvent,
+    bindings: Arc<Mutex<HashMap<KeyEvent, Action>>>,
+    edit: Arc<PendingEdit>,
+}
+
+impl BoundKey {
+    pub fn new(
+        key: KeyEvent,
+        bindings: Arc<Mutex<HashMap<KeyEvent, Action>>>,
+        edit: Arc<PendingEdit>,
+    ) -> Self {
+        Self {
+            key,
+            bindings,
+            edit,
+        }
+    }
+}
+
+impl ConditionalEventHandler for BoundKey {
+    fn handle(&self, _: &Event, n: RepeatCount, positive: bool, ctx: &EventContext) -> Option<Cmd> {
+        if !positive {
+            return None;
+        }
+        let action = *self.bindings.lock().unwrap().get(&self.key)?;
+        Some(action.command(n, ctx, &self.edit))
+    }
+}
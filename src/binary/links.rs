@@ -0,0 +1,97 @@
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use std::path::{Path, PathBuf};
+
+lazy_static! {
+    /// Escape codes for colors and the like, which links can't go inside.
+    static ref ESCAPE: Regex = Regex::new(r"\x1b(\[[0-9;?]*[A-Za-z]|\][^\x07\x1b]*(\x07|\x1b\\))").unwrap();
+    /// Things that might be links: URLs, or words that might be paths.
+    static ref LINKABLE: Regex = Regex::new(r#"https?://[^\s"'<>()\[\]{}│┃]+|[\w~./\\:@%+-]+"#).unwrap();
+}
+
+/// Turn the URLs and the paths of existing files in some text into OSC 8
+/// hyperlinks, which modern consoles let you click to open.
+pub fn link(text: &str, cwd: &str) -> String {
+    let cwd = Path::new(cwd);
+    let mut result = String::new();
+    let mut last = 0;
+    for escape in ESCAPE.find_iter(text) {
+        result += &link_words(&text[last..escape.start()], cwd);
+        result += escape.as_str();
+        last = escape.end();
+    }
+    result + &link_words(&text[last..], cwd)
+}
+
+fn link_words(text: &str, cwd: &Path) -> String {
+    LINKABLE
+        .replace_all(text, |captures: &Captures| {
+            let word = &captures[0];
+            if word.starts_with("http://") || word.starts_with("https://") {
+                // Punctuation after a URL is usually part of the sentence around it.
+                let url = word.trim_end_matches(['.', ',', ';', ':', '!', '?']);
+                return hyperlink(url, url) + &word[url.len()..];
+            }
+            match path(word, cwd) {
+                Some(path) => hyperlink(&file_url(&path.to_string_lossy()), word),
+                None => word.to_string(),
+            }
+        })
+        .into_owned()
+}
+
+/// Get the path of the file a word names, if it looks like a path and the file exists.
+fn path(word: &str, cwd: &Path) -> Option<PathBuf> {
+    let looks_like_path = (word.contains('/') || word.contains('\\') || word.contains('.'))
+        && word.chars().any(char::is_alphabetic);
+    if !looks_like_path {
+        return None;
+    }
+    let path = match word.strip_prefix('~') {
+        Some(rest) => dirs::home_dir()?.join(rest.trim_start_matches(['/', '\\'])),
+        None => cwd.join(word),
+    };
+    path.exists()
+        .then(|| dunce::canonicalize(&path).unwrap_or(path))
+}
+
+fn hyperlink(url: &str, text: &str) -> String {
+    format!("\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\", url, text)
+}
+
+/// Get the `file://` URL of an absolute path.
+pub fn file_url(path: &str) -> String {
+    let mut path = path.replace('\\', "/");
+    if !path.starts_with('/') {
+        // Windows paths like `C:/Users` are written `/C:/Users` in URLs.
+        path.insert(0, '/');
+    }
+    let path = path
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'.' | b'_' | b'~' | b':' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect::<String>();
+    format!("file://{}{}", hostname(), path)
+}
+
+/// The name of this computer, which `file://` URLs need so consoles can tell
+/// local files from ones on other machines, like over SSH.
+fn hostname() -> String {
+    #[cfg(unix)]
+    {
+        let mut name = [0u8; 256];
+        if unsafe { libc::gethostname(name.as_mut_ptr() as *mut libc::c_char, name.len()) } == 0 {
+            let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+            return String::from_utf8_lossy(&name[..len]).into_owned();
+        }
+        String::new()
+    }
+    #[cfg(not(unix))]
+    {
+        std::env::var("COMPUTERNAME").unwrap_or_default()
+    }
+}
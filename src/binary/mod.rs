@@ -1,3 +1,19 @@
+pub mod abbreviations;
+pub mod completion;
+pub mod daemon;
+pub mod doctor;
+pub mod edit;
+pub mod fuzzy;
+pub mod history;
 mod init;
+pub mod inspector;
+pub mod keybindings;
+pub mod links;
+pub mod notify;
+pub mod packages;
+pub mod pager;
+pub mod scripts;
+pub mod timezone;
+pub mod update;
 
-pub use init::init;
+pub use init::{annotation, init, report_cwd, set_title};
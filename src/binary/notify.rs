@@ -0,0 +1,129 @@
+use super::init::format_duration;
+use dune::Run;
+use std::{
+    io::{stdout, IsTerminal, Write},
+    process::{Command, Stdio},
+    sync::RwLock,
+};
+
+/// When and how to tell the user that a slow command finished.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    /// The seconds a command has to run for, or `None` to never notify.
+    pub after: Option<f64>,
+    /// Ring the console's bell.
+    pub bell: bool,
+    /// Show a desktop notification.
+    pub desktop: bool,
+}
+
+static SETTINGS: RwLock<Settings> = RwLock::new(Settings {
+    after: None,
+    bell: true,
+    desktop: true,
+});
+
+pub fn settings() -> Settings {
+    SETTINGS.read().unwrap().clone()
+}
+
+pub fn set_settings(settings: Settings) {
+    *SETTINGS.write().unwrap() = settings;
+}
+
+/// Tell the user a command finished, if it ran for long enough and they
+/// aren't looking at the console.
+pub fn finished(run: &Run) {
+    let settings = settings();
+    let seconds = run.elapsed.as_secs_f64();
+    if !settings.after.is_some_and(|after| seconds >= after) || is_focused() == Some(true) {
+        return;
+    }
+
+    let outcome = match run.code {
+        Some(0) => String::from("finished"),
+        Some(code) => format!("failed with exit code {}", code),
+        None => String::from("was stopped"),
+    };
+    let message = format!(
+        "{} {} after {}",
+        run.command,
+        outcome,
+        format_duration(seconds)
+    );
+    if settings.bell && stdout().is_terminal() {
+        print!("\x07");
+        let _ = stdout().flush();
+    }
+    if settings.desktop {
+        desktop_notification("dune", &message);
+    }
+}
+
+/// Show a notification with the desktop's notifier, or ask the terminal to
+/// show one, which many terminals do for OSC 9 and OSC 777.
+fn desktop_notification(title: &str, message: &str) {
+    let shown = if cfg!(target_os = "macos") {
+        let quote = |text: &str| format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""));
+        let script = format!(
+            "display notification {} with title {}",
+            quote(message),
+            quote(title)
+        );
+        run_quietly(Command::new("osascript").args(["-e", &script]))
+    } else if cfg!(unix) && std::env::var_os("SSH_CONNECTION").is_none() {
+        run_quietly(Command::new("notify-send").args([title, message]))
+    } else {
+        false
+    };
+    if !shown && stdout().is_terminal() {
+        let message = message.replace(['\x07', '\x1b', ';'], " ");
+        if std::env::var("TERM").is_ok_and(|term| term.contains("rxvt") || term == "foot") {
+            print!("\x1b]777;notify;{};{}\x07", title, message);
+        } else {
+            print!("\x1b]9;{}\x07", message);
+        }
+        let _ = stdout().flush();
+    }
+}
+
+fn run_quietly(command: &mut Command) -> bool {
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// Whether the console's window has the focus, which can only be told on
+/// macOS and X11. Elsewhere, it's `None`, and the user is notified anyway.
+fn is_focused() -> Option<bool> {
+    let output = |command: &mut Command| {
+        let output = command.stderr(Stdio::null()).output().ok()?;
+        Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+            .filter(|_| output.status.success())
+    };
+
+    if cfg!(target_os = "macos") {
+        // Terminals name themselves in TERM_PROGRAM, like `Apple_Terminal`
+        // for Terminal and `iTerm.app` for iTerm2.
+        let terminal = std::env::var("TERM_PROGRAM").ok()?;
+        let terminal = terminal
+            .trim_start_matches("Apple_")
+            .trim_end_matches(".app")
+            .to_lowercase();
+        let front = output(Command::new("osascript").args([
+            "-e",
+            "tell application \"System Events\" to get name of first application process whose frontmost is true",
+        ]))?;
+        return Some(front.to_lowercase().starts_with(&terminal));
+    }
+
+    // X11 terminals set WINDOWID to their window.
+    std::env::var_os("DISPLAY")?;
+    let window = std::env::var("WINDOWID").ok()?.parse::<u64>().ok()?;
+    let active = output(Command::new("xprop").args(["-root", "_NET_ACTIVE_WINDOW"]))?;
+    let active = active.rsplit(' ').next()?.trim_start_matches("0x");
+    Some(u64::from_str_radix(active, 16).ok()? == window)
+}
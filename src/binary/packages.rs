@@ -0,0 +1,304 @@
+use dune::Error;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// The script a package runs when it's used, which gets the package's module.
+const ENTRY: &str = "main.dn";
+
+/// A package as it's recorded in the lockfile: where it came from, and the
+/// commit that was installed, so installing from the lockfile again gets the
+/// same code.
+#[derive(Debug, Clone, PartialEq)]
+struct Locked {
+    source: String,
+    version: String,
+}
+
+/// The directory packages are installed in, `~/.dune/packages`.
+pub fn dir() -> Option<PathBuf> {
+    Some(dirs::home_dir()?.join(".dune").join("packages"))
+}
+
+/// The lockfile recording the installed packages, `~/.dune/packages.lock`.
+fn lock_path() -> Option<PathBuf> {
+    Some(dirs::home_dir()?.join(".dune").join("packages.lock"))
+}
+
+fn home_error() -> Error {
+    Error::CustomError(String::from(
+        "could not find your home directory to install packages in",
+    ))
+}
+
+/// The script to import to use an installed package, if there is one by that name.
+pub fn entry(name: &str) -> Option<PathBuf> {
+    if !is_name(name) {
+        return None;
+    }
+    Some(dir()?.join(name).join(ENTRY)).filter(|path| path.is_file())
+}
+
+/// Whether a package name can be used as a directory name and with `use`.
+fn is_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(['.', '-'])
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Turn what was given to `pkg install` into something `git clone` understands.
+/// GitHub repositories can be given as `owner/name`.
+fn source_url(source: &str) -> Result<String, Error> {
+    if source.contains("://") || source.starts_with("git@") {
+        return Ok(source.to_string());
+    }
+    if Path::new(source).exists() {
+        // The lockfile has to work from any directory.
+        return dunce::canonicalize(source)
+            .map(|path| path.display().to_string())
+            .map_err(|e| Error::CustomError(format!("could not find {}: {}", source, e)));
+    }
+    match source.split_once('/') {
+        Some((owner, name)) if !owner.is_empty() && is_name(name.trim_end_matches(".git")) => Ok(
+            format!("https://github.com/{}/{}.git", owner, name.trim_end_matches(".git")),
+        ),
+        _ => Err(Error::CustomError(format!(
+            "expected a git URL, a path to a git repository, or a GitHub repository like `owner/name`, but got `{}`",
+            source
+        ))),
+    }
+}
+
+/// The name of the package in a repository, which is the repository's name.
+fn package_name(url: &str) -> Result<String, Error> {
+    let name = url
+        .trim_end_matches('/')
+        .rsplit(['/', ':', '\\'])
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches(".git");
+    if is_name(name) {
+        Ok(name.to_string())
+    } else {
+        Err(Error::CustomError(format!(
+            "could not name the package in {}, because `{}` isn't a valid name",
+            url, name
+        )))
+    }
+}
+
+/// Run git, and get what it prints.
+fn git(args: &[&str], dir: Option<&Path>) -> Result<String, Error> {
+    let mut command = Command::new("git");
+    if let Some(dir) = dir {
+        command.arg("-C").arg(dir);
+    }
+    let output = command.args(args).output().map_err(|e| {
+        Error::CustomError(format!(
+            "could not run git, which is needed to install packages: {}",
+            e
+        ))
+    })?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        Err(Error::CustomError(format!(
+            "`git {}` failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    }
+}
+
+fn read_lock() -> Result<BTreeMap<String, Locked>, Error> {
+    let path = lock_path().ok_or_else(home_error)?;
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(_) if !path.exists() => return Ok(BTreeMap::new()),
+        Err(e) => {
+            return Err(Error::CustomError(format!(
+                "could not read {}: {}",
+                path.display(),
+                e
+            )))
+        }
+    };
+    let error = |why: &str| Error::CustomError(format!("{} {}", path.display(), why));
+    let lock = text
+        .parse::<toml::Value>()
+        .map_err(|e| error(&format!("isn't valid TOML: {}", e)))?;
+
+    let mut packages = BTreeMap::new();
+    if let Some(table) = lock.get("packages").and_then(toml::Value::as_table) {
+        for (name, package) in table {
+            let field = |field: &str| {
+                package
+                    .get(field)
+                    .and_then(toml::Value::as_str)
+                    .map(String::from)
+                    .ok_or_else(|| error(&format!("has no {} for the package `{}`", field, name)))
+            };
+            packages.insert(
+                name.clone(),
+                Locked {
+                    source: field("source")?,
+                    version: field("version")?,
+                },
+            );
+        }
+    }
+    Ok(packages)
+}
+
+fn write_lock(packages: &BTreeMap<String, Locked>) -> Result<(), Error> {
+    let path = lock_path().ok_or_else(home_error)?;
+    let table = packages
+        .iter()
+        .map(|(name, package)| {
+            let mut fields = toml::value::Table::new();
+            fields.insert(
+                String::from("source"),
+                toml::Value::String(package.source.clone()),
+            );
+            fields.insert(
+                String::from("version"),
+                toml::Value::String(package.version.clone()),
+            );
+            (name.clone(), toml::Value::Table(fields))
+        })
+        .collect::<toml::value::Table>();
+    let mut lock = toml::value::Table::new();
+    lock.insert(String::from("packages"), toml::Value::Table(table));
+
+    let text = format!(
+        "# The Dune packages installed with `dunesh pkg install`.\n# Run `dunesh pkg install` to install these versions again.\n\n{}",
+        toml::Value::Table(lock)
+    );
+    std::fs::write(&path, text)
+        .map_err(|e| Error::CustomError(format!("could not write {}: {}", path.display(), e)))
+}
+
+/// Fetch a package into the packages directory, replacing any version of it
+/// already there, and get the commit that was fetched.
+fn fetch(name: &str, url: &str, version: Option<&str>) -> Result<String, Error> {
+    let dir = dir().ok_or_else(home_error)?;
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| Error::CustomError(format!("could not create {}: {}", dir.display(), e)))?;
+
+    // Clone next to where the package goes, so a failed install leaves the
+    // installed version alone.
+    let partial = dir.join(format!(".{}.partial", name));
+    let _ = std::fs::remove_dir_all(&partial);
+    let result = (|| {
+        git(
+            &["clone", "--quiet", url, &partial.display().to_string()],
+            None,
+        )?;
+        if let Some(version) = version {
+            git(&["checkout", "--quiet", version], Some(&partial))?;
+        }
+        if !partial.join(ENTRY).is_file() {
+            return Err(Error::CustomError(format!(
+                "{} isn't a Dune package, because it doesn't have a {}",
+                url, ENTRY
+            )));
+        }
+        git(&["rev-parse", "HEAD"], Some(&partial))
+    })();
+    let commit = match result {
+        Ok(commit) => commit,
+        Err(e) => {
+            let _ = std::fs::remove_dir_all(&partial);
+            return Err(e);
+        }
+    };
+
+    let installed = dir.join(name);
+    if installed.exists() {
+        std::fs::remove_dir_all(&installed).map_err(|e| {
+            Error::CustomError(format!("could not remove {}: {}", installed.display(), e))
+        })?;
+    }
+    std::fs::rename(&partial, &installed).map_err(|e| {
+        Error::CustomError(format!("could not install {}: {}", installed.display(), e))
+    })?;
+    Ok(commit)
+}
+
+/// Install a package from a git repository, at a tag, branch, or commit if
+/// one is given, and record it in the lockfile.
+pub fn install(source: &str, version: Option<&str>) -> Result<(), Error> {
+    let url = source_url(source)?;
+    let name = package_name(&url)?;
+    let mut packages = read_lock()?;
+    let commit = fetch(&name, &url, version)?;
+    println!("installed {} at {}", name, short(&commit));
+    packages.insert(
+        name,
+        Locked {
+            source: url,
+            version: commit,
+        },
+    );
+    write_lock(&packages)
+}
+
+/// Install every package in the lockfile at the version recorded there.
+pub fn install_locked() -> Result<(), Error> {
+    let packages = read_lock()?;
+    if packages.is_empty() {
+        println!("no packages are recorded in the lockfile, install one with `dunesh pkg install <name-or-git-url>`");
+    }
+    for (name, package) in &packages {
+        let installed = dir().ok_or_else(home_error)?.join(name);
+        if installed.join(ENTRY).is_file()
+            && git(&["rev-parse", "HEAD"], Some(&installed)).ok() == Some(package.version.clone())
+        {
+            println!("{} is already at {}", name, short(&package.version));
+            continue;
+        }
+        fetch(name, &package.source, Some(&package.version))?;
+        println!("installed {} at {}", name, short(&package.version));
+    }
+    Ok(())
+}
+
+/// Uninstall a package and remove it from the lockfile.
+pub fn remove(name: &str) -> Result<(), Error> {
+    let mut packages = read_lock()?;
+    let installed = dir().ok_or_else(home_error)?.join(name);
+    if !is_name(name) || (packages.remove(name).is_none() && !installed.exists()) {
+        return Err(Error::CustomError(format!(
+            "no package named `{}` is installed",
+            name
+        )));
+    }
+    if installed.exists() {
+        std::fs::remove_dir_all(&installed).map_err(|e| {
+            Error::CustomError(format!("could not remove {}: {}", installed.display(), e))
+        })?;
+    }
+    println!("removed {}", name);
+    write_lock(&packages)
+}
+
+/// Print the installed packages, their versions, and where they came from.
+pub fn list() -> Result<(), Error> {
+    let packages = read_lock()?;
+    if packages.is_empty() {
+        println!("no packages are installed");
+    }
+    for (name, package) in packages {
+        println!("{} {} {}", name, short(&package.version), package.source);
+    }
+    Ok(())
+}
+
+/// A commit abbreviated the way git prints it.
+fn short(commit: &str) -> &str {
+    &commit[..commit.len().min(12)]
+}
@@ -0,0 +1,239 @@
+use crossterm::{
+    cursor::{Hide, MoveTo, Show},
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    queue,
+    style::Print,
+    terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use dune::{Environment, Foreground};
+use std::{
+    io::{stdout, IsTerminal, Write},
+    process::{Command, Stdio},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// Is output that doesn't fit on the screen paged?
+static PAGING: AtomicBool = AtomicBool::new(false);
+
+/// Page the output printed while `f` runs when it's too tall for the console,
+/// like the results reported in the REPL, and link the paths and URLs in it.
+/// Output that isn't going to a console is left alone.
+pub fn paging<T>(f: impl FnOnce() -> T) -> T {
+    let enabled = std::io::stdin().is_terminal() && stdout().is_terminal();
+    let was_paging = PAGING.swap(enabled, Ordering::SeqCst);
+    let result = f();
+    PAGING.store(was_paging, Ordering::SeqCst);
+    result
+}
+
+/// Print some text, paging it if it's too tall for the console.
+///
+/// The pager is the program in `$PAGER` if it's set, and otherwise a simple
+/// one built in to the shell.
+pub fn print(text: &str, env: &Environment) {
+    if !PAGING.load(Ordering::SeqCst) {
+        return println!("{}", text);
+    }
+    let height = match terminal::size() {
+        // Some consoles don't know their size.
+        Ok((width, height)) if width > 0 && height > 0 => {
+            (rows(text, width as usize) >= height as usize).then_some(height)
+        }
+        _ => None,
+    };
+    let text = &if env.options().hyperlinks {
+        super::links::link(text, &env.get_cwd())
+    } else {
+        text.to_string()
+    };
+    let height = match height {
+        Some(height) => height,
+        None => return println!("{}", text),
+    };
+
+    let pager = env
+        .get("PAGER")
+        .map(|pager| pager.to_string())
+        .or_else(|| std::env::var("PAGER").ok())
+        .filter(|pager| !pager.trim().is_empty());
+    if let Some(pager) = pager {
+        if run_pager(&pager, text, env).is_ok() {
+            return;
+        }
+    }
+    if Pager::new(text, height as usize).run().is_err() {
+        println!("{}", text);
+    }
+}
+
+/// How many rows of the console some text takes up, once long lines wrap.
+fn rows(text: &str, width: usize) -> usize {
+    crate::strip_ansi_escapes(text)
+        .lines()
+        .map(|line| line.chars().count().max(1).div_ceil(width.max(1)))
+        .sum()
+}
+
+/// Pipe some text to a pager program, like `less -R`.
+fn run_pager(pager: &str, text: &str, env: &Environment) -> std::io::Result<()> {
+    let mut words = pager.split_whitespace();
+    let program = words.next().unwrap_or_default();
+    let mut foreground = Foreground::new();
+    let mut child = foreground.spawn(
+        Command::new(program)
+            .args(words)
+            .current_dir(env.get_cwd())
+            .stdin(Stdio::piped()),
+    )?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // The pager may be quit before it reads everything.
+        let _ = writeln!(stdin, "{}", text);
+    }
+    foreground.wait(&mut child)?;
+    Ok(())
+}
+
+/// The pager built in to the shell, for scrolling through text in the
+/// alternate screen.
+struct Pager {
+    lines: Vec<String>,
+    /// The first row shown.
+    top: usize,
+    /// The rows the console had last time the text was drawn.
+    page: usize,
+}
+
+impl Pager {
+    fn new(text: &str, height: usize) -> Self {
+        Self {
+            lines: text.lines().map(String::from).collect(),
+            top: 0,
+            page: height.saturating_sub(1).max(1),
+        }
+    }
+
+    fn run(&mut self) -> std::io::Result<()> {
+        let was_raw = terminal::is_raw_mode_enabled().unwrap_or(false);
+        terminal::enable_raw_mode()?;
+        let mut out = stdout();
+        queue!(out, EnterAlternateScreen, Hide)?;
+        let result = self.scroll(&mut out);
+        queue!(out, Show, LeaveAlternateScreen)?;
+        out.flush()?;
+        if !was_raw {
+            terminal::disable_raw_mode()?;
+        }
+        result
+    }
+
+    /// Scroll through the text until the pager is quit.
+    fn scroll(&mut self, out: &mut impl Write) -> std::io::Result<()> {
+        loop {
+            self.draw(out)?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press && self.handle(key) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    fn draw(&mut self, out: &mut impl Write) -> std::io::Result<()> {
+        let (width, height) = terminal::size()?;
+        let (width, height) = (width as usize, height as usize);
+        // Leave room for the status line.
+        self.page = height.saturating_sub(1).max(1);
+
+        let rows = self
+            .lines
+            .iter()
+            .flat_map(|line| wrap(line, width))
+            .collect::<Vec<_>>();
+        self.top = self.top.min(rows.len().saturating_sub(self.page));
+
+        queue!(out, MoveTo(0, 0), Clear(ClearType::All))?;
+        for (i, row) in rows.iter().skip(self.top).take(self.page).enumerate() {
+            queue!(out, MoveTo(0, i as u16), Print(row))?;
+        }
+
+        let last = (self.top + self.page).min(rows.len());
+        let status = format!(
+            "rows {}-{} of {} ({}%)  ↑↓ scroll  space/b page  g/G top/bottom  q quit",
+            self.top + 1,
+            last,
+            rows.len(),
+            last * 100 / rows.len().max(1)
+        );
+        let status = status.chars().take(width).collect::<String>();
+        queue!(
+            out,
+            MoveTo(0, height.saturating_sub(1) as u16),
+            Print(format!("\x1b[7m{}\x1b[0m", status))
+        )?;
+        out.flush()
+    }
+
+    /// Handle a key press. Returns true when the pager is quit.
+    fn handle(&mut self, key: KeyEvent) -> bool {
+        match (key.code, key.modifiers) {
+            (KeyCode::Char('q') | KeyCode::Esc, _)
+            | (KeyCode::Char('c'), KeyModifiers::CONTROL) => return true,
+            (KeyCode::Up | KeyCode::Char('k'), _) => self.top = self.top.saturating_sub(1),
+            (KeyCode::Down | KeyCode::Char('j') | KeyCode::Enter, _) => self.top += 1,
+            (KeyCode::PageUp | KeyCode::Char('b'), _) => {
+                self.top = self.top.saturating_sub(self.page)
+            }
+            (KeyCode::PageDown | KeyCode::Char(' ') | KeyCode::Char('f'), _) => {
+                self.top += self.page
+            }
+            (KeyCode::Home | KeyCode::Char('g'), _) => self.top = 0,
+            // Drawing moves this back to the last page.
+            (KeyCode::End | KeyCode::Char('G'), _) => self.top = usize::MAX,
+            _ => {}
+        }
+        false
+    }
+}
+
+/// Ends the colors and links at the end of a row, so they don't run into the next one.
+const END: &str = "\x1b]8;;\x1b\\\x1b[0m";
+
+/// Split a line into rows that fit the width of the console, ignoring escape
+/// codes. The escape codes from earlier in the line are repeated at the start
+/// of each row, so colors carry over.
+fn wrap(line: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut rows = vec![];
+    let mut row = String::new();
+    let mut escapes = String::new();
+    let mut shown = 0;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            let mut escape = String::from(c);
+            // Links and other OSC escape codes end with BEL or ESC \, and the
+            // others with a letter.
+            let osc = chars.as_str().starts_with(']');
+            for c in chars.by_ref() {
+                escape.push(c);
+                if osc && (c == '\x07' || escape.ends_with("\x1b\\"))
+                    || !osc && c.is_ascii_alphabetic()
+                {
+                    break;
+                }
+            }
+            row += &escape;
+            escapes += &escape;
+            continue;
+        }
+        if shown == width {
+            rows.push(row + END);
+            row = escapes.clone();
+            shown = 0;
+        }
+        row.push(if c == '\t' { ' ' } else { c });
+        shown += 1;
+    }
+    rows.push(row + END);
+    rows
+}
@@ -0,0 +1,81 @@
+use dune::{Environment, Error, Expression};
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// The scripts being run, innermost last, so a script can refer to files
+/// next to it no matter where it's run from.
+static SCRIPTS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// The arguments given to the script the shell was started with.
+static ARGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Set the arguments given to the script the shell was started with.
+pub fn set_args(args: Vec<String>) {
+    *ARGS.lock().unwrap() = args;
+}
+
+/// The arguments given to the script the shell was started with.
+pub fn args() -> Vec<String> {
+    ARGS.lock().unwrap().clone()
+}
+
+/// Run `f` while the script at `path` is the one being run.
+pub fn running<T>(path: &Path, f: impl FnOnce() -> T) -> T {
+    let path = dunce::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    SCRIPTS.lock().unwrap().push(path);
+    let result = f();
+    SCRIPTS.lock().unwrap().pop();
+    result
+}
+
+/// The script being run, if any.
+pub fn current() -> Option<PathBuf> {
+    SCRIPTS.lock().unwrap().last().cloned()
+}
+
+/// Resolve a path against the directory of the script being run, or the
+/// current directory outside of a script.
+pub fn resolve(path: &str, env: &Environment) -> PathBuf {
+    let dir = current()
+        .and_then(|script| script.parent().map(Path::to_path_buf))
+        .unwrap_or_else(|| PathBuf::from(env.get_cwd()));
+    dir.join(path)
+}
+
+/// Read, parse, and evaluate a script in an environment.
+pub fn source(path: &str, env: &mut Environment) -> Result<Expression, Error> {
+    let path = resolve(path, env);
+    let contents = std::fs::read_to_string(&path).map_err(|e| {
+        Error::CustomError(format!("could not read file {}: {}", path.display(), e))
+    })?;
+    let expr = crate::parse(&contents)?;
+    running(&path, || expr.eval(env))
+}
+
+/// Read, parse, and evaluate a script in a copy of an environment, so it
+/// can't change it, and get the script's value. A path that isn't a file is
+/// looked up in the installed packages.
+pub fn import(path: &str, env: &Environment) -> Result<Expression, Error> {
+    let resolved = resolve(path, env);
+    let path = match super::packages::entry(path) {
+        Some(package) if !resolved.is_file() => package,
+        _ => resolved,
+    };
+    let canon_path = dunce::canonicalize(&path).map_err(|_| {
+        Error::CustomError(format!("could not canonicalize path {}", path.display()))
+    })?;
+    let contents = std::fs::read_to_string(&canon_path).map_err(|e| {
+        Error::CustomError(format!(
+            "could not read file {}: {}",
+            canon_path.display(),
+            e
+        ))
+    })?;
+    let expr = crate::parse(&contents).map_err(|_| {
+        Error::CustomError(format!("could not parse file {}", canon_path.display()))
+    })?;
+    let mut new_env = env.clone();
+    running(&canon_path, || expr.eval(&mut new_env))
+}
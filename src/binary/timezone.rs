@@ -0,0 +1,436 @@
+use chrono::{Datelike, NaiveDate};
+use dune::Error;
+use std::path::PathBuf;
+
+/// The offset from UTC a zone uses for a while, like `CEST` at two hours ahead.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Offset {
+    /// The seconds to add to UTC to get the local time.
+    pub seconds: i64,
+    pub dst: bool,
+    pub abbreviation: String,
+}
+
+/// When daylight saving time starts or ends each year, from the POSIX TZ
+/// string at the end of a zoneinfo file, like `M3.5.0/2`.
+#[derive(Debug, Clone, Copy)]
+enum Day {
+    /// `Jn`, a day from 1 to 365 that never counts February 29th.
+    Julian(i64),
+    /// `n`, a day from 0 to 365 that counts February 29th.
+    Zero(i64),
+    /// `Mm.w.d`, the `w`th `d`day of month `m`, where the 5th is the last.
+    Month(u32, u32, u32),
+}
+
+/// When daylight saving time starts and ends, as a day and the seconds
+/// after midnight on it.
+#[derive(Debug, Clone)]
+struct Dst {
+    offset: Offset,
+    start: (Day, i64),
+    end: (Day, i64),
+}
+
+/// The rule a zone follows after the last transition in its file.
+#[derive(Debug, Clone)]
+struct Rule {
+    standard: Offset,
+    dst: Option<Dst>,
+}
+
+/// A timezone from the tz database, like `Europe/Berlin`.
+#[derive(Debug, Clone)]
+pub struct Zone {
+    pub name: String,
+    /// When the zone changed offsets, and the index of the offset it changed to.
+    transitions: Vec<(i64, usize)>,
+    offsets: Vec<Offset>,
+    rule: Option<Rule>,
+}
+
+/// The directories the tz database is installed in, with `TZDIR` first.
+fn database_dirs() -> Vec<PathBuf> {
+    let mut dirs = vec![];
+    if let Some(dir) = std::env::var_os("TZDIR") {
+        dirs.push(PathBuf::from(dir));
+    }
+    for dir in [
+        "/usr/share/zoneinfo",
+        "/usr/lib/zoneinfo",
+        "/usr/share/lib/zoneinfo",
+        "/etc/zoneinfo",
+    ] {
+        dirs.push(PathBuf::from(dir));
+    }
+    dirs
+}
+
+impl Zone {
+    /// A zone that's always the same offset from UTC.
+    fn fixed(name: &str, seconds: i64) -> Self {
+        Self {
+            name: name.to_string(),
+            transitions: vec![],
+            offsets: vec![Offset {
+                seconds,
+                dst: false,
+                abbreviation: name.to_string(),
+            }],
+            rule: None,
+        }
+    }
+
+    /// Find a zone by its name in the tz database, like `America/New_York`.
+    /// `local` is the system's zone, and offsets like `+05:30` are fixed zones.
+    pub fn named(name: &str) -> Result<Self, Error> {
+        if name.eq_ignore_ascii_case("local") {
+            return Ok(Self::local());
+        }
+        if let Some(seconds) = name.strip_prefix(['+', '-']).and_then(parse_clock) {
+            let seconds = if name.starts_with('-') {
+                -seconds
+            } else {
+                seconds
+            };
+            return Ok(Self::fixed(name, seconds));
+        }
+
+        // Names are paths into the database, which shouldn't leave it.
+        let is_name = !name.is_empty()
+            && !name.starts_with('/')
+            && name.split('/').all(|part| !part.is_empty() && part != "..");
+        if is_name {
+            for dir in database_dirs() {
+                if let Ok(bytes) = std::fs::read(dir.join(name)) {
+                    return Self::parse(name, &bytes);
+                }
+            }
+        }
+        if ["UTC", "GMT", "Z"]
+            .iter()
+            .any(|utc| name.eq_ignore_ascii_case(utc))
+        {
+            return Ok(Self::fixed("UTC", 0));
+        }
+        Err(Error::CustomError(format!(
+            "unknown timezone `{}`, expected a name from the tz database like \"Europe/Berlin\", \"local\", or an offset like \"+05:30\" (set TZDIR if the database isn't installed in /usr/share/zoneinfo)",
+            name
+        )))
+    }
+
+    /// The system's zone, from `TZ` or `/etc/localtime`.
+    pub fn local() -> Self {
+        if let Ok(tz) = std::env::var("TZ") {
+            if let Ok(zone) = Self::named(tz.trim_start_matches(':')) {
+                return zone;
+            }
+        }
+        if let Ok(bytes) = std::fs::read("/etc/localtime") {
+            if let Ok(zone) = Self::parse("local", &bytes) {
+                return zone;
+            }
+        }
+        // Without the database, the best that can be done is the offset now.
+        let seconds = chrono::Local::now().offset().local_minus_utc() as i64;
+        Self::fixed("local", seconds)
+    }
+
+    /// Read a zoneinfo file, which is described in RFC 8536.
+    fn parse(name: &str, bytes: &[u8]) -> Result<Self, Error> {
+        let error = || Error::CustomError(format!("the zoneinfo file for {} is invalid", name));
+        let mut reader = Reader { bytes, at: 0 };
+        let header = |reader: &mut Reader| -> Option<(u8, [usize; 6])> {
+            if reader.take(4)? != b"TZif" {
+                return None;
+            }
+            let version = reader.take(1)?[0];
+            reader.take(15)?;
+            let mut counts = [0; 6];
+            for count in &mut counts {
+                *count = reader.int(4)? as usize;
+            }
+            Some((version, counts))
+        };
+
+        let (version, counts) = header(&mut reader).ok_or_else(error)?;
+        // Version 2 and later repeat the data with 64-bit times after the
+        // 32-bit times, which is what should be used.
+        let (counts, time_size) = if version >= b'2' {
+            let [is_ut, is_std, leaps, times, types, chars] = counts;
+            reader
+                .take(times * 5 + types * 6 + chars + leaps * 8 + is_std + is_ut)
+                .ok_or_else(error)?;
+            (header(&mut reader).ok_or_else(error)?.1, 8)
+        } else {
+            (counts, 4)
+        };
+        let [is_ut, is_std, leaps, times, types, chars] = counts;
+
+        let data = (|| {
+            let mut starts = vec![];
+            for _ in 0..times {
+                starts.push(reader.int(time_size)?);
+            }
+            let indices = reader.take(times)?.to_vec();
+            let mut raw_offsets = vec![];
+            for _ in 0..types {
+                let seconds = reader.int(4)? as i32 as i64;
+                let dst = reader.take(1)?[0] != 0;
+                let abbreviation = reader.take(1)?[0] as usize;
+                raw_offsets.push((seconds, dst, abbreviation));
+            }
+            let names = reader.take(chars)?;
+            reader.take(leaps * (time_size + 4) + is_std + is_ut)?;
+
+            let offsets = raw_offsets
+                .into_iter()
+                .map(|(seconds, dst, start)| {
+                    let name = names.get(start..).unwrap_or_default();
+                    let end = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+                    Offset {
+                        seconds,
+                        dst,
+                        abbreviation: String::from_utf8_lossy(&name[..end]).to_string(),
+                    }
+                })
+                .collect::<Vec<_>>();
+            let transitions = starts
+                .into_iter()
+                .zip(indices.into_iter().map(usize::from))
+                .collect::<Vec<_>>();
+            Some((transitions, offsets))
+        })();
+        let (transitions, offsets) = data.ok_or_else(error)?;
+        if offsets.is_empty() || transitions.iter().any(|(_, i)| *i >= offsets.len()) {
+            return Err(error());
+        }
+
+        let footer = String::from_utf8_lossy(&bytes[reader.at..]).to_string();
+        let rule = if version >= b'2' {
+            parse_rule(footer.trim())
+        } else {
+            None
+        };
+        Ok(Self {
+            name: name.to_string(),
+            transitions,
+            offsets,
+            rule,
+        })
+    }
+
+    /// The offset the zone uses at a UNIX timestamp.
+    pub fn offset_at(&self, stamp: i64) -> Offset {
+        let after = self
+            .transitions
+            .partition_point(|(start, _)| *start <= stamp);
+        match (after, &self.rule) {
+            (0, _) => self.offsets[0].clone(),
+            (n, Some(rule)) if n == self.transitions.len() => rule.offset_at(stamp),
+            (n, _) => self.offsets[self.transitions[n - 1].1].clone(),
+        }
+    }
+
+    /// The UNIX timestamp of a time on the zone's clocks, given in seconds
+    /// since 1970 as if it were UTC. A time that happens twice when the clocks
+    /// go back is the first one, and a time skipped when they go forward is
+    /// read with the offset from before.
+    pub fn to_stamp(&self, local: i64) -> i64 {
+        let before = self.offset_at(local - 86400).seconds;
+        let after = self.offset_at(local + 86400).seconds;
+        let mut candidates = [local - before, local - after];
+        candidates.sort();
+        candidates
+            .into_iter()
+            .find(|stamp| local - self.offset_at(*stamp).seconds == *stamp)
+            .unwrap_or(local - before)
+    }
+}
+
+impl Rule {
+    fn offset_at(&self, stamp: i64) -> Offset {
+        let Dst {
+            offset: dst,
+            start,
+            end,
+        } = match &self.dst {
+            Some(dst) => dst,
+            None => return self.standard.clone(),
+        };
+        let year = NaiveDate::from_num_days_from_ce_opt(
+            ((stamp + self.standard.seconds).div_euclid(86400) + 719163) as i32,
+        )
+        .map(|date| date.year())
+        .unwrap_or(1970);
+        // Daylight saving time starts at a time on the standard clock, and
+        // ends at a time on the daylight saving clock.
+        let start = day_stamp(year, start.0) + start.1 - self.standard.seconds;
+        let end = day_stamp(year, end.0) + end.1 - dst.seconds;
+        let in_dst = if start < end {
+            start <= stamp && stamp < end
+        } else {
+            // In the southern hemisphere, it's across the new year.
+            !(end <= stamp && stamp < start)
+        };
+        if in_dst {
+            dst.clone()
+        } else {
+            self.standard.clone()
+        }
+    }
+}
+
+/// The UNIX timestamp of midnight UTC on a day of a year.
+fn day_stamp(year: i32, day: Day) -> i64 {
+    let first = NaiveDate::from_ymd_opt(year, 1, 1).unwrap_or_default();
+    let leap = NaiveDate::from_ymd_opt(year, 2, 29).is_some();
+    let date = match day {
+        Day::Julian(n) => first + chrono::Duration::days(n - if leap && n >= 60 { 0 } else { 1 }),
+        Day::Zero(n) => first + chrono::Duration::days(n),
+        Day::Month(month, week, weekday) => {
+            let start = NaiveDate::from_ymd_opt(year, month, 1).unwrap_or(first);
+            let shift = (weekday + 7 - start.weekday().num_days_from_sunday()) % 7;
+            let mut day = 1 + shift + (week - 1) * 7;
+            while NaiveDate::from_ymd_opt(year, month, day).is_none() {
+                day -= 7;
+            }
+            NaiveDate::from_ymd_opt(year, month, day).unwrap_or(start)
+        }
+    };
+    (date.num_days_from_ce() as i64 - 719163) * 86400
+}
+
+/// Read a time like `2`, `-1:30`, or `25:00:00` as seconds, without a sign.
+fn parse_clock(text: &str) -> Option<i64> {
+    let mut seconds = 0;
+    let mut parts = 0;
+    for (part, scale) in text.split(':').zip([3600, 60, 1]) {
+        if part.is_empty() || part.len() > 3 || !part.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        seconds += part.parse::<i64>().ok()? * scale;
+        parts += 1;
+    }
+    (text.split(':').count() == parts).then_some(seconds)
+}
+
+/// Read the POSIX TZ string at the end of a zoneinfo file, like
+/// `CET-1CEST,M3.5.0,M10.5.0/3`.
+fn parse_rule(text: &str) -> Option<Rule> {
+    let mut rest = text;
+    let standard_name = abbreviation(&mut rest)?;
+
+    // The offsets are how far behind UTC the zone is, so they're negated.
+    let signed = |text: &str| -> Option<(i64, usize)> {
+        let end = text
+            .find(|c: char| !(c.is_ascii_digit() || "+-:".contains(c)))
+            .unwrap_or(text.len());
+        let part = &text[..end];
+        let seconds = match part.strip_prefix('-') {
+            Some(part) => -parse_clock(part)?,
+            None => parse_clock(part.trim_start_matches('+'))?,
+        };
+        Some((seconds, end))
+    };
+    let (behind, end) = signed(rest)?;
+    rest = &rest[end..];
+    let standard = Offset {
+        seconds: -behind,
+        dst: false,
+        abbreviation: standard_name,
+    };
+    if rest.is_empty() {
+        return Some(Rule {
+            standard,
+            dst: None,
+        });
+    }
+
+    let dst_name = abbreviation(&mut rest)?;
+    let dst_seconds = match signed(rest) {
+        Some((behind, end)) if end > 0 => {
+            rest = &rest[end..];
+            -behind
+        }
+        _ => standard.seconds + 3600,
+    };
+    let dst = Offset {
+        seconds: dst_seconds,
+        dst: true,
+        abbreviation: dst_name,
+    };
+
+    let transition = |text: &str| -> Option<(Day, i64)> {
+        let (day, time) = match text.split_once('/') {
+            Some((day, time)) => (day, signed(time).filter(|(_, end)| *end == time.len())?.0),
+            None => (text, 7200),
+        };
+        let day = if let Some(n) = day.strip_prefix('J') {
+            Day::Julian(n.parse().ok().filter(|n| (1..=365).contains(n))?)
+        } else if let Some(rule) = day.strip_prefix('M') {
+            let parts = rule
+                .split('.')
+                .map(|n| n.parse::<u32>().ok())
+                .collect::<Option<Vec<_>>>()?;
+            match parts[..] {
+                [m, w, d] if (1..=12).contains(&m) && (1..=5).contains(&w) && d <= 6 => {
+                    Day::Month(m, w, d)
+                }
+                _ => return None,
+            }
+        } else {
+            Day::Zero(day.parse().ok().filter(|n| (0..=365).contains(n))?)
+        };
+        Some((day, time))
+    };
+    let mut rules = rest.strip_prefix(',')?.split(',');
+    let start = transition(rules.next()?)?;
+    let end = transition(rules.next()?)?;
+    Some(Rule {
+        standard,
+        dst: Some(Dst {
+            offset: dst,
+            start,
+            end,
+        }),
+    })
+}
+
+/// Read the abbreviation of an offset in a TZ string, which is letters or
+/// anything between `<` and `>`, like `<+0530>`.
+fn abbreviation(rest: &mut &str) -> Option<String> {
+    let (name, after) = if let Some(quoted) = rest.strip_prefix('<') {
+        let end = quoted.find('>')?;
+        (&quoted[..end], &quoted[end + 1..])
+    } else {
+        let end = rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len());
+        rest.split_at(end)
+    };
+    *rest = after;
+    Some(name.to_string()).filter(|name| !name.is_empty())
+}
+
+/// Reads big-endian numbers from a zoneinfo file.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        let bytes = self.bytes.get(self.at..self.at.checked_add(n)?)?;
+        self.at += n;
+        Some(bytes)
+    }
+
+    fn int(&mut self, size: usize) -> Option<i64> {
+        let bytes = self.take(size)?;
+        Some(match size {
+            4 => i32::from_be_bytes(bytes.try_into().ok()?) as i64,
+            _ => i64::from_be_bytes(bytes.try_into().ok()?),
+        })
+    }
+}
@@ -0,0 +1,300 @@
+use super::init::sha256;
+use dune::Error;
+use std::{
+    cmp::Ordering,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+/// The version of the running shell.
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// A file attached to a release.
+#[derive(Debug, Clone)]
+struct Asset {
+    name: String,
+    url: String,
+}
+
+/// The latest release, with the files built for each platform.
+#[derive(Debug, Clone)]
+struct Release {
+    version: String,
+    assets: Vec<Asset>,
+}
+
+/// Download a url with curl, to a file or as bytes.
+fn curl(url: &str, output: Option<&Path>) -> Result<Vec<u8>, Error> {
+    let mut curl = Command::new("curl");
+    curl.args(["--fail", "--silent", "--show-error", "--location"]);
+    if let Some(path) = output {
+        curl.arg("--output").arg(path);
+    }
+    let result = curl.arg(url).stdin(Stdio::null()).output().map_err(|e| {
+        Error::CustomError(format!("could not run curl to download {}: {}", url, e))
+    })?;
+    if !result.status.success() {
+        return Err(Error::CustomError(format!(
+            "could not download {}: {}",
+            url,
+            String::from_utf8_lossy(&result.stderr).trim()
+        )));
+    }
+    Ok(result.stdout)
+}
+
+/// Ask GitHub for the latest release of the repository the shell was built from.
+fn latest_release() -> Result<Release, Error> {
+    let repository = env!("CARGO_PKG_REPOSITORY").trim_end_matches('/');
+    let api = repository.replacen("https://github.com/", "https://api.github.com/repos/", 1);
+    let url = format!("{}/releases/latest", api);
+    let text = String::from_utf8_lossy(&curl(&url, None)?).to_string();
+    let release = json::parse(&text).map_err(|e| {
+        Error::CustomError(format!("could not read the release from {}: {}", url, e))
+    })?;
+
+    let version = match release["tag_name"].as_str() {
+        Some(tag) => tag.trim_start_matches('v').to_string(),
+        None => {
+            return Err(Error::CustomError(format!(
+                "could not find the latest release at {}",
+                url
+            )))
+        }
+    };
+    let assets = release["assets"]
+        .members()
+        .filter_map(|asset| {
+            Some(Asset {
+                name: asset["name"].as_str()?.to_string(),
+                url: asset["browser_download_url"].as_str()?.to_string(),
+            })
+        })
+        .collect();
+    Ok(Release { version, assets })
+}
+
+/// Compare versions like `0.2.0-alpha`, where a prerelease comes before the
+/// release it's for.
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    let parts = |version: &str| {
+        let (numbers, prerelease) = match version.split_once('-') {
+            Some((numbers, prerelease)) => (numbers.to_string(), Some(prerelease.to_string())),
+            None => (version.to_string(), None),
+        };
+        let numbers = numbers
+            .split('.')
+            .map(|n| n.parse::<u64>().unwrap_or(0))
+            .collect::<Vec<_>>();
+        (numbers, prerelease)
+    };
+    let ((a_numbers, a_pre), (b_numbers, b_pre)) = (parts(a), parts(b));
+    a_numbers
+        .cmp(&b_numbers)
+        .then_with(|| match (a_pre, b_pre) {
+            (None, None) => Ordering::Equal,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(_), None) => Ordering::Less,
+            (Some(a), Some(b)) => a.cmp(&b),
+        })
+}
+
+/// Whether an asset is a checksum rather than a build of the shell.
+fn is_checksum(name: &str) -> bool {
+    let name = name.to_lowercase();
+    name.contains("sha256") || name.contains("checksum") || name.ends_with(".sig")
+}
+
+/// Find the build of the shell for this platform. Builds are told apart by
+/// the operating system and architecture in their names, like
+/// `dunesh-x86_64-linux.tar.gz`.
+fn platform_asset(assets: &[Asset]) -> Option<&Asset> {
+    let os_names: &[&str] = match std::env::consts::OS {
+        "macos" => &["macos", "darwin", "apple", "osx"],
+        "windows" => &["windows", "win64", ".exe"],
+        os => &[os],
+    };
+    let arch_names: &[&str] = match std::env::consts::ARCH {
+        "x86_64" => &["x86_64", "amd64", "x64"],
+        "aarch64" => &["aarch64", "arm64"],
+        arch => &[arch],
+    };
+    let mentions = |name: &str, names: &[&str]| names.iter().any(|n| name.contains(n));
+    let builds = assets
+        .iter()
+        .filter(|asset| !is_checksum(&asset.name))
+        .filter(|asset| mentions(&asset.name.to_lowercase(), os_names))
+        .collect::<Vec<_>>();
+
+    // A release with one build per system doesn't name the architecture.
+    builds
+        .iter()
+        .find(|asset| mentions(&asset.name.to_lowercase(), arch_names))
+        .or_else(|| builds.first().filter(|_| builds.len() == 1))
+        .copied()
+}
+
+/// Find the published checksum of an asset, from either a file of its own
+/// like `dunesh-linux.sha256` or a list of them like `SHA256SUMS`.
+fn published_checksum(assets: &[Asset], asset: &Asset) -> Result<String, Error> {
+    let is_hash = |word: &str| word.len() == 64 && word.chars().all(|c| c.is_ascii_hexdigit());
+    let own = assets
+        .iter()
+        .find(|a| a.name.starts_with(&asset.name) && is_checksum(&a.name));
+    let lists = assets
+        .iter()
+        .filter(|a| is_checksum(&a.name) && !a.name.starts_with(&asset.name));
+
+    for checksums in own.into_iter().chain(lists) {
+        let text = String::from_utf8_lossy(&curl(&checksums.url, None)?).to_string();
+        for line in text.lines() {
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            // A file of its own has only the hash, and a list names each file.
+            let names_asset = words
+                .iter()
+                .any(|word| word.trim_start_matches('*') == asset.name);
+            let is_own = own.is_some_and(|own| own.url == checksums.url);
+            if names_asset || (is_own && words.len() == 1) {
+                if let Some(hash) = words.iter().find(|word| is_hash(word)) {
+                    return Ok(hash.to_lowercase());
+                }
+            }
+        }
+    }
+    Err(Error::CustomError(format!(
+        "the release doesn't publish a SHA-256 checksum for {}, so it can't be verified",
+        asset.name
+    )))
+}
+
+/// Get the shell out of a downloaded archive, or the download itself if it
+/// isn't one.
+fn unpack(download: &Path, name: &str, dir: &Path) -> Result<PathBuf, Error> {
+    let is_archive = [".tar.gz", ".tgz", ".tar.xz", ".zip"]
+        .iter()
+        .any(|ext| name.ends_with(ext));
+    if !is_archive {
+        return Ok(download.to_path_buf());
+    }
+
+    let status = Command::new("tar")
+        .arg("-xf")
+        .arg(download)
+        .arg("-C")
+        .arg(dir)
+        .status()
+        .map_err(|e| Error::CustomError(format!("could not run tar to unpack {}: {}", name, e)))?;
+    if !status.success() {
+        return Err(Error::CustomError(format!("could not unpack {}", name)));
+    }
+    let binary = if cfg!(windows) {
+        "dunesh.exe"
+    } else {
+        "dunesh"
+    };
+    find_file(dir, binary)
+        .ok_or_else(|| Error::CustomError(format!("{} doesn't have {} in it", name, binary)))
+}
+
+/// Find a file by name in a directory or the directories in it.
+fn find_file(dir: &Path, name: &str) -> Option<PathBuf> {
+    let mut dirs = vec![];
+    for entry in std::fs::read_dir(dir).ok()?.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            dirs.push(path);
+        } else if path.file_name().is_some_and(|n| n == name) {
+            return Some(path);
+        }
+    }
+    dirs.iter().find_map(|dir| find_file(dir, name))
+}
+
+/// Replace the running executable with a new one in the same directory, so
+/// the rename is atomic and there's never a moment without a shell.
+fn replace(exe: &Path, new: &Path) -> Result<(), Error> {
+    let error = |e: std::io::Error| {
+        Error::CustomError(format!("could not replace {}: {}", exe.display(), e))
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(new, std::fs::Permissions::from_mode(0o755)).map_err(error)?;
+    }
+    // Windows won't replace a running executable, but it will rename one.
+    #[cfg(windows)]
+    {
+        let old = exe.with_extension("old.exe");
+        let _ = std::fs::remove_file(&old);
+        std::fs::rename(exe, &old).map_err(error)?;
+        if let Err(e) = std::fs::rename(new, exe) {
+            let _ = std::fs::rename(&old, exe);
+            return Err(error(e));
+        }
+        return Ok(());
+    }
+    #[cfg(not(windows))]
+    std::fs::rename(new, exe).map_err(error)
+}
+
+/// Update the shell to the latest release, or only say whether there's one
+/// with `check`.
+pub fn run(check: bool) -> Result<(), Error> {
+    let release = latest_release()?;
+    if compare_versions(&release.version, VERSION) != Ordering::Greater {
+        println!(
+            "dunesh {} is up to date, the latest release is {}",
+            VERSION, release.version
+        );
+        return Ok(());
+    }
+
+    let asset = platform_asset(&release.assets).ok_or_else(|| {
+        Error::CustomError(format!(
+            "release {} doesn't have a build for {} on {}, install it with `cargo install -f dune`",
+            release.version,
+            std::env::consts::OS,
+            std::env::consts::ARCH
+        ))
+    })?;
+    let expected = published_checksum(&release.assets, asset)?;
+    if check {
+        println!(
+            "dunesh {} can be updated to {}, run `dunesh self-update` to download {}",
+            VERSION, release.version, asset.name
+        );
+        return Ok(());
+    }
+
+    let exe = std::env::current_exe()
+        .and_then(dunce::canonicalize)
+        .map_err(|e| Error::CustomError(format!("could not find the running dunesh: {}", e)))?;
+    let dir = exe.parent().unwrap_or_else(|| Path::new("."));
+    // Download next to the executable, so it can be renamed over it.
+    let staging = dir.join(format!(".dunesh-update-{}", std::process::id()));
+    let result = (|| {
+        std::fs::create_dir_all(&staging).map_err(|e| {
+            Error::CustomError(format!("could not create {}: {}", staging.display(), e))
+        })?;
+        let download = staging.join(&asset.name);
+        println!("downloading {}", asset.name);
+        curl(&asset.url, Some(&download))?;
+
+        let contents = std::fs::read(&download).map_err(|e| {
+            Error::CustomError(format!("could not read {}: {}", download.display(), e))
+        })?;
+        let digest = sha256(&contents);
+        if digest != expected {
+            return Err(Error::CustomError(format!(
+                "the checksum of {} is {}, but the release says it's {}, so it wasn't installed",
+                asset.name, digest, expected
+            )));
+        }
+        let new = unpack(&download, &asset.name, &staging)?;
+        replace(&exe, &new)
+    })();
+    let _ = std::fs::remove_dir_all(&staging);
+    result?;
+    println!("updated dunesh from {} to {}", VERSION, release.version);
+    Ok(())
+}
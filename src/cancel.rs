@@ -0,0 +1,48 @@
+use super::{Environment, Error, Expression};
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+};
+
+/// The stack size of the thread that evaluates code that can be canceled. This
+/// matches the main thread's default so the code can recurse as deeply as usual.
+const EVAL_STACK_SIZE: usize = 8 * 1024 * 1024;
+
+/// Whether code is being evaluated by `eval_cancelable`.
+static RUNNING: AtomicBool = AtomicBool::new(false);
+/// Whether the code being evaluated has been canceled.
+static CANCELED: AtomicBool = AtomicBool::new(false);
+
+/// Cancel the code being evaluated by `eval_cancelable`, like when Ctrl-C is
+/// pressed. Evaluating anything fails with `Error::Interrupted` until it stops,
+/// so the code can't carry on by catching the error.
+pub fn cancel() {
+    if RUNNING.load(Ordering::SeqCst) {
+        CANCELED.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Has the code being evaluated been canceled? Builtins that block for a while,
+/// like `sleep`, check this to stop early.
+pub fn is_canceled() -> bool {
+    CANCELED.load(Ordering::Relaxed)
+}
+
+/// Evaluate an expression on a worker thread, so it can be stopped with `cancel`.
+/// A builtin that panics only fails the evaluation, instead of the whole shell.
+pub fn eval_cancelable(expr: &Expression, env: &mut Environment) -> Result<Expression, Error> {
+    CANCELED.store(false, Ordering::SeqCst);
+    RUNNING.store(true, Ordering::SeqCst);
+    let result = thread::scope(|scope| {
+        thread::Builder::new()
+            .name(String::from("eval"))
+            .stack_size(EVAL_STACK_SIZE)
+            .spawn_scoped(scope, || expr.eval(env))
+            .map_err(|e| Error::CustomError(format!("could not start evaluating: {}", e)))?
+            .join()
+            .unwrap_or_else(|_| Err(Error::CustomError(String::from("evaluation panicked"))))
+    });
+    RUNNING.store(false, Ordering::SeqCst);
+    CANCELED.store(false, Ordering::SeqCst);
+    result
+}
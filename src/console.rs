@@ -0,0 +1,188 @@
+use crate::Expression;
+use indexmap::IndexMap;
+use prettytable::{
+    format::{FormatBuilder, LinePosition, LineSeparator},
+    Cell, Row, Table,
+};
+use std::sync::RwLock;
+use terminal_size::{terminal_size, Height, Width};
+
+/// The width of the console, which tables of values are fit to. Setting
+/// `COLUMNS` overrides it, like for a pane whose size can't be found, or for
+/// output going to a file.
+pub fn console_width() -> Option<usize> {
+    size_override("COLUMNS").or_else(|| terminal_size().map(|(Width(w), _)| w as usize))
+}
+
+/// The height of the console, which `LINES` overrides like `COLUMNS` does the width.
+pub fn console_height() -> Option<usize> {
+    size_override("LINES").or_else(|| terminal_size().map(|(_, Height(h))| h as usize))
+}
+
+fn size_override(name: &str) -> Option<usize> {
+    std::env::var(name)
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+        .filter(|size| *size > 0)
+}
+
+/// How much of a value is shown when it's printed, so a huge nested value
+/// doesn't flood the console. Each limit is `None` to show everything.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DisplayLimits {
+    /// The levels of maps and lists shown. Deeper ones are summarized.
+    pub depth: Option<usize>,
+    /// The items of a list and the keys of a map shown.
+    pub items: Option<usize>,
+    /// The characters of a string shown, counting the `…` it's cut off with.
+    pub string: Option<usize>,
+}
+
+static DISPLAY_LIMITS: RwLock<DisplayLimits> = RwLock::new(DisplayLimits {
+    depth: None,
+    items: None,
+    string: None,
+});
+
+/// The limits on how much of a value the REPL prints.
+pub fn display_limits() -> DisplayLimits {
+    *DISPLAY_LIMITS.read().unwrap()
+}
+
+pub fn set_display_limits(limits: DisplayLimits) {
+    *DISPLAY_LIMITS.write().unwrap() = limits;
+}
+
+impl DisplayLimits {
+    /// A copy of a value with only what's within the limits, and a note of
+    /// how much was left out wherever something was. Values are trees, so
+    /// limiting their depth also keeps printing them from running forever.
+    pub fn apply(&self, value: &Expression) -> Expression {
+        self.apply_at(value, 1)
+    }
+
+    fn apply_at(&self, value: &Expression, depth: usize) -> Expression {
+        let plural =
+            |n: usize, name: &str| format!("{} {}{}", n, name, if n == 1 { "" } else { "s" });
+        let shown = |len: usize| self.items.unwrap_or(len).min(len);
+        match value {
+            // Maps that display themselves are left to do it.
+            Expression::Map(map) if map.contains_key("__display") => value.clone(),
+            Expression::Map(map) if !map.is_empty() && self.depth.is_some_and(|d| depth > d) => {
+                Expression::Symbol(format!("{{… {}}}", plural(map.len(), "key")))
+            }
+            Expression::List(list) if !list.is_empty() && self.depth.is_some_and(|d| depth > d) => {
+                Expression::Symbol(format!("[… {}]", plural(list.len(), "item")))
+            }
+            Expression::Map(map) => {
+                let mut limited = map
+                    .iter()
+                    .take(shown(map.len()))
+                    .map(|(k, v)| (k.clone(), self.apply_at(v, depth + 1)))
+                    .collect::<IndexMap<_, _>>();
+                if map.len() > limited.len() {
+                    let left = map.len() - limited.len();
+                    limited.insert(
+                        String::from("…"),
+                        Expression::Symbol(format!(
+                            "{} more key{}",
+                            left,
+                            if left == 1 { "" } else { "s" }
+                        )),
+                    );
+                }
+                Expression::Map(limited)
+            }
+            Expression::List(list) => {
+                let mut limited = list
+                    .iter()
+                    .take(shown(list.len()))
+                    .map(|v| self.apply_at(v, depth + 1))
+                    .collect::<Vec<_>>();
+                if list.len() > limited.len() {
+                    let left = list.len() - limited.len();
+                    limited.push(Expression::Symbol(format!("… {} more", left)));
+                }
+                Expression::List(limited)
+            }
+            Expression::String(text) => match self.string {
+                Some(max) if text.chars().count() > max => {
+                    let mut cut = text.chars().take(max.saturating_sub(1)).collect::<String>();
+                    cut.push('…');
+                    Expression::String(cut)
+                }
+                _ => value.clone(),
+            },
+            _ => value.clone(),
+        }
+    }
+}
+
+/// Draw a list of maps with the same keys, like the entries `fs@ls` gives, as
+/// a table with a row for each map and a column for each key. Other values,
+/// and tables wider than the console, are `None`, and are drawn like usual.
+pub fn records_table(value: &Expression, width: usize) -> Option<String> {
+    let items = match value {
+        Expression::List(items) => items,
+        _ => return None,
+    };
+    // A list cut off by the display limits ends with a note of what's left.
+    let (rows, more) = match items.split_last() {
+        Some((Expression::Symbol(note), rows)) if note.starts_with('…') => (rows, Some(note)),
+        _ => (&items[..], None),
+    };
+    let rows = rows
+        .iter()
+        .map(|row| match row {
+            Expression::Map(row) if !row.contains_key("__display") => Some(row),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    let columns = rows.first()?.keys().collect::<Vec<_>>();
+    if rows
+        .iter()
+        .any(|row| row.len() != columns.len() || !columns.iter().all(|c| row.contains_key(*c)))
+    {
+        return None;
+    }
+
+    // Unlike a map's table, the rows aren't separated, to fit more of them.
+    let mut t = Table::new();
+    t.set_format(
+        FormatBuilder::new()
+            .padding(1, 1)
+            .borders('│')
+            .column_separator('│')
+            .separator(LinePosition::Top, LineSeparator::new('═', '╤', '╒', '╕'))
+            .separator(LinePosition::Title, LineSeparator::new('═', '╪', '╞', '╡'))
+            .separator(LinePosition::Bottom, LineSeparator::new('─', '┴', '└', '┘'))
+            .build(),
+    );
+    t.set_titles(Row::new(columns.iter().map(|c| Cell::new(c)).collect()));
+    for row in &rows {
+        t.add_row(Row::new(
+            columns
+                .iter()
+                .map(|c| match &row[*c] {
+                    Expression::String(text) => Cell::new(text),
+                    Expression::Map(_) | Expression::List(_) => {
+                        Cell::new(&format!("{:?}", row[*c]))
+                    }
+                    cell => Cell::new(&cell.to_string()),
+                })
+                .collect(),
+        ));
+    }
+
+    let mut text = t.to_string();
+    if text.lines().any(|line| line.chars().count() > width) {
+        return None;
+    }
+    if let Some(more) = more {
+        text += more;
+        text.push('\n');
+    }
+    Some(text)
+}
@@ -1,12 +1,194 @@
 use super::{Error, Expression};
-use std::collections::BTreeMap;
+use indexmap::IndexMap;
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    sync::Arc,
+};
 
 const CWD_ENV_VAR: &str = "CWD";
 
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Environment {
-    pub bindings: BTreeMap<String, Expression>,
+    bindings: BTreeMap<String, Expression>,
     parent: Option<Box<Self>>,
+    options: Options,
+    /// Every lambda holds an environment, so this is boxed to make room for
+    /// the exports without making every expression bigger.
+    details: Box<Details>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    exports: Exports,
+}
+
+/// What an environment keeps besides its bindings and options.
+#[derive(Debug, Clone, Default, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Details {
+    /// The names bound with `const`, which can't be bound again.
+    constants: BTreeSet<String>,
+    /// How the last line entered in the REPL went, for the prompt to show.
+    last_command: Option<LastCommand>,
+}
+
+/// The bindings as the environment variables of the programs the shell runs.
+/// They're kept as bindings change, so running a program doesn't mean
+/// formatting every binding again.
+#[derive(Clone, Default)]
+struct Exports(Option<Box<ExportCache>>);
+
+#[derive(Clone)]
+struct ExportCache {
+    vars: Arc<BTreeMap<String, String>>,
+    /// The names bound or unbound since the variables were last asked for.
+    stale: BTreeSet<String>,
+}
+
+/// Values this long are left out of the environment variables. Really long
+/// ones can make it impossible to run any program at all on Linux, because
+/// `the argument list is too long`.
+const MAX_EXPORT_LEN: usize = 1024;
+
+impl Exports {
+    fn update(&mut self, bindings: &BTreeMap<String, Expression>) -> Arc<BTreeMap<String, String>> {
+        let export =
+            |value: &Expression| Some(value.to_string()).filter(|s| s.len() <= MAX_EXPORT_LEN);
+        let cache = self.0.get_or_insert_with(|| {
+            Box::new(ExportCache {
+                vars: Arc::new(
+                    bindings
+                        .iter()
+                        .filter_map(|(name, value)| Some((name.clone(), export(value)?)))
+                        .collect(),
+                ),
+                stale: BTreeSet::new(),
+            })
+        });
+        if !cache.stale.is_empty() {
+            let vars = Arc::make_mut(&mut cache.vars);
+            for name in std::mem::take(&mut cache.stale) {
+                match bindings.get(&name).and_then(export) {
+                    Some(value) => vars.insert(name, value),
+                    None => vars.remove(&name),
+                };
+            }
+        }
+        cache.vars.clone()
+    }
+
+    fn invalidate(&mut self, name: &str) {
+        if let Some(cache) = &mut self.0 {
+            cache.stale.insert(name.to_string());
+        }
+    }
+}
+
+// The exports only depend on the bindings, so they're left out of comparisons.
+impl PartialEq for Exports {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl PartialOrd for Exports {
+    fn partial_cmp(&self, _: &Self) -> Option<Ordering> {
+        Some(Ordering::Equal)
+    }
+}
+
+impl fmt::Debug for Exports {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Exports")
+    }
+}
+
+/// Whether a member of a module is private to it: names starting with `_` are
+/// left out of `help`, completion, and `use`.
+pub fn is_private(name: &str) -> bool {
+    name.starts_with('_')
+}
+
+/// Switches that change how the shell evaluates code, set with `shopt`.
+#[derive(Debug, Clone, Default, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Options {
+    /// A program exiting unsuccessfully is an error, which stops a script.
+    pub errexit: bool,
+    /// A pipeline fails if any program in it fails, not just the last one.
+    pub pipefail: bool,
+    /// Evaluating an unbound symbol is an error, instead of the symbol itself.
+    pub strict: bool,
+    /// Entering the name of a directory in the REPL changes to it.
+    pub autocd: bool,
+    /// Wildcards in the arguments to a program are expanded to the paths they match.
+    pub glob: bool,
+    /// Print each program and its arguments before running it.
+    pub trace: bool,
+    /// Changing directories loads the variables in the new directory's `.env` file.
+    pub dotenv: bool,
+    /// Paths and URLs in the values the REPL prints are links the console can open.
+    pub hyperlinks: bool,
+    /// The REPL prints the type, size, and evaluation time of each result under it.
+    pub annotate: bool,
+    /// Integer arithmetic wraps around when it overflows, instead of being an error.
+    pub wrapping: bool,
+}
+
+impl Options {
+    /// The names of all the options.
+    pub const NAMES: &'static [&'static str] = &[
+        "annotate",
+        "autocd",
+        "dotenv",
+        "errexit",
+        "glob",
+        "hyperlinks",
+        "pipefail",
+        "strict",
+        "trace",
+        "wrapping",
+    ];
+
+    fn flag(&mut self, name: &str) -> Result<&mut bool, Error> {
+        Ok(match name {
+            "errexit" => &mut self.errexit,
+            "pipefail" => &mut self.pipefail,
+            "strict" => &mut self.strict,
+            "autocd" => &mut self.autocd,
+            "glob" => &mut self.glob,
+            "trace" => &mut self.trace,
+            "dotenv" => &mut self.dotenv,
+            "hyperlinks" => &mut self.hyperlinks,
+            "annotate" => &mut self.annotate,
+            "wrapping" => &mut self.wrapping,
+            _ => {
+                return Err(Error::CustomError(format!(
+                    "unknown option `{}`, expected one of {}",
+                    name,
+                    Self::NAMES.join(", ")
+                )))
+            }
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Result<bool, Error> {
+        self.clone().flag(name).map(|flag| *flag)
+    }
+
+    pub fn set(&mut self, name: &str, value: bool) -> Result<(), Error> {
+        *self.flag(name)? = value;
+        Ok(())
+    }
+}
+
+/// How a line entered in the REPL went: the exit code of the last program it
+/// ran, or of the error it failed with, and how long it took.
+#[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LastCommand {
+    pub code: i64,
+    pub seconds: f64,
 }
 
 impl Default for Environment {
@@ -20,6 +202,9 @@ impl Environment {
         Self {
             bindings: BTreeMap::new(),
             parent: None,
+            options: Options::default(),
+            details: Box::default(),
+            exports: Exports::default(),
         }
     }
 
@@ -28,7 +213,7 @@ impl Environment {
         name: A,
         module: impl Into<BTreeMap<B, Expression>>,
     ) {
-        let mut result = BTreeMap::new();
+        let mut result = IndexMap::new();
         for (key, value) in module.into() {
             result.insert(key.to_string(), value);
         }
@@ -46,6 +231,26 @@ impl Environment {
         self.define(CWD_ENV_VAR, Expression::String(cwd.to_string()));
     }
 
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    pub fn options_mut(&mut self) -> &mut Options {
+        &mut self.options
+    }
+
+    pub fn set_options(&mut self, options: Options) {
+        self.options = options;
+    }
+
+    pub fn last_command(&self) -> Option<LastCommand> {
+        self.details.last_command
+    }
+
+    pub fn set_last_command(&mut self, last_command: Option<LastCommand>) {
+        self.details.last_command = last_command;
+    }
+
     pub fn get(&self, name: &str) -> Option<Expression> {
         match self.bindings.get(name) {
             Some(expr) => Some(expr.clone()),
@@ -67,10 +272,44 @@ impl Environment {
 
     pub fn undefine(&mut self, name: &str) {
         self.bindings.remove(name);
+        self.exports.invalidate(name);
     }
 
     pub fn define(&mut self, name: &str, expr: Expression) {
         self.bindings.insert(name.to_string(), expr);
+        self.exports.invalidate(name);
+    }
+
+    /// Define every name and value, like `define` does for one.
+    pub fn extend_bindings(&mut self, bindings: impl IntoIterator<Item = (String, Expression)>) {
+        for (name, expr) in bindings {
+            self.define(&name, expr);
+        }
+    }
+
+    /// The names bound in this scope, without the ones from the scopes around it.
+    pub fn bindings(&self) -> &BTreeMap<String, Expression> {
+        &self.bindings
+    }
+
+    /// The bindings as environment variables, for the programs the shell runs.
+    /// Only the bindings changed since the last call are formatted again.
+    pub fn exported(&mut self) -> Arc<BTreeMap<String, String>> {
+        self.exports.update(&self.bindings)
+    }
+
+    /// Define a constant, which can't be bound again with `let` or anything else.
+    pub fn define_constant(&mut self, name: &str, expr: Expression) {
+        self.define(name, expr);
+        self.details.constants.insert(name.to_string());
+    }
+
+    pub fn is_constant(&self, name: &str) -> bool {
+        self.details.constants.contains(name)
+            || match &self.parent {
+                Some(parent) => parent.is_constant(name),
+                None => false,
+            }
     }
 
     pub fn define_builtin(
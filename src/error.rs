@@ -1,9 +1,9 @@
 use common_macros::b_tree_map;
 use detached_str::{Str, StrSlice};
 
-use core::{cmp::max, fmt};
+use core::fmt;
 
-use crate::Diagnostic;
+use crate::{Diagnostic, TokenKind};
 
 use super::{Expression, Int, SyntaxError};
 
@@ -12,12 +12,15 @@ pub enum Error {
     CannotApply(Expression, Vec<Expression>),
     SymbolNotDefined(String),
     CommandFailed(String, Vec<Expression>),
+    CommandExited(String, Vec<Expression>, Int),
     ForNonList(Expression),
     RecursionDepth(Expression),
     PermissionDenied(Expression),
     ProgramNotFound(String),
+    ConstantRebound(String),
     SyntaxError(Str, SyntaxError),
     CustomError(String),
+    Interrupted,
 }
 
 impl Error {
@@ -31,9 +34,11 @@ impl Error {
     pub const ERROR_CODE_PROGRAM_NOT_FOUND: Int = 7;
     pub const ERROR_CODE_SYNTAX_ERROR: Int = 8;
     pub const ERROR_CODE_CUSTOM_ERROR: Int = 9;
+    pub const ERROR_CODE_COMMAND_EXITED: Int = 10;
+    pub const ERROR_CODE_INTERRUPTED: Int = 11;
 
     pub fn codes() -> Expression {
-        Expression::Map(b_tree_map! {
+        Expression::from(b_tree_map! {
             String::from("cannot-apply") => Expression::Integer(Self::ERROR_CODE_CANNOT_APPLY),
             String::from("symbol-not-defined") => Expression::Integer(Self::ERROR_CODE_SYMBOL_NOT_DEFINED),
             String::from("command-failed") => Expression::Integer(Self::ERROR_CODE_COMMAND_FAILED),
@@ -43,6 +48,8 @@ impl Error {
             String::from("program-not-found") => Expression::Integer(Self::ERROR_CODE_PROGRAM_NOT_FOUND),
             String::from("syntax-error") => Expression::Integer(Self::ERROR_CODE_SYNTAX_ERROR),
             String::from("custom-error") => Expression::Integer(Self::ERROR_CODE_CUSTOM_ERROR),
+            String::from("command-exited") => Expression::Integer(Self::ERROR_CODE_COMMAND_EXITED),
+            String::from("interrupted") => Expression::Integer(Self::ERROR_CODE_INTERRUPTED),
         })
     }
 
@@ -52,12 +59,15 @@ impl Error {
             Self::CannotApply(..) => Self::ERROR_CODE_CANNOT_APPLY,
             Self::SymbolNotDefined(..) => Self::ERROR_CODE_SYMBOL_NOT_DEFINED,
             Self::CommandFailed(..) => Self::ERROR_CODE_COMMAND_FAILED,
+            Self::CommandExited(..) => Self::ERROR_CODE_COMMAND_EXITED,
             Self::ForNonList(..) => Self::ERROR_CODE_FOR_NON_LIST,
             Self::RecursionDepth(..) => Self::ERROR_CODE_RECURSION_DEPTH,
             Self::CustomError(..) => Self::ERROR_CODE_CUSTOM_ERROR,
             Self::PermissionDenied(..) => Self::ERROR_CODE_CUSTOM_ERROR,
             Self::ProgramNotFound(..) => Self::ERROR_CODE_CUSTOM_ERROR,
+            Self::ConstantRebound(..) => Self::ERROR_CODE_CUSTOM_ERROR,
             Self::SyntaxError(..) => Self::ERROR_CODE_CUSTOM_ERROR,
+            Self::Interrupted => Self::ERROR_CODE_INTERRUPTED,
         }
     }
 }
@@ -77,6 +87,9 @@ impl fmt::Display for Error {
             Self::SymbolNotDefined(name) => {
                 write!(f, "symbol \"{}\" not defined", name)
             }
+            Self::ConstantRebound(name) => {
+                write!(f, "cannot bind `{}` again, because it's a constant", name)
+            }
             Self::RecursionDepth(expr) => {
                 write!(f, "recursion depth exceeded while evaluating {:?}", expr)
             }
@@ -87,17 +100,128 @@ impl fmt::Display for Error {
                     Expression::Apply(Box::new(Expression::Symbol(name.clone())), args.clone())
                 )
             }
+            Self::CommandExited(name, args, status) => {
+                write!(
+                    f,
+                    "command `{:?}` exited with status {}",
+                    Expression::Apply(Box::new(Expression::Symbol(name.clone())), args.clone()),
+                    status
+                )
+            }
             Self::ForNonList(nonlist) => {
                 write!(f, "attempted to iterate over non-list `{:?}`", nonlist)
             }
             Self::CustomError(e) => {
                 write!(f, "{}", e)
             }
+            Self::Interrupted => {
+                write!(f, "interrupted")
+            }
             Self::SyntaxError(string, err) => fmt_syntax_error(string, err, f),
         }
     }
 }
 
+/// A runtime error shown with the line of the script it came from.
+pub struct InSource<'a> {
+    error: &'a Error,
+    source: Str,
+    at: Vec<(StrSlice, Option<&'static str>)>,
+}
+
+impl Error {
+    /// Show the error with the code it came from, if the place in `source` can be found.
+    ///
+    /// Expressions don't remember where they were written, so a runtime error can
+    /// only be traced back by the name of the program or symbol it's about, which
+    /// is pointed at when it's written exactly once in `source`. A constant that's
+    /// bound again points at its `const` and at the binding after it, when each
+    /// is written once. Any other error, or a name used more than once, is shown
+    /// on its own, rather than pointing at a use that might not be the one that failed.
+    pub fn in_source(&self, source: &str) -> InSource<'_> {
+        let (tokens, _) = crate::tokenize(source);
+        let tokens = tokens
+            .into_iter()
+            .filter(|token| !matches!(token.kind, TokenKind::Whitespace | TokenKind::Comment))
+            .collect::<Vec<_>>();
+
+        let at = match self {
+            Self::ProgramNotFound(name)
+            | Self::SymbolNotDefined(name)
+            | Self::CommandFailed(name, _)
+            | Self::CommandExited(name, _, _) => only(tokens.iter().filter(|token| {
+                token.kind != TokenKind::StringLiteral && token.range.to_str(source) == name
+            }))
+            .map(|token| vec![(token.range, None)])
+            .unwrap_or_default(),
+            Self::ConstantRebound(name) => {
+                // Where the name is bound: after `let`, `local`, `const` or `for`,
+                // or as one of the names before the `in` of a for loop.
+                let bindings = tokens
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, token)| {
+                        token.kind == TokenKind::Symbol && token.range.to_str(source) == name
+                    })
+                    .filter_map(|(i, token)| {
+                        let previous = i.checked_sub(1).map(|i| tokens[i].range.to_str(source));
+                        let next = tokens.get(i + 1).map(|t| t.range.to_str(source));
+                        match (previous, next) {
+                            (Some("const"), _) => Some((true, token.range)),
+                            (Some("let" | "local" | "for"), _) | (_, Some("in")) => {
+                                Some((false, token.range))
+                            }
+                            _ => None,
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                let constant = only(bindings.iter().filter(|(constant, _)| *constant));
+                let again = constant.and_then(|&(_, constant)| {
+                    only(
+                        bindings
+                            .iter()
+                            .filter(|(_, at)| at.start() > constant.start()),
+                    )
+                });
+                match (constant, again) {
+                    (Some(&(_, constant)), Some(&(_, again))) => vec![
+                        (constant, Some("it's defined as a constant here")),
+                        (again, Some("so binding it here is an error")),
+                    ],
+                    _ => vec![],
+                }
+            }
+            _ => vec![],
+        };
+        InSource {
+            error: self,
+            source: Str::from(source),
+            at,
+        }
+    }
+}
+
+/// The only item, if there's exactly one.
+fn only<T>(mut items: impl Iterator<Item = T>) -> Option<T> {
+    match (items.next(), items.next()) {
+        (Some(item), None) => Some(item),
+        _ => None,
+    }
+}
+
+impl fmt::Display for InSource<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.at.is_empty() {
+            return write!(f, "{}", self.error);
+        }
+        writeln!(f, "{}{}error{}: {}", error_start(), BOLD, RESET, self.error)?;
+        for &(at, label) in &self.at {
+            print_error_lines(&self.source, at, label, &error_start(), f, 72)?;
+        }
+        Ok(())
+    }
+}
+
 fn fmt_syntax_error(string: &Str, err: &SyntaxError, f: &mut fmt::Formatter) -> fmt::Result {
     match err {
         SyntaxError::Expected {
@@ -106,17 +230,13 @@ fn fmt_syntax_error(string: &Str, err: &SyntaxError, f: &mut fmt::Formatter) ->
             found,
             hint,
         } => {
-            write!(f, "{}{}syntax error{}: ", RED_START, BOLD, RESET)?;
+            write!(f, "{}{}syntax error{}: ", error_start(), BOLD, RESET)?;
             write!(f, "expected {}", expected)?;
             if let Some(found) = found {
                 write!(f, ", found {}", found)?;
             }
             writeln!(f)?;
-            print_error_lines(string, *input, f, 72)?;
-            if let Some(hint) = *hint {
-                writeln!(f, "    hint: {}", hint)?;
-            }
-            Ok(())
+            print_error_lines(string, *input, *hint, &error_start(), f, 72)
         }
         SyntaxError::TokenizationErrors(errors) => {
             for err in errors.iter() {
@@ -125,18 +245,24 @@ fn fmt_syntax_error(string: &Str, err: &SyntaxError, f: &mut fmt::Formatter) ->
             Ok(())
         }
         SyntaxError::ExpectedChar { expected, at } => {
-            write!(f, "{}{}syntax error{}: ", RED_START, BOLD, RESET)?;
+            write!(f, "{}{}syntax error{}: ", error_start(), BOLD, RESET)?;
             writeln!(f, "expected {:?}", expected)?;
             if let Some(at) = *at {
-                print_error_lines(string, at, f, 72)?;
+                print_error_lines(string, at, None, &error_start(), f, 72)?;
             }
             Ok(())
         }
         SyntaxError::NomError { kind, at, cause } => {
-            write!(f, "{}{}unexpected syntax error{}: ", RED_START, BOLD, RESET)?;
+            write!(
+                f,
+                "{}{}unexpected syntax error{}: ",
+                error_start(),
+                BOLD,
+                RESET
+            )?;
             writeln!(f, "`{:?}`", kind)?;
             if let Some(at) = *at {
-                print_error_lines(string, at, f, 72)?;
+                print_error_lines(string, at, None, &error_start(), f, 72)?;
             }
             if let Some(cause) = cause {
                 fmt_syntax_error(string, cause, f)?;
@@ -144,7 +270,13 @@ fn fmt_syntax_error(string: &Str, err: &SyntaxError, f: &mut fmt::Formatter) ->
             Ok(())
         }
         SyntaxError::InternalError => {
-            writeln!(f, "{}{}unexpected syntax error{}", RED_START, BOLD, RESET)
+            writeln!(
+                f,
+                "{}{}unexpected syntax error{}",
+                error_start(),
+                BOLD,
+                RESET
+            )
         }
     }
 }
@@ -154,39 +286,62 @@ fn fmt_token_error(string: &Str, err: &Diagnostic, f: &mut fmt::Formatter) -> fm
         Diagnostic::Valid => Ok(()),
         Diagnostic::InvalidStringEscapes(ranges) => {
             for &at in ranges.iter() {
-                write!(f, "{}{}syntax error{}: ", RED_START, BOLD, RESET)?;
+                write!(f, "{}{}syntax error{}: ", error_start(), BOLD, RESET)?;
                 let escape = at.to_str(string).trim();
                 writeln!(f, "invalid string escape sequence `{}`", escape)?;
-                print_error_lines(string, at, f, 72)?;
+                print_error_lines(string, at, None, &error_start(), f, 72)?;
             }
             Ok(())
         }
         &Diagnostic::InvalidNumber(at) => {
-            write!(f, "{}{}syntax error{}: ", RED_START, BOLD, RESET)?;
+            write!(f, "{}{}syntax error{}: ", error_start(), BOLD, RESET)?;
             let num = at.to_str(string).trim();
             writeln!(f, "invalid number `{}`", num)?;
-            print_error_lines(string, at, f, 72)
+            print_error_lines(string, at, None, &error_start(), f, 72)
         }
         &Diagnostic::IllegalChar(at) => {
-            write!(f, "{}{}syntax error{}: ", RED_START, BOLD, RESET)?;
+            write!(f, "{}{}syntax error{}: ", error_start(), BOLD, RESET)?;
             writeln!(f, "invalid token {:?}", at.to_str(string))?;
-            print_error_lines(string, at, f, 72)
+            print_error_lines(string, at, None, &error_start(), f, 72)
         }
         &Diagnostic::NotTokenized(at) => {
-            write!(f, "{}{}error{}: ", RED_START, BOLD, RESET)?;
+            write!(f, "{}{}error{}: ", error_start(), BOLD, RESET)?;
             writeln!(
                 f,
                 "there are leftover tokens after tokenizing: {}",
                 at.to_str(string)
             )?;
-            print_error_lines(string, at, f, 72)
+            print_error_lines(string, at, None, &error_start(), f, 72)
         }
     }
 }
 
+pub(crate) fn fmt_warning(
+    string: &Str,
+    at: Option<StrSlice>,
+    message: &str,
+    label: Option<&str>,
+    f: &mut fmt::Formatter,
+) -> fmt::Result {
+    writeln!(
+        f,
+        "{}{}warning{}: {}",
+        warning_start(),
+        BOLD,
+        RESET,
+        message
+    )?;
+    match at {
+        Some(at) => print_error_lines(string, at, label, &warning_start(), f, 72),
+        None => Ok(()),
+    }
+}
+
 fn print_error_lines(
     string: &Str,
     at: StrSlice,
+    label: Option<&str>,
+    color: &str,
     f: &mut fmt::Formatter,
     max_width: usize,
 ) -> fmt::Result {
@@ -194,64 +349,75 @@ fn print_error_lines(
     if lines.is_empty() {
         lines.push("");
     }
-    let singleline = lines.len() == 1;
 
     let before = &string[..at.start()];
     let after = &string[at.end()..];
 
     let line_before = before.lines().next_back().unwrap_or_default();
     let line_after = after.lines().next().unwrap_or_default();
+    // If the error starts at the beginning of a line, `lines` doesn't count it yet.
+    let line_before = if before.ends_with('\n') {
+        ""
+    } else {
+        line_before
+    };
 
-    let first_line_number = max(before.lines().count(), 1);
+    let first_line_number = before.lines().count() + usize::from(line_before.is_empty());
+    let last_line_number = first_line_number + lines.len() - 1;
+    let gutter = last_line_number.to_string().len().max(5);
 
-    writeln!(f, "      |")?;
+    writeln!(f, "{:>gutter$} |", "", gutter = gutter)?;
 
-    if singleline {
-        let before_len = line_before.chars().take(max_width).count().min(max_width);
+    let before_len = line_before.chars().take(max_width).count();
+    for (i, line) in lines.iter().enumerate() {
+        let prefix = if i == 0 { line_before } else { "" };
+        let prefix_len = prefix.chars().take(max_width).count();
+        let line_len = line.chars().take(max_width - prefix_len).count();
+        let suffix = if i + 1 == lines.len() { line_after } else { "" };
 
-        let line = line_before
+        let text = prefix
             .chars()
             .take(max_width)
-            .chain(RED_START.chars())
-            .chain(lines[0].chars())
+            .chain(color.chars())
+            .chain(line.chars().take(line_len))
             .chain(RESET.chars())
-            .chain(line_after.chars().take(max_width - before_len))
+            .chain(suffix.chars().take(max_width - prefix_len - line_len))
             .collect::<String>();
+        writeln!(
+            f,
+            "{:>gutter$} | {}",
+            first_line_number + i,
+            text,
+            gutter = gutter
+        )?;
+    }
 
-        writeln!(f, "{:>5} | {}", first_line_number, line)?;
-    } else {
-        let first_line = line_before
-            .chars()
-            .chain(RED_START.chars())
-            .chain(lines[0].chars())
-            .take(max_width)
-            .chain(RESET.chars())
-            .collect::<String>();
-        write!(f, "{:>5} | {}", first_line_number, first_line)?;
-
-        for (i, line) in lines.iter().copied().enumerate().skip(1) {
-            let line = RED_START
-                .chars()
-                .chain(line.chars().take(max_width))
-                .chain(RESET.chars())
-                .collect::<String>();
-            write!(f, "\n{:>5} | {}", first_line_number + i, line)?;
-        }
-
-        let last_len = lines.last().unwrap().chars().count();
-        let suffix = line_after
-            .chars()
-            .take(max_width - last_len)
-            .chain(RESET.chars())
-            .collect::<String>();
-        writeln!(f, "\n{}", suffix)?;
+    // Underline the start of the error, with the hint next to it.
+    let first_len = lines[0].chars().take(max_width - before_len).count().max(1);
+    write!(
+        f,
+        "{:>gutter$} | {}{}{}{}",
+        "",
+        " ".repeat(before_len),
+        color,
+        "^".repeat(first_len),
+        RESET,
+        gutter = gutter
+    )?;
+    if let Some(label) = label {
+        write!(f, " {}{}{}", BOLD, label, RESET)?;
     }
+    writeln!(f)
+}
 
-    writeln!(f, "      |")?;
+/// The start of the theme's colors for errors and warnings.
+fn error_start() -> String {
+    crate::theme().error.start()
+}
 
-    Ok(())
+fn warning_start() -> String {
+    crate::theme().warning.start()
 }
 
-const RED_START: &str = "\x1b[38;5;9m";
 const BOLD: &str = "\x1b[1m";
 const RESET: &str = "\x1b[m\x1b[0m";
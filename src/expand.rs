@@ -0,0 +1,303 @@
+use crate::{Environment, Expression};
+
+/// How many macros deep to expand, so a macro that calls itself stops somewhere.
+const MAX_DEPTH: usize = 16;
+
+/// Apply the macros in an expression without running anything, to show what
+/// it runs as. Operators are already applications of the functions they call
+/// once an expression is parsed, so with the macros applied, what's left is
+/// what Dune evaluates.
+///
+/// A macro binds its parameter in the scope it's called from and then runs
+/// its body there, so `m x`, where `m` is `p ~> body`, expands to
+/// `{ let p = x; body }`. Names bound by a function, a loop, or a `let` are
+/// never taken for the macros they shadow.
+pub fn expand_macros(expr: &Expression, env: &Environment) -> Expression {
+    Expand { env }.expand(expr, &mut vec![], 0)
+}
+
+struct Expand<'a> {
+    env: &'a Environment,
+}
+
+impl Expand<'_> {
+    fn expand(&self, expr: &Expression, bound: &mut Vec<String>, depth: usize) -> Expression {
+        match expr {
+            Expression::Apply(f, args) => match self.apply(f, args, bound) {
+                Some(expanded) if depth < MAX_DEPTH => self.expand(&expanded, bound, depth + 1),
+                _ => Expression::Apply(
+                    self.boxed(f, bound, depth),
+                    args.iter()
+                        .map(|arg| *self.boxed(arg, bound, depth))
+                        .collect(),
+                ),
+            },
+            Expression::Do(exprs) => {
+                // What a block binds with `let` shadows macros for the rest of it.
+                let len = bound.len();
+                let exprs = exprs
+                    .iter()
+                    .map(|expr| {
+                        let result = self.expand(expr, bound, depth);
+                        if let Expression::Assign(name, _)
+                        | Expression::Local(name, _)
+                        | Expression::Const(name, _) = expr
+                        {
+                            bound.push(name.clone());
+                        }
+                        result
+                    })
+                    .collect();
+                bound.truncate(len);
+                Expression::Do(exprs)
+            }
+            Expression::Lambda(param, body, captured) => Expression::Lambda(
+                param.clone(),
+                self.within(std::slice::from_ref(param), body, bound, depth),
+                captured.clone(),
+            ),
+            Expression::Macro(param, body) => Expression::Macro(
+                param.clone(),
+                self.within(std::slice::from_ref(param), body, bound, depth),
+            ),
+            Expression::For(names, list, body) => Expression::For(
+                names.clone(),
+                self.boxed(list, bound, depth),
+                self.within(names, body, bound, depth),
+            ),
+            Expression::If(cond, then, otherwise) => {
+                let cond = self.boxed(cond, bound, depth);
+                let then = self.boxed(then, bound, depth);
+                Expression::If(cond, then, self.boxed(otherwise, bound, depth))
+            }
+            Expression::Group(inner) => Expression::Group(self.boxed(inner, bound, depth)),
+            Expression::Yield(inner) => Expression::Yield(self.boxed(inner, bound, depth)),
+            Expression::Assign(name, value) => {
+                Expression::Assign(name.clone(), self.boxed(value, bound, depth))
+            }
+            Expression::Local(name, value) => {
+                Expression::Local(name.clone(), self.boxed(value, bound, depth))
+            }
+            Expression::Const(name, value) => {
+                Expression::Const(name.clone(), self.boxed(value, bound, depth))
+            }
+            Expression::List(items) => Expression::List(
+                items
+                    .iter()
+                    .map(|item| *self.boxed(item, bound, depth))
+                    .collect(),
+            ),
+            Expression::Map(map) => Expression::Map(
+                map.iter()
+                    .map(|(key, value)| (key.clone(), *self.boxed(value, bound, depth)))
+                    .collect(),
+            ),
+            // Quoted code is a value, which isn't run where it's written.
+            expr => expr.clone(),
+        }
+    }
+
+    fn boxed(&self, expr: &Expression, bound: &mut Vec<String>, depth: usize) -> Box<Expression> {
+        Box::new(self.expand(expr, bound, depth))
+    }
+
+    /// Expand an expression where some names are bound, like the body of a function.
+    fn within(
+        &self,
+        names: &[String],
+        expr: &Expression,
+        bound: &mut Vec<String>,
+        depth: usize,
+    ) -> Box<Expression> {
+        let len = bound.len();
+        bound.extend(names.iter().cloned());
+        let result = self.boxed(expr, bound, depth);
+        bound.truncate(len);
+        result
+    }
+
+    /// Apply a macro to the first argument of an application, if it's called on any.
+    fn apply(&self, f: &Expression, args: &[Expression], bound: &[String]) -> Option<Expression> {
+        let (mut exprs, param, body) = self.macro_head(f, bound)?;
+        let (arg, rest) = args.split_first()?;
+        exprs.push(Expression::Assign(param, Box::new(arg.clone())));
+        exprs.push(body);
+        let block = Expression::Group(Box::new(Expression::Do(exprs)));
+        Some(match rest {
+            [] => block,
+            rest => Expression::Apply(Box::new(block), rest.to_vec()),
+        })
+    }
+
+    /// The macro an expression evaluates to, if it's one, with the statements
+    /// run before it. A block ending in a macro is what applying a macro that
+    /// takes more than one argument leaves, like `a ~> b ~> ...`.
+    fn macro_head(
+        &self,
+        f: &Expression,
+        bound: &[String],
+    ) -> Option<(Vec<Expression>, String, Expression)> {
+        match f {
+            Expression::Symbol(name) if !bound.contains(name) => match self.env.get(name)? {
+                Expression::Macro(param, body) => Some((vec![], param, *body)),
+                _ => None,
+            },
+            Expression::Macro(param, body) => Some((vec![], param.clone(), *body.clone())),
+            Expression::Group(inner) => self.macro_head(inner, bound),
+            Expression::Do(exprs) => {
+                let (last, before) = exprs.split_last()?;
+                let (mut exprs, param, body) = self.macro_head(last, bound)?;
+                exprs.splice(0..0, before.iter().cloned());
+                Some((exprs, param, body))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Write an expression as source, with blocks on lines of their own and
+/// every application in prefix form, like `(+ 1 (* 2 3))` for `1 + 2 * 3`.
+/// A block is written as the statements of a script.
+pub fn to_source(expr: &Expression) -> String {
+    let mut out = String::new();
+    match expr {
+        Expression::Do(exprs) if !exprs.is_empty() => {
+            for (i, expr) in exprs.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(";\n");
+                }
+                write_source(expr, 0, &mut out);
+            }
+        }
+        expr => write_source(expr, 0, &mut out),
+    }
+    out
+}
+
+fn write_source(expr: &Expression, depth: usize, out: &mut String) {
+    let indent = "    ".repeat(depth);
+    match expr {
+        Expression::Group(inner) => match inner.as_ref() {
+            // Parentheses around parentheses, or a block, don't change anything.
+            Expression::Group(_) | Expression::Do(_) => write_source(inner, depth, out),
+            inner => {
+                out.push('(');
+                write_source(inner, depth, out);
+                out.push(')');
+            }
+        },
+        Expression::Apply(f, args) => {
+            write_part(f, depth, out);
+            for arg in args {
+                out.push(' ');
+                write_part(arg, depth, out);
+            }
+        }
+        Expression::Do(exprs) if exprs.is_empty() => out.push_str("{}"),
+        Expression::Do(exprs) => {
+            out.push_str("{\n");
+            for (i, expr) in exprs.iter().enumerate() {
+                out.push_str(&indent);
+                out.push_str("    ");
+                write_source(expr, depth + 1, out);
+                if i + 1 < exprs.len() {
+                    out.push(';');
+                }
+                out.push('\n');
+            }
+            out.push_str(&indent);
+            out.push('}');
+        }
+        Expression::If(cond, then, otherwise) => {
+            out.push_str("if ");
+            write_part(cond, depth, out);
+            out.push(' ');
+            write_part(then, depth, out);
+            if **otherwise != Expression::None {
+                out.push_str(" else ");
+                write_part(otherwise, depth, out);
+            }
+        }
+        Expression::For(names, list, body) => {
+            out.push_str(&format!("for {} in ", names.join(" ")));
+            write_part(list, depth, out);
+            out.push(' ');
+            write_part(body, depth, out);
+        }
+        Expression::Lambda(param, body, _) | Expression::Macro(param, body) => {
+            let arrow = if matches!(expr, Expression::Lambda(..)) {
+                "->"
+            } else {
+                "~>"
+            };
+            out.push_str(&format!("{} {} ", param, arrow));
+            write_source(body, depth, out);
+        }
+        Expression::Assign(name, value)
+        | Expression::Local(name, value)
+        | Expression::Const(name, value) => {
+            let keyword = match expr {
+                Expression::Assign(..) => "let",
+                Expression::Local(..) => "local",
+                _ => "const",
+            };
+            out.push_str(&format!("{} {} = ", keyword, name));
+            write_source(value, depth, out);
+        }
+        Expression::Yield(inner) => {
+            out.push_str("yield ");
+            write_part(inner, depth, out);
+        }
+        Expression::Quote(inner) => {
+            out.push('\'');
+            write_part(inner, depth, out);
+        }
+        Expression::List(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                write_part(item, depth, out);
+            }
+            out.push(']');
+        }
+        Expression::Map(map) => {
+            out.push('{');
+            for (i, (key, value)) in map.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                out.push_str(&Expression::repr_key(key));
+                out.push('=');
+                write_part(value, depth, out);
+            }
+            out.push('}');
+        }
+        expr => match expr.repr() {
+            Ok(text) => out.push_str(&text),
+            Err(_) => out.push_str(&format!("{:?}", expr)),
+        },
+    }
+}
+
+/// Write part of a larger expression, in parentheses if it would run into
+/// what's around it otherwise.
+fn write_part(expr: &Expression, depth: usize, out: &mut String) {
+    match expr {
+        Expression::Apply(..)
+        | Expression::Lambda(..)
+        | Expression::Macro(..)
+        | Expression::Assign(..)
+        | Expression::Local(..)
+        | Expression::Const(..)
+        | Expression::If(..)
+        | Expression::For(..)
+        | Expression::Yield(..) => {
+            out.push('(');
+            write_source(expr, depth, out);
+            out.push(')');
+        }
+        expr => write_source(expr, depth, out),
+    }
+}
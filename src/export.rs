@@ -0,0 +1,421 @@
+use std::collections::BTreeMap;
+
+use crate::{Error, Expression};
+
+/// Translate a script into a POSIX sh script, for systems where Dune isn't
+/// installed.
+///
+/// Only part of Dune has a translation: running programs, pipes and
+/// redirections, variables holding strings, numbers, and booleans, integer
+/// arithmetic, `if` with comparisons, and `for` over a list. Anything else,
+/// like functions or the standard library, is an error naming what couldn't
+/// be translated.
+///
+/// Names in capitals that the script doesn't define are taken to be
+/// environment variables, like they are when Dune runs it.
+pub fn export_sh(script: &Expression) -> Result<String, Error> {
+    let mut export = Export::default();
+    let mut out = String::from("#!/bin/sh\n");
+    export.statement(script, 0, &mut out)?;
+    Ok(out)
+}
+
+#[derive(Default)]
+struct Export {
+    /// The variables the script has defined so far, and whether each holds a boolean.
+    variables: BTreeMap<String, bool>,
+}
+
+impl Export {
+    fn statement(
+        &mut self,
+        expr: &Expression,
+        depth: usize,
+        out: &mut String,
+    ) -> Result<(), Error> {
+        let indent = "    ".repeat(depth);
+        match expr {
+            Expression::Do(exprs) => {
+                for expr in exprs {
+                    self.statement(expr, depth, out)?;
+                }
+            }
+            Expression::Group(inner) => self.statement(inner, depth, out)?,
+            Expression::None => {}
+            // An empty block, like in `else {}`.
+            Expression::Map(map) if map.is_empty() => {}
+
+            Expression::Assign(name, value) | Expression::Local(name, value) => {
+                let value = self.value(value)?;
+                let is_boolean = value == "true" || value == "false";
+                out.push_str(&format!("{}{}={}\n", indent, variable(name)?, value));
+                self.variables.insert(name.clone(), is_boolean);
+            }
+            Expression::Const(name, value) => {
+                let value = self.value(value)?;
+                let is_boolean = value == "true" || value == "false";
+                out.push_str(&format!(
+                    "{}readonly {}={}\n",
+                    indent,
+                    variable(name)?,
+                    value
+                ));
+                self.variables.insert(name.clone(), is_boolean);
+            }
+
+            Expression::If(..) => {
+                let mut keyword = "if";
+                let mut expr = expr;
+                while let Expression::If(cond, then, otherwise) = expr {
+                    out.push_str(&format!(
+                        "{}{} {}; then\n",
+                        indent,
+                        keyword,
+                        self.condition(cond)?
+                    ));
+                    self.block(then, depth + 1, out)?;
+                    keyword = "elif";
+                    expr = otherwise;
+                    if let Expression::Group(inner) = expr {
+                        expr = inner;
+                    }
+                }
+                if !matches!(expr, Expression::None) {
+                    out.push_str(&format!("{}else\n", indent));
+                    self.block(expr, depth + 1, out)?;
+                }
+                out.push_str(&format!("{}fi\n", indent));
+            }
+
+            Expression::For(names, list, body) => {
+                let name = match names.as_slice() {
+                    [name] => name,
+                    _ => {
+                        return Err(Error::CustomError(format!(
+                            "cannot export `for {} in ...` to sh, which can only loop with one variable",
+                            names.join(" ")
+                        )))
+                    }
+                };
+                let items = match list.as_ref() {
+                    Expression::List(items) => items
+                        .iter()
+                        .map(|item| self.value(item))
+                        .collect::<Result<Vec<_>, _>>()?,
+                    _ => {
+                        return Err(Error::CustomError(format!(
+                            "cannot export a loop over {:?} to sh, only over a list like [a, b, c]",
+                            list
+                        )))
+                    }
+                };
+                out.push_str(&format!(
+                    "{}for {} in {}; do\n",
+                    indent,
+                    variable(name)?,
+                    items.join(" ")
+                ));
+                self.variables.insert(name.clone(), false);
+                self.block(body, depth + 1, out)?;
+                out.push_str(&format!("{}done\n", indent));
+            }
+
+            _ => out.push_str(&format!("{}{}\n", indent, self.command(expr)?)),
+        }
+        Ok(())
+    }
+
+    /// The body of an `if` or `for`, which sh doesn't allow to be empty.
+    fn block(&mut self, expr: &Expression, depth: usize, out: &mut String) -> Result<(), Error> {
+        let start = out.len();
+        self.statement(expr, depth, out)?;
+        if out.len() == start {
+            out.push_str(&format!("{}:\n", "    ".repeat(depth)));
+        }
+        Ok(())
+    }
+
+    /// A program to run, with its arguments, or a pipe of them.
+    fn command(&mut self, expr: &Expression) -> Result<String, Error> {
+        match expr {
+            Expression::Group(inner) => self.command(inner),
+            Expression::Apply(f, args) => match (f.as_ref(), args.as_slice()) {
+                (Expression::Symbol(op), _) if op == "|" => self.pipe(args),
+                (Expression::Symbol(op), _) if op == "&&" || op == "||" => Ok(args
+                    .iter()
+                    .map(|arg| self.condition(arg))
+                    .collect::<Result<Vec<_>, _>>()?
+                    .join(&format!(" {} ", op))),
+                (Expression::Symbol(op), [arg]) if op == "!" => {
+                    Ok(format!("! {}", self.condition(arg)?))
+                }
+                (Expression::Symbol(name), _) | (Expression::String(name), _) => {
+                    let mut words = vec![program(name, &self.variables)?];
+                    for arg in args {
+                        if !matches!(arg, Expression::None) {
+                            words.push(self.value(arg)?);
+                        }
+                    }
+                    Ok(words.join(" "))
+                }
+                (Expression::Quote(program), _) => {
+                    self.command(&Expression::Apply(program.clone(), args.clone()))
+                }
+                _ => Err(cannot_export(expr)),
+            },
+            Expression::Symbol(name) | Expression::String(name) => program(name, &self.variables),
+            Expression::Quote(inner) => self.command(inner),
+            _ => Err(cannot_export(expr)),
+        }
+    }
+
+    /// Programs piped together, reading from a file with `<<` and writing to
+    /// one with `>>` or `>>>`.
+    fn pipe(&mut self, parts: &[Expression]) -> Result<String, Error> {
+        let mut commands: Vec<String> = vec![];
+        let mut input = None;
+        for (i, part) in parts.iter().enumerate() {
+            let redirect = match part {
+                Expression::Apply(f, args) if args.len() == 1 => match f.as_ref() {
+                    Expression::Symbol(op) if [">>", ">>>", "<<"].contains(&op.as_str()) => {
+                        Some((op.as_str(), &args[0]))
+                    }
+                    _ => None,
+                },
+                _ => None,
+            };
+            match redirect {
+                Some(("<<", file)) => input = Some(self.value(file)?),
+                Some((op, file)) => {
+                    let command = match commands.pop() {
+                        Some(command) if i + 1 == parts.len() => command,
+                        _ => {
+                            return Err(Error::CustomError(format!(
+                            "cannot export {:?} to sh, only the end of a pipe can write to a file",
+                            part
+                        )))
+                        }
+                    };
+                    let op = if op == ">>" { ">" } else { ">>" };
+                    commands.push(format!("{} {} {}", command, op, self.value(file)?));
+                }
+                None => {
+                    let mut command = self.command(part)?;
+                    if let Some(file) = input.take() {
+                        command = format!("{} < {}", command, file);
+                    }
+                    commands.push(command);
+                }
+            }
+        }
+        Ok(commands.join(" | "))
+    }
+
+    /// The condition of an `if`, as a command that succeeds when it's true.
+    fn condition(&mut self, expr: &Expression) -> Result<String, Error> {
+        match expr {
+            Expression::Group(inner) => self.condition(inner),
+            Expression::Boolean(b) => Ok(b.to_string()),
+            Expression::Symbol(name) if self.variables.get(name) == Some(&true) => {
+                Ok(format!("\"${}\"", variable(name)?))
+            }
+            Expression::Symbol(name) if self.variables.contains_key(name) => {
+                Err(Error::CustomError(format!(
+                    "cannot export `if {}` to sh, compare it with something instead, like `if {} != \"\"`",
+                    name, name
+                )))
+            }
+            Expression::Apply(f, args) => match (f.as_ref(), args.as_slice()) {
+                (Expression::Symbol(op), [a, b])
+                    if ["==", "!=", "<", "<=", ">", ">="].contains(&op.as_str()) =>
+                {
+                    let numeric = is_numeric(a) || is_numeric(b);
+                    let test = match (op.as_str(), numeric) {
+                        ("==", false) => "=",
+                        ("!=", false) => "!=",
+                        ("==", true) => "-eq",
+                        ("!=", true) => "-ne",
+                        ("<", true) => "-lt",
+                        ("<=", true) => "-le",
+                        (">", true) => "-gt",
+                        (">=", true) => "-ge",
+                        _ => {
+                            return Err(Error::CustomError(format!(
+                                "cannot export `{:?}` to sh, which can only order numbers",
+                                expr
+                            )))
+                        }
+                    };
+                    Ok(format!("[ {} {} {} ]", self.value(a)?, test, self.value(b)?))
+                }
+                _ => self.command(expr),
+            },
+            _ => self.command(expr),
+        }
+    }
+
+    /// A value as one shell word, like the value of a variable or an
+    /// argument to a program.
+    fn value(&mut self, expr: &Expression) -> Result<String, Error> {
+        match expr {
+            Expression::Group(inner) => self.value(inner),
+            Expression::String(text) => Ok(quote(text)),
+            Expression::Integer(n) => Ok(n.to_string()),
+            Expression::Float(n) => Ok(n.to_string()),
+            Expression::Boolean(b) => Ok(b.to_string()),
+            Expression::Symbol(name) if is_variable(name, &self.variables) => {
+                Ok(format!("\"${}\"", variable(name)?))
+            }
+            Expression::Symbol(name) => Ok(quote(name)),
+            Expression::Apply(f, args) => match (f.as_ref(), args.as_slice()) {
+                (Expression::Symbol(op), [a, b]) if op == "+" && (is_text(a) || is_text(b)) => {
+                    Ok(self.value(a)? + &self.value(b)?)
+                }
+                (Expression::Symbol(op), [_, _]) if is_arithmetic(op) => {
+                    Ok(format!("$(({}))", self.arithmetic(expr)?))
+                }
+                _ => Err(cannot_export(expr)),
+            },
+            _ => Err(cannot_export(expr)),
+        }
+    }
+
+    /// Integer arithmetic, inside sh's `$((...))`.
+    fn arithmetic(&mut self, expr: &Expression) -> Result<String, Error> {
+        match expr {
+            Expression::Group(inner) => self.arithmetic(inner),
+            Expression::Integer(n) => Ok(n.to_string()),
+            Expression::Symbol(name) if is_variable(name, &self.variables) => variable(name),
+            Expression::Apply(f, args) => match (f.as_ref(), args.as_slice()) {
+                (Expression::Symbol(op), [a, b]) if is_arithmetic(op) => {
+                    let op = if op == "//" { "/" } else { op };
+                    let operand = |export: &mut Self, expr: &Expression| {
+                        let text = export.arithmetic(expr)?;
+                        Ok::<_, Error>(match expr {
+                            Expression::Integer(_) | Expression::Symbol(_) => text,
+                            _ => format!("({})", text),
+                        })
+                    };
+                    Ok(format!(
+                        "{} {} {}",
+                        operand(self, a)?,
+                        op,
+                        operand(self, b)?
+                    ))
+                }
+                _ => Err(cannot_export(expr)),
+            },
+            _ => Err(cannot_export(expr)),
+        }
+    }
+}
+
+fn cannot_export(expr: &Expression) -> Error {
+    Error::CustomError(match (expr, library_name(expr)) {
+        (_, Some(name)) => format!(
+            "cannot export `{}` to sh, which doesn't have Dune's standard library",
+            name
+        ),
+        (Expression::Lambda(..) | Expression::Macro(..), _) => {
+            format!("cannot export the function `{:?}` to sh", expr)
+        }
+        _ => format!("cannot export `{:?}` to sh", expr),
+    })
+}
+
+/// The name of a function from the standard library, like `fs@read`, that
+/// an expression calls.
+fn library_name(expr: &Expression) -> Option<String> {
+    match expr {
+        Expression::Apply(f, args) => match f.as_ref() {
+            Expression::Symbol(op) if op == "@" => Some(
+                args.iter()
+                    .map(|arg| format!("{:?}", arg))
+                    .collect::<Vec<_>>()
+                    .join("@"),
+            ),
+            f => library_name(f),
+        },
+        _ => None,
+    }
+}
+
+fn is_arithmetic(op: &str) -> bool {
+    ["+", "-", "*", "//", "%"].contains(&op)
+}
+
+/// Whether a value is a number, so comparing it needs sh's numeric tests.
+fn is_numeric(expr: &Expression) -> bool {
+    match expr {
+        Expression::Group(inner) => is_numeric(inner),
+        Expression::Integer(_) => true,
+        Expression::Apply(f, args) => {
+            matches!(f.as_ref(), Expression::Symbol(op) if is_arithmetic(op))
+                && !args.iter().any(is_text)
+        }
+        _ => false,
+    }
+}
+
+/// Whether a value is text, so `+` joins it instead of adding it.
+fn is_text(expr: &Expression) -> bool {
+    match expr {
+        Expression::Group(inner) => is_text(inner),
+        Expression::String(_) => true,
+        Expression::Apply(f, args) => {
+            matches!(f.as_ref(), Expression::Symbol(op) if op == "+") && args.iter().any(is_text)
+        }
+        _ => false,
+    }
+}
+
+/// Whether a name refers to a variable, rather than being a word of its own.
+fn is_variable(name: &str, variables: &BTreeMap<String, bool>) -> bool {
+    variables.contains_key(name)
+        || (name.chars().any(|c| c.is_ascii_uppercase())
+            && name
+                .chars()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_'))
+}
+
+/// The name of a variable in sh, which can't have dashes in it.
+fn variable(name: &str) -> Result<String, Error> {
+    if name == "CWD" {
+        return Ok(String::from("PWD"));
+    }
+    let name = name.replace('-', "_");
+    let mut chars = name.chars();
+    if chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        Ok(name)
+    } else {
+        Err(Error::CustomError(format!(
+            "cannot export the variable `{}` to sh, which only allows letters, digits, and underscores in names",
+            name
+        )))
+    }
+}
+
+/// The name of a program to run, which can't be a variable.
+fn program(name: &str, variables: &BTreeMap<String, bool>) -> Result<String, Error> {
+    if variables.contains_key(name) {
+        return Err(Error::CustomError(format!(
+            "cannot export a call to the variable `{}` to sh, only running programs",
+            name
+        )));
+    }
+    Ok(quote(name))
+}
+
+/// Quote a word for sh, if it has anything sh would treat specially.
+fn quote(text: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "_-+=/.,:@%~".contains(c);
+    if !text.is_empty() && text.chars().all(plain) {
+        text.to_string()
+    } else {
+        format!("'{}'", text.replace('\'', "'\\''"))
+    }
+}
@@ -1,14 +1,24 @@
-use super::{Environment, Error, Int};
+use super::{
+    console_width, find_program, is_canceled, redact, run_foreground, status_code, tokenize,
+    was_interrupted, Diagnostic, Environment, Error, Int, TokenKind,
+};
 use std::{
+    cell::RefCell,
     cmp::Ordering,
-    collections::BTreeMap,
+    collections::{BTreeMap, BTreeSet},
     fmt,
     io::ErrorKind,
     ops::{Add, Div, Index, Mul, Neg, Rem, Sub},
+    path::{Path, PathBuf},
     process::Command,
+    sync::{
+        mpsc::{channel, sync_channel, Receiver, Sender, SyncSender},
+        Arc, Mutex,
+    },
+    thread,
 };
 
-use terminal_size::{terminal_size, Width};
+use indexmap::IndexMap;
 
 use prettytable::{
     format::{LinePosition, LineSeparator},
@@ -21,6 +31,21 @@ use prettytable::{
 /// we don't really want to do this because it's better to halt.
 const MAX_RECURSION_DEPTH: Option<usize> = Some(800);
 
+/// The stack size of the thread that runs a generator's body. This matches
+/// the main thread's default so generators can recurse as deeply as any other code.
+const GENERATOR_STACK_SIZE: usize = 8 * 1024 * 1024;
+
+/// The channels a `yield` uses to hand a value to its consumer, and to wait to be resumed.
+type YieldChannel = (SyncSender<Result<Expression, Error>>, Receiver<()>);
+
+thread_local! {
+    /// This is only set on threads running the body of a generator.
+    static YIELD_CHANNEL: RefCell<Option<YieldChannel>> = const { RefCell::new(None) };
+    /// Is a map's `__display` or `__debug` function running? Maps shown while it
+    /// runs are shown the usual way, so it can't call itself forever.
+    static IN_HOOK: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
 impl From<Int> for Expression {
     fn from(x: Int) -> Self {
         Self::Integer(x)
@@ -65,7 +90,20 @@ where
         Self::Map(
             map.into_iter()
                 .map(|(name, item)| (name, item.into()))
-                .collect::<BTreeMap<String, Self>>(),
+                .collect::<IndexMap<String, Self>>(),
+        )
+    }
+}
+
+impl<T> From<IndexMap<String, T>> for Expression
+where
+    T: Into<Self>,
+{
+    fn from(map: IndexMap<String, T>) -> Self {
+        Self::Map(
+            map.into_iter()
+                .map(|(name, item)| (name, item.into()))
+                .collect::<IndexMap<String, Self>>(),
         )
     }
 }
@@ -85,11 +123,17 @@ where
 
 impl From<Environment> for Expression {
     fn from(env: Environment) -> Self {
-        Self::Map(env.bindings.into_iter().collect::<BTreeMap<String, Self>>())
+        Self::Map(
+            env.bindings()
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect::<IndexMap<String, Self>>(),
+        )
     }
 }
 
 #[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Expression {
     Group(Box<Self>),
 
@@ -106,15 +150,19 @@ pub enum Expression {
     Boolean(bool),
     // A list of expressions
     List(Vec<Self>),
-    // A map of expressions
-    Map(BTreeMap<String, Self>),
+    // A map of expressions, in the order their keys were added
+    Map(IndexMap<String, Self>),
     None,
 
     // Assign an expression to a variable
     Assign(String, Box<Self>),
+    // Assign an expression to a variable until the end of the block it's in
+    Local(String, Box<Self>),
+    // Assign an expression to a variable that can't be bound again
+    Const(String, Box<Self>),
 
-    // Control flow
-    For(String, Box<Self>, Box<Self>),
+    // Control flow, binding each item to a name, or unpacking it into several names
+    For(Vec<String>, Box<Self>, Box<Self>),
 
     // Control flow
     If(Box<Self>, Box<Self>, Box<Self>),
@@ -129,6 +177,11 @@ pub enum Expression {
     Builtin(Builtin),
 
     Quote(Box<Self>),
+
+    // Hand a value to the consumer of the surrounding generator
+    Yield(Box<Self>),
+    // A resumable iterator produced by applying a lambda that yields
+    Generator(Generator),
 }
 
 #[derive(Clone)]
@@ -159,6 +212,194 @@ impl PartialEq for Builtin {
     }
 }
 
+#[cfg(feature = "serde")]
+lazy_static::lazy_static! {
+    /// Every builtin made so far, by name, to restore deserialized builtins from.
+    static ref BUILTINS: Mutex<BTreeMap<String, Builtin>> = Mutex::new(BTreeMap::new());
+}
+
+/// Builtins are serialized by name. Their function pointers can't be written
+/// down, so a builtin is deserialized as the one of the same name made by
+/// `Expression::builtin` in this program, which is an error if there isn't one.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Builtin {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.name)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Builtin {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        BUILTINS.lock().unwrap().get(&name).cloned().ok_or_else(|| {
+            serde::de::Error::custom(format!(
+                "cannot deserialize builtin `{}`, which this program doesn't have",
+                name
+            ))
+        })
+    }
+}
+
+enum GeneratorState {
+    /// The generator has not been resumed yet, so its body hasn't started running.
+    Ready(Box<Expression>, Environment),
+    /// The body is running on its own thread, waiting to be resumed.
+    Running {
+        resume: Sender<()>,
+        values: Receiver<Result<Expression, Error>>,
+    },
+    /// The body is running until it yields its next value.
+    Resumed,
+    /// The body has finished, or failed.
+    Done,
+}
+
+/// A generator is created when a lambda whose body contains `yield` is applied.
+///
+/// The body runs on a separate thread, which is paused at every `yield` until
+/// the consumer asks for the next value. This way, values are produced one at
+/// a time instead of being buffered into a list.
+#[derive(Clone)]
+pub struct Generator {
+    state: Arc<Mutex<GeneratorState>>,
+}
+
+impl Generator {
+    fn new(body: Expression, env: Environment) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(GeneratorState::Ready(Box::new(body), env))),
+        }
+    }
+
+    /// Resume the generator, and get the next value it yields.
+    /// This returns `Ok(None)` once the generator's body has finished.
+    ///
+    /// The lock is only held while the state is taken out and put back, so the
+    /// generator's body or another thread can't block on it while the body runs.
+    /// Resuming a generator while it's producing a value is an error.
+    pub fn next(&self) -> Result<Option<Expression>, Error> {
+        let state = std::mem::replace(&mut *self.state.lock().unwrap(), GeneratorState::Resumed);
+
+        let (resume, values) = match state {
+            GeneratorState::Ready(body, env) => match Self::start(*body, env) {
+                Ok(GeneratorState::Running { resume, values }) => (resume, values),
+                result => {
+                    *self.state.lock().unwrap() = GeneratorState::Done;
+                    return result.map(|_| None);
+                }
+            },
+            GeneratorState::Running { resume, values } => (resume, values),
+            GeneratorState::Resumed => {
+                *self.state.lock().unwrap() = GeneratorState::Resumed;
+                return Err(Error::CustomError(
+                    "generator is already running".to_string(),
+                ));
+            }
+            GeneratorState::Done => {
+                *self.state.lock().unwrap() = GeneratorState::Done;
+                return Ok(None);
+            }
+        };
+
+        let result = if resume.send(()).is_err() {
+            Ok(None)
+        } else {
+            match values.recv() {
+                Ok(Ok(value)) => {
+                    *self.state.lock().unwrap() = GeneratorState::Running { resume, values };
+                    return Ok(Some(value));
+                }
+                Ok(Err(e)) => Err(e),
+                // The body finished without yielding another value.
+                Err(_) => Ok(None),
+            }
+        };
+
+        *self.state.lock().unwrap() = GeneratorState::Done;
+        result
+    }
+
+    /// Spawn the thread that runs the body of the generator.
+    fn start(body: Expression, mut env: Environment) -> Result<GeneratorState, Error> {
+        let (resume_sender, resume) = channel();
+        let (values, value_receiver) = sync_channel(0);
+
+        thread::Builder::new()
+            .name(String::from("generator"))
+            .stack_size(GENERATOR_STACK_SIZE)
+            .spawn(move || {
+                // Don't run any of the body until the consumer asks for the first value.
+                if resume.recv().is_err() {
+                    return;
+                }
+
+                YIELD_CHANNEL.with(|channel| *channel.borrow_mut() = Some((values, resume)));
+                let result = body.eval_mut(&mut env, 0);
+
+                if let Some((values, _)) = YIELD_CHANNEL.with(|channel| channel.borrow_mut().take())
+                {
+                    if let Err(e) = result {
+                        let _ = values.send(Err(e));
+                    }
+                }
+            })
+            .map_err(|e| Error::CustomError(format!("could not start generator: {}", e)))?;
+
+        Ok(GeneratorState::Running {
+            resume: resume_sender,
+            values: value_receiver,
+        })
+    }
+
+    /// Called by `yield` on the generator's thread: hand a value to the consumer,
+    /// and wait until it asks for the next one.
+    fn yield_value(value: Expression) -> Result<(), Error> {
+        YIELD_CHANNEL.with(|channel| match &*channel.borrow() {
+            Some((values, resume)) => {
+                if values.send(Ok(value)).is_err() || resume.recv().is_err() {
+                    // The consumer dropped the generator, so unwind the body.
+                    Err(Error::CustomError(
+                        "generator was dropped before it finished".to_string(),
+                    ))
+                } else {
+                    Ok(())
+                }
+            }
+            None => Err(Error::CustomError(
+                "cannot yield outside of a generator".to_string(),
+            )),
+        })
+    }
+}
+
+impl fmt::Debug for Generator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<generator>")
+    }
+}
+
+impl PartialEq for Generator {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.state, &other.state)
+    }
+}
+
+/// A generator is a running computation, so it can't be persisted.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Generator {
+    fn serialize<S: serde::Serializer>(&self, _: S) -> Result<S::Ok, S::Error> {
+        Err(serde::ser::Error::custom("cannot serialize a generator"))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Generator {
+    fn deserialize<D: serde::Deserializer<'de>>(_: D) -> Result<Self, D::Error> {
+        Err(serde::de::Error::custom("cannot deserialize a generator"))
+    }
+}
+
 impl fmt::Debug for Expression {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -180,20 +421,26 @@ impl fmt::Debug for Expression {
                     .join(", ")
             ),
 
-            Self::Map(exprs) => write!(
-                f,
-                "{{{}}}",
-                exprs
-                    .iter()
-                    .map(|(k, e)| format!("{}: {:?}", k, e))
-                    .collect::<Vec<String>>()
-                    .join(", ")
-            ),
+            Self::Map(exprs) => match Self::call_hook(exprs, "__debug") {
+                Some(Self::String(text)) => write!(f, "{}", text),
+                Some(result) => write!(f, "{:?}", result),
+                None => write!(
+                    f,
+                    "{{{}}}",
+                    exprs
+                        .iter()
+                        .map(|(k, e)| format!("{}: {:?}", k, e))
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                ),
+            },
 
             Self::None => write!(f, "None"),
             Self::Lambda(param, body, _) => write!(f, "{} -> {:?}", param, body),
             Self::Macro(param, body) => write!(f, "{} ~> {:?}", param, body),
-            Self::For(name, list, body) => write!(f, "for {} in {:?} {:?}", name, list, body),
+            Self::For(names, list, body) => {
+                write!(f, "for {} in {:?} {:?}", names.join(" "), list, body)
+            }
             Self::Do(exprs) => write!(
                 f,
                 "{{ {} }}",
@@ -205,6 +452,8 @@ impl fmt::Debug for Expression {
             ),
 
             Self::Assign(name, expr) => write!(f, "let {} = {:?}", name, expr),
+            Self::Local(name, expr) => write!(f, "local {} = {:?}", name, expr),
+            Self::Const(name, expr) => write!(f, "const {} = {:?}", name, expr),
             Self::If(cond, true_expr, false_expr) => {
                 write!(f, "if {:?} {:?} else {:?}", cond, true_expr, false_expr)
             }
@@ -218,17 +467,15 @@ impl fmt::Debug for Expression {
                     .join(" ")
             ),
             Self::Builtin(builtin) => fmt::Debug::fmt(builtin, f),
+            Self::Yield(inner) => write!(f, "yield {:?}", inner),
+            Self::Generator(generator) => fmt::Debug::fmt(generator, f),
         }
     }
 }
 
 impl fmt::Display for Expression {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let specified_width = f.width().unwrap_or(
-            terminal_size()
-                .map(|(Width(w), _)| w as usize)
-                .unwrap_or(120),
-        );
+        let specified_width = f.width().or_else(console_width).unwrap_or(120);
         // let width = match terminal_size() {
         //     Some((Width(width), _)) => Some(width as usize),
         //     _ => None,
@@ -280,6 +527,9 @@ impl fmt::Display for Expression {
                 write!(f, "{}", t)
             }
             Self::Map(exprs) => {
+                if let Some(result) = Self::call_hook(exprs, "__display") {
+                    return write!(f, "{}", result);
+                }
                 let mut t = Table::new();
                 let fmt = t.get_format();
                 fmt.padding(1, 1);
@@ -322,7 +572,9 @@ impl fmt::Display for Expression {
             Self::None => write!(f, "None"),
             Self::Lambda(param, body, _) => write!(f, "{} -> {:?}", param, body),
             Self::Macro(param, body) => write!(f, "{} ~> {:?}", param, body),
-            Self::For(name, list, body) => write!(f, "for {} in {:?} {:?}", name, list, body),
+            Self::For(names, list, body) => {
+                write!(f, "for {} in {:?} {:?}", names.join(" "), list, body)
+            }
             Self::Do(exprs) => write!(
                 f,
                 "{{ {} }}",
@@ -334,6 +586,8 @@ impl fmt::Display for Expression {
             ),
 
             Self::Assign(name, expr) => write!(f, "let {} = {:?}", name, expr),
+            Self::Local(name, expr) => write!(f, "local {} = {:?}", name, expr),
+            Self::Const(name, expr) => write!(f, "const {} = {:?}", name, expr),
             Self::If(cond, true_expr, false_expr) => {
                 write!(f, "if {:?} {:?} else {:?}", cond, true_expr, false_expr)
             }
@@ -347,6 +601,8 @@ impl fmt::Display for Expression {
                     .join(" ")
             ),
             Self::Builtin(builtin) => fmt::Display::fmt(builtin, f),
+            Self::Yield(inner) => write!(f, "yield {:?}", inner),
+            Self::Generator(generator) => fmt::Debug::fmt(generator, f),
         }
     }
 }
@@ -359,23 +615,61 @@ impl PartialOrd for Expression {
             (Self::String(a), Self::String(b)) => a.partial_cmp(b),
             (Self::Bytes(a), Self::Bytes(b)) => a.partial_cmp(b),
             (Self::List(a), Self::List(b)) => a.partial_cmp(b),
-            (Self::Map(a), Self::Map(b)) => a.partial_cmp(b),
+            // Maps with the same keys and values are equal in any order.
+            (Self::Map(a), Self::Map(b)) => a
+                .iter()
+                .collect::<BTreeMap<_, _>>()
+                .partial_cmp(&b.iter().collect::<BTreeMap<_, _>>()),
             _ => None,
         }
     }
 }
 
+/// Expand an argument containing wildcards into the paths it matches, relative to `cwd`.
+/// An argument that doesn't match any paths is left as it is.
+fn expand_glob(arg: String, cwd: &str) -> Vec<String> {
+    if !arg.contains(['*', '?', '[']) {
+        return vec![arg];
+    }
+
+    let pattern = Path::new(cwd).join(&arg);
+    let paths = match glob::glob(&pattern.to_string_lossy()) {
+        Ok(paths) => paths
+            .filter_map(Result::ok)
+            .map(|path| {
+                path.strip_prefix(cwd)
+                    .unwrap_or(&path)
+                    .to_string_lossy()
+                    .to_string()
+            })
+            .collect::<Vec<_>>(),
+        Err(_) => vec![],
+    };
+
+    if paths.is_empty() {
+        vec![arg]
+    } else {
+        paths
+    }
+}
+
 impl Expression {
     pub fn builtin(
         name: impl ToString,
         body: fn(Vec<Self>, &mut Environment) -> Result<Self, Error>,
         help: impl ToString,
     ) -> Self {
-        Self::Builtin(Builtin {
+        let builtin = Builtin {
             name: name.to_string(),
             body,
             help: help.to_string(),
-        })
+        };
+        #[cfg(feature = "serde")]
+        BUILTINS
+            .lock()
+            .unwrap()
+            .insert(builtin.name.clone(), builtin.clone());
+        Self::Builtin(builtin)
     }
 
     pub fn new(x: impl Into<Self>) -> Self {
@@ -398,6 +692,7 @@ impl Expression {
             Self::Lambda(_, _, _) => true,
             Self::Macro(_, _) => true,
             Self::Builtin(_) => true,
+            Self::Generator(_) => true,
             _ => false,
         }
     }
@@ -416,14 +711,19 @@ impl Expression {
 
     fn get_used_symbols(&self) -> Vec<String> {
         match self {
-            Self::Symbol(name) => vec![name.clone()],
+            Self::Symbol(name) => match name.split_once('.') {
+                // `obj.method` uses `obj` too.
+                Some((object, _)) if !object.is_empty() => vec![name.clone(), object.to_string()],
+                _ => vec![name.clone()],
+            },
             Self::None
             | Self::Integer(_)
             | Self::Float(_)
             | Self::Bytes(_)
             | Self::String(_)
             | Self::Boolean(_)
-            | Self::Builtin(_) => vec![],
+            | Self::Builtin(_)
+            | Self::Generator(_) => vec![],
 
             Self::For(_, list, body) => {
                 let mut result = vec![];
@@ -447,11 +747,15 @@ impl Expression {
                 result
             }
 
-            Self::Group(inner) | Self::Quote(inner) => inner.get_used_symbols(),
+            Self::Group(inner) | Self::Quote(inner) | Self::Yield(inner) => {
+                inner.get_used_symbols()
+            }
             Self::Lambda(_, body, _) => body.get_used_symbols(),
             Self::Macro(_, body) => body.get_used_symbols(),
 
-            Self::Assign(_, expr) => expr.get_used_symbols(),
+            Self::Assign(_, expr) | Self::Local(_, expr) | Self::Const(_, expr) => {
+                expr.get_used_symbols()
+            }
             Self::If(cond, t, e) => {
                 let mut result = vec![];
                 result.extend(cond.get_used_symbols());
@@ -469,12 +773,245 @@ impl Expression {
         }
     }
 
+    /// Does this expression yield, making the lambda it belongs to a generator?
+    /// Yields inside of nested functions, macros, and quotes don't count.
+    fn contains_yield(&self) -> bool {
+        match self {
+            Self::Yield(_) => true,
+            Self::Group(inner)
+            | Self::Assign(_, inner)
+            | Self::Local(_, inner)
+            | Self::Const(_, inner) => inner.contains_yield(),
+            Self::For(_, list, body) => list.contains_yield() || body.contains_yield(),
+            Self::If(cond, t, e) => {
+                cond.contains_yield() || t.contains_yield() || e.contains_yield()
+            }
+            Self::Apply(f, args) => f.contains_yield() || args.iter().any(Self::contains_yield),
+            Self::Do(exprs) | Self::List(exprs) => exprs.iter().any(Self::contains_yield),
+            Self::Map(exprs) => exprs.values().any(Self::contains_yield),
+            _ => false,
+        }
+    }
+
+    /// Look up `obj.method` as the function `method` in the map `obj`, with the map
+    /// applied to it as its first argument.
+    fn method(name: &str, env: &mut Environment, depth: usize) -> Result<Option<Self>, Error> {
+        let (object, method) = match name.split_once('.') {
+            Some(parts) => parts,
+            None => return Ok(None),
+        };
+        let map = match env.get(object) {
+            Some(Self::Map(map)) => map,
+            _ => return Ok(None),
+        };
+        let method = match map.get(method) {
+            Some(method @ (Self::Lambda(..) | Self::Macro(..) | Self::Builtin(_))) => {
+                method.clone()
+            }
+            _ => return Ok(None),
+        };
+        let this = Self::Quote(Box::new(Self::Map(map)));
+        Self::Apply(Box::new(method), vec![this])
+            .eval_mut(env, depth + 1)
+            .map(Some)
+    }
+
+    /// Call a map's `__display` or `__debug` function with the map, so "objects" can
+    /// choose how they're shown. Maps without one, or whose function fails, are
+    /// shown the usual way.
+    fn call_hook(map: &IndexMap<String, Self>, hook: &str) -> Option<Self> {
+        let f = map.get(hook)?;
+        if IN_HOOK.with(|in_hook| in_hook.replace(true)) {
+            return None;
+        }
+        let this = Self::Quote(Box::new(Self::Map(map.clone())));
+        let result = Self::Apply(Box::new(f.clone()), vec![this]).eval(&mut Environment::new());
+        IN_HOOK.with(|in_hook| in_hook.set(false));
+        result.ok()
+    }
+
+    /// Call the function a map defines for an operator, like `__add` for `+`,
+    /// where the operator is used, with the operands in the order they're written.
+    /// The left operand's function is used, or the right's if only it has one,
+    /// so `1 + v` works like `v + 1`. This is `None` if neither overloads the
+    /// operator, and an error if the function fails.
+    pub fn try_overload(
+        &self,
+        hook: &str,
+        other: &Self,
+        env: &mut Environment,
+    ) -> Result<Option<Self>, Error> {
+        let f = [self, other].into_iter().find_map(|operand| match operand {
+            Self::Map(map) => map.get(hook),
+            _ => None,
+        });
+        let f = match f {
+            Some(f) => f.clone(),
+            None => return Ok(None),
+        };
+        let (a, b) = (
+            Self::Quote(Box::new(self.clone())),
+            Self::Quote(Box::new(other.clone())),
+        );
+        Self::Apply(Box::new(f), vec![a, b]).eval(env).map(Some)
+    }
+
+    /// Are two values equal? Maps can decide with an `__eq` function, which is
+    /// called where they're compared, with its errors passed along.
+    pub fn try_equals(&self, other: &Self, env: &mut Environment) -> Result<bool, Error> {
+        Ok(match self.try_overload("__eq", other, env)? {
+            Some(result) => result.is_truthy(),
+            None => self == other,
+        })
+    }
+
+    /// Order two values, like `partial_cmp`, with a map's `__cmp` function called
+    /// where they're compared, and its errors passed along.
+    pub fn try_cmp(&self, other: &Self, env: &mut Environment) -> Result<Option<Ordering>, Error> {
+        Ok(match self.try_overload("__cmp", other, env)? {
+            Some(Self::Integer(n)) => Some(n.cmp(&0)),
+            Some(Self::Float(n)) => n.partial_cmp(&0.0),
+            Some(_) => None,
+            None => self.partial_cmp(other),
+        })
+    }
+
+    /// Write a value as Dune source, which parses back to an equal value.
+    /// Only plain data can be written this way, so functions and the like
+    /// are an error.
+    pub fn repr(&self) -> Result<String, Error> {
+        Ok(match self {
+            Self::None => "None".to_string(),
+            Self::Boolean(b) => if *b { "True" } else { "False" }.to_string(),
+            Self::Integer(i) => i.to_string(),
+            Self::Float(n) if n.is_finite() => {
+                let n = n.to_string();
+                // Without a decimal point, it would be read back as an integer.
+                if n.contains('.') {
+                    n
+                } else {
+                    n + ".0"
+                }
+            }
+            Self::String(s) => Self::repr_string(s),
+            Self::Symbol(name) => name.clone(),
+            Self::Quote(inner) => format!("'{}", inner.repr()?),
+            Self::List(exprs) => format!(
+                "[{}]",
+                exprs
+                    .iter()
+                    .map(Self::repr)
+                    .collect::<Result<Vec<_>, _>>()?
+                    .join(", ")
+            ),
+            Self::Map(exprs) => format!(
+                "{{{}}}",
+                exprs
+                    .iter()
+                    .map(|(k, e)| Ok(format!("{}={}", Self::repr_key(k), e.repr()?)))
+                    .collect::<Result<Vec<_>, Error>>()?
+                    .join(", ")
+            ),
+            other => {
+                return Err(Error::CustomError(format!(
+                    "cannot write {:?} as source code, only data like numbers, strings, lists, and maps",
+                    other
+                )))
+            }
+        })
+    }
+
+    /// Quote a string with the escapes the tokenizer understands.
+    fn repr_string(s: &str) -> String {
+        let mut result = String::from("\"");
+        for c in s.chars() {
+            match c {
+                '"' => result += "\\\"",
+                '\\' => result += "\\\\",
+                '\n' => result += "\\n",
+                '\r' => result += "\\r",
+                '\t' => result += "\\t",
+                c if c.is_control() => result += &format!("\\u{{{:x}}}", c as u32),
+                c => result.push(c),
+            }
+        }
+        result + "\""
+    }
+
+    /// Map keys are written bare when they read back as a single symbol,
+    /// and quoted otherwise.
+    pub(crate) fn repr_key(key: &str) -> String {
+        match tokenize(key) {
+            (tokens, diagnostics)
+                if tokens.len() == 1
+                    && matches!(tokens[0].kind, TokenKind::Symbol | TokenKind::Operator)
+                    && diagnostics.iter().all(|d| d == &Diagnostic::Valid) =>
+            {
+                key.to_string()
+            }
+            _ => Self::repr_string(key),
+        }
+    }
+
+    /// Let a lambda bound to a name with `let` call itself by that name. The lambda
+    /// captured its variables before the name was defined, so it's given a copy of
+    /// itself, and applying it gives the copy back the variables it captured.
+    fn bind_self(name: &str, lambda: Self) -> Self {
+        match lambda {
+            Self::Lambda(param, body, mut captured)
+                if param != name && body.get_used_symbols().iter().any(|s| s == name) =>
+            {
+                let copy = Self::Lambda(param.clone(), body.clone(), captured.clone());
+                captured.define(name, copy);
+                Self::Lambda(param, body, captured)
+            }
+            other => other,
+        }
+    }
+
+    /// Define the names a lambda can call itself by in the environment it's applied in,
+    /// as the lambda along with everything it captured. See `bind_self`.
+    fn rebind_self(param: &str, body: &Self, captured: &Environment, env: &mut Environment) {
+        for (name, value) in captured.bindings() {
+            if matches!(value, Self::Lambda(p, b, _) if p == param && b.as_ref() == body) {
+                let lambda =
+                    Self::Lambda(param.to_string(), Box::new(body.clone()), captured.clone());
+                env.define(name, lambda);
+            }
+        }
+    }
+
+    /// Bind an item of a `for` loop to its name, or unpack it into its names,
+    /// like `for key value in map`.
+    fn bind_loop(names: &[String], item: Self, env: &mut Environment) -> Result<(), Error> {
+        match (names, item) {
+            ([name], item) => env.define(name, item),
+            (names, Self::List(values)) if values.len() == names.len() => {
+                for (name, value) in names.iter().zip(values) {
+                    env.define(name, value);
+                }
+            }
+            (names, item) => {
+                return Err(Error::CustomError(format!(
+                    "cannot unpack {:?} into the {} names `{}` of a for loop",
+                    item,
+                    names.len(),
+                    names.join(" ")
+                )))
+            }
+        }
+        Ok(())
+    }
+
     pub fn eval(&self, env: &mut Environment) -> Result<Self, Error> {
         self.clone().eval_mut(env, 0)
     }
 
     fn eval_mut(mut self, env: &mut Environment, mut depth: usize) -> Result<Self, Error> {
         loop {
+            if is_canceled() {
+                return Err(Error::Interrupted);
+            }
             if let Some(max_depth) = MAX_RECURSION_DEPTH {
                 if depth > max_depth {
                     return Err(Error::RecursionDepth(self));
@@ -486,23 +1023,50 @@ impl Expression {
                 Self::Group(inner) => return inner.eval_mut(env, depth + 1),
 
                 Self::Symbol(name) => {
-                    return Ok(match env.get(&name) {
-                        Some(expr) => expr,
-                        None => Self::Symbol(name.clone()),
-                    })
+                    if let Some(expr) = env.get(&name) {
+                        return Ok(expr);
+                    }
+                    return match Self::method(&name, env, depth)? {
+                        Some(method) => Ok(method),
+                        // In strict mode, only flags and paths may be left unbound.
+                        None if env.options().strict
+                            && !name.starts_with('-')
+                            && !name.contains('/') =>
+                        {
+                            Err(Error::SymbolNotDefined(name))
+                        }
+                        None => Ok(Self::Symbol(name)),
+                    };
+                }
+
+                Self::For(ref names, _, _) if names.iter().any(|name| env.is_constant(name)) => {
+                    let name = names.iter().find(|name| env.is_constant(name)).unwrap();
+                    return Err(Error::ConstantRebound(name.clone()));
+                }
+
+                Self::Assign(name, _) | Self::Local(name, _) | Self::Const(name, _)
+                    if env.is_constant(&name) =>
+                {
+                    return Err(Error::ConstantRebound(name));
                 }
 
-                Self::Assign(name, expr) => {
-                    let x = expr.eval_mut(env, depth + 1)?;
+                Self::Assign(name, expr) | Self::Local(name, expr) => {
+                    let x = Self::bind_self(&name, expr.eval_mut(env, depth + 1)?);
                     env.define(&name, x);
                     return Ok(Self::None);
                 }
 
-                Self::For(name, list, body) => {
-                    if let Expression::List(items) = list.clone().eval_mut(env, depth + 1)? {
+                Self::Const(name, expr) => {
+                    let x = Self::bind_self(&name, expr.eval_mut(env, depth + 1)?);
+                    env.define_constant(&name, x);
+                    return Ok(Self::None);
+                }
+
+                Self::For(names, list, body) => match list.clone().eval_mut(env, depth + 1)? {
+                    Expression::List(items) => {
                         let mut results = vec![];
                         for item in items {
-                            env.define(&name, item);
+                            Self::bind_loop(&names, item, env)?;
                             results.push(body.clone().eval_mut(env, depth + 1)?);
                         }
                         return Ok(Self::List(results));
@@ -515,9 +1079,36 @@ impl Expression {
                         //         })
                         //         .collect::<Result<Vec<Self>, Error>>()?,
                         // ));
-                    } else {
-                        return Err(Error::ForNonList(*list));
                     }
+                    Expression::Generator(generator) => {
+                        // Pull values out of the generator one at a time.
+                        let mut results = vec![];
+                        while let Some(item) = generator.next()? {
+                            Self::bind_loop(&names, item, env)?;
+                            results.push(body.clone().eval_mut(env, depth + 1)?);
+                        }
+                        return Ok(Self::List(results));
+                    }
+                    // Maps are looped over as `[key, value]` pairs, in the order
+                    // their keys were added.
+                    Expression::Map(entries) => {
+                        let mut results = vec![];
+                        for (key, value) in entries {
+                            Self::bind_loop(
+                                &names,
+                                Self::List(vec![Self::String(key), value]),
+                                env,
+                            )?;
+                            results.push(body.clone().eval_mut(env, depth + 1)?);
+                        }
+                        return Ok(Self::List(results));
+                    }
+                    _ => return Err(Error::ForNonList(*list)),
+                },
+
+                Self::Yield(inner) => {
+                    Generator::yield_value(inner.eval_mut(env, depth + 1)?)?;
+                    return Ok(Self::None);
                 }
 
                 Self::If(cond, true_expr, false_expr) => {
@@ -529,25 +1120,27 @@ impl Expression {
                     .eval_mut(env, depth + 1)
                 }
 
-                Self::Apply(ref f, ref args) => match f.clone().eval_mut(env, depth + 1)? {
+                Self::Apply(ref f, ref args) => match match f.as_ref() {
+                    // The name of a program is never an error, even in strict mode.
+                    Self::Symbol(name) if !env.is_defined(name) => Self::method(name, env, depth)?
+                        .unwrap_or_else(|| Self::Symbol(name.clone())),
+                    f => f.clone().eval_mut(env, depth + 1)?,
+                } {
                     Self::Symbol(name) | Self::String(name) => {
-                        let bindings = env
-                            .bindings
-                            .clone()
-                            .into_iter()
-                            .map(|(k, v)| (k, v.to_string()))
-                            // This is to prevent environment variables from getting too large.
-                            // This causes some strange bugs on Linux: mainly it becomes
-                            // impossible to execute any program because `the argument
-                            // list is too long`.
-                            .filter(|(_, s)| s.len() <= 1024)
-                            .collect::<BTreeMap<String, String>>();
+                        let bindings = env.exported();
 
                         let mut cmd_args = vec![];
                         for arg in args {
-                            for flattened_arg in
-                                Self::flatten(vec![arg.clone().eval_mut(env, depth + 1)?])
-                            {
+                            let arg = match arg {
+                                // A bare word is an argument to the program, like `main`
+                                // in `git checkout main`, even in strict mode.
+                                Self::Symbol(name) if !env.is_defined(name) => {
+                                    Self::method(name, env, depth)?
+                                        .unwrap_or_else(|| Self::Symbol(name.clone()))
+                                }
+                                arg => arg.clone().eval_mut(env, depth + 1)?,
+                            };
+                            for flattened_arg in Self::flatten(vec![arg]) {
                                 match flattened_arg {
                                     Self::String(s) => cmd_args.push(s),
                                     Self::Bytes(b) => {
@@ -559,18 +1152,48 @@ impl Expression {
                             }
                         }
 
-                        match Command::new(&name)
-                            .current_dir(env.get_cwd())
-                            .args(
-                                cmd_args, // Self::flatten(args.clone()).iter()
-                                         //     .filter(|&x| x != &Self::None)
-                                         //     // .map(|x| Ok(format!("{}", x.clone().eval_mut(env, depth + 1)?)))
-                                         //     .collect::<Result<Vec<String>, Error>>()?,
-                            )
-                            .envs(bindings)
-                            .status()
-                        {
-                            Ok(_) => return Ok(Self::None),
+                        if env.options().glob {
+                            let cwd = env.get_cwd();
+                            cmd_args = cmd_args
+                                .into_iter()
+                                .flat_map(|arg| expand_glob(arg, &cwd))
+                                .collect();
+                        }
+                        if env.options().trace {
+                            eprintln!("{}", redact(&format!("+ {} {}", name, cmd_args.join(" "))));
+                        }
+
+                        // Windows only finds `.exe` programs by itself, so look for
+                        // the other kinds of programs it can run, like batch files.
+                        let program = if cfg!(windows) {
+                            find_program(&name, env).unwrap_or_else(|| PathBuf::from(&name))
+                        } else {
+                            PathBuf::from(&name)
+                        };
+                        match run_foreground(
+                            Command::new(program)
+                                .current_dir(env.get_cwd())
+                                .args(
+                                    cmd_args, // Self::flatten(args.clone()).iter()
+                                             //     .filter(|&x| x != &Self::None)
+                                             //     // .map(|x| Ok(format!("{}", x.clone().eval_mut(env, depth + 1)?)))
+                                             //     .collect::<Result<Vec<String>, Error>>()?,
+                                )
+                                .envs(&*bindings),
+                        ) {
+                            // Interrupting a program with Ctrl-C stops the rest of
+                            // the line or script too, like shells report it as 130.
+                            Ok(status) if was_interrupted(&status) => {
+                                return Err(Error::CommandExited(name, args.clone(), 130));
+                            }
+                            Ok(status) => {
+                                let code = status_code(&status) as Int;
+                                env.define("LAST_STATUS", Self::Integer(code));
+                                if code != 0 && env.options().errexit {
+                                    return Err(Error::CommandExited(name, args.clone(), code));
+                                }
+                                return Ok(Self::None);
+                            }
                             Err(e) => {
                                 return Err(match e.kind() {
                                     ErrorKind::NotFound => Error::ProgramNotFound(name),
@@ -584,15 +1207,24 @@ impl Expression {
                     }
 
                     Self::Lambda(param, body, old_env) if args.len() == 1 => {
-                        let mut new_env = old_env;
+                        let mut new_env = old_env.clone();
+                        Self::rebind_self(&param, &body, &old_env, &mut new_env);
                         new_env.set_cwd(env.get_cwd());
+                        new_env.set_options(env.options().clone());
+                        new_env.set_last_command(env.last_command());
                         new_env.define(&param, args[0].clone().eval_mut(env, depth + 1)?);
+                        if body.contains_yield() {
+                            return Ok(Self::Generator(Generator::new(*body, new_env)));
+                        }
                         return body.eval_mut(&mut new_env, depth + 1);
                     }
 
                     Self::Lambda(param, body, old_env) if args.len() > 1 => {
                         let mut new_env = old_env.clone();
+                        Self::rebind_self(&param, &body, &old_env, &mut new_env);
                         new_env.set_cwd(env.get_cwd());
+                        new_env.set_options(env.options().clone());
+                        new_env.set_last_command(env.last_command());
                         new_env.define(&param, args[0].clone().eval_mut(env, depth + 1)?);
                         self = Self::Apply(
                             Box::new(body.eval_mut(&mut new_env, depth + 1)?),
@@ -650,7 +1282,7 @@ impl Expression {
                         exprs
                             .into_iter()
                             .map(|(n, x)| Ok((n, x.eval_mut(env, depth + 1)?)))
-                            .collect::<Result<BTreeMap<String, Self>, Error>>()?,
+                            .collect::<Result<IndexMap<String, Self>, Error>>()?,
                     ))
                 }
                 Self::Do(exprs) => {
@@ -658,6 +1290,34 @@ impl Expression {
                         return Ok(Self::None);
                     }
 
+                    let locals = exprs
+                        .iter()
+                        .filter_map(|expr| match expr {
+                            Self::Local(name, _) => Some(name.clone()),
+                            _ => None,
+                        })
+                        .collect::<BTreeSet<_>>();
+                    if !locals.is_empty() {
+                        // Put back what the locals were before the block, even if it fails.
+                        let before = locals
+                            .into_iter()
+                            .map(|name| {
+                                let value = env.bindings().get(&name).cloned();
+                                (name, value)
+                            })
+                            .collect::<Vec<_>>();
+                        let result = exprs
+                            .into_iter()
+                            .try_fold(Self::None, |_, expr| expr.eval_mut(env, depth + 1));
+                        for (name, value) in before {
+                            match value {
+                                Some(value) => env.define(&name, value),
+                                None => env.undefine(&name),
+                            }
+                        }
+                        return result;
+                    }
+
                     for expr in &exprs[..exprs.len() - 1] {
                         expr.clone().eval_mut(env, depth + 1)?;
                     }
@@ -670,21 +1330,40 @@ impl Expression {
                 | Self::Bytes(_)
                 | Self::String(_)
                 | Self::Macro(_, _)
-                | Self::Builtin(_) => return Ok(self.clone()),
+                | Self::Builtin(_)
+                | Self::Generator(_) => return Ok(self.clone()),
             }
             depth += 1;
         }
     }
 }
 
+/// Integer arithmetic on two values, or an error for overflow or dividing by zero.
+fn checked(m: Int, op: &str, n: Int, f: fn(Int, Int) -> Option<Int>) -> Result<Expression, Error> {
+    match f(m, n) {
+        Some(i) => Ok(Expression::Integer(i)),
+        None if n == 0 && matches!(op, "/" | "//") => Err(Error::CustomError(format!(
+            "division by zero in {} {} {}",
+            m, op, n
+        ))),
+        None if n == 0 && op == "%" => Err(Error::CustomError(format!(
+            "modulo by zero in {} {} {}",
+            m, op, n
+        ))),
+        None => Err(Error::CustomError(format!(
+            "integer overflow in {} {} {}, use `shopt \"wrapping\" True` to wrap around instead",
+            m, op, n
+        ))),
+    }
+}
+
+/// Arithmetic on values gives `None` for values it doesn't apply to, and an
+/// error for Integers that overflow or are divided by zero.
 impl Add for Expression {
-    type Output = Self;
-    fn add(self, other: Self) -> Self {
-        match (self, other) {
-            (Self::Integer(m), Self::Integer(n)) => match m.checked_add(n) {
-                Some(i) => Self::Integer(i),
-                None => Self::None,
-            },
+    type Output = Result<Self, Error>;
+    fn add(self, other: Self) -> Self::Output {
+        Ok(match (self, other) {
+            (Self::Integer(m), Self::Integer(n)) => return checked(m, "+", n, Int::checked_add),
             (Self::Integer(m), Self::Float(n)) => Self::Float(m as f64 + n),
             (Self::Float(m), Self::Integer(n)) => Self::Float(m + n as f64),
             (Self::Float(m), Self::Float(n)) => Self::Float(m + n),
@@ -698,56 +1377,58 @@ impl Add for Expression {
                 Self::List(a)
             }
             _ => Self::None,
-        }
+        })
     }
 }
 
 impl Sub for Expression {
-    type Output = Self;
-    fn sub(self, other: Self) -> Self {
-        match (self, other) {
-            (Self::Integer(m), Self::Integer(n)) => match m.checked_sub(n) {
-                Some(i) => Self::Integer(i),
-                None => Self::None,
-            },
+    type Output = Result<Self, Error>;
+    fn sub(self, other: Self) -> Self::Output {
+        Ok(match (self, other) {
+            (Self::Integer(m), Self::Integer(n)) => return checked(m, "-", n, Int::checked_sub),
             (Self::Integer(m), Self::Float(n)) => Self::Float(m as f64 - n),
             (Self::Float(m), Self::Integer(n)) => Self::Float(m - n as f64),
             (Self::Float(m), Self::Float(n)) => Self::Float(m - n),
-            (Self::Map(mut m), Self::String(n)) => match m.remove_entry(&n) {
+            (Self::Map(mut m), Self::String(n)) => match m.shift_remove_entry(&n) {
                 Some((_, val)) => val,
                 None => Self::None,
             },
             (Self::List(mut m), Self::Integer(n)) if m.len() > n as usize => m.remove(n as usize),
             _ => Self::None,
-        }
+        })
     }
 }
 
 impl Neg for Expression {
-    type Output = Expression;
+    type Output = Result<Self, Error>;
     fn neg(self) -> Self::Output {
-        match self {
-            Self::Integer(n) => Self::Integer(-n),
+        Ok(match self {
+            Self::Integer(n) => match n.checked_neg() {
+                Some(i) => Self::Integer(i),
+                None => {
+                    return Err(Error::CustomError(format!(
+                        "integer overflow in -({}), use `shopt \"wrapping\" True` to wrap around instead",
+                        n
+                    )))
+                }
+            },
             Self::Boolean(b) => Self::Boolean(!b),
             Self::Float(n) => Self::Float(-n),
             _ => Self::None,
-        }
+        })
     }
 }
 
 impl Mul for Expression {
-    type Output = Self;
-    fn mul(self, other: Self) -> Self {
-        match (self, other) {
-            (Self::Integer(m), Self::Integer(n)) => match m.checked_mul(n) {
-                Some(i) => Self::Integer(i),
-                None => Self::None,
-            },
+    type Output = Result<Self, Error>;
+    fn mul(self, other: Self) -> Self::Output {
+        Ok(match (self, other) {
+            (Self::Integer(m), Self::Integer(n)) => return checked(m, "*", n, Int::checked_mul),
             (Self::Integer(m), Self::Float(n)) => Self::Float(m as f64 * n),
             (Self::Float(m), Self::Integer(n)) => Self::Float(m * n as f64),
             (Self::Float(m), Self::Float(n)) => Self::Float(m * n),
             (Self::String(m), Self::Integer(n)) | (Self::Integer(n), Self::String(m)) => {
-                Self::String(m.repeat(n as usize))
+                Self::String(m.repeat(n.max(0) as usize))
             }
             (Self::List(m), Self::Integer(n)) | (Self::Integer(n), Self::List(m)) => {
                 let mut result = vec![];
@@ -757,32 +1438,61 @@ impl Mul for Expression {
                 Self::List(result)
             }
             _ => Self::None,
-        }
+        })
     }
 }
 
 impl Div for Expression {
-    type Output = Self;
-    fn div(self, other: Self) -> Self {
-        match (self, other) {
-            (Self::Integer(m), Self::Integer(n)) => match m.checked_div(n) {
-                Some(i) => Self::Integer(i),
-                None => Self::None,
-            },
+    type Output = Result<Self, Error>;
+    fn div(self, other: Self) -> Self::Output {
+        Ok(match (self, other) {
+            (Self::Integer(m), Self::Integer(n)) => return checked(m, "//", n, Int::checked_div),
             (Self::Integer(m), Self::Float(n)) => Self::Float(m as f64 / n),
             (Self::Float(m), Self::Integer(n)) => Self::Float(m / n as f64),
             (Self::Float(m), Self::Float(n)) => Self::Float(m / n),
             _ => Self::None,
-        }
+        })
+    }
+}
+
+impl Expression {
+    /// Divide, giving a Float even for two Integers, unlike `//`.
+    pub fn true_div(self, other: Self) -> Result<Self, Error> {
+        Ok(match (self, other) {
+            (Self::Integer(m), Self::Integer(0)) => {
+                return Err(Error::CustomError(format!("division by zero in {} / 0", m)))
+            }
+            (Self::Integer(m), Self::Integer(n)) => Self::Float(m as f64 / n as f64),
+            (Self::Integer(m), Self::Float(n)) => Self::Float(m as f64 / n),
+            (Self::Float(m), Self::Integer(n)) => Self::Float(m / n as f64),
+            (Self::Float(m), Self::Float(n)) => Self::Float(m / n),
+            _ => Self::None,
+        })
+    }
+
+    /// Raise to a power. An Integer to a power of zero or more stays an Integer.
+    pub fn pow(self, other: Self) -> Result<Self, Error> {
+        Ok(match (self, other) {
+            (Self::Integer(m), Self::Integer(n)) if n >= 0 => {
+                return checked(m, "**", n, |m, n| {
+                    u32::try_from(n).ok().and_then(|n| m.checked_pow(n))
+                })
+            }
+            (Self::Integer(m), Self::Integer(n)) => Self::Float((m as f64).powf(n as f64)),
+            (Self::Integer(m), Self::Float(n)) => Self::Float((m as f64).powf(n)),
+            (Self::Float(m), Self::Integer(n)) => Self::Float(m.powf(n as f64)),
+            (Self::Float(m), Self::Float(n)) => Self::Float(m.powf(n)),
+            _ => Self::None,
+        })
     }
 }
 
 impl Rem for Expression {
-    type Output = Self;
-    fn rem(self, other: Self) -> Self {
+    type Output = Result<Self, Error>;
+    fn rem(self, other: Self) -> Self::Output {
         match (self, other) {
-            (Self::Integer(m), Self::Integer(n)) => Self::Integer(m % n),
-            _ => Self::None,
+            (Self::Integer(m), Self::Integer(n)) => checked(m, "%", n, Int::checked_rem),
+            _ => Ok(Self::None),
         }
     }
 }
@@ -0,0 +1,373 @@
+use std::{
+    io::{self, Read},
+    process::{Child, Command, ExitStatus, Output},
+    sync::{
+        atomic::{AtomicI32, Ordering},
+        mpsc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// What signals for the programs in the foreground are sent to: a process
+/// group as a negative number, a single program, or 0 if nothing is running.
+static FOREGROUND: AtomicI32 = AtomicI32::new(0);
+
+/// The commands that have run in the foreground since `take_runs` was last called.
+static RUNS: Mutex<Runs> = Mutex::new(Runs {
+    longest: None,
+    last: None,
+});
+
+/// A command or pipeline that ran in the foreground, and how long it took.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Run {
+    /// The programs that ran, like `cargo | grep`.
+    pub command: String,
+    pub elapsed: Duration,
+    /// The exit code of the last program, if it exited rather than being killed.
+    pub code: Option<i32>,
+}
+
+/// The command that ran the longest and the last command to run, out of the
+/// commands run in the foreground.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Runs {
+    pub longest: Option<Run>,
+    pub last: Option<Run>,
+}
+
+/// Get the commands that ran in the foreground since this was last called, so
+/// the REPL can tell when a slow one finishes and how the last one exited.
+pub fn take_runs() -> Runs {
+    std::mem::take(&mut *RUNS.lock().unwrap())
+}
+
+/// Is a program running in the foreground? Signals like Ctrl-C are meant
+/// for it rather than the shell.
+pub fn is_foreground_running() -> bool {
+    FOREGROUND.load(Ordering::SeqCst) != 0
+}
+
+/// The status a program exited with, like shells report it: its exit code,
+/// or 128 plus the signal that killed it.
+pub fn status_code(status: &ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    status.code().unwrap_or(1)
+}
+
+/// Was a program stopped by Ctrl-C?
+pub fn was_interrupted(status: &ExitStatus) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        status.signal() == Some(libc::SIGINT)
+    }
+    #[cfg(not(unix))]
+    {
+        // Windows programs stopped by Ctrl-C exit with STATUS_CONTROL_C_EXIT.
+        status.code() == Some(0xC000013Au32 as i32)
+    }
+}
+
+/// Run a program in the foreground and wait for it to finish.
+pub fn run_foreground(cmd: &mut Command) -> io::Result<ExitStatus> {
+    let mut foreground = Foreground::new();
+    let mut child = foreground.spawn(cmd)?;
+    foreground.wait(&mut child)
+}
+
+/// Run a program in the foreground like `run_foreground`, but kill it if it's
+/// still running after some time, which is a `TimedOut` error.
+pub fn run_foreground_for(cmd: &mut Command, timeout: Duration) -> io::Result<ExitStatus> {
+    let mut foreground = Foreground::new();
+    let mut child = foreground.spawn(cmd)?;
+    // This is the program's whole process group when it has the terminal.
+    let deadline = Deadline::new(FOREGROUND.load(Ordering::SeqCst), timeout);
+    let status = foreground.wait(&mut child);
+    if deadline.finish() {
+        return Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("killed after running for {:?}", timeout),
+        ));
+    }
+    status
+}
+
+/// Kills a program if it's still running after some time, unless it
+/// finishes first. It's also killed if the code running it is canceled, in
+/// case it's in a process group of its own that Ctrl-C doesn't reach.
+pub struct Deadline {
+    stop: mpsc::Sender<()>,
+    watch: thread::JoinHandle<bool>,
+}
+
+impl Deadline {
+    /// Kill a program, or a process group given as a negative number, after `timeout`.
+    pub fn new(target: i32, timeout: Duration) -> Self {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let watch = thread::spawn(move || {
+            let started = Instant::now();
+            loop {
+                let left = timeout.saturating_sub(started.elapsed());
+                match stopped.recv_timeout(left.min(Duration::from_millis(50))) {
+                    Err(mpsc::RecvTimeoutError::Timeout) if left.is_zero() => break,
+                    Err(mpsc::RecvTimeoutError::Timeout) if crate::is_canceled() => {
+                        kill(target);
+                        return false;
+                    }
+                    Err(mpsc::RecvTimeoutError::Timeout) => {}
+                    _ => return false,
+                }
+            }
+            kill(target);
+            true
+        });
+        Self { stop, watch }
+    }
+
+    /// Stop watching the program once it's finished. Returns whether it was
+    /// killed for running too long.
+    pub fn finish(self) -> bool {
+        drop(self.stop);
+        self.watch.join().unwrap_or(false)
+    }
+}
+
+/// Kill a program, or a process group given as a negative number.
+fn kill(target: i32) {
+    #[cfg(unix)]
+    unsafe {
+        libc::kill(target, libc::SIGKILL);
+    }
+    #[cfg(not(unix))]
+    let _ = Command::new("taskkill")
+        .args(["/F", "/T", "/PID", &target.abs().to_string()])
+        .output();
+}
+
+/// The programs of a command or pipeline running in the foreground.
+///
+/// When the shell controls the terminal, the programs are put in a process group
+/// of their own, which is given the terminal while they run. Then Ctrl-C and Ctrl-Z
+/// go to them, and the programs they start, and not to the shell.
+pub struct Foreground {
+    /// Can the programs be given the terminal?
+    terminal: bool,
+    /// The process group of the programs, or 0 before any have started.
+    pgid: i32,
+    /// When the first program started, and the names of the programs.
+    started: Option<Instant>,
+    programs: Vec<String>,
+    /// The exit code of the last program waited for.
+    code: Option<i32>,
+}
+
+impl Default for Foreground {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Foreground {
+    pub fn new() -> Self {
+        #[cfg(unix)]
+        let terminal = unsafe { libc::isatty(0) == 1 && libc::tcgetpgrp(0) == libc::getpgrp() };
+        #[cfg(not(unix))]
+        let terminal = false;
+        Self {
+            terminal,
+            pgid: 0,
+            started: None,
+            programs: vec![],
+            code: None,
+        }
+    }
+
+    /// Start a program in the foreground.
+    pub fn spawn(&mut self, cmd: &mut Command) -> io::Result<Child> {
+        self.started.get_or_insert_with(Instant::now);
+        let program = std::path::Path::new(cmd.get_program());
+        self.programs.push(
+            program
+                .file_name()
+                .unwrap_or(program.as_os_str())
+                .to_string_lossy()
+                .to_string(),
+        );
+        #[cfg(unix)]
+        if self.terminal {
+            use std::os::unix::process::CommandExt;
+            let pgid = self.pgid;
+            unsafe {
+                cmd.pre_exec(move || {
+                    // The group is gone if the programs in it have finished.
+                    if libc::setpgid(0, pgid) != 0 {
+                        libc::setpgid(0, 0);
+                    }
+                    give_terminal(libc::getpgrp());
+                    Ok(())
+                });
+            }
+        }
+
+        let child = match cmd.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                // The program may have taken the terminal before it failed to start.
+                self.release();
+                return Err(e);
+            }
+        };
+
+        #[cfg(unix)]
+        if self.terminal {
+            // Do this here too, in case the shell gets here before the program does.
+            let pid = child.id() as i32;
+            unsafe {
+                if libc::setpgid(pid, self.pgid) != 0 {
+                    libc::setpgid(pid, 0);
+                }
+                self.pgid = match libc::getpgid(pid) {
+                    -1 => pid,
+                    pgid => pgid,
+                };
+                give_terminal(self.pgid);
+            }
+            FOREGROUND.store(-self.pgid, Ordering::SeqCst);
+            return Ok(child);
+        }
+        FOREGROUND.store(child.id() as i32, Ordering::SeqCst);
+        Ok(child)
+    }
+
+    /// Wait for a program to exit.
+    #[cfg(unix)]
+    pub fn wait(&mut self, child: &mut Child) -> io::Result<ExitStatus> {
+        use std::os::unix::process::ExitStatusExt;
+
+        // Close the program's input, so it isn't left waiting for more.
+        drop(child.stdin.take());
+        let pid = child.id() as i32;
+        loop {
+            let mut status = 0;
+            let options = if self.terminal { libc::WUNTRACED } else { 0 };
+            if unsafe { libc::waitpid(pid, &mut status, options) } < 0 {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(e);
+            }
+
+            if libc::WIFSTOPPED(status) {
+                // The programs were stopped with Ctrl-Z. There's no job control, so stop
+                // the shell too, and continue them in the foreground when the shell is.
+                unsafe {
+                    give_terminal(libc::getpgrp());
+                    libc::raise(libc::SIGTSTP);
+                    give_terminal(self.pgid);
+                    libc::kill(-self.pgid, libc::SIGCONT);
+                }
+                continue;
+            }
+            self.release();
+            let status = ExitStatus::from_raw(status);
+            self.code = status.code();
+            return Ok(status);
+        }
+    }
+
+    /// Wait for a program to exit.
+    #[cfg(not(unix))]
+    pub fn wait(&mut self, child: &mut Child) -> io::Result<ExitStatus> {
+        let status = child.wait();
+        self.release();
+        self.code = status.as_ref().ok().and_then(ExitStatus::code);
+        status
+    }
+
+    /// Wait for a program to exit, and collect what it wrote to its piped standard output.
+    pub fn wait_with_output(&mut self, mut child: Child) -> io::Result<Output> {
+        drop(child.stdin.take());
+        let mut stdout = vec![];
+        if let Some(mut pipe) = child.stdout.take() {
+            pipe.read_to_end(&mut stdout)?;
+        }
+        Ok(Output {
+            status: self.wait(&mut child)?,
+            stdout,
+            stderr: vec![],
+        })
+    }
+
+    /// Take the terminal back for the shell, between programs or after them.
+    fn release(&self) {
+        FOREGROUND.store(0, Ordering::SeqCst);
+        #[cfg(unix)]
+        if self.terminal {
+            unsafe { give_terminal(libc::getpgrp()) };
+        }
+    }
+}
+
+impl Drop for Foreground {
+    fn drop(&mut self) {
+        self.release();
+        if let Some(started) = self.started {
+            let run = Run {
+                command: self.programs.join(" | "),
+                elapsed: started.elapsed(),
+                code: self.code,
+            };
+            let mut runs = RUNS.lock().unwrap();
+            let outlasts = |longest: &&Run| longest.elapsed >= run.elapsed;
+            if runs.longest.as_ref().filter(outlasts).is_none() {
+                runs.longest = Some(run.clone());
+            }
+            runs.last = Some(run);
+        }
+    }
+}
+
+/// Make a process group the foreground of the terminal.
+#[cfg(unix)]
+unsafe fn give_terminal(pgid: libc::pid_t) {
+    // A process that isn't in the foreground is stopped for trying this,
+    // unless it ignores SIGTTOU while it does.
+    let handler = libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+    libc::tcsetpgrp(0, pgid);
+    libc::signal(libc::SIGTTOU, handler);
+}
+
+/// When the shell is told to exit with SIGTERM, or its terminal is closed with
+/// SIGHUP, pass the signal on to the programs in the foreground before exiting,
+/// so they don't outlive the shell.
+#[cfg(unix)]
+pub fn forward_exit_signals() {
+    extern "C" fn forward(signal: libc::c_int) {
+        let target = FOREGROUND.load(Ordering::SeqCst);
+        unsafe {
+            if target != 0 {
+                libc::kill(target, signal);
+                // Stopped programs have to be continued to get the signal.
+                libc::kill(target, libc::SIGCONT);
+            }
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+    }
+
+    for signal in [libc::SIGTERM, libc::SIGHUP] {
+        unsafe { libc::signal(signal, forward as *const () as libc::sighandler_t) };
+    }
+}
+
+#[cfg(not(unix))]
+pub fn forward_exit_signals() {}
@@ -9,9 +9,36 @@ pub use expr::*;
 mod env;
 pub use env::*;
 
+mod analysis;
+pub use analysis::*;
+
+mod export;
+pub use export::*;
+
+mod expand;
+pub use expand::*;
+
 mod error;
 pub use error::*;
 
+mod redact;
+pub use redact::*;
+
+mod program;
+pub use program::*;
+
+mod foreground;
+pub use foreground::*;
+
+mod cancel;
+pub use cancel::*;
+
+mod console;
+pub use console::*;
+
+mod theme;
+pub use theme::*;
+
 mod parser;
 pub use parser::*;
 
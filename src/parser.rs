@@ -8,7 +8,7 @@ use nom::{
     IResult,
 };
 
-use std::collections::BTreeMap;
+use indexmap::IndexMap;
 
 use crate::{
     tokens::{Input, Tokens},
@@ -300,6 +300,19 @@ fn parse_quote(input: Tokens<'_>) -> IResult<Tokens<'_>, Expression, SyntaxError
     })(input)
 }
 
+/// A quasiquote like `` `(x + (unquote y)) `` is a quote with some parts filled in,
+/// which the `quasiquote` builtin evaluates.
+fn parse_quasiquote(input: Tokens<'_>) -> IResult<Tokens<'_>, Expression, SyntaxError> {
+    let (input, _) = text("`")(input)?;
+
+    map(parse_expression_prec_two, |x| {
+        Expression::Apply(
+            Box::new(Expression::Symbol("quasiquote".to_string())),
+            vec![x],
+        )
+    })(input)
+}
+
 fn parse_not(input: Tokens<'_>) -> IResult<Tokens<'_>, Expression, SyntaxError> {
     let (input, _) = text("!")(input)?;
 
@@ -318,7 +331,7 @@ fn parse_string(input: Tokens<'_>) -> IResult<Tokens<'_>, String, SyntaxError> {
 }
 
 fn parse_assign(input: Tokens<'_>) -> IResult<Tokens<'_>, Expression, SyntaxError> {
-    let (input, _) = text("let")(input)?;
+    let (input, keyword) = alt((text("let"), text("local"), text("const")))(input)?;
 
     let (input, symbol) = alt((parse_symbol, parse_operator))(input).map_err(|_| {
         SyntaxError::unrecoverable(
@@ -337,7 +350,24 @@ fn parse_assign(input: Tokens<'_>) -> IResult<Tokens<'_>, Expression, SyntaxErro
         )
     })?;
     let (input, expr) = parse_expression(input)?;
-    Ok((input, Expression::Assign(symbol, Box::new(expr))))
+    let expr = Box::new(expr);
+    Ok((
+        input,
+        match keyword.text(input) {
+            "local" => Expression::Local(symbol, expr),
+            "const" => Expression::Const(symbol, expr),
+            _ => Expression::Assign(symbol, expr),
+        },
+    ))
+}
+
+fn parse_yield(input: Tokens<'_>) -> IResult<Tokens<'_>, Expression, SyntaxError> {
+    let (input, _) = text("yield")(input)?;
+    let (input, expr) = opt(parse_expression)(input)?;
+    Ok((
+        input,
+        Expression::Yield(Box::new(expr.unwrap_or(Expression::None))),
+    ))
 }
 
 fn parse_group(input: Tokens<'_>) -> IResult<Tokens<'_>, Expression, SyntaxError> {
@@ -376,7 +406,7 @@ fn parse_map(input: Tokens<'_>) -> IResult<Tokens<'_>, Expression, SyntaxError>
     let (input, expr_map) = separated_list0(
         text(","),
         separated_pair(
-            alt((parse_symbol, parse_operator)),
+            alt((parse_symbol, parse_operator, parse_string)),
             text("="),
             parse_expression,
         ),
@@ -401,14 +431,14 @@ fn parse_map(input: Tokens<'_>) -> IResult<Tokens<'_>, Expression, SyntaxError>
 
     let expr_map = expr_map
         .into_iter()
-        .collect::<BTreeMap<String, Expression>>();
+        .collect::<IndexMap<String, Expression>>();
 
     Ok((input, Expression::Map(expr_map)))
 }
 
 fn parse_for_loop(input: Tokens<'_>) -> IResult<Tokens<'_>, Expression, SyntaxError> {
     let (input, _) = text("for")(input)?;
-    let (input, symbol) = parse_symbol(input).map_err(|_| {
+    let (input, names) = many1(parse_symbol)(input).map_err(|_| {
         SyntaxError::unrecoverable(
             input.get_str_slice(),
             "symbol",
@@ -445,7 +475,7 @@ fn parse_for_loop(input: Tokens<'_>) -> IResult<Tokens<'_>, Expression, SyntaxEr
 
     Ok((
         input,
-        Expression::For(symbol, Box::new(list), Box::new(body)),
+        Expression::For(names, Box::new(list), Box::new(body)),
     ))
 }
 
@@ -524,12 +554,50 @@ fn parse_block(input: Tokens<'_>) -> IResult<Tokens<'_>, Expression, SyntaxError
 }
 
 fn parse_apply(input: Tokens<'_>) -> IResult<Tokens<'_>, Expression, SyntaxError> {
+    let start = input;
     let (input, f) = alt((parse_expression_prec_two, parse_operator_as_symbol))(input)?;
-    let (input, args) = many1(parse_expression_prec_five)(input)?;
+    if divides(start, input) {
+        return Err(nom::Err::Error(SyntaxError::InternalError));
+    }
+    let (input, args) = many1(alt((parse_argument, parse_root_path)))(input)?;
 
     Ok((input, Expression::Apply(Box::new(f), args)))
 }
 
+/// An argument to a command, which is any expression, except that a `/` in
+/// it is the root directory rather than division, like in `tar -C / -xf a.tar`.
+/// Division in an argument has to be in parentheses, like `echo (7 / 2)`.
+fn parse_argument(input: Tokens<'_>) -> IResult<Tokens<'_>, Expression, SyntaxError> {
+    parse_comparison(input, false)
+}
+
+/// A `/` on its own as an argument, like in `cd /`.
+fn parse_root_path(input: Tokens<'_>) -> IResult<Tokens<'_>, Expression, SyntaxError> {
+    map(text("/"), |_| Expression::String(String::from("/")))(input)
+}
+
+/// Whether a `/` after the start of an expression divides, like in `x / 2`,
+/// rather than being the root directory given to a command, like in `cd /`
+/// or `find / -name a`. It divides when the whole expression is arithmetic,
+/// so nothing is left to be another argument afterward, and the right side
+/// doesn't look like a path or a flag.
+fn divides(start: Tokens<'_>, after_head: Tokens<'_>) -> bool {
+    let right = match text("/")(after_head) {
+        Ok((right, _)) => right,
+        Err(_) => return false,
+    };
+    let looks_like_path = right.first().is_some_and(|token| {
+        token.kind == TokenKind::Symbol && token.text(right).starts_with(['/', '.', '~', '-'])
+    });
+    if looks_like_path {
+        return false;
+    }
+    match parse_expression_prec_six(start) {
+        Ok((rest, _)) => text("/")(rest).is_err() && parse_argument(rest).is_err(),
+        Err(_) => false,
+    }
+}
+
 fn parse_apply_operator(input: Tokens<'_>) -> IResult<Tokens<'_>, Expression, SyntaxError> {
     let (input, f) = parse_operator_as_symbol(input)?;
     let (input, args) = many0(parse_expression_prec_five)(input)?;
@@ -618,6 +686,7 @@ fn parse_expression_prec_seven(input: Tokens<'_>) -> IResult<Tokens<'_>, Express
         parse_for_loop,
         parse_if,
         parse_assign,
+        parse_yield,
         parse_callable,
         parse_apply,
         parse_apply_operator,
@@ -675,8 +744,16 @@ fn parse_range(input: Tokens<'_>) -> IResult<Tokens<'_>, Expression, SyntaxError
 }
 
 fn parse_expression_prec_five(input: Tokens<'_>) -> IResult<Tokens<'_>, Expression, SyntaxError> {
+    parse_comparison(input, true)
+}
+
+/// Comparisons, and the arithmetic in them, with `/` dividing or not.
+fn parse_comparison(
+    input: Tokens<'_>,
+    divide: bool,
+) -> IResult<Tokens<'_>, Expression, SyntaxError> {
     no_terminating_punctuation(input)?;
-    let expr_parser = parse_expression_prec_four;
+    let expr_parser = |input| parse_sum(input, divide);
 
     let (input, mut head) = expr_parser(input)?;
 
@@ -694,7 +771,7 @@ fn parse_expression_prec_five(input: Tokens<'_>) -> IResult<Tokens<'_>, Expressi
 
     if list.is_empty() {
         if let Ok((input, _)) = text("to")(input) {
-            let (input, to) = parse_expression_prec_four(input).map_err(|_| {
+            let (input, to) = parse_sum(input, divide).map_err(|_| {
                 SyntaxError::unrecoverable(
                     input.get_str_slice(),
                     "a valid range expression",
@@ -742,8 +819,12 @@ fn parse_operator_as_symbol(input: Tokens<'_>) -> IResult<Tokens<'_>, Expression
 }
 
 fn parse_expression_prec_four(input: Tokens<'_>) -> IResult<Tokens<'_>, Expression, SyntaxError> {
+    parse_sum(input, true)
+}
+
+fn parse_sum(input: Tokens<'_>, divide: bool) -> IResult<Tokens<'_>, Expression, SyntaxError> {
     no_terminating_punctuation(input)?;
-    let expr_parser = parse_expression_prec_three;
+    let expr_parser = |input| parse_product(input, divide);
 
     let (input, mut head) = expr_parser(input)?;
 
@@ -767,14 +848,20 @@ fn parse_expression_prec_four(input: Tokens<'_>) -> IResult<Tokens<'_>, Expressi
     Ok((input, head))
 }
 
-fn parse_expression_prec_three(input: Tokens<'_>) -> IResult<Tokens<'_>, Expression, SyntaxError> {
+fn parse_product(input: Tokens<'_>, divide: bool) -> IResult<Tokens<'_>, Expression, SyntaxError> {
     no_terminating_punctuation(input)?;
-    let expr_parser = parse_expression_prec_two;
+    let expr_parser = parse_power;
+    let operator = |input| {
+        if divide {
+            alt((text("*"), text("//"), text("/"), text("%")))(input)
+        } else {
+            alt((text("*"), text("//"), text("%")))(input)
+        }
+    };
 
     let (input, mut head) = expr_parser(input)?;
 
-    let (input, mut list) =
-        many0(pair(alt((text("*"), text("//"), text("%"))), expr_parser))(input)?;
+    let (input, mut list) = many0(pair(operator, expr_parser))(input)?;
 
     if list.is_empty() {
         return Ok((input, head));
@@ -794,6 +881,22 @@ fn parse_expression_prec_three(input: Tokens<'_>) -> IResult<Tokens<'_>, Express
     Ok((input, head))
 }
 
+/// Exponents, which group to the right, so `2 ** 3 ** 2` is `2 ** 9`.
+fn parse_power(input: Tokens<'_>) -> IResult<Tokens<'_>, Expression, SyntaxError> {
+    no_terminating_punctuation(input)?;
+    let (input, base) = parse_expression_prec_two(input)?;
+    match preceded(text("**"), parse_power)(input) {
+        Ok((input, exponent)) => Ok((
+            input,
+            Expression::Group(Box::new(Expression::Apply(
+                Box::new(Expression::Symbol("**".to_string())),
+                vec![base, exponent],
+            ))),
+        )),
+        Err(_) => Ok((input, base)),
+    }
+}
+
 fn parse_expression_prec_two(input: Tokens<'_>) -> IResult<Tokens<'_>, Expression, SyntaxError> {
     no_terminating_punctuation(input)?;
 
@@ -820,6 +923,7 @@ fn parse_expression_prec_one(input: Tokens<'_>) -> IResult<Tokens<'_>, Expressio
     alt((
         parse_group,
         parse_quote,
+        parse_quasiquote,
         parse_map,
         parse_block,
         parse_list,
@@ -0,0 +1,67 @@
+use crate::Environment;
+use std::path::{Path, PathBuf};
+
+/// The extensions of files Windows can run, from `PATHEXT`. Other systems
+/// don't use extensions for programs.
+pub fn program_extensions() -> Vec<String> {
+    if !cfg!(windows) {
+        return vec![];
+    }
+    std::env::var("PATHEXT")
+        .unwrap_or_else(|_| String::from(".COM;.EXE;.BAT;.CMD"))
+        .split(';')
+        .filter(|ext| ext.starts_with('.'))
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Get the name a program file is run by, like `cargo` for `cargo.exe` on
+/// Windows, or nothing if the file can't be run.
+pub fn program_name(file_name: &str) -> Option<&str> {
+    let extensions = program_extensions();
+    if extensions.is_empty() {
+        return Some(file_name);
+    }
+    let (name, ext) = file_name.rsplit_once('.')?;
+    let ext = format!(".{}", ext.to_lowercase());
+    extensions.contains(&ext).then_some(name)
+}
+
+/// Get the directories programs are looked up in, from `PATH` as the shell
+/// has it, or as the shell was started with if it isn't defined.
+pub fn program_dirs(env: &Environment) -> Vec<PathBuf> {
+    match env.get("PATH") {
+        Some(paths) => std::env::split_paths(&paths.to_string()).collect(),
+        None => std::env::var_os("PATH")
+            .map(|paths| std::env::split_paths(&paths).collect())
+            .unwrap_or_default(),
+    }
+}
+
+/// Find the file a program is run from. Names with a path separator are
+/// relative to the working directory, and others are looked up in `PATH`.
+/// On Windows, the extensions in `PATHEXT` are tried too, so batch files
+/// like `build.cmd` can be run as `build`.
+pub fn find_program(name: &str, env: &Environment) -> Option<PathBuf> {
+    let cwd = env.get_cwd();
+    let extensions = program_extensions();
+    let find_in = |dir: &Path| {
+        let path = dir.join(name);
+        if path.is_file() {
+            return Some(path);
+        }
+        extensions
+            .iter()
+            .map(|ext| dir.join(format!("{}{}", name, ext)))
+            .find(|path| path.is_file())
+    };
+
+    if name.contains('/') || (cfg!(windows) && name.contains('\\')) {
+        return find_in(Path::new(&cwd));
+    }
+    program_dirs(env)
+        .into_iter()
+        // Windows runs programs in the working directory before ones in `PATH`.
+        .chain(cfg!(windows).then(|| PathBuf::from(&cwd)))
+        .find_map(|dir| find_in(&dir))
+}
@@ -0,0 +1,43 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::sync::RwLock;
+
+/// What secrets are replaced with.
+pub const REDACTED: &str = "********";
+
+lazy_static! {
+    /// The patterns of every secret marked with `sys@redact`.
+    static ref SECRETS: RwLock<Vec<Regex>> = RwLock::new(vec![]);
+}
+
+/// Mark a value as secret, so it's masked wherever the shell shows it.
+pub fn add_secret(value: &str) {
+    if !value.is_empty() {
+        // This can't fail, because the pattern only matches the literal value.
+        add_secret_pattern(&regex::escape(value)).unwrap();
+    }
+}
+
+/// Mark everything matching a regular expression as secret.
+pub fn add_secret_pattern(pattern: &str) -> Result<(), regex::Error> {
+    let pattern = Regex::new(pattern)?;
+    let mut secrets = SECRETS.write().unwrap();
+    if !secrets
+        .iter()
+        .any(|secret| secret.as_str() == pattern.as_str())
+    {
+        secrets.push(pattern);
+    }
+    Ok(())
+}
+
+/// Mask the secrets in some text before it's shown or saved.
+pub fn redact(text: &str) -> String {
+    let mut text = text.to_string();
+    for secret in SECRETS.read().unwrap().iter() {
+        if secret.is_match(&text) {
+            text = secret.replace_all(&text, REDACTED).into_owned();
+        }
+    }
+    text
+}
@@ -1,4 +1,9 @@
-use crate::{parse_script, tokenize, Diagnostic, SyntaxError};
+use crate::{
+    add_secret, add_secret_pattern, analyze, expand_macros, export_sh, find_program, parse_script,
+    records_table, redact, retokenize, set_color_support, to_source, tokenize, ColorSupport,
+    Diagnostic, DisplayLimits, Environment, Error, Expression, Int, Style, SyntaxError, Theme,
+    REDACTED,
+};
 
 #[track_caller]
 fn tokenize_test(input: &str, expected: &str) {
@@ -211,9 +216,29 @@ fn tokenize_invalid_strings() {
     tokenize_test_err(r#""\u{g}""#); // not a hex digit
 }
 
+#[test]
+fn retokenize_after_edits() {
+    let edits = [
+        ("ls -la | grep foo", "ls -la | grep foobar"),
+        ("echo to", "echo toast"),
+        ("let x = 1", "let x = 1.5"),
+        ("echo \"abc", "echo \"abc\" def"),
+        ("cat a.txt | wc -l", "cat b.txt | wc -l"),
+        ("", "# a comment"),
+        ("echo hi", "echo"),
+    ];
+    for (before, line) in edits {
+        let (tokens, diagnostics) = tokenize(before);
+        let retokenized = retokenize(line, before, &tokens, &diagnostics);
+        assert_eq!(
+            format!("{:?}", retokenized),
+            format!("{:?}", tokenize(line))
+        );
+    }
+}
+
 #[test]
 fn tokenize_invalid_symbols() {
-    tokenize_test_err(r#"`"#);
     tokenize_test_err(r#"§"#);
     tokenize_test_err(r#"°"#);
     tokenize_test_err(r#"–"#); // em dash
@@ -241,3 +266,564 @@ fn parse3() -> Result<(), nom::Err<SyntaxError>> {
         r#"{ let + = a -> b -> c -> (+ a b c) }"#,
     )
 }
+
+#[test]
+fn parse_yield() -> Result<(), nom::Err<SyntaxError>> {
+    parse_test(
+        r#"let count = n -> for i in 0 to n { yield i }"#,
+        r#"{ let count = n -> for i in to 0 n { yield i } }"#,
+    )
+}
+
+#[test]
+fn parse_quasiquote() -> Result<(), nom::Err<SyntaxError>> {
+    parse_test(
+        r#"`(echo (unquote x))"#,
+        r#"{ quasiquote (echo (unquote x)) }"#,
+    )
+}
+
+#[test]
+fn eval_generator() -> Result<(), nom::Err<SyntaxError>> {
+    let mut env = Environment::new();
+    let expr = parse_script(
+        r#"let gen = x -> { yield x; yield [x]; yield };
+        for item in (gen 1) { item }"#,
+    )?;
+    assert_eq!(
+        expr.eval(&mut env),
+        Ok(Expression::List(vec![
+            Expression::Integer(1),
+            Expression::List(vec![Expression::Integer(1)]),
+            Expression::None,
+        ]))
+    );
+    Ok(())
+}
+
+#[test]
+fn generator_reentry() -> Result<(), nom::Err<SyntaxError>> {
+    static GENERATOR: std::sync::Mutex<Option<Expression>> = std::sync::Mutex::new(None);
+
+    let mut env = Environment::new();
+    env.define(
+        "again",
+        Expression::builtin(
+            "again",
+            |_, _| match GENERATOR.lock().unwrap().clone() {
+                Some(Expression::Generator(generator)) => {
+                    Ok(generator.next()?.unwrap_or(Expression::None))
+                }
+                _ => Ok(Expression::None),
+            },
+            "resume the generator under test",
+        ),
+    );
+    let generator = parse_script("let gen = _ -> { yield 1; yield (again ()) }; gen ()")?
+        .eval(&mut env)
+        .unwrap();
+    *GENERATOR.lock().unwrap() = Some(generator.clone());
+    let Expression::Generator(generator) = generator else {
+        panic!("expected a generator, got {:?}", generator);
+    };
+
+    assert_eq!(generator.next(), Ok(Some(Expression::Integer(1))));
+    // Resuming the generator from its own body fails instead of deadlocking.
+    assert_eq!(
+        generator.next(),
+        Err(Error::CustomError(
+            "generator is already running".to_string()
+        ))
+    );
+    assert_eq!(generator.next(), Ok(None));
+    Ok(())
+}
+
+#[test]
+fn eval_recursive_let() -> Result<(), nom::Err<SyntaxError>> {
+    let mut env = Environment::new();
+    let expr = parse_script(
+        r#"let f = 1;
+        let f = x -> if x { [x, f False] } else { [x, "done"] };
+        f True"#,
+    )?;
+    assert_eq!(
+        expr.eval(&mut env),
+        Ok(Expression::List(vec![
+            Expression::Boolean(true),
+            Expression::List(vec![
+                Expression::Boolean(false),
+                Expression::String("done".to_string()),
+            ]),
+        ]))
+    );
+    Ok(())
+}
+
+#[test]
+fn eval_for_map() -> Result<(), nom::Err<SyntaxError>> {
+    let mut env = Environment::new();
+    let expr = parse_script(
+        r#"let m = {b=1, a=2};
+        [for k v in m { [v, k] }, for pair in m { pair }]"#,
+    )?;
+    let pair = |a: Expression, b: Expression| Expression::List(vec![a, b]);
+    let (a, b) = (
+        || Expression::String("a".to_string()),
+        || Expression::String("b".to_string()),
+    );
+    assert_eq!(
+        expr.eval(&mut env),
+        Ok(Expression::List(vec![
+            Expression::List(vec![
+                pair(Expression::Integer(1), b()),
+                pair(Expression::Integer(2), a()),
+            ]),
+            Expression::List(vec![
+                pair(b(), Expression::Integer(1)),
+                pair(a(), Expression::Integer(2)),
+            ]),
+        ]))
+    );
+    assert!(parse_script(r#"for x y in [[1, 2, 3]] { x }"#)?
+        .eval(&mut env)
+        .is_err());
+    Ok(())
+}
+
+#[test]
+fn eval_local() -> Result<(), nom::Err<SyntaxError>> {
+    let mut env = Environment::new();
+    let expr = parse_script(
+        r#"let x = "outer";
+        let y = { local x = "inner"; let z = x; [x, z] };
+        [x, y, z]"#,
+    )?;
+    assert_eq!(
+        expr.eval(&mut env),
+        Ok(Expression::List(vec![
+            Expression::String("outer".to_string()),
+            Expression::List(vec![
+                Expression::String("inner".to_string()),
+                Expression::String("inner".to_string()),
+            ]),
+            Expression::String("inner".to_string()),
+        ]))
+    );
+    Ok(())
+}
+
+#[test]
+fn parse_division_and_powers() -> Result<(), nom::Err<SyntaxError>> {
+    parse_test("x / 2 ** 3 ** 2", "{ (/ x (** 2 (** 3 2))) }")?;
+    // A `/` given to a command is the root directory.
+    parse_test("cd /", r#"{ cd "/" }"#)?;
+    parse_test("find / -name a", r#"{ find "/" -name a }"#)?;
+    parse_test("echo 7 / 2", r#"{ echo 7 "/" 2 }"#)
+}
+
+#[test]
+fn eval_const() -> Result<(), nom::Err<SyntaxError>> {
+    let mut env = Environment::new();
+    parse_script(r#"const limit = 3"#)?.eval(&mut env).unwrap();
+    assert!(parse_script(r#"let limit = 4"#)?.eval(&mut env).is_err());
+    assert!(parse_script(r#"{ local limit = 4 }"#)?
+        .eval(&mut env)
+        .is_err());
+    assert_eq!(env.get("limit"), Some(Expression::Integer(3)));
+
+    // The error points at both bindings, like the warning from `analyze` does.
+    let script = "const size = 1;\nfor size in [2] { size }";
+    let error = parse_script(script)?
+        .eval(&mut Environment::new())
+        .unwrap_err();
+    assert_eq!(error, Error::ConstantRebound(String::from("size")));
+    let framed = error.in_source(script).to_string();
+    assert!(framed.contains("cannot bind `size` again, because it's a constant"));
+    assert!(framed.contains("    1 | const "));
+    assert!(framed.contains("it's defined as a constant here"));
+    assert!(framed.contains("    2 | for "));
+    assert!(framed.contains("so binding it here is an error"));
+    Ok(())
+}
+
+#[test]
+fn eval_method_call() -> Result<(), nom::Err<SyntaxError>> {
+    let mut env = Environment::new();
+    let expr = parse_script(
+        r#"let greeter = {greet = self -> name -> ["hello", name]};
+        greeter.greet "you""#,
+    )?;
+    assert_eq!(
+        expr.eval(&mut env),
+        Ok(Expression::List(vec![
+            Expression::String("hello".to_string()),
+            Expression::String("you".to_string()),
+        ]))
+    );
+    Ok(())
+}
+
+#[test]
+fn eval_map_order() -> Result<(), nom::Err<SyntaxError>> {
+    let mut env = Environment::new();
+    let map = parse_script(r#"{b=1, a=2, c=3}"#)?.eval(&mut env).unwrap();
+    match &map {
+        Expression::Map(map) => assert_eq!(map.keys().collect::<Vec<_>>(), ["b", "a", "c"]),
+        other => panic!("expected a map, got {:?}", other),
+    }
+    let shuffled = parse_script(r#"{c=3, a=2, b=1}"#)?.eval(&mut env).unwrap();
+    assert_eq!(map, shuffled);
+    Ok(())
+}
+
+#[test]
+fn repr_round_trip() -> Result<(), nom::Err<SyntaxError>> {
+    let mut env = Environment::new();
+    let value = parse_script(r#"{name="a \"quoted\"\n\\ line", "my key"=[1.0, -2, True, None]}"#)?
+        .eval(&mut env)
+        .unwrap();
+    let source = value.repr().unwrap();
+    assert_eq!(
+        source,
+        r#"{name="a \"quoted\"\n\\ line", "my key"=[1.0, -2, True, None]}"#
+    );
+    assert_eq!(parse_script(&source)?.eval(&mut env), Ok(value));
+    Ok(())
+}
+
+#[test]
+fn display_hooks() -> Result<(), nom::Err<SyntaxError>> {
+    let mut env = Environment::new();
+    let expr = parse_script(r#"{x=0, __display = self -> "origin", __debug = self -> "point"}"#)?;
+    let point = expr.eval(&mut env).unwrap();
+    assert_eq!(point.to_string(), "origin");
+    assert_eq!(format!("{:?}", point), "point");
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn strict_mode() -> Result<(), nom::Err<SyntaxError>> {
+    let mut env = Environment::new();
+    env.options_mut().strict = true;
+    // Bare words are only arguments to programs, which don't need defining.
+    assert_eq!(
+        parse_script("true main --quiet")?.eval(&mut env),
+        Ok(Expression::None)
+    );
+    assert_eq!(
+        parse_script("let x = main")?.eval(&mut env),
+        Err(Error::SymbolNotDefined(String::from("main")))
+    );
+    assert!(parse_script("true (main)")?.eval(&mut env).is_err());
+    Ok(())
+}
+
+#[test]
+fn runtime_errors_in_source() -> Result<(), nom::Err<SyntaxError>> {
+    let mut env = Environment::new();
+    env.options_mut().strict = true;
+    let script = "let x = 1;\nlet y = missing";
+    let error = parse_script(script)?.eval(&mut env).unwrap_err();
+    let framed = error.in_source(script).to_string();
+    assert!(framed.contains("symbol \"missing\" not defined"));
+    assert!(framed.contains("    2 | let y = "));
+    assert!(framed.contains("^^^^^^^"));
+
+    // A name used more than once can't say which use failed.
+    let script = "let y = missing; missing";
+    let error = parse_script(script)?.eval(&mut env).unwrap_err();
+    assert_eq!(error.in_source(script).to_string(), error.to_string());
+    Ok(())
+}
+
+#[test]
+fn programs_from_path_binding() {
+    let dir = std::env::temp_dir().join(format!("dune-path-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("dune-path-test"), "").unwrap();
+    let mut env = Environment::new();
+    assert_eq!(find_program("dune-path-test", &env), None);
+    env.define("PATH", Expression::String(dir.display().to_string()));
+    assert_eq!(
+        find_program("dune-path-test", &env),
+        Some(dir.join("dune-path-test"))
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_round_trip() -> Result<(), nom::Err<SyntaxError>> {
+    let mut env = Environment::new();
+    env.define_builtin(
+        "double",
+        |args, env| args[0].clone().eval(env)? * Expression::Integer(2),
+        "double a number",
+    );
+    parse_script("let quad = x -> double (double x); let both = {quad = quad, double = double}")?
+        .eval(&mut env)
+        .unwrap();
+
+    let json = serde_json::to_string(&env).unwrap();
+    let mut restored: Environment = serde_json::from_str(&json).unwrap();
+    assert_eq!(restored.get("both"), env.get("both"));
+    assert_eq!(
+        parse_script("[quad 2]")?.eval(&mut restored),
+        Ok(Expression::List(vec![Expression::Integer(8)]))
+    );
+
+    // A builtin this program never made can't be restored.
+    let missing = json.replace("\"double\"", "\"dune-no-such-builtin\"");
+    assert!(serde_json::from_str::<Environment>(&missing).is_err());
+    Ok(())
+}
+
+#[test]
+fn overloaded_operators() -> Result<(), nom::Err<SyntaxError>> {
+    let mut env = Environment::new();
+    env.define("bonus", Expression::Integer(5));
+    let money = parse_script(
+        r#"{cents = 5, __add = a -> b -> [b, bonus], __eq = a -> b -> True, __cmp = a -> b -> -1}"#,
+    )?
+    .eval(&mut env)
+    .unwrap();
+    // The functions are called where the operator is used, with the operands in order.
+    let added = |b: Expression| Some(Expression::List(vec![b, Expression::Integer(5)]));
+    assert_eq!(
+        money.try_overload("__add", &Expression::Integer(1), &mut env),
+        Ok(added(Expression::Integer(1)))
+    );
+    assert!(money.try_equals(&Expression::None, &mut env).unwrap());
+    assert_eq!(
+        money.try_cmp(&Expression::None, &mut env),
+        Ok(Some(std::cmp::Ordering::Less))
+    );
+    // With only the right operand overloading it, its function is used.
+    assert_eq!(
+        Expression::None.try_cmp(&money, &mut env),
+        Ok(Some(std::cmp::Ordering::Less))
+    );
+    assert_eq!(
+        Expression::Integer(1).try_overload("__add", &money, &mut env),
+        Ok(added(money.clone()))
+    );
+    // Values on their own don't run any code to be added or ordered.
+    assert_eq!(money.clone() + Expression::Integer(1), Ok(Expression::None));
+    assert_eq!(money.partial_cmp(&Expression::None), None);
+
+    // Errors in the functions are passed along to where the operator is used.
+    let broken = parse_script(r#"{__eq = a -> b -> (dune-no-such-program a), __cmp = a -> b -> (dune-no-such-program b)}"#)?
+        .eval(&mut env)
+        .unwrap();
+    assert!(broken.try_equals(&Expression::None, &mut env).is_err());
+    assert!(broken.try_cmp(&Expression::None, &mut env).is_err());
+    assert!(broken
+        .try_overload("__add", &Expression::None, &mut env)
+        .unwrap()
+        .is_none());
+    Ok(())
+}
+
+#[test]
+fn analyze_warnings() {
+    let warnings = analyze(
+        r#"let total = 0;
+let unused = 1;
+let _ignored = 2;
+let PATH = "/bin";
+let add = total -> {
+    let total = total + 1;
+    total
+};
+echo (add total);
+exit 0;
+echo "done""#,
+    );
+    let messages = warnings
+        .iter()
+        .map(|w| w.message.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        messages,
+        [
+            "unused variable `unused`",
+            "parameter `total` shadows a variable of the same name",
+            "unreachable code",
+        ]
+    );
+}
+
+#[test]
+fn redact_secrets() {
+    add_secret("correct-horse");
+    add_secret_pattern("tok_[0-9]+").unwrap();
+    assert!(add_secret_pattern("(").is_err());
+    assert_eq!(
+        redact("login correct-horse tok_123 tok_x"),
+        format!("login {} {} tok_x", REDACTED, REDACTED)
+    );
+}
+
+#[test]
+fn exported_follows_bindings() {
+    let mut env = Environment::new();
+    env.define("x", Expression::Integer(1));
+    assert_eq!(env.exported().get("x").map(String::as_str), Some("1"));
+    env.define("x", Expression::Integer(2));
+    env.define("y", Expression::String("a".repeat(2048)));
+    assert_eq!(env.exported().get("x").map(String::as_str), Some("2"));
+    assert!(!env.exported().contains_key("y"));
+    env.undefine("x");
+    assert!(!env.exported().contains_key("x"));
+}
+
+#[test]
+fn themes_and_styles() {
+    set_color_support(Some(ColorSupport::TrueColor));
+    for name in Theme::NAMES {
+        let theme = Theme::named(name).unwrap();
+        assert_eq!(theme.name, *name);
+    }
+    let dark = Theme::named("dark").unwrap();
+    assert_eq!(dark.error.start(), "\x1b[38;5;9m");
+    assert_eq!(dark.get("unknown-command").unwrap().spec(), "bright-red");
+    assert_eq!(
+        Style::parse("bold #ff8700 on-blue").unwrap().start(),
+        "\x1b[1;38;2;255;135;0;44m"
+    );
+    assert_eq!(Style::parse("none").unwrap().paint("x"), "x");
+    assert!(Style::parse("bright-purple").is_err());
+    assert!(Theme::named("neon").is_err());
+
+    // Colors the console doesn't have are brought down to the closest ones it does.
+    let sgr = "1;38;2;255;135;0;44";
+    assert_eq!(ColorSupport::Palette.adapt(sgr), "1;38;5;208;44");
+    assert_eq!(ColorSupport::Basic.adapt(sgr), "1;33;44");
+    assert_eq!(ColorSupport::Basic.adapt("48;5;196"), "101");
+    assert_eq!(ColorSupport::None.adapt(sgr), "1");
+    set_color_support(None);
+}
+
+#[test]
+fn display_limits() -> Result<(), nom::Err<SyntaxError>> {
+    let value = parse_script(r#"{a={b={c=1}}, l=[1, 2, 3, 4], s="abcdefgh", t=True}"#)?
+        .eval(&mut Environment::new())
+        .unwrap();
+    let limits = DisplayLimits {
+        depth: Some(2),
+        items: Some(3),
+        string: Some(4),
+    };
+    assert_eq!(
+        format!("{:?}", limits.apply(&value)),
+        r#"{a: {b: {… 1 key}}, l: [1, 2, 3, … 1 more], s: "abc…", …: 1 more key}"#
+    );
+    assert_eq!(DisplayLimits::default().apply(&value), value);
+    Ok(())
+}
+
+#[test]
+fn records_as_tables() -> Result<(), nom::Err<SyntaxError>> {
+    let mut env = Environment::new();
+    let rows = parse_script(r#"[{name="a.rs", size=12}, {name="b", size=3}]"#)?
+        .eval(&mut env)
+        .unwrap();
+    assert_eq!(
+        records_table(&rows, 80).unwrap(),
+        "╒══════╤══════╕\n\
+         │ name │ size │\n\
+         ╞══════╪══════╡\n\
+         │ a.rs │ 12   │\n\
+         │ b    │ 3    │\n\
+         └──────┴──────┘\n"
+    );
+    assert_eq!(records_table(&rows, 10), None);
+    let mixed = parse_script(r#"[{name="a.rs"}, {size=3}]"#)?
+        .eval(&mut env)
+        .unwrap();
+    assert_eq!(records_table(&mixed, 80), None);
+    Ok(())
+}
+
+#[test]
+fn checked_arithmetic() {
+    use Expression::Integer;
+    let error = |message: &str| Err(Error::CustomError(message.to_string()));
+    let overflow = |text: &str| {
+        error(&format!(
+            "integer overflow in {}, use `shopt \"wrapping\" True` to wrap around instead",
+            text
+        ))
+    };
+    assert_eq!(Integer(5) % Integer(0), error("modulo by zero in 5 % 0"));
+    assert_eq!(Integer(5) / Integer(0), error("division by zero in 5 // 0"));
+    assert_eq!(
+        Integer(5).true_div(Integer(0)),
+        error("division by zero in 5 / 0")
+    );
+    assert_eq!(
+        Integer(Int::MIN) % Integer(-1),
+        overflow(&format!("{} % -1", Int::MIN))
+    );
+    assert_eq!(
+        Integer(Int::MIN) / Integer(-1),
+        overflow(&format!("{} // -1", Int::MIN))
+    );
+    assert_eq!(-Integer(Int::MIN), overflow(&format!("-({})", Int::MIN)));
+    assert_eq!(
+        Integer(Int::MAX) + Integer(1),
+        overflow(&format!("{} + 1", Int::MAX))
+    );
+    assert_eq!(Integer(2).pow(Integer(64)), overflow("2 ** 64"));
+    assert_eq!(Integer(7) % Integer(3), Ok(Integer(1)));
+    assert_eq!(
+        Expression::String("ab".to_string()) * Integer(-1),
+        Ok(Expression::String(String::new()))
+    );
+}
+
+#[test]
+fn export_to_sh() -> Result<(), nom::Err<SyntaxError>> {
+    let script = parse_script(
+        r#"let n = 2;
+        ls -la | grep "it's" >> "out";
+        if n == 2 { echo "two" n (n * 3) } else if HOME != "" { cd HOME } else {};
+        for i in [1, 2] { echo i }"#,
+    )?;
+    assert_eq!(
+        export_sh(&script).unwrap(),
+        r#"#!/bin/sh
+n=2
+ls -la | grep 'it'\''s' > out
+if [ "$n" -eq 2 ]; then
+    echo two "$n" $((n * 3))
+elif [ "$HOME" != '' ]; then
+    cd "$HOME"
+else
+    :
+fi
+for i in 1 2; do
+    echo "$i"
+done
+"#
+    );
+    assert!(export_sh(&parse_script("let f = x -> x; f 1")?).is_err());
+    Ok(())
+}
+
+#[test]
+fn expand_macros_and_operators() -> Result<(), nom::Err<SyntaxError>> {
+    let mut env = Environment::new();
+    let inc = parse_script("a ~> a + 1")?.eval(&mut env).unwrap();
+    env.define("inc", inc);
+    let expand = |code: &str, env: &Environment| -> Result<String, nom::Err<SyntaxError>> {
+        Ok(to_source(&expand_macros(&parse_script(code)?, env)))
+    };
+    assert_eq!(expand("1 + 2 * 3", &env)?, "(+ 1 (* 2 3))");
+    assert_eq!(expand("inc 2", &env)?, "{\n    let a = 2;\n    (+ a 1)\n}");
+    // A function's parameter shadows the macro.
+    assert_eq!(expand("inc -> inc 2", &env)?, "inc -> inc 2");
+    Ok(())
+}
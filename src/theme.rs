@@ -0,0 +1,730 @@
+use super::Error;
+use std::sync::RwLock;
+
+/// Ends every style.
+const RESET: &str = "\x1b[m\x1b[0m";
+
+/// How a kind of text is drawn, written the way it's set, like `bold bright-cyan`,
+/// `208` for a color of the 256 color palette, or `#268bd2`. An empty style
+/// draws text as it is.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Style {
+    spec: String,
+    sgr: String,
+}
+
+impl Style {
+    /// Read a style made of colors and attributes separated by spaces. Colors
+    /// are names like `red` or `bright-red`, numbers of the 256 color palette,
+    /// or hex like `#ff8700`, and `on-` makes any of them the background.
+    pub fn parse(spec: &str) -> Result<Self, Error> {
+        const COLORS: &[&str] = &[
+            "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+        ];
+        let mut codes = vec![];
+        for word in spec.split_whitespace() {
+            let word = word.to_lowercase();
+            let (color, background) = match word.strip_prefix("on-") {
+                Some(color) => (color, true),
+                None => (word.as_str(), false),
+            };
+            let (base, extended) = if background { (40, 48) } else { (30, 38) };
+            let code = match color {
+                "none" | "default" if !background => continue,
+                "bold" if !background => String::from("1"),
+                "dim" if !background => String::from("2"),
+                "italic" if !background => String::from("3"),
+                "underline" if !background => String::from("4"),
+                "blink" if !background => String::from("5"),
+                "reverse" if !background => String::from("7"),
+                "strikethrough" if !background => String::from("9"),
+                "gray" | "grey" => (base + 60).to_string(),
+                hex if hex.starts_with('#') && hex.len() == 7 => {
+                    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16);
+                    match (channel(1), channel(3), channel(5)) {
+                        (Ok(r), Ok(g), Ok(b)) => format!("{};2;{};{};{}", extended, r, g, b),
+                        _ => return Err(Self::error(spec, &word)),
+                    }
+                }
+                number if number.chars().all(|c| c.is_ascii_digit()) => {
+                    match number.parse::<u8>() {
+                        Ok(n) => format!("{};5;{}", extended, n),
+                        Err(_) => return Err(Self::error(spec, &word)),
+                    }
+                }
+                name => {
+                    let (name, offset) = match name.strip_prefix("bright-") {
+                        Some(name) => (name, 60),
+                        None => (name, 0),
+                    };
+                    match COLORS.iter().position(|c| *c == name) {
+                        Some(n) => (base + offset + n).to_string(),
+                        None => return Err(Self::error(spec, &word)),
+                    }
+                }
+            };
+            codes.push(code);
+        }
+        Ok(Self {
+            spec: spec.split_whitespace().collect::<Vec<_>>().join(" "),
+            sgr: codes.join(";"),
+        })
+    }
+
+    fn error(spec: &str, word: &str) -> Error {
+        Error::CustomError(format!(
+            "unknown color or attribute `{}` in the style `{}`, expected something like \"bold bright-cyan\", \"208\", or \"#268bd2\"",
+            word, spec
+        ))
+    }
+
+    /// The style as it was written.
+    pub fn spec(&self) -> &str {
+        &self.spec
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sgr.is_empty()
+    }
+
+    /// The escape code that starts the style, with its colors brought down to
+    /// the ones the console has. It's empty for an empty style, or a style of
+    /// only colors without any.
+    pub fn start(&self) -> String {
+        let sgr = color_support().adapt(&self.sgr);
+        if sgr.is_empty() {
+            String::new()
+        } else {
+            format!("\x1b[{}m", sgr)
+        }
+    }
+
+    /// Draw some text in the style.
+    pub fn paint(&self, text: impl AsRef<str>) -> String {
+        let start = self.start();
+        if start.is_empty() {
+            text.as_ref().to_string()
+        } else {
+            format!("{}{}{}", start, text.as_ref(), RESET)
+        }
+    }
+}
+
+/// The colors a console can draw. Styles with colors it doesn't have are drawn
+/// in the closest ones it does, and attributes like `bold` are kept even
+/// without any colors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ColorSupport {
+    /// No colors, when `NO_COLOR` is set or the console is `dumb`.
+    None,
+    /// The 16 colors every color console has.
+    Basic,
+    /// The 256 color palette.
+    Palette,
+    /// Any color, like `#268bd2`.
+    TrueColor,
+}
+
+/// The color support set with `set_color_support`, or none to detect it.
+static COLOR_SUPPORT: RwLock<Option<ColorSupport>> = RwLock::new(None);
+
+/// The colors styles are drawn with.
+pub fn color_support() -> ColorSupport {
+    COLOR_SUPPORT
+        .read()
+        .unwrap()
+        .unwrap_or_else(ColorSupport::detect)
+}
+
+/// Draw styles with some colors, or `None` to detect what the console has.
+pub fn set_color_support(support: Option<ColorSupport>) {
+    *COLOR_SUPPORT.write().unwrap() = support;
+}
+
+/// The 16 basic colors, like xterm draws them.
+const BASIC_COLORS: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+/// The levels of red, green, and blue in the 6x6x6 cube of the 256 color palette.
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+impl ColorSupport {
+    /// The names the support is set with in `console@colors`.
+    pub const NAMES: &'static [&'static str] = &["none", "16", "256", "truecolor"];
+
+    pub fn named(name: &str) -> Result<Self, Error> {
+        match name {
+            "none" => Ok(Self::None),
+            "16" | "8" => Ok(Self::Basic),
+            "256" => Ok(Self::Palette),
+            "truecolor" | "24bit" => Ok(Self::TrueColor),
+            _ => Err(Error::CustomError(format!(
+                "unknown color support `{}`, expected one of {}",
+                name,
+                Self::NAMES.join(", ")
+            ))),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        Self::NAMES[self as usize]
+    }
+
+    /// Find the colors the console has from the variables consoles set:
+    /// `NO_COLOR` turns them off, `COLORTERM` tells if it has any color, and
+    /// the name of the console's terminfo entry in `TERM` says the rest.
+    pub fn detect() -> Self {
+        let var = |name: &str| std::env::var(name).unwrap_or_default().to_lowercase();
+        let term = var("TERM");
+        if !var("NO_COLOR").is_empty() || term == "dumb" {
+            Self::None
+        } else if matches!(var("COLORTERM").as_str(), "truecolor" | "24bit")
+            || term.ends_with("-direct")
+            || std::env::var_os("WT_SESSION").is_some()
+        {
+            Self::TrueColor
+        } else if term.contains("256") {
+            Self::Palette
+        } else {
+            Self::Basic
+        }
+    }
+
+    /// Bring the colors in the parameters of an SGR escape code, like
+    /// `1;38;2;38;139;210`, down to the closest ones the console has.
+    pub fn adapt(self, sgr: &str) -> String {
+        if self == Self::TrueColor {
+            return sgr.to_string();
+        }
+        let params = sgr.split(';').collect::<Vec<_>>();
+        let number = |i: usize| params.get(i).and_then(|n| n.parse::<u8>().ok());
+        let mut result = vec![];
+        let mut i = 0;
+        while i < params.len() {
+            let (background, color, len) = match (params[i], number(i + 1)) {
+                (code @ ("38" | "48"), Some(5)) => match number(i + 2) {
+                    Some(n) => (code == "48", Some(palette_rgb(n)), 3),
+                    None => (false, None, 1),
+                },
+                (code @ ("38" | "48"), Some(2)) => {
+                    match (number(i + 2), number(i + 3), number(i + 4)) {
+                        (Some(r), Some(g), Some(b)) => (code == "48", Some((r, g, b)), 5),
+                        _ => (false, None, 1),
+                    }
+                }
+                _ => (false, None, 1),
+            };
+            match color {
+                Some(rgb) => match self {
+                    Self::Palette if len == 3 => {
+                        result.extend(params[i..i + 3].iter().map(|p| p.to_string()))
+                    }
+                    Self::Palette => result.push(format!(
+                        "{};5;{}",
+                        if background { 48 } else { 38 },
+                        nearest_palette(rgb)
+                    )),
+                    Self::Basic => {
+                        let n = nearest(rgb, BASIC_COLORS.iter().copied()) as u8;
+                        let base = if background { 40 } else { 30 };
+                        result.push((base + n % 8 + if n >= 8 { 60 } else { 0 }).to_string());
+                    }
+                    _ => {}
+                },
+                None => match params[i].parse::<u8>() {
+                    // Without colors, only the attributes are left.
+                    Ok(30..=49 | 90..=107) if self == Self::None => {}
+                    _ => result.push(params[i].to_string()),
+                },
+            }
+            i += len;
+        }
+        result.retain(|param| !param.is_empty());
+        result.join(";")
+    }
+}
+
+/// The red, green, and blue of a color of the 256 color palette.
+fn palette_rgb(n: u8) -> (u8, u8, u8) {
+    match n {
+        0..=15 => BASIC_COLORS[n as usize],
+        16..=231 => {
+            let n = n - 16;
+            (
+                CUBE_LEVELS[(n / 36) as usize],
+                CUBE_LEVELS[(n / 6 % 6) as usize],
+                CUBE_LEVELS[(n % 6) as usize],
+            )
+        }
+        _ => {
+            let gray = 8 + 10 * (n - 232);
+            (gray, gray, gray)
+        }
+    }
+}
+
+/// The color of the 256 color palette closest to some red, green, and blue.
+fn nearest_palette(rgb: (u8, u8, u8)) -> u8 {
+    // The basic colors are left out, since consoles often change them.
+    16 + nearest(rgb, (16..=255).map(palette_rgb)) as u8
+}
+
+/// The index of the color closest to some red, green, and blue.
+fn nearest(rgb: (u8, u8, u8), colors: impl Iterator<Item = (u8, u8, u8)>) -> usize {
+    let distance = |(r, g, b): (u8, u8, u8)| {
+        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+        d(r, rgb.0) + d(g, rgb.1) + d(b, rgb.2)
+    };
+    colors
+        .enumerate()
+        .min_by_key(|(_, color)| distance(*color))
+        .map(|(i, _)| i)
+        .unwrap_or(0)
+}
+
+/// Bring the colors of the SGR escape codes in some text, like the output of
+/// `fmt@red`, down to the ones the console has.
+pub fn adapt_colors(text: &str) -> String {
+    let support = color_support();
+    if support == ColorSupport::TrueColor || !text.contains('\x1b') {
+        return text.to_string();
+    }
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("\x1b[") {
+        result += &rest[..start];
+        let after = &rest[start + 2..];
+        let len = after
+            .find(|c: char| !c.is_ascii_digit() && c != ';')
+            .unwrap_or(after.len());
+        if after[len..].starts_with('m') {
+            let sgr = &after[..len];
+            let adapted = support.adapt(sgr);
+            // An escape code of only colors goes away without them, but a reset stays.
+            if sgr.is_empty() || !adapted.is_empty() {
+                result += &format!("\x1b[{}m", adapted);
+            }
+            rest = &after[len + 1..];
+        } else {
+            result += "\x1b[";
+            rest = after;
+        }
+    }
+    result + rest
+}
+
+/// The colors used across the shell, for highlighting code as it's typed,
+/// errors and warnings, the borders of tables, the default prompt, and the
+/// output of builtins like `log` and `diff`. Set with `console@theme`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Theme {
+    pub name: String,
+    pub keyword: Style,
+    pub boolean: Style,
+    /// Punctuation with a meaning of its own, like `@`, `=`, and `->`.
+    pub punctuation: Style,
+    pub operator: Style,
+    pub string: Style,
+    pub number: Style,
+    /// The names of programs and functions that exist, at the start of a command.
+    pub command: Style,
+    /// The names of commands that don't exist, which are likely typos.
+    pub unknown_command: Style,
+    /// Builtins used all the time, like `echo` and `cd`.
+    pub builtin: Style,
+    pub none: Style,
+    pub comment: Style,
+    /// Code that can't be read, like an invalid escape in a string.
+    pub invalid: Style,
+    /// The bracket under the cursor, and the one matching it.
+    pub bracket: Style,
+    pub hint: Style,
+    pub error: Style,
+    pub warning: Style,
+    pub info: Style,
+    pub debug: Style,
+    pub trace: Style,
+    pub diff_add: Style,
+    pub diff_remove: Style,
+    pub diff_hunk: Style,
+    pub directory: Style,
+    /// The letters of an item matching what's typed in a fuzzy finder.
+    pub matched: Style,
+    pub table_border: Style,
+    pub prompt: Style,
+    pub prompt_path: Style,
+    pub prompt_incomplete: Style,
+    /// The exit code in the prompt after a line fails.
+    pub prompt_status: Style,
+    /// How long the last line took in the prompt, when it was slow.
+    pub prompt_duration: Style,
+    /// The line under each result in the REPL with `shopt annotate True`.
+    pub annotation: Style,
+}
+
+/// The theme set with `set_theme`, or none to detect one.
+static THEME: RwLock<Option<Theme>> = RwLock::new(None);
+
+/// The theme in use.
+pub fn theme() -> Theme {
+    THEME.read().unwrap().clone().unwrap_or_else(Theme::detect)
+}
+
+/// Use a theme across the shell.
+pub fn set_theme(theme: Theme) {
+    *THEME.write().unwrap() = Some(theme);
+}
+
+/// The styles of each built-in theme, in the order of `Theme::ROLES`.
+const DARK: &[&str] = &[
+    "bright-magenta",
+    "bright-magenta",
+    "bright-cyan",
+    "220",
+    "208",
+    "",
+    "bright-green",
+    "bright-red",
+    "bright-blue",
+    "bright-red",
+    "247",
+    "9",
+    "bold underline",
+    "bold",
+    "9",
+    "11",
+    "bright-green",
+    "bright-blue",
+    "bright-magenta",
+    "bright-green",
+    "bright-red",
+    "bright-cyan",
+    "bold bright-blue",
+    "bold bright-green",
+    "",
+    "bold blue",
+    "bold green",
+    "bold yellow",
+    "bold bright-red",
+    "yellow",
+    "dim",
+];
+
+const LIGHT: &[&str] = &[
+    "magenta",
+    "magenta",
+    "cyan",
+    "130",
+    "166",
+    "",
+    "green",
+    "red",
+    "blue",
+    "red",
+    "244",
+    "160",
+    "bold underline",
+    "bold",
+    "160",
+    "136",
+    "green",
+    "blue",
+    "magenta",
+    "green",
+    "red",
+    "cyan",
+    "bold blue",
+    "bold green",
+    "250",
+    "bold blue",
+    "bold green",
+    "bold 136",
+    "bold red",
+    "136",
+    "dim",
+];
+
+const SOLARIZED: &[&str] = &[
+    "#d33682",
+    "#6c71c4",
+    "#268bd2",
+    "#b58900",
+    "#2aa198",
+    "#6c71c4",
+    "#859900",
+    "#dc322f",
+    "#268bd2",
+    "#dc322f",
+    "#586e75",
+    "#dc322f",
+    "bold underline",
+    "#586e75",
+    "#dc322f",
+    "#b58900",
+    "#859900",
+    "#268bd2",
+    "#d33682",
+    "#859900",
+    "#dc322f",
+    "#2aa198",
+    "bold #268bd2",
+    "bold #859900",
+    "#586e75",
+    "bold #268bd2",
+    "bold #859900",
+    "bold #b58900",
+    "bold #dc322f",
+    "#b58900",
+    "#586e75",
+];
+
+/// Bold, bright colors that stand apart for color blindness too, with blue and
+/// orange instead of green and red for what's added and removed.
+const HIGH_CONTRAST: &[&str] = &[
+    "bold 117",
+    "bold 176",
+    "bold 15",
+    "214",
+    "226",
+    "176",
+    "bold 117",
+    "bold underline 202",
+    "bold 33",
+    "176",
+    "italic 250",
+    "bold reverse 202",
+    "bold reverse",
+    "250",
+    "bold 202",
+    "bold 226",
+    "117",
+    "250",
+    "250",
+    "bold 33",
+    "bold 214",
+    "bold 176",
+    "bold 117",
+    "bold underline 226",
+    "15",
+    "bold 15",
+    "bold 117",
+    "bold 226",
+    "bold 202",
+    "226",
+    "250",
+];
+
+/// Without colors, for consoles that don't have them or people who don't want them.
+const MONO: &[&str] = &[
+    "bold",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "underline",
+    "",
+    "",
+    "dim",
+    "reverse",
+    "bold underline",
+    "dim",
+    "bold",
+    "bold",
+    "",
+    "",
+    "",
+    "bold",
+    "dim",
+    "bold",
+    "bold",
+    "bold",
+    "",
+    "bold",
+    "bold",
+    "bold",
+    "bold",
+    "dim",
+    "dim",
+];
+
+impl Theme {
+    /// The names of the roles of a theme, in the order of its fields.
+    pub const ROLES: &'static [&'static str] = &[
+        "keyword",
+        "boolean",
+        "punctuation",
+        "operator",
+        "string",
+        "number",
+        "command",
+        "unknown-command",
+        "builtin",
+        "none",
+        "comment",
+        "invalid",
+        "bracket",
+        "hint",
+        "error",
+        "warning",
+        "info",
+        "debug",
+        "trace",
+        "diff-add",
+        "diff-remove",
+        "diff-hunk",
+        "directory",
+        "matched",
+        "table-border",
+        "prompt",
+        "prompt-path",
+        "prompt-incomplete",
+        "prompt-status",
+        "prompt-duration",
+        "annotation",
+    ];
+
+    /// The names of the built-in themes.
+    pub const NAMES: &'static [&'static str] =
+        &["dark", "light", "solarized", "mono", "high-contrast"];
+
+    /// A built-in theme, or `auto` to pick one for the console.
+    pub fn named(name: &str) -> Result<Self, Error> {
+        let styles = match name {
+            "auto" => return Ok(Self::detect()),
+            "dark" => DARK,
+            "light" => LIGHT,
+            "solarized" => SOLARIZED,
+            "mono" => MONO,
+            "high-contrast" => HIGH_CONTRAST,
+            _ => {
+                return Err(Error::CustomError(format!(
+                    "unknown theme `{}`, expected one of auto, {}",
+                    name,
+                    Self::NAMES.join(", ")
+                )))
+            }
+        };
+        let mut theme = Self {
+            name: name.to_string(),
+            ..Self::default()
+        };
+        for (role, spec) in Self::ROLES.iter().zip(styles) {
+            theme.set(role, spec)?;
+        }
+        Ok(theme)
+    }
+
+    /// Pick a theme for the console: `mono` when it has no colors, and
+    /// `light` when `COLORFGBG` says the background is light, like some
+    /// consoles set it. Otherwise, `dark`.
+    pub fn detect() -> Self {
+        let name = if color_support() == ColorSupport::None {
+            "mono"
+        } else {
+            // This is the foreground and background colors, like `15;0`.
+            match std::env::var("COLORFGBG")
+                .ok()
+                .and_then(|colors| colors.rsplit(';').next()?.parse::<u8>().ok())
+            {
+                Some(7 | 15) => "light",
+                _ => "dark",
+            }
+        };
+        Self::named(name).unwrap_or_default()
+    }
+
+    fn role(&mut self, name: &str) -> Result<&mut Style, Error> {
+        Ok(match name {
+            "keyword" => &mut self.keyword,
+            "boolean" => &mut self.boolean,
+            "punctuation" => &mut self.punctuation,
+            "operator" => &mut self.operator,
+            "string" => &mut self.string,
+            "number" => &mut self.number,
+            "command" => &mut self.command,
+            "unknown-command" => &mut self.unknown_command,
+            "builtin" => &mut self.builtin,
+            "none" => &mut self.none,
+            "comment" => &mut self.comment,
+            "invalid" => &mut self.invalid,
+            "bracket" => &mut self.bracket,
+            "hint" => &mut self.hint,
+            "error" => &mut self.error,
+            "warning" => &mut self.warning,
+            "info" => &mut self.info,
+            "debug" => &mut self.debug,
+            "trace" => &mut self.trace,
+            "diff-add" => &mut self.diff_add,
+            "diff-remove" => &mut self.diff_remove,
+            "diff-hunk" => &mut self.diff_hunk,
+            "directory" => &mut self.directory,
+            "matched" => &mut self.matched,
+            "table-border" => &mut self.table_border,
+            "prompt" => &mut self.prompt,
+            "prompt-path" => &mut self.prompt_path,
+            "prompt-incomplete" => &mut self.prompt_incomplete,
+            "prompt-status" => &mut self.prompt_status,
+            "prompt-duration" => &mut self.prompt_duration,
+            "annotation" => &mut self.annotation,
+            _ => {
+                return Err(Error::CustomError(format!(
+                    "unknown theme role `{}`, expected one of {}",
+                    name,
+                    Self::ROLES.join(", ")
+                )))
+            }
+        })
+    }
+
+    pub fn get(&self, role: &str) -> Result<Style, Error> {
+        self.clone().role(role).map(|style| style.clone())
+    }
+
+    /// Set the style of a role, like `set("string", "bright-yellow")`.
+    pub fn set(&mut self, role: &str, spec: &str) -> Result<(), Error> {
+        *self.role(role)? = Style::parse(spec)?;
+        Ok(())
+    }
+
+    /// Draw the lines of the tables in some text in the `table-border` style.
+    pub fn paint_borders(&self, text: &str) -> String {
+        if self.table_border.is_empty() {
+            return text.to_string();
+        }
+        let is_border = |c: char| ('\u{2500}'..='\u{257f}').contains(&c);
+        let mut result = String::new();
+        let mut border = String::new();
+        for c in text.chars() {
+            if is_border(c) {
+                border.push(c);
+                continue;
+            }
+            if !border.is_empty() {
+                result += &self.table_border.paint(std::mem::take(&mut border));
+            }
+            result.push(c);
+        }
+        if !border.is_empty() {
+            result += &self.table_border.paint(border);
+        }
+        result
+    }
+}
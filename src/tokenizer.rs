@@ -82,6 +82,7 @@ fn any_punctuation(input: Input<'_>) -> TokenizationResult<'_> {
         punctuation_tag("{"),
         punctuation_tag("}"),
         punctuation_tag("\'"),
+        punctuation_tag("`"),
         punctuation_tag(","),
         punctuation_tag(";"),
         punctuation_tag("="),
@@ -100,6 +101,7 @@ fn long_operator(input: Input<'_>) -> TokenizationResult<'_> {
         keyword_tag("&&"),
         keyword_tag("||"),
         keyword_tag("//"),
+        keyword_tag("**"),
         keyword_tag("<<"),
         keyword_tag(">>"),
         keyword_tag(">>>"),
@@ -113,6 +115,8 @@ fn short_operator(input: Input<'_>) -> TokenizationResult<'_> {
         keyword_tag("+"),
         keyword_tag("-"),
         keyword_tag("*"),
+        // Only a `/` on its own, because it's in paths too.
+        keyword_tag("/"),
         keyword_tag("%"),
         keyword_tag("|"),
         punctuation_tag("@"),
@@ -126,9 +130,12 @@ fn any_keyword(input: Input<'_>) -> TokenizationResult<'_> {
         keyword_tag("then"),
         keyword_tag("else"),
         keyword_tag("let"),
+        keyword_tag("local"),
+        keyword_tag("const"),
         keyword_tag("for"),
         keyword_tag("if"),
         keyword_tag("in"),
+        keyword_tag("yield"),
     ))(input)
 }
 
@@ -396,3 +403,37 @@ pub fn tokenize(input: &str) -> (Vec<Token>, Vec<Diagnostic>) {
     let input = Input::new(&str);
     parse_tokens(input)
 }
+
+/// Tokenize a line after it was edited, given the tokens of the line before.
+/// The tokens that end before the first change are kept, so only the rest of
+/// the line is tokenized again, which keeps highlighting long lines fast.
+///
+/// A token is only kept if at least one character after it is unchanged too,
+/// because whether a token ends where it does can depend on the character
+/// after it, like `to` in `toast`.
+pub fn retokenize(
+    line: &str,
+    before: &str,
+    tokens: &[Token],
+    diagnostics: &[Diagnostic],
+) -> (Vec<Token>, Vec<Diagnostic>) {
+    let unchanged = line
+        .char_indices()
+        .zip(before.chars())
+        .find(|((_, a), b)| a != b)
+        .map(|((i, _), _)| i)
+        .unwrap_or_else(|| line.len().min(before.len()));
+    let kept = tokens
+        .iter()
+        .take_while(|token| token.range.end() < unchanged)
+        .count();
+    let start = kept.checked_sub(1).map_or(0, |i| tokens[i].range.end());
+
+    let str = line.into();
+    let (rest, rest_diagnostics) = parse_tokens(Input::new_at(&str, start));
+    let mut tokens = tokens[..kept].to_vec();
+    tokens.extend(rest);
+    let mut diagnostics = diagnostics[..kept].to_vec();
+    diagnostics.extend(rest_diagnostics);
+    (tokens, diagnostics)
+}
@@ -28,6 +28,11 @@ impl<'a> Input<'a> {
         Input { str, offset: 0 }
     }
 
+    /// Start partway through a string, at a byte offset.
+    pub fn new_at(str: &'a Str, offset: usize) -> Self {
+        Input { str, offset }
+    }
+
     pub fn is_empty(self) -> bool {
         self.offset == self.str.len()
     }